@@ -1,33 +1,52 @@
 #import wanderer_tales::noise::{
 perlin_noise_2d,
-Value2Dt2,
-Value2Dt1,
+value_noise_2d,
+Dt2Value,
+Dt1Value,
 mul_dt2_f,
+div_dt2_f,
+add_dt2_f,
 dt2_length,
 add_dt1_f,
 mul_dt1_f,
-div_dt1_dt1,
+div_dt1,
 dt1_length,
 div_dt1_f,
-dt2_to_dt1,
-add_dt1_dt1
+truncate_dt2,
+add_dt1,
+mul_dt1,
 }
 
-
-
-
-
-const LAYERS_COUNT = 10;
+// Mirrors TerrainSampler::sample, keep both in sync
 
 struct LayerWeight {
     size: f32,
     amplitude: f32,
     erosion: f32,
     seed: u32,
+    // NoiseKind, 0 is perlin and 1 is value
+    noise: u32,
+}
+
+// Mirrors BiomeLayers, its layers are weights[first_layer..first_layer + layer_count]
+struct Biome {
+    temperature: f32,
+    moisture: f32,
+    first_layer: u32,
+    layer_count: u32,
+}
+
+struct Climate {
+    size: f32,
+    blend: f32,
+    temperature_seed: u32,
+    moisture_seed: u32,
+    // HasherKind of every noise of the sampler
+    hasher: u32,
 }
 
 struct Layer {
-    sample: Value2Dt1,
+    sample: Dt1Value,
     erosion: f32,
 }
 
@@ -36,28 +55,84 @@ struct Layer {
 var<storage, read> positions: array<vec2<f32>>;
 
 @group(0) @binding(1)
-var<uniform> weights: array<LayerWeight, LAYERS_COUNT>;
+var<storage, read> weights: array<LayerWeight>;
 
 @group(0) @binding(2)
-var<storage, read_write> result: array<Value2Dt1>;
+var<storage, read_write> result: array<Dt1Value>;
+
+@group(0) @binding(3)
+var<storage, read> biomes: array<Biome>;
 
-@compute @workgroup_size(1)
+@group(0) @binding(4)
+var<uniform> climate: Climate;
+
+@compute @workgroup_size(64)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    let position = positions[global_id.x];
-    result[global_id.x] = sample_many_base(position, LAYERS_COUNT).sample;
+    let index = global_id.x;
+    if index >= arrayLength(&positions) {
+        return;
+    }
+
+    result[index] = sample_biomes(positions[index]);
+}
+
+fn sample_climate(pos: vec2<f32>, seed: u32) -> Dt1Value {
+    let noise = perlin_noise_2d(pos, 1.0 / climate.size, seed, climate.hasher);
+    return truncate_dt2(add_dt2_f(div_dt2_f(noise, 2.0), 0.5));
+}
+
+// Mirrors BiomeLayers::climate_distance
+fn climate_distance(temperature: Dt1Value, moisture: Dt1Value, biome: Biome) -> Dt1Value {
+    let t = add_dt1_f(temperature, -biome.temperature);
+    let m = add_dt1_f(moisture, -biome.moisture);
+    return add_dt1(mul_dt1(t, t), mul_dt1(m, m));
+}
+
+// Mirrors biome_influences and the blending in TerrainSampler::sample
+fn sample_biomes(pos: vec2<f32>) -> Dt1Value {
+    let temperature = sample_climate(pos, climate.temperature_seed);
+    let moisture = sample_climate(pos, climate.moisture_seed);
+    let sharpness = 1.0 / (climate.blend * climate.blend);
+
+    var min_distance = 3.4e38;
+    for (var i: u32 = 0u; i < arrayLength(&biomes); i = i + 1u) {
+        min_distance = min(min_distance, climate_distance(temperature, moisture, biomes[i]).value);
+    }
+
+    var total = Dt1Value(0.0, vec2<f32>(0.0, 0.0));
+    var height = Dt1Value(0.0, vec2<f32>(0.0, 0.0));
+    for (var i: u32 = 0u; i < arrayLength(&biomes); i = i + 1u) {
+        let biome = biomes[i];
+        let distance = climate_distance(temperature, moisture, biome);
+        let value = exp(-(distance.value - min_distance) * sharpness);
+        let influence = Dt1Value(value, -distance.gradient * value * sharpness);
+
+        total = add_dt1(total, influence);
+        // far away biomes underflow to nothing
+        if value == 0.0 {
+            continue;
+        }
+
+        let layers = sample_many_base(pos, biome.first_layer, biome.layer_count).sample;
+        height = add_dt1(height, mul_dt1(influence, layers));
+    }
+
+    return div_dt1(height, total);
 }
 
 fn sample_many_base(
     pos: vec2<f32>,
+    first_layer: u32,
+    layer_count: u32,
 ) -> Layer {
     var erosion_factor = 0.0;
-    var terrain = Value2Dt1(0.0, vec2<f32>(0.0, 0.0));
+    var terrain = Dt1Value(0.0, vec2<f32>(0.0, 0.0));
 
-    for (var i: u32 = 0; i < LAYERS_COUNT; i = i + 1) {
+    for (var i: u32 = first_layer; i < first_layer + layer_count; i = i + 1u) {
         let weight: LayerWeight = weights[i];
         let layer: Layer = sample_erosion_base(pos, weight, erosion_factor);
 
-        terrain = add_dt1_dt1(terrain, layer.sample);
+        terrain = add_dt1(terrain, layer.sample);
         erosion_factor = layer.erosion;
     }
 
@@ -69,20 +144,28 @@ fn sample_erosion_base(
     weight: LayerWeight,
     erosion_factor: f32,
 ) -> Layer {
-    let layer: Value2Dt2 = sample_perlin_2d(pos, weight.size, weight.amplitude, weight.seed);
-    let layer_steepiness: Value2Dt1 = dt2_length(layer);
+    let layer: Dt2Value = sample_layer(pos, weight);
+    let layer_steepiness: Dt1Value = dt2_length(layer);
 
-    let pre_erosion_factor: Value2Dt1 = add_dt1_f(layer_steepiness, erosion_factor);
-    let v: Value2Dt1 = add_dt1_f(mul_dt1_f(pre_erosion_factor, weight.erosion), 1.0);
+    let pre_erosion_factor: Dt1Value = add_dt1_f(layer_steepiness, erosion_factor);
+    let v: Dt1Value = add_dt1_f(mul_dt1_f(pre_erosion_factor, weight.erosion), 1.0);
 
-    let layer_sample: Value2Dt2 = div_dt1_dt1(dt2_to_dt1(layer), v);
-    let layer_erosion: f32 = dt1_length(div_dt1_f(dt2_to_dt1(layer), v.value));
+    let layer_sample: Dt1Value = div_dt1(truncate_dt2(layer), v);
+    let layer_erosion: f32 = dt1_length(div_dt1_f(truncate_dt2(layer), v.value));
 
     return Layer(layer_sample, layer_erosion);
 }
 
-fn sample_perlin_2d(pos: vec2<f32>, size: f32, amplitude: f32, seed: u32) -> Value2Dt2 {
-    let v = perlin_noise_2d(pos, 1.0 / size, seed) / 2.0 + 0.5;
-    return mul_dt2_f(v, amplitude);
+fn sample_layer(pos: vec2<f32>, weight: LayerWeight) -> Dt2Value {
+    var noise: Dt2Value;
+    switch weight.noise {
+        case 1u: {
+            noise = value_noise_2d(pos, 1.0 / weight.size, weight.seed, climate.hasher);
+        }
+        default: {
+            noise = perlin_noise_2d(pos, 1.0 / weight.size, weight.seed, climate.hasher);
+        }
+    }
+    let v = add_dt2_f(div_dt2_f(noise, 2.0), 0.5);
+    return mul_dt2_f(v, weight.amplitude);
 }
-
@@ -0,0 +1,42 @@
+#import bevy_pbr::mesh_functions
+#import wanderer_tales::utils::{lod_morph_factor, lod_morph_vertex}
+
+struct TerrainMaterial {
+    grass_color: vec4<f32>,
+    dirt_color: vec4<f32>,
+    rock_color: vec4<f32>,
+    snow_color: vec4<f32>,
+    dirt_slope: f32,
+    rock_slope: f32,
+    snow_height: f32,
+    max_height: f32,
+    blend_width: f32,
+    noise_scale: f32,
+    noise_strength: f32,
+    camera_position: vec3<f32>,
+    lod_distance_scale: f32,
+    lod_morph_start: f32,
+    lod_morph_end: f32,
+}
+
+@group(2) @binding(100)
+var<uniform> terrain_material: TerrainMaterial;
+
+// World position of a chunk vertex, morphed towards the coarser LOD with the distance to the camera.
+// `lod_morph` is the height on the coarser LOD and the size of the chunk.
+// Shadow views morph with the camera too, so shadows match the visible terrain.
+fn terrain_world_position(
+    world_from_local: mat4x4<f32>,
+    position: vec3<f32>,
+    lod_morph: vec2<f32>,
+) -> vec4<f32> {
+    let world_position = mesh_functions::mesh_position_local_to_world(world_from_local, vec4(position, 1.0));
+    let morph = lod_morph_factor(
+        distance(world_position.xyz, terrain_material.camera_position),
+        lod_morph.y * terrain_material.lod_distance_scale,
+        terrain_material.lod_morph_start,
+        terrain_material.lod_morph_end,
+    );
+    let morphed_position = lod_morph_vertex(position, lod_morph.x, morph);
+    return mesh_functions::mesh_position_local_to_world(world_from_local, vec4(morphed_position, 1.0));
+}
@@ -0,0 +1,76 @@
+#import bevy_pbr::{
+    mesh_functions,
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::{alpha_discard, apply_pbr_lighting, main_pass_post_lighting_processing},
+    forward_io::{VertexOutput, FragmentOutput},
+    view_transformations::position_world_to_clip,
+}
+#import wanderer_tales::noise::{perlin_noise_2d, HASHER_SIMPLE}
+#import "shaders/terrain_common.wgsl"::{terrain_material, terrain_world_position}
+
+// forward_io::Vertex with the LOD morph, chunks are neither skinned nor morph targets
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(8) lod_morph: vec2<f32>,
+};
+
+fn layer_weight(value: f32, threshold: f32) -> f32 {
+    let half_width = terrain_material.blend_width * 0.5;
+    return smoothstep(threshold - half_width, threshold + half_width, value);
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+
+    let world_from_local = mesh_functions::get_world_from_local(vertex.instance_index);
+    out.world_position = terrain_world_position(world_from_local, vertex.position, vertex.lod_morph);
+    out.position = position_world_to_clip(out.world_position.xyz);
+    out.world_normal = mesh_functions::mesh_normal_local_to_world(vertex.normal, vertex.instance_index);
+#ifdef VERTEX_UVS_A
+    out.uv = vertex.uv;
+#endif
+#ifdef VERTEX_OUTPUT_INSTANCE_INDEX
+    out.instance_index = vertex.instance_index;
+#endif
+#ifdef VISIBILITY_RANGE_DITHER
+    out.visibility_range_dither = mesh_functions::get_visibility_range_dither_level(
+        vertex.instance_index, world_from_local[3]);
+#endif
+
+    return out;
+}
+
+@fragment
+fn fragment(
+    in: VertexOutput,
+    @builtin(front_facing) is_front: bool,
+) -> FragmentOutput {
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+
+    let world_position = pbr_input.world_position.xyz;
+    // 0 on flat ground, 1 on vertical cliffs
+    let slope = 1.0 - normalize(pbr_input.world_normal).y;
+    let height = world_position.y / terrain_material.max_height;
+    let mask = perlin_noise_2d(world_position.xz, terrain_material.noise_scale, 0u, HASHER_SIMPLE).value;
+    let offset = mask * terrain_material.noise_strength;
+
+    let dirt = layer_weight(slope + offset, terrain_material.dirt_slope);
+    let rock = layer_weight(slope + offset, terrain_material.rock_slope);
+    let snow = layer_weight(height + offset, terrain_material.snow_height) * (1.0 - rock);
+
+    var color = mix(terrain_material.grass_color, terrain_material.dirt_color, dirt);
+    color = mix(color, terrain_material.rock_color, rock);
+    color = mix(color, terrain_material.snow_color, snow);
+
+    pbr_input.material.base_color = pbr_input.material.base_color * color;
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+    return out;
+}
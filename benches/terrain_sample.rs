@@ -1,16 +1,44 @@
 use bevy::math::vec2;
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use wanderer_tales::game::map::TerrainSampler;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use wanderer_tales::{
+    game::map::{BaseSeed, TerrainSampler},
+    utils::noise::Value2Dt1,
+};
 
-pub fn terrain_chunk_sample(c: &mut Criterion) {
+/// Vertices per side of a chunk.
+const GRID_SIZE: u32 = 17;
+const GRID_STEP: f32 = 4.0;
+
+pub fn terrain_sample(c: &mut Criterion) {
     let terrain = TerrainSampler::default();
-    c.bench_function("terrain chunk sampler", |b| {
+    let base_seed = BaseSeed(42);
+    let origin = vec2(1.1, 2.2);
+
+    let mut group = c.benchmark_group("terrain sample");
+    group.throughput(Throughput::Elements((GRID_SIZE * GRID_SIZE) as u64));
+    group.bench_function("scalar", |b| {
+        b.iter(|| {
+            for (y, x) in itertools::iproduct!(0..GRID_SIZE, 0..GRID_SIZE) {
+                let pos = origin + vec2(x as f32, y as f32) * GRID_STEP;
+                black_box(terrain.sample(black_box(pos), &base_seed));
+            }
+        })
+    });
+    group.bench_function("grid", |b| {
+        let mut heights = vec![Value2Dt1::default(); (GRID_SIZE * GRID_SIZE) as usize];
         b.iter(|| {
-            let chunk_sampler = terrain.chunk_sampler(black_box(vec2(1.1, 2.2)));
-            chunk_sampler(black_box(10.0), black_box(20.0));
+            terrain.sample_grid(
+                black_box(origin),
+                GRID_STEP,
+                GRID_SIZE,
+                &base_seed,
+                &mut heights,
+            );
+            black_box(&heights);
         })
     });
+    group.finish();
 }
 
-criterion_group!(benches, terrain_chunk_sample);
+criterion_group!(benches, terrain_sample);
 criterion_main!(benches);
@@ -111,30 +111,23 @@ fn receive(wgsl_main: Res<TestMainBurrito>) {
 fn setup_pipeline(
     mut wgsl: ResMut<TestRenderBurrito>,
     render_device: Res<RenderDevice>,
-    pipeline_cache: Res<PipelineCache>,
     asset_server: Res<AssetServer>,
 ) {
     let layout = wgsl
-        .builder_layout(
-            LayoutLabels::TestLayout,
-            render_device.as_ref(),
-            ShaderStages::COMPUTE,
-        )
-        .with_storage_slot::<BufferVecType>()
-        .build();
+        .builder_layout(LayoutLabels::TestLayout, ShaderStages::COMPUTE)
+        .with_storage_slot::<BufferVecType>();
+    wgsl.create_layout(layout, render_device.as_ref());
 
-    let pipeline = pipeline_cache.queue_compute_pipeline(
-        wgsl.builder_pipeline(
+    // queued once the shader is loaded and matches the layout
+    let pipeline = wgsl
+        .builder_pipeline(
             PipelineLabels::TestPipeline,
             asset_server.load(SHADER_ASSET_PATH),
             "main",
         )
-        .with_layout_value(layout.clone())
-        .build(),
-    );
-
-    wgsl.insert_layout(LayoutLabels::TestLayout, layout)
-        .insert_pipeline(PipelineLabels::TestPipeline, pipeline);
+        .with_layout(&LayoutLabels::TestLayout)
+        .build();
+    wgsl.queue_pipeline(PipelineLabels::TestPipeline, pipeline);
 }
 
 fn prepare_bind_group(render_device: Res<RenderDevice>, mut wgsl: ResMut<TestRenderBurrito>) {
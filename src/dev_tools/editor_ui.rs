@@ -102,7 +102,7 @@ enum EditorView {
     Resources,
     Assets,
     Inspector,
-    // TerrainGen,
+    TerrainGen,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
@@ -143,10 +143,10 @@ impl EditorView {
                 let tree = state.main_surface_mut();
                 tree.split_right(egui_dock::NodeIndex::root(), 0.8, vec![*self]);
             }
-            // EditorView::TerrainGen => {
-            //     let tree = state.main_surface_mut();
-            //     tree.split_right(egui_dock::NodeIndex::root(), 0.8, vec![*self]);
-            // }
+            EditorView::TerrainGen => {
+                let tree = state.main_surface_mut();
+                tree.split_right(egui_dock::NodeIndex::root(), 0.8, vec![*self]);
+            }
             _ => {}
         }
     }
@@ -175,7 +175,7 @@ impl EditorView {
                 selection,
                 selected_entities,
             }),
-            // EditorView::TerrainGen => Box::new(game::map::devtools::EditorTerrain {}),
+            EditorView::TerrainGen => Box::new(game::map::devtools::EditorTerrain {}),
             EditorView::GameView => Box::new(GameUI {}),
         }
     }
@@ -186,7 +186,7 @@ impl EditorView {
             EditorView::Resources => "Resources",
             EditorView::Assets => "Assets",
             EditorView::Inspector => "Inspector",
-            // EditorView::TerrainGen => "Terrain Gen",
+            EditorView::TerrainGen => "Terrain Gen",
             EditorView::GameView => "Game View",
         }
     }
@@ -319,9 +319,7 @@ impl egui_dock::TabViewer for EditorTabs<'_> {
         ui.set_min_width(120.0);
         ui.style_mut().visuals.button_frame = false;
 
-        let top_openable: Vec<EditorView> = vec![
-            // EditorView::TerrainGen
-        ];
+        let top_openable = vec![EditorView::TerrainGen];
         let window_openable = vec![WindowView::Flags];
         let other_openable = vec![
             EditorView::Hierarchy,
@@ -23,7 +23,7 @@ pub fn plugin(app: &mut App) {
     app.add_plugins((
         minimal_dev_tools_plugin,
         game::physics::devtools::plugin,
-        // game::map::devtools::plugin,
+        game::map::devtools::plugin,
         game::character_controller::devtools::plugin,
     ));
 }
@@ -4,6 +4,8 @@ use bevy::{
     utils::HashMap,
 };
 
+use super::map::preset::{TerrainPreset, TerrainPresetLoader};
+
 pub(super) fn plugin(app: &mut App) {
     app.register_type::<HandleMap<ImageKey>>();
     app.init_resource::<HandleMap<ImageKey>>();
@@ -13,6 +15,11 @@ pub(super) fn plugin(app: &mut App) {
 
     app.register_type::<HandleMap<SoundtrackKey>>();
     app.init_resource::<HandleMap<SoundtrackKey>>();
+
+    app.init_asset::<TerrainPreset>();
+    app.init_asset_loader::<TerrainPresetLoader>();
+    app.register_type::<HandleMap<TerrainPresetKey>>();
+    app.init_resource::<HandleMap<TerrainPresetKey>>();
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Reflect)]
@@ -102,6 +109,26 @@ impl FromWorld for HandleMap<SoundtrackKey> {
     }
 }
 
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Reflect)]
+pub enum TerrainPresetKey {
+    Default,
+}
+
+impl AssetKey for TerrainPresetKey {
+    type Asset = TerrainPreset;
+}
+
+impl FromWorld for HandleMap<TerrainPresetKey> {
+    fn from_world(world: &mut World) -> Self {
+        let asset_server = world.resource::<AssetServer>();
+        [(
+            TerrainPresetKey::Default,
+            asset_server.load("terrain/default.terrain.ron"),
+        )]
+        .into()
+    }
+}
+
 pub trait AssetKey: Sized {
     type Asset: Asset;
 }
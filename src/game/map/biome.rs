@@ -0,0 +1,320 @@
+//! Biomes, picked from low frequency temperature and moisture fields.
+//! Every biome has its own height graph, heights are blended across borders by the biome weights.
+
+use super::*;
+
+#[derive(Debug, Reflect, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub enum Biome {
+    Plains,
+    Mountains,
+    Desert,
+    Forest,
+    Tundra,
+}
+
+impl Biome {
+    pub const COUNT: usize = 5;
+    pub const ALL: [Biome; Biome::COUNT] = [
+        Biome::Plains,
+        Biome::Mountains,
+        Biome::Desert,
+        Biome::Forest,
+        Biome::Tundra,
+    ];
+}
+
+/// How much each biome contributes to a point, the weights sum to 1.
+#[derive(Debug, Reflect, Clone, Copy, Default, PartialEq)]
+pub struct BiomeWeights([f32; Biome::COUNT]);
+
+impl BiomeWeights {
+    pub(super) fn add(&mut self, biome: Biome, weight: f32) {
+        self.0[biome as usize] += weight;
+    }
+
+    pub fn get(&self, biome: Biome) -> f32 {
+        self.0[biome as usize]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Biome, f32)> + '_ {
+        Biome::ALL.into_iter().zip(self.0.iter().copied())
+    }
+
+    /// Biome with the biggest weight.
+    pub fn dominant(&self) -> Biome {
+        self.iter()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(biome, _)| biome)
+            .unwrap_or(Biome::Plains)
+    }
+}
+
+/// Temperature and moisture fields the biomes are picked from.
+#[derive(Debug, Reflect, Clone, Copy, Deserialize)]
+pub struct Climate {
+    /// Size of the temperature and moisture noise.
+    pub size: f32,
+    /// Distance in climate space over which biomes blend, smaller gives sharper borders.
+    pub blend: f32,
+}
+
+impl Default for Climate {
+    fn default() -> Self {
+        Self {
+            size: 6000.0,
+            blend: 0.15,
+        }
+    }
+}
+
+impl Climate {
+    /// Seeds of the temperature and moisture noise.
+    pub fn seeds(&self, base_seed: &BaseSeed) -> [u32; 2] {
+        [0, 1].map(|index| base_seed.hasher(SeedDomain::Climate, index).seed())
+    }
+
+    /// Temperature and moisture at `pos`, in [0, 1].
+    pub fn sample(
+        &self,
+        pos: Vec2,
+        base_seed: &BaseSeed,
+        hasher: HasherKind,
+    ) -> [noise::Value2Dt1; 2] {
+        self.seeds(base_seed).map(|seed| {
+            let hasher = hasher.hasher(seed);
+            noise::perlin_noise_2d(pos, 1.0 / self.size, &hasher).to_dt1() / 2.0 + 0.5
+        })
+    }
+
+    /// [`Climate::sample`] on a grid of `n` by `n` points spaced by `step` from `origin`, row by row.
+    pub fn sample_grid(
+        &self,
+        origin: Vec2,
+        step: f32,
+        n: u32,
+        base_seed: &BaseSeed,
+        hasher: HasherKind,
+    ) -> [Vec<noise::Value2Dt1>; 2] {
+        self.seeds(base_seed).map(|seed| {
+            let hasher = hasher.hasher(seed);
+            let mut samples = vec![noise::Value2Dt2::default(); (n * n) as usize];
+            noise::perlin_noise_2d_grid(
+                origin,
+                step,
+                UVec2::splat(n),
+                1.0 / self.size,
+                &hasher,
+                &mut samples,
+            );
+            samples
+                .into_iter()
+                .map(|sample| sample.to_dt1() / 2.0 + 0.5)
+                .collect()
+        })
+    }
+}
+
+/// Height graph of a biome and where the biome sits in the climate.
+#[derive(Debug, Reflect, Clone, Deserialize)]
+pub struct BiomeLayers {
+    pub biome: Biome,
+    pub temperature: f32,
+    pub moisture: f32,
+    /// Height of the biome, usually a single stack of eroded layers.
+    pub height: NoiseGraph,
+}
+
+impl BiomeLayers {
+    /// Squared distance between the biome and the climate at a point.
+    fn climate_distance(&self, climate: &[noise::Value2Dt1; 2]) -> noise::Value2Dt1 {
+        let temperature = climate[0] + -self.temperature;
+        let moisture = climate[1] + -self.moisture;
+        temperature * temperature + moisture * moisture
+    }
+
+    pub fn max_height(&self) -> f32 {
+        self.height.bounds().1
+    }
+}
+
+/// Unnormalized weight of every biome at a climate point, a gaussian around each biome.
+///
+/// Weights are scaled by a common factor so the closest biome never underflows,
+/// the factor cancels out once the weights are normalized, gradients included.
+pub(super) fn biome_influences(
+    climate: &Climate,
+    climate_sample: &[noise::Value2Dt1; 2],
+    biomes: &[BiomeLayers],
+) -> Vec<noise::Value2Dt1> {
+    let distances = biomes
+        .iter()
+        .map(|biome| biome.climate_distance(climate_sample))
+        .collect_vec();
+    let min_distance = distances
+        .iter()
+        .map(|distance| distance.value)
+        .fold(f32::INFINITY, f32::min);
+    let sharpness = 1.0 / (climate.blend * climate.blend);
+
+    distances
+        .into_iter()
+        .map(|distance| {
+            let value = (-(distance.value - min_distance) * sharpness).exp();
+            noise::Value2Dt1::new(value, -distance.d1.0 * value * sharpness)
+        })
+        .collect()
+}
+
+/// Mirrors `terrain/default.terrain.ron`.
+pub(super) fn default_biomes() -> Vec<BiomeLayers> {
+    let biome = |biome, temperature, moisture, layers| BiomeLayers {
+        biome,
+        temperature,
+        moisture,
+        height: NoiseGraph::from_layers(layers),
+    };
+
+    // every biome has its own seed offsets, blended biomes would otherwise share their noise
+    vec![
+        biome(
+            Biome::Plains,
+            0.5,
+            0.45,
+            vec![
+                NoiseLayer::new(2000.0, 300.0, 0.2, 0),
+                NoiseLayer::new(1000.0, 150.0, 0.9, 1),
+                NoiseLayer::new(500.0, 50.0, 0.25, 2),
+            ],
+        ),
+        biome(
+            Biome::Mountains,
+            0.35,
+            0.3,
+            vec![
+                NoiseLayer::new(2000.0, 1000.0, 0.2, 3),
+                NoiseLayer::new(1000.0, 1000.0, 0.9, 4),
+                NoiseLayer::new(500.0, 500.0, 0.25, 5),
+            ],
+        ),
+        biome(
+            Biome::Desert,
+            0.7,
+            0.25,
+            vec![
+                NoiseLayer::new(2000.0, 200.0, 0.2, 6),
+                NoiseLayer::new(600.0, 120.0, 0.1, 7),
+                NoiseLayer::new(150.0, 10.0, 0.0, 8).with_noise(NoiseKind::Value),
+            ],
+        ),
+        biome(
+            Biome::Forest,
+            0.55,
+            0.7,
+            vec![
+                NoiseLayer::new(2000.0, 500.0, 0.2, 9),
+                NoiseLayer::new(1000.0, 300.0, 0.9, 10),
+                NoiseLayer::new(300.0, 60.0, 0.25, 11),
+            ],
+        ),
+        biome(
+            Biome::Tundra,
+            0.25,
+            0.55,
+            vec![
+                NoiseLayer::new(2000.0, 400.0, 0.3, 12),
+                NoiseLayer::new(1000.0, 200.0, 0.9, 13),
+                NoiseLayer::new(500.0, 100.0, 0.25, 14),
+            ],
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_positions() -> impl Iterator<Item = Vec2> {
+        (-10..10)
+            .cartesian_product(-10..10)
+            .map(|(x, y)| vec2(x as f32 * 731.3 + 0.5, y as f32 * 677.9 + 0.25))
+    }
+
+    #[test]
+    fn biome_weights_sum_to_one() {
+        let terrain_sampler = TerrainSampler::default();
+        let base_seed = BaseSeed(3);
+
+        let mut dominant = HashSet::new();
+        for position in sample_positions() {
+            let weights = terrain_sampler.sample(position, &base_seed).biomes;
+            let total = weights.iter().map(|(_, weight)| weight).sum::<f32>();
+            assert!((total - 1.0).abs() < 1e-4, "{position}: {weights:?}");
+            dominant.insert(weights.dominant());
+        }
+
+        assert!(
+            dominant.len() > 1,
+            "only {dominant:?} across the sampled area"
+        );
+    }
+
+    #[test]
+    fn default_biomes_have_distinct_seed_offsets() {
+        let seed_offsets = default_biomes()
+            .iter()
+            .flat_map(|biome| biome.height.as_layers().unwrap().to_vec())
+            .map(|layer| layer.seed_offset)
+            .collect_vec();
+
+        assert!(seed_offsets.iter().all_unique(), "{seed_offsets:?}");
+    }
+
+    #[test]
+    fn blended_height_has_continuous_derivatives() {
+        // without erosion layer derivatives are exact, so any error comes from the blending
+        let biomes = Biome::ALL
+            .into_iter()
+            .enumerate()
+            .map(|(index, biome)| BiomeLayers {
+                biome,
+                temperature: index as f32 / 4.0,
+                moisture: 1.0 - index as f32 / 4.0,
+                height: NoiseGraph::from_layers(vec![NoiseLayer::new(
+                    300.0,
+                    100.0 * (index + 1) as f32,
+                    0.0,
+                    0,
+                )]),
+            })
+            .collect();
+        let climate = Climate {
+            size: 1000.0,
+            blend: 0.15,
+        };
+        let terrain_sampler = TerrainSampler::new(climate, biomes, HasherKind::Simple);
+        let base_seed = BaseSeed(3);
+
+        for position in sample_positions().map(|position| position / 10.0) {
+            let sample = terrain_sampler.sample(position, &base_seed).height;
+            let dfdx = noise::estimate_dt1(position.x, |x| {
+                terrain_sampler
+                    .sample(vec2(x, position.y), &base_seed)
+                    .height
+                    .value
+            });
+            let dfdy = noise::estimate_dt1(position.y, |y| {
+                terrain_sampler
+                    .sample(vec2(position.x, y), &base_seed)
+                    .height
+                    .value
+            });
+
+            let expected = vec2(dfdx, dfdy);
+            assert!(
+                (sample.d1.0 - expected).length() < 0.05 * (1.0 + expected.length()),
+                "{position}: {:?} != {expected:?}",
+                sample.d1.0
+            );
+        }
+    }
+}
@@ -0,0 +1,269 @@
+//! Terrain colliders, streamed as heightfield tiles around everything that collides with terrain.
+//! Tiles have a fixed resolution so physics does not depend on the render LOD of the chunks.
+
+use avian3d::prelude::{Collider, CollisionLayers, RigidBody};
+use bevy::ecs::system::SystemParam;
+
+use super::*;
+use crate::game::physics::CollisionLayersExt;
+
+/// Size of a collider tile side.
+const COLLIDER_TILE_SIZE: f32 = 32.0;
+/// Height samples per collider tile side.
+const COLLIDER_TILE_RESOLUTION: usize = 33;
+/// Tiles closer than this to a collidable get a collider.
+const COLLIDER_ENTER_RADIUS: f32 = 8.0;
+/// Tiles keep their collider until every collidable is further than this,
+/// so moving along a tile edge does not rebuild colliders every frame.
+const COLLIDER_EXIT_RADIUS: f32 = 16.0;
+
+pub(super) fn plugin(app: &mut App) {
+    app.register_type::<(TerrainColliderTiles, TerrainColliderTile)>()
+        .init_resource::<TerrainColliderTiles>()
+        .add_systems(OnExit(GameState::Playing), clear_collider_tiles)
+        .add_systems(
+            Update,
+            stream_collider_tiles
+                .in_set(GameSet::UpdateDataLayer)
+                .run_if(in_state(GameState::Playing)),
+        );
+}
+
+/// Collider tiles by their position on the tile grid.
+#[derive(Resource, Debug, Default, Reflect)]
+#[reflect(Resource)]
+pub struct TerrainColliderTiles {
+    tiles: HashMap<IVec2, Entity>,
+}
+
+#[derive(Component, Clone, Copy, Debug, Reflect)]
+#[reflect(Component)]
+struct TerrainColliderTile {
+    position: IVec2,
+}
+
+#[derive(SystemParam)]
+struct TerrainCollidablesQuery<'w, 's> {
+    collidables_query: Query<
+        'w,
+        's,
+        (&'static Transform, &'static CollisionLayers),
+        (With<Collider>, Without<TerrainColliderTile>),
+    >,
+}
+
+impl<'w, 's> TerrainCollidablesQuery<'w, 's> {
+    fn iter(&self) -> impl Iterator<Item = Vec2> + '_ {
+        let terrain_collision_layers = CollisionLayers::get_terrain_colliders();
+        self.collidables_query
+            .iter()
+            .filter_map(move |(transform, layers)| {
+                layers
+                    .interacts_with(terrain_collision_layers)
+                    .then_some(transform.translation.xz())
+            })
+    }
+}
+
+fn tile_min(position: IVec2) -> Vec2 {
+    position.as_vec2() * COLLIDER_TILE_SIZE
+}
+
+/// Tiles on the grid within `radius` of `point`.
+fn tiles_in_radius(point: Vec2, radius: f32) -> impl Iterator<Item = IVec2> {
+    let min = ((point - radius) / COLLIDER_TILE_SIZE).floor().as_ivec2();
+    let max = ((point + radius) / COLLIDER_TILE_SIZE).floor().as_ivec2();
+
+    (min.x..=max.x)
+        .cartesian_product(min.y..=max.y)
+        .map(|(x, y)| ivec2(x, y))
+        .filter(move |position| {
+            let min = tile_min(*position);
+            let closest = point.clamp(min, min + COLLIDER_TILE_SIZE);
+            closest.distance_squared(point) <= radius * radius
+        })
+}
+
+/// Tiles that should be spawned and despawned for collidables at `collidables`.
+fn plan_collider_tiles(
+    current: &HashMap<IVec2, Entity>,
+    collidables: &[Vec2],
+) -> (HashSet<IVec2>, HashSet<IVec2>) {
+    let required = collidables
+        .iter()
+        .flat_map(|point| tiles_in_radius(*point, COLLIDER_ENTER_RADIUS))
+        .filter(|position| !current.contains_key(position))
+        .collect::<HashSet<_>>();
+    let kept = collidables
+        .iter()
+        .flat_map(|point| tiles_in_radius(*point, COLLIDER_EXIT_RADIUS))
+        .collect::<HashSet<_>>();
+    let released = current
+        .keys()
+        .filter(|position| !kept.contains(*position))
+        .copied()
+        .collect();
+
+    (required, released)
+}
+
+/// Tiles of `current` overlapping one of `rects`, their heights are outdated.
+fn tiles_overlapping<'a>(
+    current: &'a HashMap<IVec2, Entity>,
+    rects: &'a [Rect],
+) -> impl Iterator<Item = (IVec2, Entity)> + 'a {
+    current
+        .iter()
+        .filter(|(position, _)| {
+            let min = tile_min(**position);
+            let tile_rect = Rect::from_corners(min, min + COLLIDER_TILE_SIZE);
+            rects
+                .iter()
+                .any(|rect| !tile_rect.intersect(*rect).is_empty())
+        })
+        .map(|(position, entity)| (*position, *entity))
+}
+
+fn create_tile_collider(
+    position: IVec2,
+    terrain_sampler: &TerrainSampler,
+    eroded_terrain: &ErodedTerrain,
+    base_seed: &BaseSeed,
+) -> Collider {
+    let min = tile_min(position);
+    let step = COLLIDER_TILE_SIZE / (COLLIDER_TILE_RESOLUTION - 1) as f32;
+
+    let mut grid = vec![noise::Value2Dt1::default(); COLLIDER_TILE_RESOLUTION.pow(2)];
+    terrain_sampler.sample_grid(
+        min,
+        step,
+        COLLIDER_TILE_RESOLUTION as u32,
+        base_seed,
+        &mut grid,
+    );
+
+    // heightfields are indexed by x first, the grid by z first
+    let heights = (0..COLLIDER_TILE_RESOLUTION)
+        .map(|x| {
+            (0..COLLIDER_TILE_RESOLUTION)
+                .map(|z| {
+                    let pos = min + vec2(x as f32, z as f32) * step;
+                    let height = grid[z * COLLIDER_TILE_RESOLUTION + x];
+                    eroded_terrain.apply(pos, height).value
+                })
+                .collect_vec()
+        })
+        .collect_vec();
+
+    Collider::heightfield(heights, vec3(COLLIDER_TILE_SIZE, 1.0, COLLIDER_TILE_SIZE))
+}
+
+fn stream_collider_tiles(
+    mut commands: Commands,
+    mut collider_tiles: ResMut<TerrainColliderTiles>,
+    collidables_query: TerrainCollidablesQuery,
+    base_seed: Res<BaseSeed>,
+    terrain_sampler: Res<TerrainSampler>,
+    eroded_terrain: Res<ErodedTerrain>,
+    mut eroded_events: EventReader<ErodedTerrainChanged>,
+) {
+    // tiles sampled from a different terrain are rebuilt from scratch
+    if base_seed.is_changed() || terrain_sampler.is_changed() {
+        for (_, entity) in collider_tiles.tiles.drain() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    // erosion only changes the tiles under the eroded ones
+    let eroded_rects = eroded_events.read().map(|event| event.rect).collect_vec();
+    for (position, entity) in tiles_overlapping(&collider_tiles.tiles, &eroded_rects) {
+        commands.entity(entity).insert(create_tile_collider(
+            position,
+            &terrain_sampler,
+            &eroded_terrain,
+            &base_seed,
+        ));
+    }
+
+    let collidables = collidables_query.iter().collect_vec();
+    let (required, released) = plan_collider_tiles(&collider_tiles.tiles, &collidables);
+
+    for position in released {
+        if let Some(entity) = collider_tiles.tiles.remove(&position) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    for position in required {
+        let center = tile_min(position) + COLLIDER_TILE_SIZE / 2.0;
+        let entity = commands
+            .spawn((
+                StateScoped(GameState::Playing),
+                Name::from(format!("Collider tile ({}, {})", position.x, position.y)),
+                TerrainColliderTile { position },
+                RigidBody::Static,
+                create_tile_collider(position, &terrain_sampler, &eroded_terrain, &base_seed),
+                CollisionLayers::get_terrain_colliders(),
+                TransformBundle::from_transform(Transform::from_xyz(center.x, 0.0, center.y)),
+            ))
+            .id();
+
+        collider_tiles.tiles.insert(position, entity);
+    }
+}
+
+fn clear_collider_tiles(mut collider_tiles: ResMut<TerrainColliderTiles>) {
+    collider_tiles.tiles.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_in_radius_touch_the_circle() {
+        let tiles = tiles_in_radius(vec2(1.0, 1.0), 4.0).collect::<HashSet<_>>();
+        assert_eq!(
+            tiles,
+            HashSet::from_iter([ivec2(-1, -1), ivec2(-1, 0), ivec2(0, -1), ivec2(0, 0)])
+        );
+
+        let tiles = tiles_in_radius(vec2(16.0, 16.0), 4.0).collect_vec();
+        assert_eq!(tiles, vec![ivec2(0, 0)]);
+    }
+
+    #[test]
+    fn eroded_rects_outdate_overlapping_tiles() {
+        let entity = Entity::from_raw(0);
+        let current = HashMap::from_iter(
+            [ivec2(0, 0), ivec2(1, 0), ivec2(2, 0), ivec2(5, 5)].map(|position| (position, entity)),
+        );
+        let rects = [Rect::new(10.0, 10.0, 2.0 * COLLIDER_TILE_SIZE, 20.0)];
+
+        let outdated = tiles_overlapping(&current, &rects)
+            .map(|(position, _)| position)
+            .collect::<HashSet<_>>();
+        // tiles only sharing an edge with the rect are left alone
+        assert_eq!(outdated, HashSet::from_iter([ivec2(0, 0), ivec2(1, 0)]));
+        assert_eq!(tiles_overlapping(&current, &[]).count(), 0);
+    }
+
+    #[test]
+    fn collider_tiles_have_hysteresis() {
+        let entity = Entity::from_raw(0);
+        let edge = COLLIDER_TILE_SIZE;
+
+        // crossing into the next tile keeps the old one until the exit radius
+        let current = HashMap::from_iter([(ivec2(0, 0), entity)]);
+        let (required, released) =
+            plan_collider_tiles(&current, &[vec2(edge + COLLIDER_ENTER_RADIUS, 16.0)]);
+        assert_eq!(required, HashSet::from_iter([ivec2(1, 0)]));
+        assert!(released.is_empty());
+
+        let current = HashMap::from_iter([(ivec2(0, 0), entity), (ivec2(1, 0), entity)]);
+        let (required, released) =
+            plan_collider_tiles(&current, &[vec2(edge + COLLIDER_EXIT_RADIUS + 0.1, 16.0)]);
+        assert!(required.is_empty());
+        assert_eq!(released, HashSet::from_iter([ivec2(0, 0)]));
+    }
+}
@@ -0,0 +1,636 @@
+//! Volumetric density of the terrain in cave regions, caves and overhangs carved from 3D noise.
+//! The density is positive inside the ground and negative in the air, the surface is where it crosses 0.
+//! Outside of the regions the density is the height of the terrain above a point, so meshes built
+//! from it meet the heightfield chunks at the region border.
+
+use noise::{perlin_noise_3d, HasherKind, ValueDt3};
+
+use super::*;
+
+/// Side of a cave volume cell.
+pub const CAVE_CELL_SIZE: f32 = 32.0;
+/// Density samples per cave cell side.
+const CAVE_CELL_RESOLUTION: u32 = 16;
+/// Distance over which the carving fades out at the region border.
+const CAVE_EDGE_FADE: f32 = 32.0;
+
+pub(super) fn plugin(app: &mut App) {
+    app.register_type::<(CaveSettings, CaveCells)>()
+        .init_resource::<CaveSettings>()
+        .init_resource::<CaveCells>()
+        .add_systems(OnExit(GameState::Playing), clear_cave_cells)
+        .add_systems(
+            Update,
+            (
+                spawn_cave_cells,
+                apply_cave_meshes,
+                start_cave_mesh_tasks,
+                hide_replaced_chunks,
+            )
+                .chain()
+                .in_set(GameSet::UpdateDataLayer)
+                .run_if(in_state(GameState::Playing)),
+        );
+}
+
+/// Spawned cave cells by their position on the cell grid, and the column of the player they are
+/// gathered around.
+#[derive(Resource, Debug, Default, Reflect)]
+#[reflect(Resource)]
+pub struct CaveCells {
+    cells: HashMap<IVec3, Entity>,
+    center: Option<IVec2>,
+}
+
+#[derive(Component, Clone, Copy, Debug, Reflect)]
+#[reflect(Component)]
+struct CaveCell {
+    position: IVec3,
+}
+
+/// Cave cell has no mesh yet or the terrain under it changed.
+#[derive(Component, Clone, Copy, Debug, Default, Reflect)]
+#[reflect(Component)]
+struct CaveMeshOutdated;
+
+/// Mesh being generated in the background, dropping it cancels the generation.
+#[derive(Component)]
+struct CaveMeshTask(Task<Mesh>);
+
+/// Heightfield chunk hidden because meshed cave cells cover all of it.
+#[derive(Component, Clone, Copy, Debug, Default, Reflect)]
+#[reflect(Component)]
+struct ReplacedByCaves;
+
+/// Where and how the terrain is carved, the density field only depends on these and the terrain.
+#[derive(Resource, Debug, Clone, Reflect)]
+#[reflect(Resource)]
+pub struct CaveSettings {
+    /// Regions caves and overhangs are carved in.
+    pub regions: Vec<Rect>,
+    /// Horizontal distance to the player within which cells of the regions become cave volumes.
+    pub radius: f32,
+    /// Size of the tunnel noise, bigger gives longer and straighter tunnels.
+    pub tunnel_size: f32,
+    /// Radius of the tunnels in noise units, in [0, 1].
+    pub tunnel_width: f32,
+    /// Depth below the surface past which no tunnels are carved.
+    pub max_depth: f32,
+    /// Size of the noise pushing the surface sideways.
+    pub overhang_size: f32,
+    /// How far the surface is pushed, overhangs appear once it is about half the overhang size.
+    pub overhang_amplitude: f32,
+    /// Hasher of the cave noise, caves are only sampled on the cpu so it needs no WGSL mirror.
+    pub hasher: HasherKind,
+}
+
+impl Default for CaveSettings {
+    fn default() -> Self {
+        Self {
+            regions: vec![Rect::from_center_half_size(Vec2::ZERO, Vec2::splat(512.0))],
+            radius: 128.0,
+            tunnel_size: 96.0,
+            tunnel_width: 0.12,
+            max_depth: 64.0,
+            overhang_size: 48.0,
+            overhang_amplitude: 32.0,
+            hasher: HasherKind::Simple,
+        }
+    }
+}
+
+impl CaveSettings {
+    /// Density at `pos`, `height` is the terrain height sampled at `pos.xz()`.
+    pub fn density(&self, pos: Vec3, height: noise::Value2Dt1, base_seed: &BaseSeed) -> ValueDt3 {
+        // height of the terrain above the point
+        let surface = ValueDt3::from_xz(height) - ValueDt3::new(pos.y, Vec3::Y);
+
+        let weight = self.region_weight(pos.xz());
+        if weight.value == 0.0 {
+            return surface;
+        }
+
+        let hasher = |index| {
+            let seed = base_seed.hasher(SeedDomain::Caves, index).seed();
+            self.hasher.hasher(seed)
+        };
+        let overhang = perlin_noise_3d(pos, 1.0 / self.overhang_size, &hasher(2));
+        let ground = surface + overhang * self.overhang_amplitude;
+
+        // spaghetti tunnels, where two noises are close to 0 at once
+        let [a, b] =
+            [0, 1].map(|index| perlin_noise_3d(pos, 1.0 / self.tunnel_size, &hasher(index)));
+        let mut tunnel = (a * a + b * b + -self.tunnel_width.powi(2))
+            * (self.tunnel_size / (2.0 * self.tunnel_width));
+        // closes the tunnels below the max depth
+        if surface.value > self.max_depth {
+            tunnel = tunnel + surface + -self.max_depth;
+        }
+
+        let carved = ground.min(tunnel);
+        surface + (carved - surface) * weight
+    }
+
+    /// How much of the carving is kept at `pos`, 1 inside the regions fading to 0 at their border.
+    fn region_weight(&self, pos: Vec2) -> ValueDt3 {
+        self.regions
+            .iter()
+            .map(|region| {
+                let to_min = pos - region.min;
+                let to_max = region.max - pos;
+                let (distance, direction) = [
+                    (to_min.x, Vec3::X),
+                    (to_max.x, Vec3::NEG_X),
+                    (to_min.y, Vec3::Z),
+                    (to_max.y, Vec3::NEG_Z),
+                ]
+                .into_iter()
+                .min_by(|(a, _), (b, _)| a.total_cmp(b))
+                .unwrap();
+
+                // smoothstep over the fade distance
+                let t = (distance / CAVE_EDGE_FADE).clamp(0.0, 1.0);
+                let dt = if t > 0.0 && t < 1.0 {
+                    6.0 * t * (1.0 - t) / CAVE_EDGE_FADE
+                } else {
+                    0.0
+                };
+                ValueDt3::new(t * t * (3.0 - 2.0 * t), direction * dt)
+            })
+            .fold(ValueDt3::default(), ValueDt3::max)
+    }
+
+    /// Cells around `center` the carving can reach, `height` is the terrain height of a column.
+    pub fn cells_around(&self, center: Vec3, height: impl Fn(Vec2) -> f32) -> HashSet<IVec3> {
+        let reach = (self.radius / CAVE_CELL_SIZE).ceil() as i32;
+        let center_column = (center.xz() / CAVE_CELL_SIZE).floor().as_ivec2();
+
+        (-reach..=reach)
+            .cartesian_product(-reach..=reach)
+            .map(|(x, z)| center_column + ivec2(x, z))
+            .filter(|column| {
+                let rect = Rect::from_corners(
+                    column.as_vec2() * CAVE_CELL_SIZE,
+                    (*column + 1).as_vec2() * CAVE_CELL_SIZE,
+                );
+                let closest = center.xz().clamp(rect.min, rect.max);
+                closest.distance(center.xz()) <= self.radius
+                    && self
+                        .regions
+                        .iter()
+                        .any(|region| !region.intersect(rect).is_empty())
+            })
+            .flat_map(|column| {
+                let rect_min = column.as_vec2() * CAVE_CELL_SIZE;
+                let heights = [
+                    vec2(0.0, 0.0),
+                    vec2(1.0, 0.0),
+                    vec2(0.0, 1.0),
+                    vec2(1.0, 1.0),
+                ]
+                .map(|corner| height(rect_min + (corner * CAVE_CELL_SIZE)));
+                let (min_height, max_height) = heights
+                    .into_iter()
+                    .minmax()
+                    .into_option()
+                    .unwrap_or_default();
+
+                // one cell of margin for the height between the sampled corners
+                let bottom = ((min_height - self.max_depth) / CAVE_CELL_SIZE).floor() as i32 - 1;
+                let top =
+                    ((max_height + self.overhang_amplitude) / CAVE_CELL_SIZE).floor() as i32 + 1;
+                (bottom..=top).map(move |y| ivec3(column.x, y, column.y))
+            })
+            .collect()
+    }
+}
+
+fn cell_min(position: IVec3) -> Vec3 {
+    position.as_vec3() * CAVE_CELL_SIZE
+}
+
+fn create_cave_mesh(
+    position: IVec3,
+    cave_settings: &CaveSettings,
+    terrain_sampler: &TerrainSampler,
+    eroded_terrain: &ErodedTerrain,
+    base_seed: &BaseSeed,
+) -> Mesh {
+    let step = CAVE_CELL_SIZE / CAVE_CELL_RESOLUTION as f32;
+    // the mesher also samples the layer before the min faces and the one past the max faces
+    let columns = CAVE_CELL_RESOLUTION + 2;
+    let columns_min = cell_min(position).xz() - step;
+    let mut heights = vec![noise::Value2Dt1::default(); columns.pow(2) as usize];
+    terrain_sampler.sample_grid(columns_min, step, columns, base_seed, &mut heights);
+
+    let mut mesh = utils::primitives::create_isosurface(
+        position * CAVE_CELL_RESOLUTION as i32,
+        CAVE_CELL_RESOLUTION,
+        step,
+        |pos| {
+            let column = ((pos.xz() - columns_min) / step).round().as_uvec2();
+            let height = heights[(column.y * columns + column.x) as usize];
+            let height = eroded_terrain.apply(pos.xz(), height);
+            let density = cave_settings.density(pos, height, base_seed);
+            (density.value, density.derivative)
+        },
+    );
+
+    // caves don't morph, their coarser height is their own
+    let lod_morph = mesh
+        .attribute(Mesh::ATTRIBUTE_POSITION)
+        .and_then(|positions| positions.as_float3())
+        .map(|positions| {
+            positions
+                .iter()
+                .map(|position| [position[1], CAVE_CELL_SIZE])
+                .collect_vec()
+        })
+        .unwrap_or_default();
+    mesh.insert_attribute(material::ATTRIBUTE_LOD_MORPH, lod_morph);
+
+    mesh.asset_usage = RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD;
+    mesh
+}
+
+/// Spawns the cells around the player when it enters another column, and despawns the cells
+/// left behind. Cells under eroded tiles are meshed again.
+fn spawn_cave_cells(
+    mut commands: Commands,
+    mut cave_cells: ResMut<CaveCells>,
+    player: Query<&Transform, With<camera::CameraFocus>>,
+    cave_settings: Res<CaveSettings>,
+    base_seed: Res<BaseSeed>,
+    terrain_sampler: Res<TerrainSampler>,
+    eroded_terrain: Res<ErodedTerrain>,
+    mut eroded_events: EventReader<ErodedTerrainChanged>,
+) {
+    // cells carved from a different terrain are rebuilt from scratch
+    if cave_settings.is_changed() || base_seed.is_changed() || terrain_sampler.is_changed() {
+        for (_, entity) in cave_cells.cells.drain() {
+            commands.entity(entity).despawn_recursive();
+        }
+        cave_cells.center = None;
+    }
+
+    for rect in eroded_events.read().map(|event| event.rect) {
+        for (position, entity) in cave_cells.cells.iter() {
+            let min = cell_min(*position).xz();
+            let cell_rect = Rect::from_corners(min, min + CAVE_CELL_SIZE);
+            if !cell_rect.intersect(rect).is_empty() {
+                commands
+                    .entity(*entity)
+                    .remove::<CaveMeshTask>()
+                    .insert(CaveMeshOutdated);
+            }
+        }
+    }
+
+    let Ok(player) = player.get_single() else {
+        return;
+    };
+    let center = player.translation;
+    let center_column = (center.xz() / CAVE_CELL_SIZE).floor().as_ivec2();
+    if cave_cells.center == Some(center_column) {
+        return;
+    }
+    cave_cells.center = Some(center_column);
+
+    let desired = cave_settings.cells_around(center, |pos| {
+        let height = terrain_sampler.sample(pos, &base_seed).height;
+        eroded_terrain.apply(pos, height).value
+    });
+
+    cave_cells.cells.retain(|position, entity| {
+        let keep = desired.contains(position);
+        if !keep {
+            commands.entity(*entity).despawn_recursive();
+        }
+        keep
+    });
+
+    for position in desired {
+        if cave_cells.cells.contains_key(&position) {
+            continue;
+        }
+
+        let min = cell_min(position);
+        let entity = commands
+            .spawn((
+                StateScoped(GameState::Playing),
+                Name::from(format!(
+                    "Cave cell ({}, {}, {})",
+                    position.x, position.y, position.z
+                )),
+                CaveCell { position },
+                CaveMeshOutdated,
+                SpatialBundle::from_transform(Transform::from_translation(min)),
+            ))
+            .id();
+        cave_cells.cells.insert(position, entity);
+    }
+}
+
+fn start_cave_mesh_tasks(
+    mut commands: Commands,
+    cells: Query<(Entity, &CaveCell), With<CaveMeshOutdated>>,
+    cave_settings: Res<CaveSettings>,
+    base_seed: Res<BaseSeed>,
+    terrain_sampler: Res<TerrainSampler>,
+    eroded_terrain: Res<ErodedTerrain>,
+    budget: Res<ChunkMeshBudget>,
+) {
+    let task_pool = AsyncComputeTaskPool::get();
+
+    for (entity, cell) in cells.iter().take(budget.tasks_per_frame) {
+        let position = cell.position;
+        let base_seed = *base_seed;
+        let cave_settings = CaveSettings::clone(&cave_settings);
+        let terrain_sampler = TerrainSampler::clone(&terrain_sampler);
+        let eroded_terrain = ErodedTerrain::clone(&eroded_terrain);
+        let task = task_pool.spawn(async move {
+            create_cave_mesh(
+                position,
+                &cave_settings,
+                &terrain_sampler,
+                &eroded_terrain,
+                &base_seed,
+            )
+        });
+
+        // replacing a running task cancels it
+        commands
+            .entity(entity)
+            .remove::<CaveMeshOutdated>()
+            .insert(CaveMeshTask(task));
+    }
+}
+
+fn apply_cave_meshes(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut CaveMeshTask)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    material: Res<material::TerrainMaterialHandle>,
+) {
+    for (entity, mut task) in tasks.iter_mut() {
+        let Some(mesh) = block_on(poll_once(&mut task.0)) else {
+            continue;
+        };
+
+        commands
+            .entity(entity)
+            .remove::<CaveMeshTask>()
+            .insert((meshes.add(mesh), material.0.clone()));
+    }
+}
+
+/// Columns of cells whose cells all have a mesh, the heightfield under them can be hidden.
+fn meshed_columns(
+    cells: &HashMap<IVec3, Entity>,
+    is_meshed: impl Fn(Entity) -> bool,
+) -> HashSet<IVec2> {
+    let mut columns = HashMap::<IVec2, bool>::new();
+    for (position, entity) in cells.iter() {
+        *columns.entry(position.xz()).or_insert(true) &= is_meshed(*entity);
+    }
+
+    columns
+        .into_iter()
+        .filter_map(|(column, meshed)| meshed.then_some(column))
+        .collect()
+}
+
+/// Whether the columns cover all of the chunk, a partly covered chunk would leave a hole.
+fn columns_cover_chunk(columns: &HashSet<IVec2>, key: ChunkKey) -> bool {
+    let min = (key.min() / CAVE_CELL_SIZE).floor().as_ivec2();
+    let max = (key.max() / CAVE_CELL_SIZE).ceil().as_ivec2();
+
+    (min.x..max.x)
+        .cartesian_product(min.y..max.y)
+        .all(|(x, z)| columns.contains(&ivec2(x, z)))
+}
+
+/// Hides the heightfield chunks the meshed cave cells replace, and shows them again once the
+/// cells are gone.
+fn hide_replaced_chunks(
+    mut commands: Commands,
+    cave_cells: Res<CaveCells>,
+    meshed_cells: Query<(), (With<Handle<Mesh>>, Without<CaveMeshTask>)>,
+    mut chunks: Query<(Entity, &Chunk, &mut Visibility, Has<ReplacedByCaves>)>,
+) {
+    let columns = meshed_columns(&cave_cells.cells, |entity| meshed_cells.contains(entity));
+
+    for (entity, chunk, mut visibility, replaced) in chunks.iter_mut() {
+        let replace = columns_cover_chunk(&columns, chunk.key);
+        if replace == replaced {
+            continue;
+        }
+
+        if replace {
+            *visibility = Visibility::Hidden;
+            commands.entity(entity).insert(ReplacedByCaves);
+        } else {
+            *visibility = Visibility::Inherited;
+            commands.entity(entity).remove::<ReplacedByCaves>();
+        }
+    }
+}
+
+fn clear_cave_cells(mut cave_cells: ResMut<CaveCells>) {
+    cave_cells.cells.clear();
+    cave_cells.center = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flat_height(height: f32) -> noise::Value2Dt1 {
+        noise::Value2Dt1::new(height, Vec2::ZERO)
+    }
+
+    fn sloped_height(pos: Vec2) -> noise::Value2Dt1 {
+        noise::Value2Dt1::new(0.3 * pos.x - 0.1 * pos.y, vec2(0.3, -0.1))
+    }
+
+    #[test]
+    fn density_is_the_heightfield_outside_regions() {
+        let settings = CaveSettings {
+            regions: vec![Rect::new(1000.0, 1000.0, 2000.0, 2000.0)],
+            ..default()
+        };
+        let base_seed = BaseSeed(3);
+
+        for (x, y, z) in itertools::iproduct!(-5..5, -5..5, -5..5) {
+            let pos = vec3(x as f32, y as f32, z as f32) * 17.3;
+            let density = settings.density(pos, sloped_height(pos.xz()), &base_seed);
+
+            assert_eq!(density.value, sloped_height(pos.xz()).value - pos.y);
+            assert_eq!(density.derivative, vec3(0.3, -1.0, -0.1));
+        }
+    }
+
+    #[test]
+    fn density_derivatives_match_finite_differences() {
+        let settings = CaveSettings::default();
+        let base_seed = BaseSeed(3);
+        let density = |pos: Vec3| settings.density(pos, sloped_height(pos.xz()), &base_seed);
+
+        // the edge fade is included, the region border is at 512
+        for (x, y, z) in itertools::iproduct!(-4..4, -4..4, -4..4) {
+            let pos = vec3(
+                x as f32 * 131.7 + 0.3,
+                y as f32 * 13.1,
+                z as f32 * 127.9 + 0.7,
+            );
+            let result = density(pos);
+
+            for axis in 0..3 {
+                let along_axis = |v: f32| {
+                    let mut p = pos;
+                    p[axis] = v;
+                    density(p)
+                };
+                // the carving picks between the ground and the tunnels, skip the seams between them
+                let seam = [-0.01, 0.01].into_iter().any(|step| {
+                    (along_axis(pos[axis] + step).derivative - result.derivative).length() > 0.05
+                });
+                if seam {
+                    continue;
+                }
+
+                let numerical_derivative = noise::estimate_dt1(pos[axis], |v| along_axis(v).value);
+                assert!(
+                    (result.derivative[axis] - numerical_derivative).abs()
+                        < 0.02 * (1.0 + numerical_derivative.abs()),
+                    "{pos} {axis}: {}!={numerical_derivative}",
+                    result.derivative[axis],
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn caves_are_carved_above_the_max_depth() {
+        // without overhangs all the air below the surface is in tunnels
+        let settings = CaveSettings {
+            overhang_amplitude: 0.0,
+            ..default()
+        };
+        let base_seed = BaseSeed(3);
+        let positions =
+            itertools::iproduct!(-20..20, -20..20).map(|(x, z)| ivec2(x, z).as_vec2() * 7.3);
+
+        let air_below_surface = positions
+            .clone()
+            .flat_map(|pos| (1..8).map(move |depth| pos.extend(-(depth as f32) * 7.0).xzy()))
+            .filter(|pos| settings.density(*pos, flat_height(0.0), &base_seed).value < 0.0)
+            .count();
+        assert!(air_below_surface > 0, "no caves were carved");
+
+        for pos in positions {
+            let deep = pos
+                .extend(-settings.max_depth - 2.0 * settings.tunnel_size)
+                .xzy();
+            let density = settings.density(deep, flat_height(0.0), &base_seed);
+            assert!(density.value > 0.0, "{deep}: {}", density.value);
+        }
+    }
+
+    #[test]
+    fn cave_cells_surround_the_surface_near_the_player() {
+        let settings = CaveSettings::default();
+        let cells = settings.cells_around(vec3(10.0, 50.0, 10.0), |_| 40.0);
+
+        assert!(cells.contains(&ivec3(0, 1, 0)));
+        assert!(cells.contains(&ivec3(0, -1, 0)));
+        assert!(!cells.contains(&ivec3(0, 10, 0)));
+        assert!(!cells.contains(&ivec3(0, -10, 0)));
+        assert!(cells
+            .iter()
+            .all(
+                |cell| (cell.xz().as_vec2() * CAVE_CELL_SIZE).distance(Vec2::ZERO)
+                    <= settings.radius + 2.0 * CAVE_CELL_SIZE
+            ));
+
+        let far = CaveSettings {
+            regions: vec![Rect::new(1000.0, 1000.0, 2000.0, 2000.0)],
+            ..default()
+        };
+        assert!(far.cells_around(Vec3::ZERO, |_| 0.0).is_empty());
+    }
+
+    #[test]
+    fn cave_mesh_outside_regions_follows_the_heightfield() {
+        let settings = CaveSettings {
+            regions: vec![Rect::new(1000.0, 1000.0, 2000.0, 2000.0)],
+            ..default()
+        };
+        let terrain_sampler = TerrainSampler::default();
+        let eroded_terrain = ErodedTerrain::default();
+        let base_seed = BaseSeed(3);
+        let height = |pos: Vec2| terrain_sampler.sample(pos, &base_seed).height.value;
+
+        let center = vec2(16.0, 16.0);
+        let position = (center.extend(height(center)).xzy() / CAVE_CELL_SIZE)
+            .floor()
+            .as_ivec3();
+        let mesh = create_cave_mesh(
+            position,
+            &settings,
+            &terrain_sampler,
+            &eroded_terrain,
+            &base_seed,
+        );
+
+        let positions = mesh
+            .attribute(Mesh::ATTRIBUTE_POSITION)
+            .and_then(|positions| positions.as_float3())
+            .unwrap();
+        assert!(!positions.is_empty(), "the surface crosses the cell");
+        for local in positions {
+            let pos = cell_min(position) + Vec3::from_array(*local);
+            assert!(
+                (pos.y - height(pos.xz())).abs() < 0.5,
+                "{pos}: {}",
+                height(pos.xz())
+            );
+        }
+        assert_eq!(
+            mesh.attribute(material::ATTRIBUTE_LOD_MORPH)
+                .map(|lod_morph| lod_morph.len()),
+            Some(positions.len())
+        );
+    }
+
+    #[test]
+    fn chunks_are_replaced_once_their_columns_are_meshed() {
+        let meshed = Entity::from_raw(0);
+        let meshing = Entity::from_raw(1);
+        let cells = HashMap::from_iter([
+            (ivec3(0, 0, 0), meshed),
+            (ivec3(0, 1, 0), meshed),
+            (ivec3(1, 0, 0), meshed),
+            (ivec3(1, 1, 0), meshing),
+            (ivec3(0, 0, 1), meshed),
+            (ivec3(1, 0, 1), meshed),
+        ]);
+        let columns = meshed_columns(&cells, |entity| entity == meshed);
+        assert_eq!(
+            columns,
+            HashSet::from_iter([ivec2(0, 0), ivec2(0, 1), ivec2(1, 1)])
+        );
+
+        // chunks at LOD 0 and 1 fit in a column, bigger ones need all of theirs
+        assert!(columns_cover_chunk(&columns, ChunkKey::new(0, ivec2(1, 1))));
+        assert!(columns_cover_chunk(&columns, ChunkKey::new(1, ivec2(0, 1))));
+        assert!(!columns_cover_chunk(
+            &columns,
+            ChunkKey::new(1, ivec2(1, 0))
+        ));
+        assert!(!columns_cover_chunk(
+            &columns,
+            ChunkKey::new(2, ivec2(0, 0))
+        ));
+    }
+}
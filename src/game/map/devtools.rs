@@ -2,6 +2,9 @@ use super::*;
 use crate::dev_tools::editor_ui::EditorDock;
 use bevy::window::PrimaryWindow;
 use bevy_inspector_egui::bevy_inspector;
+use debug_flags::*;
+use material::TerrainMaterialSettings;
+use preset::TerrainPreset;
 
 enum Flags {
     DebugUnrenderedChunks,
@@ -20,36 +23,74 @@ impl DebugFlagsExt for Flags {
     }
 }
 
-// #[derive(Component)]
-// struct ShaderMap;
-
 const DEBUG_IMAGE_SIZE: usize = 100;
 
-#[derive(Component)]
-pub struct DebugChunk;
-
-#[derive(Resource)]
+/// Layers of every biome as edited in the dock, hidden layers are kept out of the [`TerrainSampler`].
+#[derive(Resource, Default)]
 pub struct EditorTerrainState {
     manual_has_changed: bool,
-    weights: Vec<(bool, TerrainWeight)>,
+    biomes: Vec<EditorBiome>,
+    selected_biome: usize,
     seed: u32,
-    chunk_subdivisions: u32,
-    // These are in chunks
-    chunk_spawn_radius: u8,
-    chunk_visibility_radius: u8,
+}
+
+struct EditorBiome {
+    biome: Biome,
+    /// `None` for height graphs that are not a single layer stack, those are edited in the preset.
+    layers: Option<Vec<(bool, NoiseLayer)>>,
+}
+
+impl EditorTerrainState {
+    fn from_sampler(terrain_sampler: &TerrainSampler, base_seed: &BaseSeed) -> Self {
+        Self {
+            manual_has_changed: false,
+            biomes: terrain_sampler
+                .biomes
+                .iter()
+                .map(|biome| EditorBiome {
+                    biome: biome.biome,
+                    layers: biome
+                        .height
+                        .as_layers()
+                        .map(|layers| layers.iter().map(|w| (true, *w)).collect()),
+                })
+                .collect(),
+            selected_biome: 0,
+            seed: base_seed.0,
+        }
+    }
+
+    fn visible_layers(&self, biome: usize) -> impl Iterator<Item = &NoiseLayer> {
+        self.biomes
+            .get(biome)
+            .and_then(|biome| biome.layers.as_ref())
+            .into_iter()
+            .flatten()
+            .filter(|(is_visible, _)| *is_visible)
+            .map(|(_, w)| w)
+    }
 }
 
 #[derive(Resource)]
 pub struct EditorTerrainImages {
     weights_preview: Vec<TerrainPreview>,
+    terrain_preview: Option<egui::TextureHandle>,
+    /// Material layers by slope, left to right, and height, bottom to top.
+    material_preview: Option<egui::TextureHandle>,
     preview_scale: f32,
+    /// Ground point below the camera the previews were drawn around.
+    preview_center: Vec2,
     manual_has_changed: bool,
 }
+
 impl Default for EditorTerrainImages {
     fn default() -> Self {
         Self {
             weights_preview: Vec::new(),
+            terrain_preview: None,
+            material_preview: None,
             preview_scale: 25.0,
+            preview_center: Vec2::ZERO,
             manual_has_changed: false,
         }
     }
@@ -64,7 +105,7 @@ struct TerrainPreview {
     erosion_combined: egui::TextureHandle,
 }
 
-pub fn plugin(app: &mut App) {
+pub(crate) fn plugin(app: &mut App) {
     register_debug_flags(
         app,
         vec![Flags::DisplayWorldGrid, Flags::DebugUnrenderedChunks],
@@ -72,81 +113,109 @@ pub fn plugin(app: &mut App) {
 
     app.init_resource::<EditorTerrainImages>()
         .init_resource::<EditorTerrainState>()
-        .add_systems(OnEnter(GameState::Playing), update_terrain_previews)
+        .add_systems(OnEnter(GameState::Playing), reset_editor_state)
         .add_systems(
             Update,
             (
-                display_world_grid
-                    .in_set(ChunkSystemSet::Render)
-                    .run_if(debug_flag_enabled(&Flags::DisplayWorldGrid)),
-                debug_invisible_chunks
-                    .in_set(ChunkSystemSet::Render)
-                    .run_if(debug_flag_enabled(&Flags::DebugUnrenderedChunks)),
-                sync_terrain_with_ui
-                    .in_set(ChunkSystemSet::ChunkReload)
-                    .run_if(editor_terrain_changed),
-                update_terrain_previews
-                    .in_set(ChunkSystemSet::ChunkReload)
-                    .run_if(
-                        editor_terrain_changed
-                            .or_else(editor_terrain_previews_changed)
-                            .or_else(render_center_changed),
-                    ),
-                unflag_manual_terrain_change
-                    .in_set(ChunkSystemSet::Render)
-                    .run_if(editor_terrain_changed),
-                unflag_manual_terrain_previews_change
-                    .in_set(ChunkSystemSet::Render)
-                    .run_if(editor_terrain_previews_changed),
-                (
-                    log_terrain_changed.in_set(ChunkSystemSet::ChunkReload),
-                    clear_chunk_registry.in_set(ChunkSystemSet::ChunkReload),
-                    despawn_entities::<Chunk>.in_set(ChunkSystemSet::ChunkReload),
-                    spawn_chunks.in_set(ChunkSystemSet::Mutate),
-                    render_chunks.in_set(ChunkSystemSet::Render),
-                )
-                    .chain()
-                    .run_if(resource_changed::<TerrainSampler>),
-            ),
+                reset_editor_state.run_if(on_event::<AssetEvent<TerrainPreset>>()),
+                sync_terrain_with_ui.run_if(editor_terrain_changed),
+                update_terrain_previews.run_if(
+                    editor_terrain_changed
+                        .or_else(editor_terrain_previews_changed)
+                        .or_else(camera_left_previews),
+                ),
+                unflag_manual_terrain_change.run_if(editor_terrain_changed),
+                unflag_manual_terrain_previews_change.run_if(editor_terrain_previews_changed),
+            )
+                .chain()
+                .after(preset::apply_terrain_preset)
+                .in_set(GameSet::UpdateApply)
+                .run_if(in_state(GameState::Playing)),
+        )
+        .add_systems(
+            Update,
+            update_material_preview
+                .run_if(resource_changed::<TerrainMaterialSettings>)
+                .in_set(GameSet::UpdateApply)
+                .run_if(in_state(GameState::Playing)),
+        )
+        .add_systems(
+            Update,
+            (
+                display_world_grid.run_if(debug_flag_enabled(&Flags::DisplayWorldGrid)),
+                debug_invisible_chunks.run_if(debug_flag_enabled(&Flags::DebugUnrenderedChunks)),
+            )
+                .in_set(GameSet::PostUpdate)
+                .run_if(in_state(GameState::Playing)),
         );
 }
 
+/// Reads the layers back from the sampler, a reloaded preset replaces the edited ones.
+fn reset_editor_state(
+    mut editor_state: ResMut<EditorTerrainState>,
+    mut terrain_images: ResMut<EditorTerrainImages>,
+    terrain_sampler: Res<TerrainSampler>,
+    base_seed: Res<BaseSeed>,
+) {
+    let selected_biome = editor_state.selected_biome;
+    *editor_state = EditorTerrainState::from_sampler(&terrain_sampler, &base_seed);
+    editor_state.selected_biome = selected_biome.min(editor_state.biomes.len().saturating_sub(1));
+    terrain_images.manual_has_changed = true;
+}
+
 fn sync_terrain_with_ui(
-    mut terrain: ResMut<TerrainSampler>,
+    mut terrain_sampler: ResMut<TerrainSampler>,
+    mut base_seed: ResMut<BaseSeed>,
     editor_state: Res<EditorTerrainState>,
 ) {
-    terrain.noise_seed = editor_state.seed;
-    terrain.noise_weights = editor_state
-        .weights
-        .iter()
-        .filter(|(is_visible, _)| *is_visible)
-        .map(|(_, w)| *w)
-        .collect_vec();
+    // the seed and the sampler regenerate the live chunks when they change
+    base_seed.set_if_neq(BaseSeed(editor_state.seed));
+
+    for (index, biome) in terrain_sampler.biomes.iter_mut().enumerate() {
+        let is_layer_stack = editor_state
+            .biomes
+            .get(index)
+            .is_some_and(|biome| biome.layers.is_some());
+        if is_layer_stack {
+            biome.height =
+                NoiseGraph::from_layers(editor_state.visible_layers(index).copied().collect());
+        }
+    }
 }
 
-fn log_terrain_changed() {
-    info!("Terrain changed");
+fn camera_ground_point(camera: &Query<&Transform, With<GameplayCamera>>) -> Option<Vec2> {
+    camera
+        .get_single()
+        .ok()
+        .map(|transform| transform.translation.xz())
 }
 
-pub fn change_map_seed(mut terrain: ResMut<TerrainSampler>) {
-    terrain.noise_seed = terrain.noise_seed.wrapping_add(1);
-    info!("Map seed: {}", terrain.noise_seed);
-}
+fn camera_left_previews(
+    camera: Query<&Transform, With<GameplayCamera>>,
+    terrain_images: Res<EditorTerrainImages>,
+) -> bool {
+    let Some(ground_point) = camera_ground_point(&camera) else {
+        return false;
+    };
 
-fn clear_chunk_registry(mut chunk_manager: ResMut<ChunkManager>) {
-    info!("Clearing chunk registry");
-    chunk_manager.chunks.clear();
+    // a quarter of the preview is enough to notice it is stale
+    let threshold = terrain_images.preview_scale * DEBUG_IMAGE_SIZE as f32 / 4.0;
+    ground_point.distance(terrain_images.preview_center) > threshold
 }
 
 fn debug_invisible_chunks(
-    chunks: Query<&ChunkPosition3, (With<Chunk>, Without<Handle<Mesh>>)>,
+    chunks: Query<&Chunk, Without<Handle<Mesh>>>,
+    terrain_sampler: Res<TerrainSampler>,
+    base_seed: Res<BaseSeed>,
     mut gizmos: Gizmos,
 ) {
-    for pos in chunks.iter() {
+    for chunk in chunks.iter() {
+        let center = chunk.key.center();
+        let height = terrain_sampler.sample(center, &base_seed).height.value;
         gizmos.rect(
-            pos.to_world_pos(),
+            vec3(center.x, height, center.y),
             Quat::from_euler(EulerRot::XYZ, 90.0_f32.to_radians(), 0.0, 0.0),
-            Vec2::splat(CHUNK_SIZE),
+            Vec2::splat(chunk.size()),
             tailwind::RED_500,
         );
     }
@@ -166,26 +235,12 @@ fn unflag_manual_terrain_previews_change(mut previews_state: ResMut<EditorTerrai
     previews_state.manual_has_changed = false;
 }
 
-impl Default for EditorTerrainState {
-    fn default() -> Self {
-        let terrain = TerrainSampler::default();
-
-        Self {
-            manual_has_changed: false,
-            weights: terrain.noise_weights.iter().map(|w| (true, *w)).collect(),
-            seed: terrain.noise_seed,
-            chunk_subdivisions: terrain.chunk_subdivisions,
-            chunk_spawn_radius: terrain.chunk_spawn_radius,
-            chunk_visibility_radius: terrain.chunk_visibility_radius,
-        }
-    }
-}
-
 fn update_terrain_previews(
     ui_context: Query<&bevy_inspector_egui::bevy_egui::EguiContext, With<PrimaryWindow>>,
+    camera: Query<&Transform, With<GameplayCamera>>,
     mut terrain_images: ResMut<EditorTerrainImages>,
     editor_terrain: Res<EditorTerrainState>,
-    render_center: Res<MapRenderCenter>,
+    terrain_sampler: Res<TerrainSampler>,
 ) {
     let Ok(egui_context) = ui_context.get_single() else {
         return;
@@ -193,25 +248,37 @@ fn update_terrain_previews(
     let mut egui_context = egui_context.clone();
     let ctx = egui_context.get_mut();
     let preview_scale = terrain_images.preview_scale;
-    let offset = render_center.to_world_pos().xz();
+    let offset = camera_ground_point(&camera).unwrap_or(terrain_images.preview_center);
+    terrain_images.preview_center = offset;
+
+    let base_seed = BaseSeed(editor_terrain.seed);
+    let seeds = base_seed.terrain_seeds(terrain_sampler.hasher);
+    let max_height = terrain_sampler.max_height().max(1.0);
+    terrain_images.terrain_preview = Some(create_preview_texture(
+        ctx,
+        [DEBUG_IMAGE_SIZE, DEBUG_IMAGE_SIZE],
+        preview_scale,
+        |pos| {
+            terrain_sampler
+                .sample(pos + offset, &base_seed)
+                .height
+                .value
+                / max_height
+        },
+    ));
 
-    let highest_amplitude = editor_terrain
-        .weights
+    let selected_layers = editor_terrain
+        .visible_layers(editor_terrain.selected_biome)
+        .collect_vec();
+    let highest_amplitude = selected_layers
         .iter()
-        .map(|(_, w)| w)
-        .max_by(|a, b| a.amplitude.partial_cmp(&b.amplitude).unwrap())
         .map(|w| w.amplitude)
+        .max_by(|a, b| a.total_cmp(b))
         .unwrap_or(1.0);
 
-    let hasher = noise::SimpleHasher::from_seed(editor_terrain.seed);
-    let mut passed_weights = Vec::with_capacity(editor_terrain.weights.len());
-
-    let mut weights_preview = Vec::with_capacity(editor_terrain.weights.len());
-    for (is_visible, weight) in &editor_terrain.weights {
-        if !*is_visible {
-            continue;
-        }
-        passed_weights.push(weight);
+    let mut weights_preview = Vec::with_capacity(selected_layers.len());
+    for (index, weight) in selected_layers.iter().enumerate() {
+        let passed_weights = &selected_layers[..=index];
 
         let individual = create_preview_texture(
             ctx,
@@ -219,7 +286,7 @@ fn update_terrain_previews(
             preview_scale,
             |pos| {
                 weight
-                    .sample_erosion_base(&hasher, pos + offset, 0.0)
+                    .sample_eroded(pos + offset, 0.0, &seeds(weight.seed_offset))
                     .0
                     .value
                     / highest_amplitude
@@ -231,13 +298,9 @@ fn update_terrain_previews(
             [DEBUG_IMAGE_SIZE, DEBUG_IMAGE_SIZE],
             preview_scale,
             |pos| {
-                TerrainWeight::sample_many(
-                    hasher.clone(),
-                    pos + offset,
-                    passed_weights.iter().map(|w| w as &_),
-                )
-                .0
-                .value
+                NoiseLayer::sample_many(pos + offset, passed_weights.iter().copied(), &seeds)
+                    .0
+                    .value
                     / highest_amplitude
             },
         );
@@ -246,21 +309,18 @@ fn update_terrain_previews(
             ctx,
             [DEBUG_IMAGE_SIZE, DEBUG_IMAGE_SIZE],
             preview_scale,
-            |pos| weight.sample_erosion_base(&hasher, pos + offset, 0.0).1,
+            |pos| {
+                weight
+                    .sample_eroded(pos + offset, 0.0, &seeds(weight.seed_offset))
+                    .1
+            },
         );
 
         let erosion_combined = create_preview_texture(
             ctx,
             [DEBUG_IMAGE_SIZE, DEBUG_IMAGE_SIZE],
             preview_scale,
-            |pos| {
-                TerrainWeight::sample_many(
-                    hasher.clone(),
-                    pos + offset,
-                    passed_weights.iter().map(|w| w as &_),
-                )
-                .1
-            },
+            |pos| NoiseLayer::sample_many(pos + offset, passed_weights.iter().copied(), &seeds).1,
         );
 
         weights_preview.push(TerrainPreview {
@@ -275,33 +335,70 @@ fn update_terrain_previews(
     terrain_images.weights_preview = weights_preview;
 }
 
+fn update_material_preview(
+    ui_context: Query<&bevy_inspector_egui::bevy_egui::EguiContext, With<PrimaryWindow>>,
+    mut terrain_images: ResMut<EditorTerrainImages>,
+    settings: Res<TerrainMaterialSettings>,
+) {
+    let Ok(egui_context) = ui_context.get_single() else {
+        return;
+    };
+    let mut egui_context = egui_context.clone();
+    let ctx = egui_context.get_mut();
+
+    let size = [DEBUG_IMAGE_SIZE, DEBUG_IMAGE_SIZE];
+    let mut image = egui::ColorImage::new(size, egui::Color32::BLACK);
+    for y in 0..size[1] {
+        for x in 0..size[0] {
+            let slope = x as f32 / (size[0] - 1) as f32;
+            let height = 1.0 - y as f32 / (size[1] - 1) as f32;
+            let [r, g, b, _] = Srgba::from(settings.layer_color(slope, height)).to_u8_array();
+            image.pixels[x + y * size[0]] = egui::Color32::from_rgb(r, g, b);
+        }
+    }
+
+    terrain_images.material_preview =
+        Some(ctx.load_texture("material", image, egui::TextureOptions::default()));
+}
+
+/// What a layer row asked for, applied once the layers are no longer borrowed.
+enum LayerAction {
+    MoveUp(usize),
+    MoveDown(usize),
+    Remove(usize),
+}
+
 pub struct EditorTerrain {}
 
 impl EditorDock for EditorTerrain {
     fn ui(&mut self, world: &mut World, ui: &mut bevy_inspector_egui::egui::Ui) {
         world.resource_scope::<EditorTerrainImages, _>(|world, mut terrain_images| {
             world.resource_scope::<EditorTerrainState, _>(|world, mut state| {
-                // let is_focused = ui.memory(|m| m.clone()).focused().is_some();
-
                 ui.collapsing("Preview", |ui| {
-                    terrain_images.manual_has_changed = ui
+                    terrain_images.manual_has_changed |= ui
                         .add(
                             egui::Slider::new(&mut terrain_images.preview_scale, 0.01..=1000.0)
                                 .text("preview scale"),
                         )
                         .changed();
 
+                    if let Some(terrain_preview) = terrain_images.terrain_preview.as_ref() {
+                        ui.label("Terrain");
+                        ui.image(terrain_preview);
+                        ui.separator();
+                    }
+
                     for weight_preview in terrain_images.weights_preview.iter() {
                         ui.horizontal(|ui| {
                             ui.vertical(|ui| {
                                 ui.label("Individual");
                                 ui.image(&weight_preview.individual);
-                                // ui.image(&weight_preview.erosion_individual);
+                                ui.image(&weight_preview.erosion_individual);
                             });
                             ui.vertical(|ui| {
                                 ui.label("Combined");
                                 ui.image(&weight_preview.combined);
-                                // ui.image(&weight_preview.erosion_combined);
+                                ui.image(&weight_preview.erosion_combined);
                             });
                         });
                         ui.separator();
@@ -311,64 +408,115 @@ impl EditorDock for EditorTerrain {
 
                 ui.collapsing("Settings", |ui| {
                     ui.horizontal(|ui| {
-                        ui.vertical(|ui| {
-                            ui.label("Seed:");
-                            if bevy_inspector::ui_for_value(&mut state.seed, ui, world) {
-                                let mut terrain =
-                                    world.get_resource_mut::<TerrainSampler>().unwrap();
-                                terrain.noise_seed = state.seed;
-                                state.manual_has_changed = true;
-                            }
-                        });
-                        ui.vertical(|ui| {
-                            ui.label("Mesh Subdivisions:");
-                            if bevy_inspector::ui_for_value(
-                                &mut state.chunk_subdivisions,
-                                ui,
-                                world,
-                            ) {
-                                let mut terrain =
-                                    world.get_resource_mut::<TerrainSampler>().unwrap();
-                                terrain.chunk_subdivisions = state.chunk_subdivisions;
-                                state.manual_has_changed = true;
-                            }
-                        });
-                        ui.vertical(|ui| {
-                            ui.label("Visibility radius:");
-                            if bevy_inspector::ui_for_value(
-                                &mut state.chunk_visibility_radius,
-                                ui,
-                                world,
-                            ) {
-                                let mut terrain =
-                                    world.get_resource_mut::<TerrainSampler>().unwrap();
-                                terrain.chunk_visibility_radius = state.chunk_visibility_radius;
-                                state.manual_has_changed = true;
-                            }
-                        });
-                        ui.vertical(|ui| {
-                            ui.label("Spawn radius:");
-                            if bevy_inspector::ui_for_value(
-                                &mut state.chunk_spawn_radius,
-                                ui,
-                                world,
-                            ) {
-                                let mut terrain =
-                                    world.get_resource_mut::<TerrainSampler>().unwrap();
-                                terrain.chunk_spawn_radius = state.chunk_spawn_radius;
-                                state.manual_has_changed = true;
-                            }
-                        });
+                        ui.label("Seed:");
+                        if bevy_inspector::ui_for_value(&mut state.seed, ui, world) {
+                            state.manual_has_changed = true;
+                        }
                     });
                     ui.horizontal(|ui| {
-                        ui.label("Weights:");
-                        if bevy_inspector::ui_for_value(&mut state.weights, ui, world) {
-                            let mut terrain = world.get_resource_mut::<TerrainSampler>().unwrap();
-                            terrain.noise_weights = state.weights.iter().map(|(_, w)| *w).collect();
-                            state.manual_has_changed = true;
+                        ui.label("Chunk surface area:");
+                        let mut desired_surface_area = world.resource_mut::<DesiredSurfaceArea>();
+                        ui.add(egui::Slider::new(&mut desired_surface_area.0, 0.05..=10.0));
+                    });
+                });
+
+                ui.collapsing("Material", |ui| {
+                    if let Some(material_preview) = terrain_images.material_preview.as_ref() {
+                        ui.label("Slope →, height ↑");
+                        ui.image(material_preview);
+                        ui.separator();
+                    }
+
+                    world.resource_scope::<TerrainMaterialSettings, _>(|world, mut settings| {
+                        // only a real edit re-creates the material
+                        if bevy_inspector::ui_for_value(
+                            settings.bypass_change_detection(),
+                            ui,
+                            world,
+                        ) {
+                            settings.set_changed();
                         }
                     });
                 });
+
+                ui.collapsing("Layers", |ui| {
+                    let selected_biome = state.selected_biome;
+                    let selected_text = state
+                        .biomes
+                        .get(selected_biome)
+                        .map(|biome| format!("{:?}", biome.biome))
+                        .unwrap_or_default();
+                    egui::ComboBox::from_label("Biome")
+                        .selected_text(selected_text)
+                        .show_ui(ui, |ui| {
+                            for index in 0..state.biomes.len() {
+                                let label = format!("{:?}", state.biomes[index].biome);
+                                ui.selectable_value(&mut state.selected_biome, index, label);
+                            }
+                        });
+                    if state.selected_biome != selected_biome {
+                        terrain_images.manual_has_changed = true;
+                    }
+
+                    let state = &mut *state;
+                    // new layers get a seed offset no biome uses yet
+                    let next_seed_offset = state
+                        .biomes
+                        .iter()
+                        .flat_map(|biome| biome.layers.iter().flatten())
+                        .map(|(_, w)| w.seed_offset + 1)
+                        .max()
+                        .unwrap_or(0);
+                    let Some(biome) = state.biomes.get_mut(state.selected_biome) else {
+                        return;
+                    };
+                    let Some(layers) = biome.layers.as_mut() else {
+                        ui.label(
+                            "The height of this biome is a graph, edit it in the terrain preset.",
+                        );
+                        return;
+                    };
+
+                    let mut action = None;
+                    let layer_count = layers.len();
+                    for (index, (is_visible, weight)) in layers.iter_mut().enumerate() {
+                        ui.push_id(index, |ui| {
+                            ui.horizontal(|ui| {
+                                state.manual_has_changed |= ui.checkbox(is_visible, "").changed();
+                                if ui.add_enabled(index > 0, egui::Button::new("⏶")).clicked() {
+                                    action = Some(LayerAction::MoveUp(index));
+                                }
+                                if ui
+                                    .add_enabled(index + 1 < layer_count, egui::Button::new("⏷"))
+                                    .clicked()
+                                {
+                                    action = Some(LayerAction::MoveDown(index));
+                                }
+                                if ui.button("✕").clicked() {
+                                    action = Some(LayerAction::Remove(index));
+                                }
+                            });
+                            state.manual_has_changed |=
+                                bevy_inspector::ui_for_value(weight, ui, world);
+                        });
+                        ui.separator();
+                    }
+
+                    state.manual_has_changed |= action.is_some();
+                    match action {
+                        Some(LayerAction::MoveUp(index)) => layers.swap(index - 1, index),
+                        Some(LayerAction::MoveDown(index)) => layers.swap(index, index + 1),
+                        Some(LayerAction::Remove(index)) => {
+                            layers.remove(index);
+                        }
+                        None => {}
+                    }
+
+                    if ui.button("Add layer").clicked() {
+                        layers.push((true, NoiseLayer::new(500.0, 100.0, 0.2, next_seed_offset)));
+                        state.manual_has_changed = true;
+                    }
+                });
             });
         });
     }
@@ -408,64 +556,35 @@ fn create_preview_texture(
     )
 }
 
-fn display_world_grid(mut gizmos: Gizmos, query: Query<&Transform, With<ChunkOrigin>>) {
-    for transform in query.iter() {
-        let origin = transform.translation.floor();
-        let range = 5;
-        let color = tailwind::GRAY_400;
-        for x in -range..range {
-            gizmos.line(
-                origin + vec3(x as f32, 0.0, -range as f32),
-                origin + vec3(x as f32, 0.0, range as f32),
-                color,
-            );
-        }
-        for z in -range..range {
-            gizmos.line(
-                origin + vec3(-range as f32, 0.0, z as f32),
-                origin + vec3(range as f32, 0.0, z as f32),
-                color,
-            );
-        }
+fn display_world_grid(
+    mut gizmos: Gizmos,
+    camera: Query<&Transform, With<GameplayCamera>>,
+    terrain_sampler: Res<TerrainSampler>,
+    base_seed: Res<BaseSeed>,
+) {
+    let Some(ground_point) = camera_ground_point(&camera) else {
+        return;
+    };
+
+    let height = terrain_sampler
+        .sample(ground_point, &base_seed)
+        .height
+        .value;
+    let origin = vec3(ground_point.x, height, ground_point.y).floor();
+    let range = 5;
+    let color = tailwind::GRAY_400;
+    for x in -range..range {
+        gizmos.line(
+            origin + vec3(x as f32, 0.0, -range as f32),
+            origin + vec3(x as f32, 0.0, range as f32),
+            color,
+        );
+    }
+    for z in -range..range {
+        gizmos.line(
+            origin + vec3(-range as f32, 0.0, z as f32),
+            origin + vec3(range as f32, 0.0, z as f32),
+            color,
+        );
     }
 }
-
-// #[derive(Asset, AsBindGroup, TypePath, Debug, Clone)]
-// struct MapMaterialExtension {}
-//
-// impl MaterialExtension for MapMaterialExtension {
-//     fn vertex_shader() -> ShaderRef {
-//         "shaders/map.wgsl".into()
-//     }
-// }
-
-// fn spawn_map_shader(mut commands: Commands, asset_server: Res<AssetServer>) {
-//     let mesh = asset_server.add(utils::primitives::create_subdivided_plane(
-//         CHUNK_SUBDIVISIONS * CHUNK_SPAWN_RADIUS as u32 * 2,
-//         CHUNK_SIZE * CHUNK_SPAWN_RADIUS as f32 * 2.0,
-//         |_x, _y| (0.0, Vec3::ZERO.into()),
-//     ));
-//
-//     let base = StandardMaterial {
-//         base_color: tailwind::GREEN_700.into(),
-//         ..default()
-//     };
-//     let base = ExtendedMaterial {
-//         base,
-//         extension: MapMaterialExtension {},
-//     };
-//     let asset = ExtendedMaterial {
-//         base,
-//         extension: DebugNormalsMaterialExtension {},
-//     };
-//     let material = asset_server.add(asset);
-//
-//     commands.spawn((
-//         ShaderMap,
-//         MaterialMeshBundle {
-//             mesh,
-//             material,
-//             ..default()
-//         },
-//     ));
-// }
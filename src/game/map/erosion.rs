@@ -0,0 +1,541 @@
+//! Particle based hydraulic erosion and thermal weathering of the terrain in hero regions.
+//! Regions are split in tiles, each tile is simulated once in the background and cached as
+//! a height offset from the analytic terrain, faded out at the tile border so tiles stay seamless.
+
+use std::sync::Arc;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::*;
+
+/// Size of an erosion tile side.
+const EROSION_TILE_SIZE: f32 = 512.0;
+/// Height samples per erosion tile side.
+const EROSION_TILE_RESOLUTION: usize = 129;
+/// Cells over which the erosion fades out at the tile border.
+const EROSION_EDGE_FADE: f32 = 16.0;
+
+pub(super) fn plugin(app: &mut App) {
+    app.register_type::<ErosionSettings>()
+        .init_resource::<ErosionSettings>()
+        .init_resource::<ErodedTerrain>()
+        .init_resource::<ErosionTasks>()
+        .add_event::<ErodedTerrainChanged>()
+        .add_systems(OnExit(GameState::Playing), clear_erosion_tasks)
+        .add_systems(
+            Update,
+            (
+                reset_eroded_terrain.run_if(
+                    resource_changed::<ErosionSettings>
+                        .or_else(resource_changed::<TerrainSampler>)
+                        .or_else(resource_changed::<BaseSeed>),
+                ),
+                apply_erosion_tasks,
+                start_erosion_tasks,
+            )
+                .chain()
+                .in_set(GameSet::UpdateDataLayer)
+                .before(apply_chunk_meshes)
+                .run_if(in_state(GameState::Playing)),
+        );
+}
+
+/// Where and how the terrain is eroded, changing it erodes every tile again.
+#[derive(Resource, Debug, Clone, Reflect)]
+#[reflect(Resource)]
+pub struct ErosionSettings {
+    /// Hero regions, every tile touching one of them is eroded.
+    pub regions: Vec<Rect>,
+    /// Droplets simulated per tile.
+    pub droplets: u32,
+    /// Steps a droplet lives for.
+    pub droplet_lifetime: u32,
+    /// How much a droplet keeps its direction instead of following the slope, in [0, 1].
+    pub inertia: f32,
+    /// Sediment a droplet carries per unit of speed, water and slope.
+    pub sediment_capacity: f32,
+    /// Capacity of droplets on flat ground, keeps them eroding at the bottom of slopes.
+    pub min_sediment_capacity: f32,
+    /// Fraction of the missing sediment a droplet picks up each step.
+    pub erode_speed: f32,
+    /// Fraction of the extra sediment a droplet drops each step.
+    pub deposit_speed: f32,
+    /// Fraction of the water that evaporates each step.
+    pub evaporate_speed: f32,
+    pub gravity: f32,
+    /// Steepest slope thermal weathering leaves in place, in radians.
+    pub talus_angle: f32,
+    pub thermal_iterations: u32,
+    /// Fraction of the material above the talus angle moved each iteration.
+    pub thermal_rate: f32,
+}
+
+impl Default for ErosionSettings {
+    fn default() -> Self {
+        Self {
+            regions: vec![Rect::from_center_half_size(Vec2::ZERO, Vec2::splat(512.0))],
+            droplets: 20_000,
+            droplet_lifetime: 30,
+            inertia: 0.05,
+            sediment_capacity: 4.0,
+            min_sediment_capacity: 0.01,
+            erode_speed: 0.3,
+            deposit_speed: 0.3,
+            evaporate_speed: 0.01,
+            gravity: 4.0,
+            talus_angle: 40.0_f32.to_radians(),
+            thermal_iterations: 20,
+            thermal_rate: 0.5,
+        }
+    }
+}
+
+impl ErosionSettings {
+    /// Tiles touching any of the hero regions.
+    fn tiles(&self) -> impl Iterator<Item = IVec2> + '_ {
+        self.regions
+            .iter()
+            .flat_map(|region| {
+                let min = (region.min / EROSION_TILE_SIZE).floor().as_ivec2();
+                let max = (region.max / EROSION_TILE_SIZE).ceil().as_ivec2() - 1;
+                (min.x..=max.x)
+                    .cartesian_product(min.y..=max.y)
+                    .map(|(x, y)| ivec2(x, y))
+            })
+            .unique()
+    }
+}
+
+/// Square grid of heights, positions are in cells.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heightfield {
+    resolution: usize,
+    heights: Vec<f32>,
+}
+
+impl Heightfield {
+    pub fn from_fn(resolution: usize, mut f: impl FnMut(usize, usize) -> f32) -> Self {
+        let heights = (0..resolution)
+            .cartesian_product(0..resolution)
+            .map(|(y, x)| f(x, y))
+            .collect();
+
+        Self {
+            resolution,
+            heights,
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> f32 {
+        self.heights[y * self.resolution + x]
+    }
+
+    fn contains(&self, pos: Vec2) -> bool {
+        let max = (self.resolution - 1) as f32;
+        pos.x >= 0.0 && pos.y >= 0.0 && pos.x < max && pos.y < max
+    }
+
+    /// Cell containing `pos` and the position inside of it, clamped to the grid.
+    fn cell(&self, pos: Vec2) -> (usize, usize, Vec2) {
+        let max = (self.resolution - 2) as f32;
+        let cell = pos.floor().clamp(Vec2::ZERO, Vec2::splat(max));
+        let offset = (pos - cell).clamp(Vec2::ZERO, Vec2::ONE);
+        (cell.x as usize, cell.y as usize, offset)
+    }
+
+    /// Height and gradient at `pos`, bilinearly interpolated.
+    pub fn sample(&self, pos: Vec2) -> noise::Value2Dt1 {
+        let (x, y, offset) = self.cell(pos);
+        let a = self.get(x, y);
+        let b = self.get(x + 1, y);
+        let c = self.get(x, y + 1);
+        let d = self.get(x + 1, y + 1);
+
+        let value = a.lerp(b, offset.x).lerp(c.lerp(d, offset.x), offset.y);
+        let gradient = vec2(
+            (b - a) * (1.0 - offset.y) + (d - c) * offset.y,
+            (c - a) * (1.0 - offset.x) + (d - b) * offset.x,
+        );
+        noise::Value2Dt1::new(value, gradient)
+    }
+
+    /// Adds `amount` spread over the corners of the cell containing `pos`.
+    fn add(&mut self, pos: Vec2, amount: f32) {
+        let (x, y, offset) = self.cell(pos);
+        let resolution = self.resolution;
+        let corners = [
+            (x, y, (1.0 - offset.x) * (1.0 - offset.y)),
+            (x + 1, y, offset.x * (1.0 - offset.y)),
+            (x, y + 1, (1.0 - offset.x) * offset.y),
+            (x + 1, y + 1, offset.x * offset.y),
+        ];
+        for (x, y, weight) in corners {
+            self.heights[y * resolution + x] += amount * weight;
+        }
+    }
+}
+
+/// Moves droplets down the heightfield, each one picks up sediment on steep slopes
+/// and deposits it where it slows down.
+pub fn erode_hydraulic(heightfield: &mut Heightfield, settings: &ErosionSettings, seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let max = (heightfield.resolution - 1) as f32;
+
+    for _ in 0..settings.droplets {
+        let mut pos = vec2(rng.gen_range(0.0..max), rng.gen_range(0.0..max));
+        let mut direction = Vec2::ZERO;
+        let mut speed = 1.0_f32;
+        let mut water = 1.0;
+        let mut sediment = 0.0;
+
+        for _ in 0..settings.droplet_lifetime {
+            let sample = heightfield.sample(pos);
+            let Some(next_direction) = (direction * settings.inertia
+                - sample.d1.0 * (1.0 - settings.inertia))
+                .try_normalize()
+            else {
+                break;
+            };
+            direction = next_direction;
+
+            let next = pos + direction;
+            if !heightfield.contains(next) {
+                break;
+            }
+
+            let delta = heightfield.sample(next).value - sample.value;
+            let capacity = (-delta * speed * water * settings.sediment_capacity)
+                .max(settings.min_sediment_capacity);
+
+            if sediment > capacity || delta > 0.0 {
+                // fill the pit it climbs out of, or drop what it can not carry
+                let deposit = if delta > 0.0 {
+                    delta.min(sediment)
+                } else {
+                    (sediment - capacity) * settings.deposit_speed
+                };
+                sediment -= deposit;
+                heightfield.add(pos, deposit);
+            } else {
+                // never dig deeper than the next position, that would make a pit
+                let erode = ((capacity - sediment) * settings.erode_speed).min(-delta);
+                sediment += erode;
+                heightfield.add(pos, -erode);
+            }
+
+            speed = (speed * speed - delta * settings.gravity).max(0.0).sqrt();
+            water *= 1.0 - settings.evaporate_speed;
+            pos = next;
+        }
+    }
+}
+
+/// Moves material down every slope steeper than `talus`, in height per cell.
+pub fn erode_thermal(heightfield: &mut Heightfield, settings: &ErosionSettings, talus: f32) {
+    let resolution = heightfield.resolution;
+    let neighbours = [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y];
+    let mut moved = vec![0.0; heightfield.heights.len()];
+
+    for _ in 0..settings.thermal_iterations {
+        moved.fill(0.0);
+        for (x, y) in (0..resolution).cartesian_product(0..resolution) {
+            let height = heightfield.get(x, y);
+            for neighbour in neighbours {
+                let n = ivec2(x as i32, y as i32) + neighbour;
+                if n.min_element() < 0 || n.max_element() >= resolution as i32 {
+                    continue;
+                }
+
+                let (nx, ny) = (n.x as usize, n.y as usize);
+                let difference = height - heightfield.get(nx, ny);
+                if difference > talus {
+                    // split between the neighbours so a peak never moves past its base
+                    let amount = settings.thermal_rate * (difference - talus) / 8.0;
+                    moved[y * resolution + x] -= amount;
+                    moved[ny * resolution + nx] += amount;
+                }
+            }
+        }
+
+        for (height, moved) in heightfield.heights.iter_mut().zip(moved.iter()) {
+            *height += moved;
+        }
+    }
+}
+
+/// Erosion of the tile at `position`, as an offset from the analytic terrain in world units.
+fn erode_tile(
+    position: IVec2,
+    terrain_sampler: &TerrainSampler,
+    base_seed: &BaseSeed,
+    settings: &ErosionSettings,
+) -> Heightfield {
+    let min = position.as_vec2() * EROSION_TILE_SIZE;
+    let cell_size = EROSION_TILE_SIZE / (EROSION_TILE_RESOLUTION - 1) as f32;
+    // the simulation is tuned for heights in [0, 1]
+    let height_scale = terrain_sampler.max_height().max(1.0);
+
+    let original = Heightfield::from_fn(EROSION_TILE_RESOLUTION, |x, y| {
+        let pos = min + vec2(x as f32, y as f32) * cell_size;
+        terrain_sampler.sample(pos, base_seed).height.value / height_scale
+    });
+
+    let tile_seed = (position.x as u32 as u64) << 32 | position.y as u32 as u64;
+    let seed = base_seed.hasher(SeedDomain::Erosion, 0).seed() as u64 ^ tile_seed;
+    let mut eroded = original.clone();
+    erode_hydraulic(&mut eroded, settings, seed);
+    erode_thermal(
+        &mut eroded,
+        settings,
+        settings.talus_angle.tan() * cell_size / height_scale,
+    );
+
+    let max = (EROSION_TILE_RESOLUTION - 1) as f32;
+    Heightfield::from_fn(EROSION_TILE_RESOLUTION, |x, y| {
+        let edge_distance = (x as f32)
+            .min(y as f32)
+            .min(max - x as f32)
+            .min(max - y as f32);
+        let t = (edge_distance / EROSION_EDGE_FADE).clamp(0.0, 1.0);
+        let fade = t * t * (3.0 - 2.0 * t);
+        (eroded.get(x, y) - original.get(x, y)) * height_scale * fade
+    })
+}
+
+/// Eroded tiles, cached until the terrain or the erosion settings change.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct ErodedTerrain {
+    tiles: HashMap<IVec2, Arc<Heightfield>>,
+}
+
+impl ErodedTerrain {
+    fn tile_rect(position: IVec2) -> Rect {
+        let min = position.as_vec2() * EROSION_TILE_SIZE;
+        Rect::from_corners(min, min + EROSION_TILE_SIZE)
+    }
+
+    /// Height offset of the erosion at `pos`, zero outside of the eroded tiles.
+    pub fn offset(&self, pos: Vec2) -> noise::Value2Dt1 {
+        let position = (pos / EROSION_TILE_SIZE).floor().as_ivec2();
+        let Some(tile) = self.tiles.get(&position) else {
+            return noise::Value2Dt1::default();
+        };
+
+        let cell_size = EROSION_TILE_SIZE / (EROSION_TILE_RESOLUTION - 1) as f32;
+        let sample = tile.sample((pos - position.as_vec2() * EROSION_TILE_SIZE) / cell_size);
+        noise::Value2Dt1::new(sample.value, sample.d1.0 / cell_size)
+    }
+
+    /// Adds the erosion to a sample of the analytic terrain at `pos`.
+    pub fn apply(&self, pos: Vec2, sample: noise::Value2Dt1) -> noise::Value2Dt1 {
+        sample + self.offset(pos)
+    }
+}
+
+/// Sent when the erosion of a tile is added or removed, the terrain inside `rect` changed.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct ErodedTerrainChanged {
+    pub rect: Rect,
+}
+
+/// Tiles being eroded in the background, dropping a task cancels it.
+#[derive(Resource, Default)]
+struct ErosionTasks {
+    tasks: HashMap<IVec2, Task<Heightfield>>,
+}
+
+/// Remeshes the chunks overlapping `rect`, the erosion inside it changed.
+fn mark_chunks_outdated(commands: &mut Commands, chunks: &Query<(Entity, &Chunk)>, rect: Rect) {
+    for (entity, chunk) in chunks.iter() {
+        let chunk_rect = Rect::from_corners(chunk.key.min(), chunk.key.max());
+        if !chunk_rect.intersect(rect).is_empty() {
+            commands.entity(entity).insert(ChunkMeshOutdated);
+        }
+    }
+}
+
+fn reset_eroded_terrain(
+    mut commands: Commands,
+    mut eroded_terrain: ResMut<ErodedTerrain>,
+    mut erosion_tasks: ResMut<ErosionTasks>,
+    mut changed_events: EventWriter<ErodedTerrainChanged>,
+    chunks: Query<(Entity, &Chunk)>,
+) {
+    // skip the change detection of an already empty cache, clearing it does not change the terrain
+    if !eroded_terrain.tiles.is_empty() {
+        for (position, _) in eroded_terrain.tiles.drain() {
+            let rect = ErodedTerrain::tile_rect(position);
+            changed_events.send(ErodedTerrainChanged { rect });
+            mark_chunks_outdated(&mut commands, &chunks, rect);
+        }
+    }
+    erosion_tasks.tasks.clear();
+}
+
+fn start_erosion_tasks(
+    mut erosion_tasks: ResMut<ErosionTasks>,
+    eroded_terrain: Res<ErodedTerrain>,
+    settings: Res<ErosionSettings>,
+    terrain_sampler: Res<TerrainSampler>,
+    base_seed: Res<BaseSeed>,
+) {
+    let task_pool = AsyncComputeTaskPool::get();
+
+    for position in settings.tiles() {
+        if eroded_terrain.tiles.contains_key(&position)
+            || erosion_tasks.tasks.contains_key(&position)
+        {
+            continue;
+        }
+
+        let terrain_sampler = TerrainSampler::clone(&terrain_sampler);
+        let base_seed = *base_seed;
+        let settings = ErosionSettings::clone(&settings);
+        let task = task_pool
+            .spawn(async move { erode_tile(position, &terrain_sampler, &base_seed, &settings) });
+        erosion_tasks.tasks.insert(position, task);
+    }
+}
+
+fn apply_erosion_tasks(
+    mut commands: Commands,
+    mut erosion_tasks: ResMut<ErosionTasks>,
+    mut eroded_terrain: ResMut<ErodedTerrain>,
+    mut changed_events: EventWriter<ErodedTerrainChanged>,
+    chunks: Query<(Entity, &Chunk)>,
+) {
+    let mut finished = Vec::new();
+    for (position, task) in erosion_tasks.tasks.iter_mut() {
+        if let Some(tile) = block_on(poll_once(task)) {
+            finished.push((*position, tile));
+        }
+    }
+
+    for (position, tile) in finished {
+        erosion_tasks.tasks.remove(&position);
+        eroded_terrain.tiles.insert(position, Arc::new(tile));
+
+        let rect = ErodedTerrain::tile_rect(position);
+        changed_events.send(ErodedTerrainChanged { rect });
+        mark_chunks_outdated(&mut commands, &chunks, rect);
+    }
+}
+
+fn clear_erosion_tasks(mut erosion_tasks: ResMut<ErosionTasks>) {
+    erosion_tasks.tasks.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    fn cone(resolution: usize) -> Heightfield {
+        let center = (resolution - 1) as f32 / 2.0;
+        Heightfield::from_fn(resolution, |x, y| {
+            1.0 - vec2(x as f32 - center, y as f32 - center).length() / center
+        })
+    }
+
+    #[test]
+    fn heightfield_sample_interpolates() {
+        let heightfield = Heightfield::from_fn(3, |x, y| x as f32 + 2.0 * y as f32);
+
+        let sample = heightfield.sample(vec2(0.5, 1.25));
+        assert!((sample.value - 3.0).abs() < 1e-6);
+        assert!((sample.d1.0 - vec2(1.0, 2.0)).length() < 1e-6);
+        assert_eq!(heightfield.sample(vec2(2.0, 2.0)).value, 6.0);
+    }
+
+    #[test]
+    fn hydraulic_erosion_moves_material_downhill() {
+        let settings = ErosionSettings {
+            droplets: 2000,
+            ..default()
+        };
+        let original = cone(33);
+        let mut eroded = original.clone();
+        erode_hydraulic(&mut eroded, &settings, 7);
+
+        let total = |heightfield: &Heightfield| heightfield.heights.iter().sum::<f32>();
+        assert_ne!(original, eroded);
+        // droplets leaving the grid carry their sediment away, they never add any
+        assert!(total(&eroded) <= total(&original) + 1e-3);
+
+        let mut again = original.clone();
+        erode_hydraulic(&mut again, &settings, 7);
+        assert_eq!(eroded, again);
+    }
+
+    #[test]
+    fn thermal_erosion_flattens_steep_slopes() {
+        let settings = ErosionSettings {
+            thermal_iterations: 200,
+            ..default()
+        };
+        let mut heightfield =
+            Heightfield::from_fn(9, |x, y| if (x, y) == (4, 4) { 4.0 } else { 0.0 });
+        let total = heightfield.heights.iter().sum::<f32>();
+        erode_thermal(&mut heightfield, &settings, 0.5);
+
+        assert!((heightfield.heights.iter().sum::<f32>() - total).abs() < 1e-3);
+        assert!(heightfield.get(4, 4) - heightfield.get(5, 4) <= 0.5 + 1e-2);
+    }
+
+    #[test]
+    fn eroded_tiles_fade_out_at_the_border() {
+        let mut eroded_terrain = ErodedTerrain::default();
+        let settings = ErosionSettings {
+            droplets: 2000,
+            thermal_iterations: 2,
+            ..default()
+        };
+        let tile = erode_tile(
+            IVec2::ZERO,
+            &TerrainSampler::default(),
+            &BaseSeed(1),
+            &settings,
+        );
+        eroded_terrain.tiles.insert(IVec2::ZERO, Arc::new(tile));
+
+        assert_eq!(eroded_terrain.offset(vec2(0.0, 100.0)).value, 0.0);
+        assert_eq!(
+            eroded_terrain.offset(vec2(EROSION_TILE_SIZE, 100.0)).value,
+            0.0
+        );
+        assert_eq!(eroded_terrain.offset(vec2(-10.0, 100.0)).value, 0.0);
+        assert!((0..EROSION_TILE_RESOLUTION)
+            .map(|x| eroded_terrain.offset(vec2(x as f32 * 4.0, 256.0)).value)
+            .any(|offset| offset != 0.0));
+    }
+
+    #[test]
+    fn reset_tiles_remesh_their_chunks() {
+        let mut world = World::new();
+        world.init_resource::<ErosionTasks>();
+        world.init_resource::<Events<ErodedTerrainChanged>>();
+        let mut eroded_terrain = ErodedTerrain::default();
+        eroded_terrain
+            .tiles
+            .insert(IVec2::ZERO, Arc::new(Heightfield::from_fn(2, |_, _| 1.0)));
+        world.insert_resource(eroded_terrain);
+
+        let inside = world
+            .spawn(Chunk::new(ChunkKey::new(0, IVec2::ZERO), 2, default()))
+            .id();
+        let outside = world
+            .spawn(Chunk::new(
+                ChunkKey::new(0, ivec2(-1000, 1000)),
+                2,
+                default(),
+            ))
+            .id();
+        world.run_system_once(reset_eroded_terrain);
+
+        assert!(world.resource::<ErodedTerrain>().tiles.is_empty());
+        assert!(world.entity(inside).contains::<ChunkMeshOutdated>());
+        assert!(!world.entity(outside).contains::<ChunkMeshOutdated>());
+        assert_eq!(world.resource::<Events<ErodedTerrainChanged>>().len(), 1);
+    }
+}
@@ -0,0 +1,604 @@
+//! Terrain sampling on the gpu with `compute_terrain.wgsl`.
+//! Chunk vertices are batched in the main world and sampled by [`TerrainSampleJob`]s.
+
+use std::{cell::Cell, time::Duration};
+
+use bevy::render::{render_resource::*, renderer::RenderDevice};
+
+use super::*;
+use crate::shader_struct;
+use utils::prelude::{
+    storage_buffer, storage_empty_rw, uniform_buffer, BindGroupBuilderError, BindLayoutBuilder,
+    ComputeJob, ComputeJobBuffers, ComputeJobPlugin, ComputeJobs, JobHandle,
+};
+
+const SHADER_ASSET_PATH: &str = "shaders/compute_terrain.wgsl";
+const WORKGROUP_SIZE: u32 = 64;
+/// Batches without a result after this long are given to the cpu.
+const BATCH_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_BATCHES_IN_FLIGHT: usize = 3;
+
+pub(super) fn plugin(app: &mut App) {
+    app.init_resource::<TerrainGpuBatches>().add_plugins(
+        ComputeJobPlugin::<TerrainSampleJob>::new().with_max_in_flight(MAX_BATCHES_IN_FLIGHT),
+    );
+}
+
+shader_struct! {
+    /// Layer of [`TerrainSampler`] as laid out in `compute_terrain.wgsl`.
+    #[derive(ShaderType, Debug, Clone, Copy)]
+    pub(super) struct GpuLayerWeight {
+        size: f32,
+        amplitude: f32,
+        erosion: f32,
+        seed: u32,
+        noise: u32,
+    }
+}
+
+impl GpuLayerWeight {
+    fn new(weight: &NoiseLayer, base_seed: &BaseSeed) -> Self {
+        Self {
+            size: weight.size,
+            amplitude: weight.amplitude,
+            erosion: weight.erosion,
+            seed: base_seed
+                .hasher(SeedDomain::Terrain, weight.seed_offset)
+                .seed(),
+            noise: weight.noise as u32,
+        }
+    }
+}
+
+shader_struct! {
+    /// [`BiomeLayers`] as laid out in `compute_terrain.wgsl`,
+    /// its layers are a range of the weights.
+    /// Only biomes made of a single layer stack are sampled on the gpu.
+    #[derive(ShaderType, Debug, Clone, Copy)]
+    pub(super) struct GpuBiome {
+        temperature: f32,
+        moisture: f32,
+        first_layer: u32,
+        layer_count: u32,
+    }
+}
+
+shader_struct! {
+    /// [`Climate`] as laid out in `compute_terrain.wgsl`,
+    /// with the [`HasherKind`] of the whole sampler.
+    #[derive(ShaderType, Debug, Clone, Copy, Default)]
+    pub(super) struct GpuClimate {
+        size: f32,
+        blend: f32,
+        temperature_seed: u32,
+        moisture_seed: u32,
+        hasher: u32,
+    }
+}
+
+impl GpuClimate {
+    fn new(climate: &Climate, base_seed: &BaseSeed, hasher: HasherKind) -> Self {
+        let [temperature_seed, moisture_seed] = climate.seeds(base_seed);
+        Self {
+            size: climate.size,
+            blend: climate.blend,
+            temperature_seed,
+            moisture_seed,
+            hasher: hasher as u32,
+        }
+    }
+}
+
+shader_struct! {
+    /// `Dt1Value` as laid out in `compute_terrain.wgsl` results.
+    #[derive(ShaderType, Debug, Clone, Copy)]
+    struct GpuTerrainSample {
+        value: f32,
+        gradient: Vec2,
+    }
+}
+
+impl GpuTerrainSample {
+    /// Decodes the readback bytes, `value` is followed by 4 bytes of padding.
+    fn decode(raw: [f32; 4]) -> noise::Value2Dt1 {
+        noise::Value2Dt1::new(raw[0], vec2(raw[2], raw[3]))
+    }
+}
+
+/// Positions to sample with the sampler laid out for `compute_terrain.wgsl`.
+pub(super) struct TerrainGpuRequest {
+    positions: Vec<Vec2>,
+    weights: Vec<GpuLayerWeight>,
+    biomes: Vec<GpuBiome>,
+    climate: GpuClimate,
+}
+
+impl TerrainGpuRequest {
+    fn new(positions: Vec<Vec2>, terrain_sampler: &TerrainSampler, base_seed: &BaseSeed) -> Self {
+        let mut weights = Vec::new();
+        let mut biomes = Vec::new();
+        for biome in terrain_sampler.biomes.iter() {
+            let layers = biome.height.as_layers().unwrap_or_default();
+            biomes.push(GpuBiome {
+                temperature: biome.temperature,
+                moisture: biome.moisture,
+                first_layer: weights.len() as u32,
+                layer_count: layers.len() as u32,
+            });
+            weights.extend(
+                layers
+                    .iter()
+                    .map(|weight| GpuLayerWeight::new(weight, base_seed)),
+            );
+        }
+
+        Self {
+            positions,
+            weights,
+            biomes,
+            climate: GpuClimate::new(&terrain_sampler.climate, base_seed, terrain_sampler.hasher),
+        }
+    }
+}
+
+/// Samples the analytic terrain at the positions of a [`TerrainGpuRequest`].
+pub(super) struct TerrainSampleJob;
+
+impl ComputeJob for TerrainSampleJob {
+    type In = TerrainGpuRequest;
+    type Out = Vec<noise::Value2Dt1>;
+
+    const LABEL: &'static str = "terrain sample";
+
+    fn shader(asset_server: &AssetServer) -> Handle<Shader> {
+        asset_server.load(SHADER_ASSET_PATH)
+    }
+
+    fn layout() -> BindLayoutBuilder<&'static str> {
+        BindLayoutBuilder::new(Self::LABEL, ShaderStages::COMPUTE)
+            .with_readonly_storage_slot::<Vec<Vec2>>()
+            .with_readonly_storage_slot::<Vec<GpuLayerWeight>>()
+            .with_storage_slot::<Vec<GpuTerrainSample>>()
+            .with_readonly_storage_slot::<Vec<GpuBiome>>()
+            .with_uniform_slot::<GpuClimate>()
+    }
+
+    fn buffers(
+        request: &TerrainGpuRequest,
+        device: &RenderDevice,
+    ) -> Result<ComputeJobBuffers, BindGroupBuilderError> {
+        // empty storage bindings are invalid, a sampler without layers has nothing to dispatch
+        for (name, len) in [
+            ("positions", request.positions.len()),
+            ("weights", request.weights.len()),
+            ("biomes", request.biomes.len()),
+        ] {
+            if len == 0 {
+                return Err(BindGroupBuilderError::empty_buffer(name));
+            }
+        }
+
+        let samples_size = request.positions.len() as u64 * GpuTerrainSample::min_size().get();
+        Ok(ComputeJobBuffers {
+            bindings: vec![
+                storage_buffer(device, &"terrain positions", &request.positions),
+                storage_buffer(device, &"terrain weights", &request.weights),
+                storage_empty_rw(device, &"terrain samples", samples_size),
+                storage_buffer(device, &"terrain biomes", &request.biomes),
+                uniform_buffer(device, &"terrain climate", &request.climate),
+            ],
+            output: 2,
+        })
+    }
+
+    fn workgroups(request: &TerrainGpuRequest) -> [u32; 3] {
+        [
+            (request.positions.len() as u32).div_ceil(WORKGROUP_SIZE),
+            1,
+            1,
+        ]
+    }
+
+    fn decode(_request: &TerrainGpuRequest, bytes: &[u8]) -> Vec<noise::Value2Dt1> {
+        bytes
+            .chunks_exact(GpuTerrainSample::min_size().get() as usize)
+            .map(|raw| GpuTerrainSample::decode(bytemuck::pod_read_unaligned(raw)))
+            .collect()
+    }
+}
+
+/// Chunks waiting for the result of their sample job, oldest first.
+#[derive(Resource, Default)]
+pub(super) struct TerrainGpuBatches {
+    in_flight: Vec<TerrainGpuBatch>,
+}
+
+struct TerrainGpuBatch {
+    job: JobHandle<Vec<noise::Value2Dt1>>,
+    chunks: Vec<(Entity, Chunk)>,
+    vertices: usize,
+    started: Duration,
+}
+
+fn chunk_vertex_positions(chunk: &Chunk) -> impl Iterator<Item = Vec2> {
+    let center = chunk.key.center();
+    utils::primitives::plane_vertex_positions(chunk.subdivisions, chunk.size())
+        .map(move |position| center + position)
+}
+
+fn create_chunk_mesh_from_samples(chunk: &Chunk, samples: &[noise::Value2Dt1]) -> Mesh {
+    let index = Cell::new(0);
+    let mut mesh = utils::primitives::create_stitched_plane(
+        chunk.subdivisions,
+        chunk.size(),
+        chunk.edge_lod_deltas,
+        |_, _| {
+            let sample = samples[index.get()];
+            index.set(index.get() + 1);
+            sample.to_mesh_input()
+        },
+    );
+    material::insert_lod_morph_attribute(&mut mesh, chunk);
+
+    mesh.asset_usage = RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD;
+    mesh
+}
+
+pub(super) fn start_gpu_batch(
+    mut commands: Commands,
+    chunks: Query<(Entity, &Chunk), With<ChunkMeshOutdated>>,
+    mut batches: ResMut<TerrainGpuBatches>,
+    mut jobs: ResMut<ComputeJobs<TerrainSampleJob>>,
+    base_seed: Res<BaseSeed>,
+    terrain_sampler: Res<TerrainSampler>,
+    budget: Res<ChunkMeshBudget>,
+    time: Res<Time<Real>>,
+) {
+    if batches.in_flight.len() >= MAX_BATCHES_IN_FLIGHT {
+        return;
+    }
+
+    // finer chunks are closer to the camera, mesh them first
+    let chunks = chunks
+        .iter()
+        .sorted_by_key(|(_, chunk)| chunk.key.lod)
+        .take(budget.tasks_per_frame)
+        .map(|(entity, chunk)| (entity, *chunk))
+        .collect_vec();
+
+    if chunks.is_empty() {
+        return;
+    }
+
+    let positions = chunks
+        .iter()
+        .flat_map(|(_, chunk)| chunk_vertex_positions(chunk))
+        .collect_vec();
+
+    for (entity, _) in chunks.iter() {
+        commands.entity(*entity).remove::<ChunkMeshOutdated>();
+    }
+
+    let vertices = positions.len();
+    let job = jobs.submit(TerrainGpuRequest::new(
+        positions,
+        &terrain_sampler,
+        &base_seed,
+    ));
+    batches.in_flight.push(TerrainGpuBatch {
+        job,
+        chunks,
+        vertices,
+        started: time.elapsed(),
+    });
+}
+
+pub(super) fn receive_gpu_batch(
+    mut commands: Commands,
+    mut batches: ResMut<TerrainGpuBatches>,
+    mut backend: ResMut<TerrainSampleBackend>,
+    current_chunks: Query<&Chunk>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    material: Res<material::TerrainMaterialHandle>,
+    eroded_terrain: Res<ErodedTerrain>,
+    time: Res<Time<Real>>,
+) {
+    let mut pending = Vec::new();
+    for mut batch in std::mem::take(&mut batches.in_flight) {
+        let samples = match batch.job.try_take() {
+            Some(Ok(samples)) => samples,
+            Some(Err(error)) => {
+                error!(
+                    "terrain gpu batch {} failed: {error}, falling back to cpu sampling",
+                    batch.job.id()
+                );
+                *backend = TerrainSampleBackend::Cpu;
+                retry_batch(&mut commands, batch);
+                continue;
+            }
+            None if time.elapsed() - batch.started > BATCH_TIMEOUT => {
+                warn!(
+                    "terrain gpu batch {} timed out, falling back to cpu sampling",
+                    batch.job.id()
+                );
+                *backend = TerrainSampleBackend::Cpu;
+                retry_batch(&mut commands, batch);
+                continue;
+            }
+            None => {
+                pending.push(batch);
+                continue;
+            }
+        };
+
+        if samples.len() != batch.vertices {
+            error!(
+                "terrain gpu batch {} expected {} samples, got {}",
+                batch.job.id(),
+                batch.vertices,
+                samples.len()
+            );
+            retry_batch(&mut commands, batch);
+            continue;
+        }
+
+        let mut offset = 0;
+        for (entity, chunk) in batch.chunks {
+            let vertices = (chunk.subdivisions * chunk.subdivisions) as usize;
+            let chunk_samples = &samples[offset..offset + vertices];
+            offset += vertices;
+
+            // despawned or changed while sampling, changed chunks are already marked outdated
+            if current_chunks.get(entity).ok() != Some(&chunk) {
+                continue;
+            }
+
+            // the gpu samples the analytic terrain, erosion is cached on the cpu
+            let chunk_samples = chunk_vertex_positions(&chunk)
+                .zip_eq(chunk_samples)
+                .map(|(pos, sample)| eroded_terrain.apply(pos, *sample))
+                .collect_vec();
+            let mesh = create_chunk_mesh_from_samples(&chunk, &chunk_samples);
+            commands
+                .entity(entity)
+                .remove::<ChunkMeshTask>()
+                .insert((meshes.add(mesh), material.0.clone()));
+        }
+    }
+    batches.in_flight = pending;
+}
+
+fn retry_batch(commands: &mut Commands, batch: TerrainGpuBatch) {
+    for (entity, _) in batch.chunks {
+        if let Some(mut entity_commands) = commands.get_entity(entity) {
+            entity_commands.insert(ChunkMeshOutdated);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::render_resource::encase::StorageBuffer;
+    use wgpu::util::DeviceExt;
+
+    use super::*;
+    use utils::{prelude::ShaderReflection, wgsl::testing::software_gpu};
+
+    /// `compute_terrain.wgsl` with its single import resolved by concatenation.
+    fn compose_shader() -> String {
+        let strip_directives = |source: &str| {
+            let mut in_import = false;
+            source
+                .lines()
+                .filter(|line| {
+                    if line.starts_with("#import") {
+                        in_import = !line.contains('}');
+                        return false;
+                    }
+                    if in_import {
+                        in_import = !line.contains('}');
+                        return false;
+                    }
+                    !line.starts_with("#define_import_path")
+                })
+                .join("\n")
+        };
+
+        [
+            include_str!("../shaders/noise.wgsl"),
+            include_str!("../../../assets/shaders/compute_terrain.wgsl"),
+        ]
+        .map(strip_directives)
+        .join("\n")
+    }
+
+    fn to_bytes<T: ShaderType + encase::internal::WriteInto>(value: &T) -> Vec<u8> {
+        let mut buffer = StorageBuffer::new(Vec::new());
+        buffer.write(value).unwrap();
+        buffer.into_inner()
+    }
+
+    fn sample_on_gpu(request: &TerrainGpuRequest) -> Vec<noise::Value2Dt1> {
+        let gpu = software_gpu();
+        let (device, queue) = (gpu.device.wgpu_device(), &gpu.queue);
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(compose_shader().into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: None,
+            module: &module,
+            entry_point: "main",
+            compilation_options: default(),
+        });
+
+        let result_size = request.positions.len() as u64 * GpuTerrainSample::min_size().get();
+        let positions = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: &to_bytes(&request.positions),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let weights = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: &to_bytes(&request.weights),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let biomes = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: &to_bytes(&request.biomes),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let climate = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: &to_bytes(&request.climate),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let result = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: result_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: result_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[&positions, &weights, &result, &biomes, &climate]
+                .into_iter()
+                .enumerate()
+                .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                    binding: binding as u32,
+                    resource: buffer.as_entire_binding(),
+                })
+                .collect_vec(),
+        });
+
+        let mut encoder = device.create_command_encoder(&default());
+        {
+            let mut pass = encoder.begin_compute_pass(&default());
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(
+                (request.positions.len() as u32).div_ceil(WORKGROUP_SIZE),
+                1,
+                1,
+            );
+        }
+        encoder.copy_buffer_to_buffer(&result, 0, &readback, 0, result_size);
+        queue.submit([encoder.finish()]);
+
+        let slice = readback.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        device.poll(wgpu::Maintain::Wait).panic_on_timeout();
+
+        let mapped = slice.get_mapped_range();
+        bytemuck::cast_slice::<u8, [f32; 4]>(&mapped)
+            .iter()
+            .copied()
+            .map(GpuTerrainSample::decode)
+            .collect_vec()
+    }
+
+    fn terrain_reflection() -> ShaderReflection {
+        let mut shaders = Assets::<Shader>::default();
+        shaders.add(Shader::from_wgsl(
+            include_str!("../shaders/noise.wgsl"),
+            "src/game/shaders/noise.wgsl",
+        ));
+        let shader = Shader::from_wgsl(
+            include_str!("../../../assets/shaders/compute_terrain.wgsl"),
+            SHADER_ASSET_PATH,
+        );
+        ShaderReflection::from_shader(&shader, &shaders).unwrap()
+    }
+
+    #[test]
+    fn terrain_layout_matches_shader() {
+        TerrainSampleJob::layout()
+            .validate(&terrain_reflection(), TerrainSampleJob::entry_point(), 0)
+            .unwrap();
+    }
+
+    #[test]
+    fn swapped_members_fail_the_layout() {
+        shader_struct! {
+            /// [`GpuLayerWeight`] with `erosion` and `seed` swapped, same size and offsets.
+            #[derive(ShaderType)]
+            struct SwappedLayerWeight {
+                size: f32,
+                amplitude: f32,
+                seed: u32,
+                erosion: f32,
+                noise: u32,
+            }
+        }
+
+        let error = BindLayoutBuilder::new(TerrainSampleJob::LABEL, ShaderStages::COMPUTE)
+            .with_readonly_storage_slot::<Vec<Vec2>>()
+            .with_readonly_storage_slot::<Vec<SwappedLayerWeight>>()
+            .with_storage_slot::<Vec<GpuTerrainSample>>()
+            .with_readonly_storage_slot::<Vec<GpuBiome>>()
+            .with_uniform_slot::<GpuClimate>()
+            .validate(&terrain_reflection(), TerrainSampleJob::entry_point(), 0)
+            .unwrap_err()
+            .to_string();
+
+        assert!(
+            error.contains("`erosion` (Float, 4 bytes at offset 8) as member 2"),
+            "{error}"
+        );
+        assert!(
+            error.contains("SwappedLayerWeight> has `seed` (Uint, 4 bytes at offset 8)"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter, run with --ignored"]
+    fn gpu_sampling_matches_cpu() {
+        let base_seed = BaseSeed(7);
+        // spread over several climate cells so every biome is blended in
+        let positions = (-20..20)
+            .cartesian_product(-20..20)
+            .map(|(x, y)| vec2(x as f32 * 537.3 + 0.5, y as f32 * 541.7 + 0.25))
+            .collect_vec();
+
+        for hasher in HasherKind::ALL
+            .into_iter()
+            .filter(|kind| kind.has_wgsl_mirror())
+        {
+            // the default desert has a value noise layer
+            let terrain_sampler =
+                TerrainSampler::new(Climate::default(), biome::default_biomes(), hasher);
+            let request = TerrainGpuRequest::new(positions.clone(), &terrain_sampler, &base_seed);
+
+            let gpu_samples = sample_on_gpu(&request);
+
+            let max_height = terrain_sampler.max_height();
+            for (position, gpu) in positions.iter().zip_eq(gpu_samples) {
+                let cpu = terrain_sampler.sample(*position, &base_seed).height;
+
+                assert!(
+                    (cpu.value - gpu.value).abs() < max_height * 1e-4,
+                    "{hasher:?} {position}: cpu {} != gpu {}",
+                    cpu.value,
+                    gpu.value
+                );
+                assert!(
+                    (cpu.d1.0 - gpu.d1.0).length() < 1e-3 * (1.0 + cpu.d1.0.length()),
+                    "{hasher:?} {position}: cpu {:?} != gpu {:?}",
+                    cpu.d1,
+                    gpu.d1
+                );
+            }
+        }
+    }
+}
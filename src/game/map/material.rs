@@ -0,0 +1,350 @@
+//! Terrain material, blends grass, dirt, rock and snow by slope, height and a noise mask.
+//! Chunk vertices are geomorphed towards the coarser LOD as they get further from the camera,
+//! so chunks match their parent by the time the quadtree swaps them.
+
+use bevy::{
+    pbr::{ExtendedMaterial, MaterialExtension, MaterialExtensionKey, MaterialExtensionPipeline},
+    render::{
+        mesh::{MeshVertexAttribute, MeshVertexBufferLayoutRef},
+        render_resource::{
+            AsBindGroup, RenderPipelineDescriptor, ShaderRef, ShaderType,
+            SpecializedMeshPipelineError, VertexFormat,
+        },
+    },
+};
+
+use super::*;
+
+const SHADER_ASSET_PATH: &str = "shaders/terrain_material.wgsl";
+const PREPASS_SHADER_ASSET_PATH: &str = "shaders/terrain_prepass.wgsl";
+
+/// Height of the vertex on the parent LOD surface and the size of its chunk.
+pub const ATTRIBUTE_LOD_MORPH: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_LodMorph", 988_540_917, VertexFormat::Float32x2);
+/// Past the locations bevy uses, in both the main pass and the prepass.
+const LOD_MORPH_SHADER_LOCATION: u32 = 8;
+/// Fraction of the morph distance of the smallest chunks the camera moves before the
+/// material is uploaded again, every upload prepares the material bind group again.
+const LOD_MORPH_CAMERA_STEP: f32 = 1.0 / 32.0;
+
+pub type TerrainMaterial = ExtendedMaterial<StandardMaterial, TerrainMaterialExtension>;
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_plugins(MaterialPlugin::<TerrainMaterial>::default())
+        .register_type::<TerrainMaterialSettings>()
+        .init_resource::<TerrainMaterialSettings>()
+        .init_resource::<TerrainMaterialHandle>()
+        .add_systems(
+            Update,
+            (
+                update_terrain_material.run_if(
+                    resource_changed::<TerrainMaterialSettings>
+                        .or_else(resource_changed::<TerrainSampler>),
+                ),
+                update_terrain_lod_morph,
+            )
+                .chain()
+                .in_set(GameSet::PostUpdate),
+        );
+}
+
+/// Layers of the terrain material, edited live from the terrain editor dock.
+#[derive(Resource, Debug, Clone, Reflect)]
+#[reflect(Resource)]
+pub struct TerrainMaterialSettings {
+    pub grass_color: Color,
+    pub dirt_color: Color,
+    pub rock_color: Color,
+    pub snow_color: Color,
+    /// Slope where dirt replaces grass, 0 is flat and 1 is vertical.
+    pub dirt_slope: f32,
+    /// Slope where rock replaces dirt, snow does not stick above it.
+    pub rock_slope: f32,
+    /// Height where snow starts, relative to the terrain max height.
+    pub snow_height: f32,
+    /// Width of the transition between two layers.
+    pub blend_width: f32,
+    /// Size of the noise that breaks up layer transitions.
+    pub noise_size: f32,
+    /// How far the noise moves layer transitions.
+    pub noise_strength: f32,
+    pub perceptual_roughness: f32,
+    /// Distance where vertices start morphing towards the coarser LOD,
+    /// as a fraction of the distance where the coarser chunk replaces theirs.
+    pub lod_morph_start: f32,
+    /// Distance where vertices match the coarser LOD, same unit as `lod_morph_start`.
+    pub lod_morph_end: f32,
+}
+
+impl Default for TerrainMaterialSettings {
+    fn default() -> Self {
+        Self {
+            grass_color: tailwind::GREEN_700.into(),
+            dirt_color: tailwind::STONE_600.into(),
+            rock_color: tailwind::STONE_400.into(),
+            snow_color: tailwind::SLATE_50.into(),
+            dirt_slope: 0.15,
+            rock_slope: 0.35,
+            snow_height: 0.7,
+            blend_width: 0.05,
+            noise_size: 40.0,
+            noise_strength: 0.1,
+            perceptual_roughness: 0.9,
+            lod_morph_start: 0.7,
+            lod_morph_end: 0.9,
+        }
+    }
+}
+
+impl TerrainMaterialSettings {
+    /// Color of the layers at `slope` and `height` relative to the max height, without the noise mask.
+    /// Mirrors the fragment shader of `terrain_material.wgsl` for the editor preview.
+    pub fn layer_color(&self, slope: f32, height: f32) -> LinearRgba {
+        let dirt = self.layer_weight(slope, self.dirt_slope);
+        let rock = self.layer_weight(slope, self.rock_slope);
+        let snow = self.layer_weight(height, self.snow_height) * (1.0 - rock);
+
+        LinearRgba::from(self.grass_color)
+            .mix(&self.dirt_color.into(), dirt)
+            .mix(&self.rock_color.into(), rock)
+            .mix(&self.snow_color.into(), snow)
+    }
+
+    fn layer_weight(&self, value: f32, threshold: f32) -> f32 {
+        let half_width = self.blend_width * 0.5;
+        if half_width <= 0.0 {
+            return if value < threshold { 0.0 } else { 1.0 };
+        }
+        let t = ((value - threshold + half_width) / (2.0 * half_width)).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+#[derive(ShaderType, Debug, Clone, Copy, Default, Reflect)]
+pub struct TerrainMaterialUniform {
+    grass_color: Vec4,
+    dirt_color: Vec4,
+    rock_color: Vec4,
+    snow_color: Vec4,
+    dirt_slope: f32,
+    rock_slope: f32,
+    snow_height: f32,
+    max_height: f32,
+    blend_width: f32,
+    noise_scale: f32,
+    noise_strength: f32,
+    camera_position: Vec3,
+    /// Distance where a chunk is replaced by its parent, per unit of chunk size.
+    lod_distance_scale: f32,
+    lod_morph_start: f32,
+    lod_morph_end: f32,
+}
+
+impl TerrainMaterialUniform {
+    fn new(settings: &TerrainMaterialSettings, terrain_sampler: &TerrainSampler) -> Self {
+        Self {
+            grass_color: LinearRgba::from(settings.grass_color).to_vec4(),
+            dirt_color: LinearRgba::from(settings.dirt_color).to_vec4(),
+            rock_color: LinearRgba::from(settings.rock_color).to_vec4(),
+            snow_color: LinearRgba::from(settings.snow_color).to_vec4(),
+            dirt_slope: settings.dirt_slope,
+            rock_slope: settings.rock_slope,
+            snow_height: settings.snow_height,
+            max_height: terrain_sampler.max_height(),
+            blend_width: settings.blend_width,
+            noise_scale: 1.0 / settings.noise_size,
+            noise_strength: settings.noise_strength,
+            // set from the camera by `update_terrain_lod_morph`
+            camera_position: Vec3::ZERO,
+            lod_distance_scale: 0.0,
+            lod_morph_start: settings.lod_morph_start,
+            lod_morph_end: settings.lod_morph_end,
+        }
+    }
+}
+
+impl TerrainMaterialUniform {
+    /// Whether the morph seen from `camera_position` is far enough from the uploaded one
+    /// to upload the material again.
+    fn is_lod_morph_outdated(&self, camera_position: Vec3, lod_distance_scale: f32) -> bool {
+        if self.lod_distance_scale != lod_distance_scale {
+            return true;
+        }
+
+        let morph_distance =
+            CHUNK_MIN_SIZE * lod_distance_scale * (self.lod_morph_end - self.lod_morph_start);
+        self.camera_position.distance(camera_position) > morph_distance * LOD_MORPH_CAMERA_STEP
+    }
+}
+
+#[derive(Asset, AsBindGroup, Reflect, Debug, Clone)]
+pub struct TerrainMaterialExtension {
+    #[uniform(100)]
+    uniform: TerrainMaterialUniform,
+}
+
+impl MaterialExtension for TerrainMaterialExtension {
+    fn vertex_shader() -> ShaderRef {
+        SHADER_ASSET_PATH.into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        SHADER_ASSET_PATH.into()
+    }
+
+    fn prepass_vertex_shader() -> ShaderRef {
+        PREPASS_SHADER_ASSET_PATH.into()
+    }
+
+    fn specialize(
+        _pipeline: &MaterialExtensionPipeline,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayoutRef,
+        _key: MaterialExtensionKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        // the base material already picked the attributes of this pass,
+        // the morph data is read from the same interleaved buffer
+        let lod_morph = layout
+            .0
+            .get_layout(&[ATTRIBUTE_LOD_MORPH.at_shader_location(LOD_MORPH_SHADER_LOCATION)])?;
+        descriptor.vertex.buffers[0]
+            .attributes
+            .extend(lod_morph.attributes);
+        Ok(())
+    }
+}
+
+/// Material shared by every chunk.
+#[derive(Resource, Debug, Clone)]
+pub struct TerrainMaterialHandle(pub Handle<TerrainMaterial>);
+
+impl FromWorld for TerrainMaterialHandle {
+    fn from_world(world: &mut World) -> Self {
+        let settings = world.resource::<TerrainMaterialSettings>();
+        let terrain_sampler = world.resource::<TerrainSampler>();
+        let material = create_terrain_material(settings, terrain_sampler);
+
+        Self(
+            world
+                .resource_mut::<Assets<TerrainMaterial>>()
+                .add(material),
+        )
+    }
+}
+
+fn create_terrain_material(
+    settings: &TerrainMaterialSettings,
+    terrain_sampler: &TerrainSampler,
+) -> TerrainMaterial {
+    ExtendedMaterial {
+        base: StandardMaterial {
+            perceptual_roughness: settings.perceptual_roughness,
+            ..default()
+        },
+        extension: TerrainMaterialExtension {
+            uniform: TerrainMaterialUniform::new(settings, terrain_sampler),
+        },
+    }
+}
+
+fn update_terrain_material(
+    settings: Res<TerrainMaterialSettings>,
+    terrain_sampler: Res<TerrainSampler>,
+    material_handle: Res<TerrainMaterialHandle>,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+) {
+    if let Some(material) = materials.get_mut(&material_handle.0) {
+        *material = create_terrain_material(&settings, &terrain_sampler);
+    }
+}
+
+/// Keeps the LOD morph in step with the camera, chunks are picked the same way in `spawn_chunks`.
+fn update_terrain_lod_morph(
+    camera: Query<(&Projection, &Transform), With<GameplayCamera>>,
+    desired_surface_area: Res<DesiredSurfaceArea>,
+    material_handle: Res<TerrainMaterialHandle>,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+) {
+    let Ok((Projection::Perspective(projection), transform)) = camera.get_single() else {
+        return;
+    };
+
+    // a chunk is replaced once its parent, twice its size, is small enough on screen
+    let camera_position = transform.translation;
+    let lod_distance_scale = 2.0 / (desired_surface_area.0 * (projection.fov / 2.0).tan());
+
+    // every change uploads the material again, small camera moves barely change the morph
+    let is_outdated = materials.get(&material_handle.0).is_some_and(|material| {
+        material
+            .extension
+            .uniform
+            .is_lod_morph_outdated(camera_position, lod_distance_scale)
+    });
+    if !is_outdated {
+        return;
+    }
+
+    if let Some(material) = materials.get_mut(&material_handle.0) {
+        material.extension.uniform.camera_position = camera_position;
+        material.extension.uniform.lod_distance_scale = lod_distance_scale;
+    }
+}
+
+/// Adds [`ATTRIBUTE_LOD_MORPH`] to a chunk mesh, the parent heights come from the chunk's own samples.
+pub(super) fn insert_lod_morph_attribute(mesh: &mut Mesh, chunk: &Chunk) {
+    let Some(heights) = mesh
+        .attribute(Mesh::ATTRIBUTE_POSITION)
+        .and_then(|positions| positions.as_float3())
+        .map(|positions| positions.iter().map(|position| position[1]).collect_vec())
+    else {
+        return;
+    };
+
+    // root chunks have no parent to morph into
+    let parent_heights = if chunk.key.lod < CHUNK_MAX_LOD {
+        utils::primitives::coarser_plane_heights(chunk.subdivisions, &heights)
+    } else {
+        heights
+    };
+
+    let lod_morph = parent_heights
+        .into_iter()
+        .map(|height| [height, chunk.size()])
+        .collect_vec();
+    mesh.insert_attribute(ATTRIBUTE_LOD_MORPH, lod_morph);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layer_color_follows_slope_and_height() {
+        let settings = TerrainMaterialSettings::default();
+        let color = |color: Color| LinearRgba::from(color);
+
+        assert_eq!(settings.layer_color(0.0, 0.0), color(settings.grass_color));
+        assert_eq!(settings.layer_color(0.25, 0.0), color(settings.dirt_color));
+        assert_eq!(settings.layer_color(1.0, 0.0), color(settings.rock_color));
+        assert_eq!(settings.layer_color(0.0, 1.0), color(settings.snow_color));
+        // snow does not stick to cliffs
+        assert_eq!(settings.layer_color(1.0, 1.0), color(settings.rock_color));
+    }
+
+    #[test]
+    fn lod_morph_is_uploaded_past_a_camera_step() {
+        let mut uniform = TerrainMaterialUniform::new(
+            &TerrainMaterialSettings::default(),
+            &TerrainSampler::default(),
+        );
+        uniform.lod_distance_scale = 4.0;
+        let morph_distance =
+            CHUNK_MIN_SIZE * 4.0 * (uniform.lod_morph_end - uniform.lod_morph_start);
+        let step = morph_distance * LOD_MORPH_CAMERA_STEP;
+
+        assert!(!uniform.is_lod_morph_outdated(Vec3::ZERO, 4.0));
+        assert!(!uniform.is_lod_morph_outdated(Vec3::X * step * 0.9, 4.0));
+        assert!(uniform.is_lod_morph_outdated(Vec3::X * step * 1.1, 4.0));
+        assert!(uniform.is_lod_morph_outdated(Vec3::ZERO, 2.0));
+    }
+}
@@ -1,176 +1,484 @@
-// #[cfg(feature = "dev")]
-// pub(crate) mod devtools;
+mod biome;
+mod collision;
+mod density;
+#[cfg(feature = "dev")]
+pub(crate) mod devtools;
+mod erosion;
+mod gpu;
+mod material;
+pub mod preset;
 
 use std::iter;
 
 use crate::prelude::*;
-use avian3d::prelude::{Collider, CollidingEntities, CollisionLayers};
-use bevy::{ecs::system::SystemParam, render::render_asset::RenderAssetUsages};
+use bevy::{
+    render::render_asset::RenderAssetUsages,
+    tasks::{block_on, poll_once, AsyncComputeTaskPool, Task},
+    utils::{HashMap, HashSet},
+};
 use camera::GameplayCamera;
-use utils::noise::{self, SimpleHasher};
+use serde::Deserialize;
+
+pub use biome::{Biome, BiomeLayers, BiomeWeights, Climate};
+pub use density::{CaveSettings, CAVE_CELL_SIZE};
+pub use erosion::{ErodedTerrain, ErodedTerrainChanged, ErosionSettings};
+use utils::{
+    noise::{
+        self, AnyHasher, HasherKind, NoiseGraph, NoiseHasher, NoiseKind, NoiseLayer, SimpleHasher,
+    },
+    primitives::EdgeLodDeltas,
+};
+
+/// Vertices per chunk side, quads per side have to be even to stitch with coarser neighbours.
+const CHUNK_SUBDIVISIONS: u32 = 17;
+/// Size of the smallest chunk, chunks at LOD `n` are `2^n` times bigger.
+const CHUNK_MIN_SIZE: f32 = 16.0;
+/// LOD of the quadtree roots.
+const CHUNK_MAX_LOD: u8 = 10;
+/// Amount of root chunks spawned around the camera in each direction.
+const CHUNK_ROOTS_RADIUS: i32 = 1;
+
+/// Stable identity of a chunk, a LOD level and a position on the grid of that level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect)]
+pub struct ChunkKey {
+    pub lod: u8,
+    pub position: IVec2,
+}
 
-use super::physics::CollisionLayersExt;
+impl ChunkKey {
+    pub fn new(lod: u8, position: IVec2) -> Self {
+        Self { lod, position }
+    }
 
-const CHUNK_SUBDIVISIONS: u32 = 4;
+    pub fn from_world_pos(lod: u8, pos: Vec2) -> Self {
+        Self::new(lod, (pos / Self::lod_size(lod)).floor().as_ivec2())
+    }
 
-#[derive(Component, Clone, Copy, Debug, Reflect)]
+    pub fn lod_size(lod: u8) -> f32 {
+        CHUNK_MIN_SIZE * (1_u32 << lod) as f32
+    }
+
+    pub fn size(&self) -> f32 {
+        Self::lod_size(self.lod)
+    }
+
+    pub fn min(&self) -> Vec2 {
+        self.position.as_vec2() * self.size()
+    }
+
+    pub fn max(&self) -> Vec2 {
+        self.min() + self.size()
+    }
+
+    pub fn center(&self) -> Vec2 {
+        self.min() + self.size() / 2.0
+    }
+
+    pub fn parent(&self) -> Self {
+        Self::new(self.lod + 1, self.position.div_euclid(IVec2::splat(2)))
+    }
+
+    /// Panics at LOD 0, there is nothing smaller to subdivide into.
+    pub fn children(&self) -> [Self; 4] {
+        assert!(self.lod > 0, "cannot subdivide chunk at lod 0");
+        let position = self.position * 2;
+        [IVec2::ZERO, IVec2::X, IVec2::Y, IVec2::ONE]
+            .map(|offset| Self::new(self.lod - 1, position + offset))
+    }
+
+    pub fn neighbour(&self, direction: IVec2) -> Self {
+        Self::new(self.lod, self.position + direction)
+    }
+
+    pub fn contains_point(&self, point: Vec2) -> bool {
+        let min = self.min();
+        let max = self.max();
+
+        point.x >= min.x && point.x <= max.x && point.y >= min.y && point.y <= max.y
+    }
+
+    pub fn contains_key(&self, other: &Self) -> bool {
+        other.lod <= self.lod
+            && other
+                .position
+                .div_euclid(IVec2::splat(1 << (self.lod - other.lod)))
+                == self.position
+    }
+}
+
+#[derive(Component, Clone, Copy, Debug, PartialEq, Reflect)]
 #[reflect(Component)]
 struct Chunk {
+    key: ChunkKey,
     subdivisions: u32,
-    size: f32,
+    edge_lod_deltas: EdgeLodDeltas,
 }
 
 #[derive(Component, Clone, Copy, Debug, Reflect)]
 #[reflect(Component)]
 struct ChunksContainer;
 
+/// Chunk has no mesh yet or its mesh does not match the [`Chunk`] anymore.
+#[derive(Component, Clone, Copy, Debug, Default, Reflect)]
+#[reflect(Component)]
+struct ChunkMeshOutdated;
+
+/// Mesh being generated in the background, dropping it cancels the generation.
+#[derive(Component)]
+struct ChunkMeshTask(Task<Mesh>);
+
+#[derive(Resource, Reflect, Clone, Copy)]
+#[reflect(Resource)]
+struct ChunkMeshBudget {
+    tasks_per_frame: usize,
+}
+
+/// Where chunk heights are sampled, the gpu backend falls back to the cpu when it stops responding.
+/// Terrain graphs the gpu can't sample are always sampled on the cpu.
+#[derive(Resource, Reflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[reflect(Resource)]
+pub enum TerrainSampleBackend {
+    Cpu,
+    #[default]
+    Gpu,
+}
+
 impl Chunk {
-    fn new(subdivisions: u32, size: f32) -> Self {
-        Self { subdivisions, size }
+    fn new(key: ChunkKey, subdivisions: u32, edge_lod_deltas: EdgeLodDeltas) -> Self {
+        Self {
+            key,
+            subdivisions,
+            edge_lod_deltas,
+        }
+    }
+
+    fn size(&self) -> f32 {
+        self.key.size()
     }
+}
+
+/// Spawned chunks by their key, chunks are only respawned when their key leaves the quadtree.
+/// Chunks that left it are retiring, they stay rendered until chunks replacing them are meshed.
+#[derive(Resource, Debug, Default, Reflect)]
+#[reflect(Resource)]
+pub struct ChunkRegistry {
+    chunks: HashMap<ChunkKey, Entity>,
+    retiring: HashMap<ChunkKey, Entity>,
+}
 
-    fn contains_point(&self, chunk_transform: &Transform, point: Vec2) -> bool {
-        let size_half = Vec2::splat(self.size / 2.0);
-        let min = chunk_transform.translation.xz() - size_half;
-        let max = chunk_transform.translation.xz() + size_half;
+impl ChunkRegistry {
+    pub fn get(&self, key: &ChunkKey) -> Option<Entity> {
+        self.chunks.get(key).copied()
+    }
 
-        point.x >= min.x && point.x <= max.x && point.y >= min.y && point.y <= max.y
+    pub fn contains(&self, key: &ChunkKey) -> bool {
+        self.chunks.contains_key(key)
     }
 
-    fn contains_chunk(&self, chunk_transform: &Transform, other_transform: &Transform) -> bool {
-        self.contains_point(chunk_transform, other_transform.translation.xz())
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&ChunkKey, &Entity)> {
+        self.chunks.iter()
+    }
+
+    pub fn iter_retiring(&self) -> impl Iterator<Item = (&ChunkKey, &Entity)> {
+        self.retiring.iter()
+    }
+
+    /// Active chunks overlapping `key`, its ancestors and descendants.
+    pub fn iter_overlapping(&self, key: ChunkKey) -> impl Iterator<Item = (&ChunkKey, &Entity)> {
+        self.chunks
+            .iter()
+            .filter(move |(other, _)| key.contains_key(other) || other.contains_key(&key))
+    }
+
+    fn insert(&mut self, key: ChunkKey, entity: Entity) -> Option<Entity> {
+        self.chunks.insert(key, entity)
+    }
+
+    fn retire(&mut self, key: &ChunkKey) -> Option<Entity> {
+        let entity = self.chunks.remove(key)?;
+        self.retiring.insert(*key, entity);
+        Some(entity)
+    }
+
+    /// Brings back a retiring chunk, if its key is part of the quadtree again.
+    fn revive(&mut self, key: &ChunkKey) -> Option<Entity> {
+        let entity = self.retiring.remove(key)?;
+        self.chunks.insert(*key, entity);
+        Some(entity)
+    }
+
+    fn remove_retiring(&mut self, key: &ChunkKey) -> Option<Entity> {
+        self.retiring.remove(key)
+    }
+
+    fn clear(&mut self) {
+        self.chunks.clear();
+        self.retiring.clear();
+    }
+}
+
+/// Leaf covering the area of `key`, it is either `key` itself or one of its ancestors.
+fn find_covering_leaf(leaves: &HashSet<ChunkKey>, key: ChunkKey) -> Option<ChunkKey> {
+    iter::successors(Some(key), |key| {
+        (key.lod < CHUNK_MAX_LOD).then(|| key.parent())
+    })
+    .find(|key| leaves.contains(key))
+}
+
+/// Subdivides leaves until every leaf neighbours leaves at most one LOD coarser.
+fn balance_chunk_leaves(leaves: &mut HashSet<ChunkKey>) {
+    let mut keys_to_check = leaves.iter().copied().collect_vec();
+
+    while let Some(key) = keys_to_check.pop() {
+        if !leaves.contains(&key) {
+            continue;
+        }
+
+        for direction in [IVec2::NEG_Y, IVec2::X, IVec2::Y, IVec2::NEG_X] {
+            let Some(neighbour) = find_covering_leaf(leaves, key.neighbour(direction)) else {
+                continue;
+            };
+
+            if neighbour.lod > key.lod + 1 {
+                leaves.remove(&neighbour);
+                for child in neighbour.children() {
+                    leaves.insert(child);
+                    keys_to_check.push(child);
+                }
+                // neighbour could still be too coarse
+                keys_to_check.push(key);
+            }
+        }
+    }
+}
+
+fn chunk_edge_lod_deltas(leaves: &HashSet<ChunkKey>, key: ChunkKey) -> EdgeLodDeltas {
+    let delta = |direction: IVec2| {
+        find_covering_leaf(leaves, key.neighbour(direction))
+            .map_or(0, |neighbour| neighbour.lod.saturating_sub(key.lod))
+    };
+
+    EdgeLodDeltas {
+        north: delta(IVec2::NEG_Y),
+        east: delta(IVec2::X),
+        south: delta(IVec2::Y),
+        west: delta(IVec2::NEG_X),
     }
 }
 
 #[derive(Resource, Debug, Clone, Reflect)]
 #[reflect(Resource)]
 pub struct TerrainSampler {
-    weights: Vec<TerrainWeight>,
+    climate: Climate,
+    biomes: Vec<BiomeLayers>,
+    /// Hasher of every noise of the terrain, climate included.
+    hasher: HasherKind,
 }
 
-#[derive(Resource, Reflect, Clone, Copy)]
+/// Height of the terrain at a point and the biomes the point belongs to.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TerrainSample {
+    pub height: noise::Value2Dt1,
+    pub biomes: BiomeWeights,
+}
+
+/// Seed of the world, chosen on the title screen.
+/// Procedural systems never use it directly, they derive their own seeds with [`BaseSeed::hasher`].
+#[derive(Resource, Reflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
 #[reflect(Resource)]
-pub struct BaseSeed(u32);
+pub struct BaseSeed(pub u32);
+
+/// Procedural systems, each gets its own chain of sub-seeds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeedDomain {
+    Terrain,
+    Foliage,
+    Props,
+    Climate,
+    Erosion,
+    Caves,
+}
+
+impl BaseSeed {
+    pub fn random() -> Self {
+        Self(rand::random())
+    }
+
+    /// Hasher the sub-seeds of `domain` are derived from, every domain gets its own chain.
+    pub fn domain_hasher(&self, domain: SeedDomain) -> SimpleHasher {
+        SimpleHasher::new(SimpleHasher::new(self.0).hash(domain as u32))
+    }
+
+    /// Hasher of the `index`th layer or instance of `domain`,
+    /// the `index`th [`NoiseHasher::with_next_seed`] of its domain hasher.
+    /// Indices are walked one seed at a time, they are meant to stay small.
+    pub fn hasher(&self, domain: SeedDomain, index: u32) -> SimpleHasher {
+        let mut hasher = self.domain_hasher(domain).with_next_seed();
+        for _ in 0..index {
+            hasher = hasher.with_next_seed();
+        }
+        hasher
+    }
+
+    /// Hashers of the terrain noise graphs, by the seed offset of their nodes.
+    pub fn terrain_seeds(&self, hasher: HasherKind) -> impl Fn(u32) -> AnyHasher {
+        let base_seed = *self;
+        move |seed_offset| hasher.hasher(base_seed.hasher(SeedDomain::Terrain, seed_offset).seed())
+    }
+}
 
 #[derive(Resource, Reflect, Clone, Copy)]
 #[reflect(Resource)]
 struct DesiredSurfaceArea(f32);
 
+/// Used until the terrain preset is loaded, mirrors `terrain/default.terrain.ron`.
 impl Default for TerrainSampler {
     fn default() -> Self {
         Self {
-            weights: TerrainWeight::from_vec(vec![
-                (2000.0, 1000.0, 0.2),
-                (1000.0, 1000.0, 0.9),
-                (500.0, 500.0, 0.25),
-            ]),
+            climate: Climate::default(),
+            biomes: biome::default_biomes(),
+            hasher: HasherKind::default(),
         }
     }
 }
 
 impl TerrainSampler {
-    pub fn sample(&self, pos: Vec2, base_seed: &BaseSeed) -> noise::Value2Dt1 {
-        TerrainWeight::sample_many(pos, base_seed, self.weights.iter()).0
-    }
-    pub fn max_height(&self) -> f32 {
-        self.weights.iter().map(|w| w.amplitude).sum()
+    pub fn new(climate: Climate, biomes: Vec<BiomeLayers>, hasher: HasherKind) -> Self {
+        Self {
+            climate,
+            biomes,
+            hasher,
+        }
     }
-}
-
-#[derive(Debug, Reflect, Clone, Copy)]
-pub struct TerrainWeight {
-    size: f32,
-    amplitude: f32,
-    erosion: f32,
-    seed_offset: u32,
-}
 
-impl TerrainWeight {
-    pub fn new(size: f32, amplitude: f32, erosion: f32, seed_offset: u32) -> Self {
-        Self {
-            size,
-            amplitude,
-            erosion,
-            seed_offset,
+    /// Heights of the biomes blended by their weights, derivatives included.
+    pub fn sample(&self, pos: Vec2, base_seed: &BaseSeed) -> TerrainSample {
+        if self.biomes.is_empty() {
+            return TerrainSample::default();
         }
+
+        let terrain_seeds = base_seed.terrain_seeds(self.hasher);
+        let climate = self.climate.sample(pos, base_seed, self.hasher);
+        let influences = biome::biome_influences(&self.climate, &climate, &self.biomes);
+
+        self.blend_biomes(influences, |index| {
+            self.biomes[index]
+                .height
+                .sample(pos, &terrain_seeds)
+                .to_dt1()
+        })
     }
 
-    pub fn from_vec(weights: Vec<(f32, f32, f32)>) -> Vec<Self> {
-        weights
+    /// Heights of [`TerrainSampler::sample`] on a grid of `n` by `n` points spaced by `step`
+    /// from `origin`, row by row.
+    /// Noise lattice hashes and fades are shared by the points of a row or a column,
+    /// the heights are identical to sampling every point on its own.
+    pub fn sample_grid(
+        &self,
+        origin: Vec2,
+        step: f32,
+        n: u32,
+        base_seed: &BaseSeed,
+        out: &mut [noise::Value2Dt1],
+    ) {
+        let count = (n * n) as usize;
+        assert_eq!(out.len(), count);
+        if self.biomes.is_empty() {
+            out.fill(noise::Value2Dt1::default());
+            return;
+        }
+
+        let terrain_seeds = base_seed.terrain_seeds(self.hasher);
+        let [temperature, moisture] =
+            self.climate
+                .sample_grid(origin, step, n, base_seed, self.hasher);
+        let influences = temperature
             .into_iter()
+            .zip(moisture)
+            .map(|(temperature, moisture)| {
+                biome::biome_influences(&self.climate, &[temperature, moisture], &self.biomes)
+            })
+            .collect_vec();
+
+        let heights = self
+            .biomes
+            .iter()
             .enumerate()
-            .map(|(seed_offset, (size, amplitude, erosion))| {
-                Self::new(size, amplitude, erosion, seed_offset as u32)
+            .map(|(index, biome)| {
+                let mut heights = vec![noise::Value2Dt2::default(); count];
+                // biomes far away from the whole grid are skipped like in `sample`
+                if influences
+                    .iter()
+                    .any(|influences| influences[index].value != 0.0)
+                {
+                    let size = UVec2::splat(n);
+                    biome
+                        .height
+                        .sample_grid(origin, step, size, &terrain_seeds, &mut heights);
+                }
+                heights
             })
-            .collect_vec()
-    }
-
-    pub fn sample(&self, pos: Vec2, base_seed: &BaseSeed) -> noise::Value2Dt2 {
-        let hasher = SimpleHasher::new(self.seed_offset + base_seed.0);
-
-        (utils::noise::perlin_noise_2d(pos, 1.0 / self.size, &hasher) / 2.0 + 0.5) * self.amplitude
-    }
-
-    // fn sample_erosion_base(
-    //     &self,
-    //     hasher: &impl noise::NoiseHasher,
-    //     pos: Vec2,
-    //     // t(x,y)
-    //     erosion_factor: noise::Value2Dt1,
-    // ) -> (noise::Value2Dt1, noise::Value2Dt1) {
-    //     // f(x,y)
-    //     let layer = self.sample(hasher, pos);
-    //     // s(x,y)
-    //     let layer_steepiness = layer.dt_length();
-    //
-    //     // d(x,y)
-    //     let pre_erosion_factor = erosion_factor + layer_steepiness;
-    //     // v(x,y)
-    //     let factor = 1.0 + self.erosion * pre_erosion_factor;
-    //     // p(x,y)
-    //     let eroded_layer = layer.to_dt1() / factor;
-    //
-    //     // if you dont have enough derivatives it might be possible to calculate them
-    //     let eroded_layer_steepiness = Value2Dt1::new(
-    //         eroded_layer.dt_length(),
-    //         layer.d1.0 / factor.value
-    //             - self.erosion
-    //                 * layer.value
-    //                 * (2.0 * layer.d1.0.y * layer.d2.zy() + 2.0 * layer.d1.0.x * layer.d2.xz())
-    //                 / (2.0 * layer_steepiness.value * factor.value.powi(2)),
-    //     );
-    //     (eroded_layer, eroded_layer_steepiness)
-    // }
-
-    fn sample_erosion_base(
-        &self,
-        pos: Vec2,
-        erosion_factor: f32,
-        base_seed: &BaseSeed,
-    ) -> (noise::Value2Dt1, f32) {
-        let layer = self.sample(pos, base_seed);
-        let layer_steepiness = layer.dt_length();
+            .collect_vec();
 
-        let pre_erosion_factor = erosion_factor + layer_steepiness;
-        let v = 1.0 + self.erosion * pre_erosion_factor;
-        (layer.to_dt1() / v, (layer.to_dt1() / v.value).dt_length())
+        for (point, (out, influences)) in out.iter_mut().zip(influences).enumerate() {
+            *out = self
+                .blend_biomes(influences, |index| heights[index][point].to_dt1())
+                .height;
+        }
     }
 
-    pub fn sample_many<'a>(
-        pos: Vec2,
-        base_seed: &BaseSeed,
-        weights: impl Iterator<Item = &'a Self>,
-    ) -> (noise::Value2Dt1, f32) {
-        let mut erosion_factor = 0.0;
-        let mut terrain = noise::Value2Dt1::default();
-
-        for w in weights {
-            let (layer, layer_steepiness) = w.sample_erosion_base(pos, erosion_factor, base_seed);
-            terrain = terrain + layer;
-            erosion_factor = layer_steepiness;
+    /// Blends the heights of the biomes by their influences, `height` is only called for the
+    /// biomes with an influence.
+    fn blend_biomes(
+        &self,
+        influences: Vec<noise::Value2Dt1>,
+        height: impl Fn(usize) -> noise::Value2Dt1,
+    ) -> TerrainSample {
+        let total = influences
+            .iter()
+            .fold(noise::Value2Dt1::default(), |total, influence| {
+                total + *influence
+            });
+
+        let mut sample = TerrainSample::default();
+        for (index, (biome, influence)) in self.biomes.iter().zip_eq(influences).enumerate() {
+            sample
+                .biomes
+                .add(biome.biome, influence.value / total.value);
+            // far away biomes underflow to nothing
+            if influence.value == 0.0 {
+                continue;
+            }
+
+            sample.height = sample.height + influence * height(index);
         }
+        sample.height = sample.height / total;
+
+        sample
+    }
+
+    pub fn max_height(&self) -> f32 {
+        self.biomes
+            .iter()
+            .map(BiomeLayers::max_height)
+            .fold(0.0, f32::max)
+    }
 
-        (terrain, erosion_factor)
+    /// The gpu only samples biomes made of a single layer stack, hashed by a hasher with a WGSL mirror.
+    fn samples_on_gpu(&self) -> bool {
+        self.hasher.has_wgsl_mirror()
+            && self
+                .biomes
+                .iter()
+                .all(|biome| biome.height.as_layers().is_some())
     }
 }
 
@@ -197,22 +505,42 @@ impl TerrainWeight {
 // }
 
 pub fn plugin(app: &mut App) {
-    app.insert_resource(BaseSeed(0))
+    app.register_type::<(ChunkRegistry, ChunkMeshBudget, TerrainSampleBackend)>()
+        .insert_resource(BaseSeed::random())
         .init_resource::<TerrainSampler>()
+        .init_resource::<ChunkRegistry>()
         .insert_resource(DesiredSurfaceArea(1.0))
+        .insert_resource(ChunkMeshBudget { tasks_per_frame: 8 })
+        .init_resource::<TerrainSampleBackend>()
         .add_systems(OnEnter(GameState::Playing), setup_chunk_container)
+        .add_systems(OnExit(GameState::Playing), clear_chunk_registry)
         .add_systems(
             Update,
             (
                 spawn_chunks.in_set(GameSet::RecordInput),
-                (render_chunks, add_colliders_to_chunks)
+                (
+                    apply_chunk_meshes,
+                    gpu::receive_gpu_batch,
+                    despawn_retired_chunks,
+                    start_chunk_mesh_tasks.run_if(not(samples_on_gpu)),
+                    gpu::start_gpu_batch.run_if(samples_on_gpu),
+                )
                     .chain()
                     .in_set(GameSet::UpdateDataLayer),
-                remove_colliders_from_chunks.in_set(GameSet::UpdateDataLayer),
             )
                 .run_if(in_state(GameState::Playing)),
         );
 
+    // submodules read the terrain resources while building
+    app.add_plugins((
+        collision::plugin,
+        density::plugin,
+        gpu::plugin,
+        erosion::plugin,
+        material::plugin,
+        preset::plugin,
+    ));
+
     // app.configure_sets(
     //     OnEnter(GameState::Playing),
     //     (
@@ -308,54 +636,55 @@ pub fn plugin(app: &mut App) {
 
 #[derive(Debug, Clone, Copy)]
 struct ChunkNode {
+    key: ChunkKey,
     center: Vec3,
-    half_size: f32,
 }
 
 impl ChunkNode {
-    fn root(center: Vec3, size: f32) -> Self {
-        Self::new(center, size / 2.0)
-    }
+    fn new(key: ChunkKey, terrain: &TerrainSampler, base_seed: &BaseSeed) -> Self {
+        let center_2d = key.center();
+        let center = vec3(
+            center_2d.x,
+            terrain.sample(center_2d, base_seed).height.value,
+            center_2d.y,
+        );
 
-    fn new(center: Vec3, half_size: f32) -> Self {
-        Self { center, half_size }
+        Self { key, center }
     }
 
     fn subdivide(self, terrain: &TerrainSampler, base_seed: &BaseSeed) -> [Self; 4] {
-        let mut result = Vec::with_capacity(4);
-        let directions = [
-            vec2(1.0, 1.0),
-            vec2(1.0, -1.0),
-            vec2(-1.0, 1.0),
-            vec2(-1.0, -1.0),
-        ];
-
-        for direction in directions {
-            let center_2d = self.center.xz() + direction * self.half_size / 2.0;
-            let center = vec3(
-                center_2d.x,
-                terrain.sample(center_2d, base_seed).value,
-                center_2d.y,
-            );
-
-            let half_size = self.half_size / 2.0;
-
-            result.push(Self::new(center, half_size));
-        }
+        let children = self.key.children();
+        // the children centers are a 2 by 2 grid in the same order
+        let mut heights = [noise::Value2Dt1::default(); 4];
+        terrain.sample_grid(
+            children[0].center(),
+            children[0].size(),
+            2,
+            base_seed,
+            &mut heights,
+        );
 
-        result.try_into().unwrap()
+        let mut heights = heights.into_iter();
+        children.map(|key| {
+            let center = key.center();
+            let height = heights.next().unwrap().value;
+            Self {
+                key,
+                center: vec3(center.x, height, center.y),
+            }
+        })
     }
+}
 
-    fn as_chunk(&self) -> (Chunk, Transform) {
-        let size = self.half_size * 2.0;
-        (
-            Chunk::new(CHUNK_SUBDIVISIONS, size),
-            Transform::from_translation(self.center),
-        )
-    }
+fn samples_on_gpu(
+    backend: Res<TerrainSampleBackend>,
+    terrain_sampler: Res<TerrainSampler>,
+) -> bool {
+    *backend == TerrainSampleBackend::Gpu && terrain_sampler.samples_on_gpu()
 }
 
-fn setup_chunk_container(mut commands: Commands) {
+fn setup_chunk_container(mut commands: Commands, mut chunk_registry: ResMut<ChunkRegistry>) {
+    chunk_registry.clear();
     commands.spawn((
         Name::new("Chunks"),
         SpatialBundle::default(),
@@ -364,10 +693,15 @@ fn setup_chunk_container(mut commands: Commands) {
     ));
 }
 
+fn clear_chunk_registry(mut chunk_registry: ResMut<ChunkRegistry>) {
+    chunk_registry.clear();
+}
+
 fn spawn_chunks(
     mut commands: Commands,
-    chunks_container: Query<(Entity, Option<&Children>), With<ChunksContainer>>,
-    existing_chunks: Query<(Entity, &Chunk, &Transform)>,
+    chunks_container: Query<Entity, With<ChunksContainer>>,
+    mut chunk_registry: ResMut<ChunkRegistry>,
+    existing_chunks: Query<&Chunk>,
     camera: Query<(&Projection, &Transform), With<GameplayCamera>>,
     base_seed: Res<BaseSeed>,
     terrain_sampler: Res<TerrainSampler>,
@@ -388,201 +722,209 @@ fn spawn_chunks(
         return;
     };
 
+    let Ok(container_entity) = chunks_container.get_single() else {
+        error!("No chunks container found");
+        return;
+    };
+
     let fov_2_tan = (fov / 2.0).tan();
-    let ground_at_camera = terrain_sampler.sample(camera_position.xz(), &base_seed);
-    let ground_position = vec3(camera_position.x, ground_at_camera.value, camera_position.z);
+    let root_key = ChunkKey::from_world_pos(CHUNK_MAX_LOD, camera_position.xz());
+
+    let mut desired_keys = HashSet::new();
+    let mut nodes_to_check = (-CHUNK_ROOTS_RADIUS..=CHUNK_ROOTS_RADIUS)
+        .cartesian_product(-CHUNK_ROOTS_RADIUS..=CHUNK_ROOTS_RADIUS)
+        .map(|(x, y)| {
+            let key = ChunkKey::new(CHUNK_MAX_LOD, root_key.position + ivec2(x, y));
+            ChunkNode::new(key, &terrain_sampler, &base_seed)
+        })
+        .collect_vec();
+
+    while let Some(node) = nodes_to_check.pop() {
+        let distance = node.center.distance(camera_position);
+        let projected_size = node.key.size() / (distance * fov_2_tan);
+
+        // subdivide until desired surface area is reached
+        if node.key.lod > 0 && projected_size > desired_surface_area.0 {
+            nodes_to_check.extend(node.subdivide(&terrain_sampler, &base_seed));
+        } else {
+            desired_keys.insert(node.key);
+        }
+    }
 
-    let distance_to_ground = (camera_position.y - ground_at_camera.value).abs();
+    balance_chunk_leaves(&mut desired_keys);
 
-    #[cfg(feature = "dev")]
-    {
-        assert!(distance_to_ground > 0.0, "{distance_to_ground}");
-        assert!(!chunks_container.is_empty(), "no chunks containers");
+    // retire chunks that are no longer part of the quadtree
+    let removed_keys = chunk_registry
+        .iter()
+        .map(|(key, _)| *key)
+        .filter(|key| !desired_keys.contains(key))
+        .collect_vec();
+
+    for key in removed_keys.iter() {
+        chunk_registry.retire(key);
     }
 
-    for (container_entity, container_children) in chunks_container.iter() {
-        let container_chunks = container_children.map(|children| {
-            children
-                .iter()
-                .filter_map(|child| existing_chunks.get(*child).ok())
-        });
+    let mut new_chunks = Vec::new();
+    for key in desired_keys.iter().copied() {
+        let chunk = Chunk::new(
+            key,
+            CHUNK_SUBDIVISIONS,
+            chunk_edge_lod_deltas(&desired_keys, key),
+        );
 
-        let mut result_nodes = Vec::with_capacity(4);
-        let mut nodes_to_check = ChunkNode::root(ground_position, distance_to_ground * fov_2_tan)
-            .subdivide(&terrain_sampler, &base_seed)
-            .to_vec();
-
-        while let Some(node) = nodes_to_check.pop() {
-            let distance = node.center.distance(camera_position);
-            let projected_size = node.half_size * 2.0 / (distance * fov_2_tan);
-
-            // subdivide until desired surface area is reached
-            if projected_size > desired_surface_area.0 {
-                nodes_to_check.extend(node.subdivide(&terrain_sampler, &base_seed));
-            } else {
-                // then try to create chunk
-                let chunk = node.as_chunk();
-
-                if let Some(container_chunks) = container_chunks.clone() {
-                    // check if exact chunk is already in container, skip if it is
-                    for (_, _, transform) in container_chunks {
-                        if (chunk.1.translation.xz() - transform.translation.xz()).element_sum()
-                            < 0.01
-                        {
-                            info!("Skipping");
-                            continue;
-                        }
-                    }
+        match chunk_registry
+            .get(&key)
+            .or_else(|| chunk_registry.revive(&key))
+        {
+            // neighbours changed LOD, mesh has to be stitched again
+            Some(entity) => {
+                if existing_chunks
+                    .get(entity)
+                    .is_ok_and(|existing| existing.edge_lod_deltas != chunk.edge_lod_deltas)
+                {
+                    commands.entity(entity).insert((chunk, ChunkMeshOutdated));
                 }
-
-                info!("Pushing");
-                result_nodes.push(chunk);
             }
+            None => new_chunks.push(chunk),
         }
+    }
 
-        // despawn replaced chunks
-        if let Some(container_chunks) = container_chunks {
-            for (entity, chunk, transform) in container_chunks {
-                for (new_chunk, new_transform) in result_nodes.iter() {
-                    let Some(entity_commands) = commands.get_entity(entity) else {
-                        continue;
-                    };
-                    if chunk.contains_chunk(transform, new_transform)
-                        || new_chunk.contains_chunk(new_transform, transform)
-                    {
-                        entity_commands.despawn_recursive();
-                    }
-                }
-            }
-        }
+    if new_chunks.is_empty() {
+        return;
+    }
 
-        // Spawn chunks
-        commands.entity(container_entity).with_children(|commands| {
-            info!("Spawning {} chunks", result_nodes.len());
-            for (chunk, transform) in result_nodes {
-                commands.spawn((
+    commands.entity(container_entity).with_children(|commands| {
+        for chunk in new_chunks {
+            let key = chunk.key;
+            let center = key.center();
+            let entity = commands
+                .spawn((
                     StateScoped(GameState::Playing),
-                    Name::from(format!("Chunk {:.0}", chunk.size)),
+                    Name::from(format!(
+                        "Chunk {} ({}, {})",
+                        key.lod, key.position.x, key.position.y
+                    )),
                     chunk,
+                    ChunkMeshOutdated,
                     SpatialBundle {
-                        transform,
+                        transform: Transform::from_xyz(center.x, 0.0, center.y),
                         ..default()
                     },
-                    CollisionLayers::get_terrain_colliders(),
-                ));
-            }
-        });
-    }
+                ))
+                .id();
+
+            chunk_registry.insert(key, entity);
+        }
+    });
 }
 
-fn render_chunks(
+fn create_chunk_mesh(
+    chunk: &Chunk,
+    terrain_sampler: &TerrainSampler,
+    eroded_terrain: &ErodedTerrain,
+    base_seed: &BaseSeed,
+) -> Mesh {
+    let center = chunk.key.center();
+    let step = chunk.size() / (chunk.subdivisions - 1) as f32;
+    let mut heights = vec![noise::Value2Dt1::default(); chunk.subdivisions.pow(2) as usize];
+    terrain_sampler.sample_grid(
+        center - chunk.size() / 2.0,
+        step,
+        chunk.subdivisions,
+        base_seed,
+        &mut heights,
+    );
+
+    let mut mesh = utils::primitives::create_stitched_plane(
+        chunk.subdivisions,
+        chunk.size(),
+        chunk.edge_lod_deltas,
+        |x, y| {
+            let pos = center + vec2(x, y);
+            let cell = ((vec2(x, y) + chunk.size() / 2.0) / step)
+                .round()
+                .as_uvec2();
+            let height = heights[(cell.y * chunk.subdivisions + cell.x) as usize];
+            eroded_terrain.apply(pos, height).to_mesh_input()
+        },
+    );
+    material::insert_lod_morph_attribute(&mut mesh, chunk);
+
+    mesh.asset_usage = RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD;
+    mesh
+}
+
+fn start_chunk_mesh_tasks(
     mut commands: Commands,
-    chunks: Query<(Entity, &Chunk), Without<Handle<Mesh>>>,
+    chunks: Query<(Entity, &Chunk), With<ChunkMeshOutdated>>,
     base_seed: Res<BaseSeed>,
     terrain_sampler: Res<TerrainSampler>,
-    mut meshes: ResMut<Assets<Mesh>>,
+    eroded_terrain: Res<ErodedTerrain>,
+    budget: Res<ChunkMeshBudget>,
 ) {
-    for (entity, chunk) in chunks.iter() {
-        let mut mesh =
-            utils::primitives::create_subdivided_plane(chunk.subdivisions, chunk.size, |x, y| {
-                terrain_sampler
-                    .sample(vec2(x, y), &base_seed)
-                    .to_mesh_input()
-            });
-
-        mesh.asset_usage = RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD;
-
-        let render_entity = commands
-            .spawn((
-                Name::new("Chunk Render"),
-                PbrBundle {
-                    mesh: meshes.add(mesh),
-                    ..default()
-                },
-            ))
-            .id();
-
-        commands.entity(entity).add_child(render_entity);
-    }
-}
+    let task_pool = AsyncComputeTaskPool::get();
 
-#[derive(SystemParam)]
-struct TerrrainCollidablesQuery<'w, 's> {
-    // TODO: replace Collider with some marker
-    collidables_query:
-        Query<'w, 's, (&'static Transform, &'static CollisionLayers), With<Collider>>,
-}
-
-impl<'w, 's> TerrrainCollidablesQuery<'w, 's> {
-    fn iter(&self) -> impl Iterator<Item = &'_ Transform> {
-        let terrain_collision_layers = CollisionLayers::get_terrain_colliders();
-        self.collidables_query
-            .iter()
-            .filter_map(move |(transform, layers)| {
-                layers
-                    .interacts_with(terrain_collision_layers)
-                    .then_some(transform)
-            })
-    }
-
-    fn is_chunk_in_proximity(
-        &self,
-        chunk: &Chunk,
-        chunk_transform: &Transform,
-        radius: f32,
-    ) -> bool {
-        // slow, use quad tree
-        for collidable_transform in self.iter() {
-            let collidable_center = collidable_transform.translation.xz();
-            let points = vec![
-                collidable_center + vec2(radius, radius),
-                collidable_center + vec2(-radius, -radius),
-                collidable_center + vec2(radius, -radius),
-                collidable_center + vec2(-radius, radius),
-            ];
-
-            for point in points {
-                if chunk.contains_point(chunk_transform, point) {
-                    return true;
-                }
-            }
-        }
+    // finer chunks are closer to the camera, mesh them first
+    for (entity, chunk) in chunks
+        .iter()
+        .sorted_by_key(|(_, chunk)| chunk.key.lod)
+        .take(budget.tasks_per_frame)
+    {
+        let chunk = *chunk;
+        let base_seed = *base_seed;
+        let terrain_sampler = TerrainSampler::clone(&terrain_sampler);
+        let eroded_terrain = ErodedTerrain::clone(&eroded_terrain);
+        let task = task_pool.spawn(async move {
+            create_chunk_mesh(&chunk, &terrain_sampler, &eroded_terrain, &base_seed)
+        });
 
-        false
+        // replacing a running task cancels it
+        commands
+            .entity(entity)
+            .remove::<ChunkMeshOutdated>()
+            .insert(ChunkMeshTask(task));
     }
 }
 
-fn add_colliders_to_chunks(
+fn apply_chunk_meshes(
     mut commands: Commands,
-    meshes: Res<Assets<Mesh>>,
-    chunks_query: Query<(Entity, &Chunk, &Handle<Mesh>, &Transform), Without<Collider>>,
-    collidables_query: TerrrainCollidablesQuery,
+    mut tasks: Query<(Entity, &mut ChunkMeshTask)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    material: Res<material::TerrainMaterialHandle>,
 ) {
-    for (entity, chunk, mesh_handle, chunk_transform) in chunks_query.iter() {
-        let Some(mesh) = collidables_query
-            .is_chunk_in_proximity(chunk, chunk_transform, 1.0)
-            .then(|| meshes.get(mesh_handle))
-            .flatten()
-        else {
+    for (entity, mut task) in tasks.iter_mut() {
+        let Some(mesh) = block_on(poll_once(&mut task.0)) else {
             continue;
         };
 
-        let collider = Collider::trimesh_from_mesh(mesh).unwrap();
-        commands.entity(entity).insert(collider);
+        commands
+            .entity(entity)
+            .remove::<ChunkMeshTask>()
+            .insert((meshes.add(mesh), material.0.clone()));
     }
 }
 
-fn remove_colliders_from_chunks(
+/// Despawns retiring chunks once every chunk replacing them has a mesh.
+fn despawn_retired_chunks(
     mut commands: Commands,
-    chunks_query: Query<(Entity, &Chunk, &Transform), With<Collider>>,
-    collidables_query: TerrrainCollidablesQuery,
+    mut chunk_registry: ResMut<ChunkRegistry>,
+    meshed_chunks: Query<(), With<Handle<Mesh>>>,
 ) {
-    for (entity, chunk, chunk_transform) in chunks_query.iter() {
-        let Some(mut entity_commands) = commands.get_entity(entity) else {
-            continue;
-        };
-        if !collidables_query.is_chunk_in_proximity(chunk, chunk_transform, 1.0) {
-            entity_commands.remove::<Collider>();
+    let retired_keys = chunk_registry
+        .iter_retiring()
+        .filter(|(key, entity)| {
+            // without a mesh there is nothing to keep on screen
+            !meshed_chunks.contains(**entity)
+                || chunk_registry
+                    .iter_overlapping(**key)
+                    .all(|(_, replacement)| meshed_chunks.contains(*replacement))
+        })
+        .map(|(key, _)| *key)
+        .collect_vec();
+
+    for key in retired_keys {
+        if let Some(entity) = chunk_registry.remove_retiring(&key) {
+            commands.entity(entity).despawn_recursive();
         }
     }
 }
@@ -590,31 +932,27 @@ fn remove_colliders_from_chunks(
 #[cfg(test)]
 mod tests {
 
+    use bevy::render::mesh::VertexAttributeValues;
+
     use super::*;
 
     #[test]
     fn chunk_contains_point() {
-        let chunk = Chunk::new(2, 10.0);
-        let transform = Transform::from_translation(vec3(3.0, 3.0, 3.0));
-
+        let chunk = Chunk::new(ChunkKey::new(1, ivec2(-1, 2)), 2, default());
+        // 32x32 chunk spanning from (-32, 64) to (0, 96)
         let points_to_contain = vec![
-            vec2(13.0, 13.0),
-            vec2(-7.0, 13.0),
-            vec2(13.0, -7.0),
-            vec2(-7.0, -7.0),
-            vec2(13.0, 13.0),
-            vec2(13.0, -7.0),
-            vec2(-7.0, 13.0),
-            vec2(-7.0, -7.0),
-            vec2(1.5, 1.5),
-            vec2(1.5, -1.5),
-            vec2(-1.5, 1.5),
-            vec2(-1.5, -1.5),
+            vec2(-32.0, 64.0),
+            vec2(0.0, 64.0),
+            vec2(-32.0, 96.0),
+            vec2(0.0, 96.0),
+            vec2(-16.0, 80.0),
+            vec2(-1.5, 65.5),
+            vec2(-30.5, 94.5),
         ];
 
         for point in points_to_contain {
             assert!(
-                chunk.contains_point(&transform, point),
+                chunk.key.contains_point(point),
                 "should contain point: {point:?}"
             );
         }
@@ -622,27 +960,236 @@ mod tests {
 
     #[test]
     fn chunk_not_contain_point() {
-        let chunk = Chunk::new(2, 10.0);
-        let transform = Transform::from_translation(vec3(3.0, 3.0, 3.0));
+        let chunk = Chunk::new(ChunkKey::new(1, ivec2(-1, 2)), 2, default());
 
         let points_not_to_contain = vec![
-            vec2(14.0, 14.0),
-            vec2(-8.0, 14.0),
-            vec2(14.0, -8.0),
-            vec2(-8.0, -8.0),
-            vec2(14.0, 14.0),
-            vec2(14.0, -8.0),
-            vec2(-8.0, 14.0),
-            vec2(-8.0, -8.0),
+            vec2(-33.0, 80.0),
+            vec2(1.0, 80.0),
+            vec2(-16.0, 63.0),
+            vec2(-16.0, 97.0),
+            vec2(16.0, 80.0),
+            vec2(-16.0, -80.0),
         ];
 
         for point in points_not_to_contain {
             assert!(
-                !chunk.contains_point(&transform, point),
+                !chunk.key.contains_point(point),
                 "should not contain point: {point:?}"
             );
         }
     }
+
+    #[test]
+    fn chunk_key_from_world_pos() {
+        let size = ChunkKey::lod_size(2);
+
+        for (pos, expected) in [
+            (vec2(0.0, 0.0), ivec2(0, 0)),
+            (vec2(size - 0.1, 0.1), ivec2(0, 0)),
+            (vec2(size, -0.1), ivec2(1, -1)),
+            (vec2(-size - 0.1, -size), ivec2(-2, -1)),
+        ] {
+            let key = ChunkKey::from_world_pos(2, pos);
+            assert_eq!(key, ChunkKey::new(2, expected), "{pos:?}");
+            assert!(key.contains_point(pos), "{pos:?}");
+        }
+    }
+
+    #[test]
+    fn chunk_key_children_roundtrip() {
+        for position in [ivec2(0, 0), ivec2(-1, 3), ivec2(5, -7)] {
+            let key = ChunkKey::new(3, position);
+
+            for child in key.children() {
+                assert_eq!(child.parent(), key);
+                assert!(key.contains_key(&child));
+                assert!(key.contains_point(child.center()));
+                assert_eq!(child.size() * 2.0, key.size());
+            }
+
+            assert!(!key.contains_key(&ChunkKey::new(3, position + IVec2::X)));
+            assert!(!key.children()[0].contains_key(&key));
+        }
+    }
+
+    #[test]
+    fn chunk_registry_retires_and_revives() {
+        let mut registry = ChunkRegistry::default();
+        let parent = ChunkKey::new(2, ivec2(0, -1));
+        let entity = Entity::from_raw(1);
+        registry.insert(parent, entity);
+        for (i, child) in parent.children().into_iter().enumerate() {
+            registry.insert(child, Entity::from_raw(i as u32 + 2));
+        }
+
+        assert_eq!(registry.retire(&parent), Some(entity));
+        assert!(!registry.contains(&parent));
+        assert_eq!(registry.iter_overlapping(parent).count(), 4);
+        assert_eq!(
+            registry
+                .iter_overlapping(ChunkKey::new(3, ivec2(0, -1)))
+                .count(),
+            4
+        );
+        assert_eq!(
+            registry
+                .iter_overlapping(ChunkKey::new(2, ivec2(1, -1)))
+                .count(),
+            0
+        );
+
+        assert_eq!(registry.revive(&parent), Some(entity));
+        assert_eq!(registry.get(&parent), Some(entity));
+        assert_eq!(registry.iter_retiring().count(), 0);
+    }
+
+    #[test]
+    fn chunk_leaves_are_balanced() {
+        // a single branch subdivided all the way down, next to untouched roots
+        let roots = (-1..=1)
+            .cartesian_product(-1..=1)
+            .map(|(x, y)| ChunkKey::new(CHUNK_MAX_LOD, ivec2(x, y)))
+            .collect_vec();
+        let mut leaves = roots.iter().copied().collect::<HashSet<_>>();
+        let mut key = roots[4];
+        while key.lod > 0 {
+            leaves.remove(&key);
+            let children = key.children();
+            leaves.extend(children);
+            key = children[0];
+        }
+
+        balance_chunk_leaves(&mut leaves);
+
+        for key in leaves.iter() {
+            assert!(
+                chunk_edge_lod_deltas(&leaves, *key).max() <= 1,
+                "{key:?} is not balanced"
+            );
+        }
+        let area = leaves.iter().map(|key| key.size().powi(2)).sum::<f32>();
+        assert_eq!(
+            area,
+            roots.len() as f32 * ChunkKey::lod_size(CHUNK_MAX_LOD).powi(2)
+        );
+    }
+
+    #[test]
+    fn chunk_edge_lod_deltas_point_to_coarser_neighbours() {
+        let coarse = ChunkKey::new(2, ivec2(1, 0));
+        let fine_parent = ChunkKey::new(2, ivec2(0, 0));
+        let mut leaves = HashSet::from_iter([coarse]);
+        leaves.extend(fine_parent.children());
+
+        // children of (0, 0) are (0, 0), (1, 0), (0, 1), (1, 1) at lod 1
+        let east_child = ChunkKey::new(1, ivec2(1, 0));
+        assert_eq!(
+            chunk_edge_lod_deltas(&leaves, east_child),
+            EdgeLodDeltas {
+                east: 1,
+                ..default()
+            }
+        );
+        assert_eq!(
+            chunk_edge_lod_deltas(&leaves, coarse),
+            EdgeLodDeltas::default()
+        );
+    }
+
+    #[test]
+    fn base_seed_sub_seeds_are_distinct() {
+        let base_seed = BaseSeed(7);
+        let seeds = [
+            SeedDomain::Terrain,
+            SeedDomain::Foliage,
+            SeedDomain::Props,
+            SeedDomain::Caves,
+        ]
+        .into_iter()
+        .flat_map(|domain| (0..16).map(move |index| base_seed.hasher(domain, index).seed()))
+        .collect_vec();
+
+        assert!(seeds.iter().all_unique());
+        assert_eq!(
+            base_seed.hasher(SeedDomain::Terrain, 0).seed(),
+            base_seed
+                .domain_hasher(SeedDomain::Terrain)
+                .with_next_seed()
+                .seed()
+        );
+        assert_eq!(
+            base_seed.hasher(SeedDomain::Terrain, 4).seed(),
+            base_seed
+                .hasher(SeedDomain::Terrain, 3)
+                .with_next_seed()
+                .seed()
+        );
+        assert_eq!(
+            base_seed.hasher(SeedDomain::Terrain, 3).seed(),
+            BaseSeed(7).hasher(SeedDomain::Terrain, 3).seed()
+        );
+    }
+
+    #[test]
+    fn same_seed_generates_identical_heightmaps() {
+        let chunk = Chunk::new(
+            ChunkKey::new(2, ivec2(3, -5)),
+            CHUNK_SUBDIVISIONS,
+            default(),
+        );
+        let heightmap = |seed: u32| {
+            let mesh = create_chunk_mesh(
+                &chunk,
+                &TerrainSampler::default(),
+                &ErodedTerrain::default(),
+                &BaseSeed(seed),
+            );
+            let Some(VertexAttributeValues::Float32x3(positions)) =
+                mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+            else {
+                panic!("chunk mesh has no positions");
+            };
+            bytemuck::cast_slice::<[f32; 3], u8>(positions).to_vec()
+        };
+
+        assert_eq!(heightmap(42), heightmap(42));
+        assert_ne!(heightmap(42), heightmap(43));
+    }
+
+    #[test]
+    fn grid_samples_match_point_samples() {
+        let mut biomes = biome::default_biomes();
+        // a graph that is not a layer stack is sampled point by point
+        biomes[1].height = NoiseGraph::new(vec![
+            noise::NoiseNode::Layers(NoiseLayer::from_vec(vec![(2000.0, 1000.0, 0.2)])),
+            noise::NoiseNode::Terrace {
+                input: noise::NodeId(0),
+                steps: 100.0,
+                sharpness: 0.5,
+            },
+        ])
+        .unwrap();
+        let base_seed = BaseSeed(5);
+        let (origin, step, n) = (vec2(-2987.3, 1213.9), 250.0, 17);
+
+        for terrain_sampler in [
+            TerrainSampler::default(),
+            TerrainSampler::new(Climate::default(), biomes, HasherKind::Simple),
+        ] {
+            let mut heights = vec![noise::Value2Dt1::default(); (n * n) as usize];
+            terrain_sampler.sample_grid(origin, step, n, &base_seed, &mut heights);
+
+            let points = (0..n).cartesian_product(0..n);
+            for (height, (y, x)) in heights.into_iter().zip_eq(points) {
+                let pos = origin + vec2(x as f32, y as f32) * step;
+                assert_eq!(
+                    height,
+                    terrain_sampler.sample(pos, &base_seed).height,
+                    "{pos}"
+                );
+            }
+        }
+    }
 }
 
 // fn render_center_changed(center: Res<MapRenderCenter>) -> bool {
@@ -0,0 +1,240 @@
+//! Terrain presets, the climate and biome height graphs of the [`TerrainSampler`] loaded from `.terrain.ron` files.
+//! Presets hot reload with the `file_watcher` feature, live chunks are regenerated on change.
+
+use std::{error::Error, fmt};
+
+use bevy::asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext};
+
+use super::*;
+use crate::game::assets::{HandleMap, TerrainPresetKey};
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(
+        Update,
+        (
+            apply_terrain_preset,
+            mark_chunks_outdated
+                .run_if(resource_changed::<TerrainSampler>.or_else(resource_changed::<BaseSeed>)),
+        )
+            .chain()
+            .in_set(GameSet::UpdateApply),
+    );
+}
+
+#[derive(Asset, TypePath, Debug, Clone, Deserialize)]
+pub struct TerrainPreset {
+    #[serde(default)]
+    pub climate: Climate,
+    pub biomes: Vec<BiomeLayers>,
+    #[serde(default)]
+    pub hasher: HasherKind,
+}
+
+#[derive(Default)]
+pub struct TerrainPresetLoader;
+
+#[derive(Debug)]
+pub enum TerrainPresetLoaderError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+}
+
+impl fmt::Display for TerrainPresetLoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TerrainPresetLoaderError::Io(error) => {
+                write!(f, "could not read terrain preset: {error}")
+            }
+            TerrainPresetLoaderError::Ron(error) => {
+                write!(f, "could not parse terrain preset: {error}")
+            }
+        }
+    }
+}
+
+impl Error for TerrainPresetLoaderError {}
+
+impl From<std::io::Error> for TerrainPresetLoaderError {
+    fn from(error: std::io::Error) -> Self {
+        TerrainPresetLoaderError::Io(error)
+    }
+}
+
+impl From<ron::error::SpannedError> for TerrainPresetLoaderError {
+    fn from(error: ron::error::SpannedError) -> Self {
+        TerrainPresetLoaderError::Ron(error)
+    }
+}
+
+impl AssetLoader for TerrainPresetLoader {
+    type Asset = TerrainPreset;
+    type Settings = ();
+    type Error = TerrainPresetLoaderError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext<'_>,
+    ) -> Result<TerrainPreset, TerrainPresetLoaderError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["terrain.ron"]
+    }
+}
+
+/// Replaces the [`TerrainSampler`] when the preset loads or is modified on disk.
+pub(super) fn apply_terrain_preset(
+    mut events: EventReader<AssetEvent<TerrainPreset>>,
+    presets: Res<Assets<TerrainPreset>>,
+    preset_handles: Res<HandleMap<TerrainPresetKey>>,
+    mut terrain_sampler: ResMut<TerrainSampler>,
+) {
+    let Some(handle) = preset_handles.get(&TerrainPresetKey::Default) else {
+        return;
+    };
+
+    for event in events.read() {
+        let (AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id }) = *event
+        else {
+            continue;
+        };
+        if id != handle.id() {
+            continue;
+        }
+
+        if let Some(preset) = presets.get(id) {
+            *terrain_sampler =
+                TerrainSampler::new(preset.climate, preset.biomes.clone(), preset.hasher);
+        }
+    }
+}
+
+/// Regenerates every live chunk, the terrain or the seed they were sampled from changed.
+fn mark_chunks_outdated(mut commands: Commands, chunks: Query<Entity, With<Chunk>>) {
+    for entity in chunks.iter() {
+        commands.entity(entity).insert(ChunkMeshOutdated);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preset_parses_biomes() {
+        let preset: TerrainPreset = ron::de::from_str(
+            "(
+                biomes: [
+                    (
+                        biome: Desert,
+                        temperature: 0.8,
+                        moisture: 0.2,
+                        height: [
+                            Layers([
+                                (size: 2000.0, amplitude: 1000.0, erosion: 0.2, seed_offset: 0),
+                                (size: 500.0, amplitude: 50.0, erosion: 0.0, noise: Value, seed_offset: 1),
+                            ]),
+                        ],
+                    ),
+                ],
+            )",
+        )
+        .unwrap();
+
+        assert_eq!(preset.climate.size, Climate::default().size);
+        assert_eq!(preset.biomes.len(), 1);
+        let layers = preset.biomes[0].height.as_layers().unwrap();
+        assert_eq!(preset.biomes[0].biome, Biome::Desert);
+        assert_eq!(layers[0].noise, NoiseKind::Perlin);
+        assert_eq!(layers[1].noise, NoiseKind::Value);
+        assert_eq!(layers[1].seed_offset, 1);
+    }
+
+    #[test]
+    fn preset_parses_height_graphs() {
+        let preset: TerrainPreset = ron::de::from_str(
+            "(
+                biomes: [
+                    (
+                        biome: Mountains,
+                        temperature: 0.3,
+                        moisture: 0.3,
+                        height: [
+                            Layers([(size: 2000.0, amplitude: 1000.0, erosion: 0.2, seed_offset: 0)]),
+                            Terrace(input: 0, steps: 0.01, sharpness: 0.8),
+                        ],
+                    ),
+                ],
+            )",
+        )
+        .unwrap();
+
+        let height = &preset.biomes[0].height;
+        assert_eq!(height.nodes().len(), 2);
+        assert!(height.as_layers().is_none());
+        assert!(
+            !TerrainSampler::new(preset.climate, preset.biomes, preset.hasher).samples_on_gpu()
+        );
+    }
+
+    #[test]
+    fn preset_picks_the_hasher() {
+        let preset = |hasher: &str| {
+            let preset: TerrainPreset = ron::de::from_str(&format!(
+                "(
+                    hasher: {hasher},
+                    biomes: [
+                        (
+                            biome: Plains,
+                            temperature: 0.5,
+                            moisture: 0.5,
+                            height: [Layers([(size: 500.0, amplitude: 10.0, erosion: 0.0, seed_offset: 0)])],
+                        ),
+                    ],
+                )"
+            ))
+            .unwrap();
+            TerrainSampler::new(preset.climate, preset.biomes, preset.hasher)
+        };
+
+        assert_eq!(preset("WgslPcg").hasher, HasherKind::WgslPcg);
+        assert!(preset("WgslPcg").samples_on_gpu());
+        // no WGSL mirror, sampled on the cpu
+        assert!(!preset("Xx").samples_on_gpu());
+    }
+
+    #[test]
+    fn preset_rejects_forward_inputs() {
+        let error = ron::de::from_str::<TerrainPreset>(
+            "(
+                biomes: [
+                    (
+                        biome: Plains,
+                        temperature: 0.5,
+                        moisture: 0.5,
+                        height: [Scale(input: 1, factor: 2.0), Constant(1.0)],
+                    ),
+                ],
+            )",
+        )
+        .unwrap_err();
+
+        assert!(error.to_string().contains("reads node 1"), "{error}");
+    }
+
+    #[test]
+    fn default_preset_mirrors_default_biomes() {
+        let preset: TerrainPreset =
+            ron::de::from_str(include_str!("../../../assets/terrain/default.terrain.ron")).unwrap();
+
+        for (loaded, default) in preset.biomes.iter().zip_eq(biome::default_biomes()) {
+            assert_eq!(loaded.biome, default.biome);
+            assert_eq!(loaded.height, default.height, "{:?}", loaded.biome);
+        }
+    }
+}
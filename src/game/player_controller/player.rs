@@ -25,7 +25,7 @@ fn spawn_player(
     terrain_sampler: Res<TerrainSampler>,
     base_seed: Res<BaseSeed>,
 ) {
-    let y = terrain_sampler.sample(Vec2::ZERO, &base_seed).value + 100.0;
+    let y = terrain_sampler.sample(Vec2::ZERO, &base_seed).height.value + 100.0;
     spawn_character(
         &mut commands,
         &asset_server,
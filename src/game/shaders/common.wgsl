@@ -32,3 +32,15 @@ fn morph_vertex(vertex_in: Vertex) -> Vertex {
     return vertex;
     }
 #endif
+
+// How far a vertex is morphed towards the coarser LOD, from 0 at `start` to 1 at `end`.
+// `start` and `end` are fractions of `lod_distance`, where the coarser chunk replaces the vertex's one.
+fn lod_morph_factor(distance: f32, lod_distance: f32, start: f32, end: f32) -> f32 {
+    return smoothstep(start, end, distance / lod_distance);
+}
+
+// Moves a vertex towards the surface of the coarser LOD, a `factor` of 1 matches it exactly.
+// Not to be confused with `morph_vertex`, which applies the morph targets of a mesh.
+fn lod_morph_vertex(position: vec3<f32>, parent_height: f32, factor: f32) -> vec3<f32> {
+    return vec3(position.x, mix(position.y, parent_height, factor), position.z);
+}
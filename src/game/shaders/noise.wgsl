@@ -1,36 +1,172 @@
 #define_import_path wanderer_tales::noise
 
-
-struct Value2Dt1 {
+// Mirror Value2Dt1 and Value2Dt2, naga_oil rejects module identifiers ending with a digit
+struct Dt1Value {
     value: f32,
-    d1: vec2<f32>,
+    gradient: vec2<f32>,
 }
 
-struct Value2Dt2 {
+struct Dt2Value {
     value: f32,
-    d1: Dt2,
-    d2: Vec3,
+    gradient: vec2<f32>,
+    // (xx, yy, xy)
+    hessian: vec3<f32>,
 }
 
+// Mirrors SimpleHasher::hash_22f_seeded without the seed, results have to match the cpu bit for bit
 fn hash(p: vec2<i32>) -> vec2<f32> {
+    // 2D -> 1D
     var n: vec2<i32> = p.x * vec2<i32>(3, 37) + p.y * vec2<i32>(311, 113);
-    n = n << 13 ^ n;
+
+    // 1D hash by Hugo Elias
+    n = (n << vec2<u32>(13u)) ^ n;
     n = n * (n * n * 15731 + 789221) + 1376312589;
-    return -1. + 2. * vec2<f32>(n & vec2<i32>(268435460.)) / f32(268435460.);
+    return -1.0 + 2.0 * vec2<f32>(n & vec2<i32>(0x0fffffff)) / f32(0x0fffffff);
 }
 
 fn hash_seeded(
     p: vec2<i32>,
     seed: u32,
-) {
-    return hash(vec2<i32>(vec2<u32>(p) ^ seed));
+) -> vec2<f32> {
+    return hash(bitcast<vec2<i32>>(bitcast<vec2<u32>>(p) ^ vec2<u32>(seed)));
+}
+
+// Mirror the pcg hashes of SimpleHasher, http://www.jcgt.org/published/0009/03/02/
+fn pcg(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn pcg2d(p: vec2<u32>) -> vec2<u32> {
+    var v = p * 1664525u + 1013904223u;
+
+    v.x += v.y * 1664525u;
+    v.y += v.x * 1664525u;
+
+    v ^= v >> vec2<u32>(16u);
+
+    v.x += v.y * 1664525u;
+    v.y += v.x * 1664525u;
+
+    v ^= v >> vec2<u32>(16u);
+
+    return v;
+}
+
+fn pcg3d(p: vec3<u32>) -> vec3<u32> {
+    var v = p * 1664525u + 1013904223u;
+
+    v.x += v.y * v.z;
+    v.y += v.z * v.x;
+    v.z += v.x * v.y;
+
+    v ^= v >> vec3<u32>(16u);
+
+    v.x += v.y * v.z;
+    v.y += v.z * v.x;
+    v.z += v.x * v.y;
+
+    return v;
+}
+
+fn pcg4d(p: vec4<u32>) -> vec4<u32> {
+    var v = p * 1664525u + 1013904223u;
+
+    v.x += v.y * v.w;
+    v.y += v.z * v.x;
+    v.z += v.x * v.y;
+    v.w += v.y * v.z;
+
+    v ^= v >> vec4<u32>(16u);
+
+    v.x += v.y * v.w;
+    v.y += v.z * v.x;
+    v.z += v.x * v.y;
+    v.w += v.y * v.z;
+
+    return v;
+}
+
+// The top 24 bits convert to f32 exactly, the results match the cpu on every gpu
+fn unorm24(v: vec4<u32>) -> vec4<f32> {
+    return vec4<f32>(v >> vec4<u32>(8u)) / 16777216.0;
+}
+
+// Mirrors WgslPcgHasher::hashf_seeded, output [0, 1]
+fn pcg_hash_11_seeded(p: u32, seed: u32) -> f32 {
+    return unorm24(vec4<u32>(pcg2d(vec2<u32>(p, seed)), 0u, 0u)).x;
 }
 
+// Mirrors WgslPcgHasher::hash_22f_seeded, output [-1, 1]
+fn pcg_hash_22_seeded(p: vec2<i32>, seed: u32) -> vec2<f32> {
+    let v = pcg3d(vec3<u32>(bitcast<vec2<u32>>(p), seed));
+    return unorm24(vec4<u32>(v, 0u)).xy * 2.0 - 1.0;
+}
+
+// Mirrors WgslPcgHasher::hash_33f_seeded, output [0, 1]
+fn pcg_hash_33_seeded(p: vec3<i32>, seed: u32) -> vec3<f32> {
+    let v = pcg4d(vec4<u32>(bitcast<vec3<u32>>(p), seed));
+    return unorm24(v).xyz;
+}
+
+// Mirrors WgslPcgHasher::hash_44f_seeded, output [0, 1]
+fn pcg_hash_44_seeded(p: vec4<i32>, seed: u32) -> vec4<f32> {
+    return unorm24(pcg4d(bitcast<vec4<u32>>(p) ^ vec4<u32>(pcg(seed))));
+}
+
+// HasherKind discriminants of the hashers with a WGSL mirror
+const HASHER_SIMPLE: u32 = 0u;
+const HASHER_WGSL_PCG: u32 = 3u;
+
+// Mirrors NoiseHasher::hash_22f_seeded of the HasherKind `hasher`, output [-1, 1]
+fn hash_22_seeded(p: vec2<i32>, seed: u32, hasher: u32) -> vec2<f32> {
+    if hasher == HASHER_WGSL_PCG {
+        return pcg_hash_22_seeded(p, seed);
+    }
+    return hash_seeded(p, seed);
+}
 
-fn perlin_noise_2d(unscaled_p: vec2<f32>, scale: f32, seed: u32) -> Value2Dt2 {
+// Mirrors noise::value_noise_2d
+// Point has to be unscaled
+// output  [-1, 1]
+fn value_noise_2d(unscaled_p: vec2<f32>, scale: f32, seed: u32, hasher: u32) -> Dt2Value {
     let p = unscaled_p * scale;
-    let i = p.floor().as_ivec2();
-    let f = p.fract_gl();
+    let i = vec2<i32>(floor(p));
+    let f = p - floor(p);
+
+    let u = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+    let du = 30.0 * f * f * (f * (f - 2.0) + 1.0);
+    let ddu = 60.0 * f * (1.0 + f * (-3.0 + 2.0 * f));
+
+    let va = hash_22_seeded(i + vec2<i32>(0, 0), seed, hasher).x;
+    let vb = hash_22_seeded(i + vec2<i32>(1, 0), seed, hasher).x;
+    let vc = hash_22_seeded(i + vec2<i32>(0, 1), seed, hasher).x;
+    let vd = hash_22_seeded(i + vec2<i32>(1, 1), seed, hasher).x;
+
+    let k0 = va;
+    let k1 = vb - va;
+    let k2 = vc - va;
+    let k4 = va - vb - vc + vd;
+
+    // value
+    let v = k0 + k1 * u.x + k2 * u.y + k4 * u.x * u.y;
+
+    // derivative
+    let de = du * (vec2<f32>(k1, k2) + k4 * u.yx) * scale;
+    let he = vec3<f32>(
+        ddu.x * (k1 + k4 * u.y),
+        ddu.y * (k2 + k4 * u.x),
+        du.x * k4 * du.y,
+    ) * (scale * scale);
+    return Dt2Value(v, de, he);
+}
+
+
+fn perlin_noise_2d(unscaled_p: vec2<f32>, scale: f32, seed: u32, hasher: u32) -> Dt2Value {
+    let p = unscaled_p * scale;
+    let i = vec2<i32>(floor(p));
+    let f = p - floor(p);
 
     // quintic interpolation
     // u(x) = 6x^5 - 15x^4 + 10x^3
@@ -48,15 +184,15 @@ fn perlin_noise_2d(unscaled_p: vec2<f32>, scale: f32, seed: u32) -> Value2Dt2 {
     // d/dy^3 v(y) = 360y^2 - 360y + 60
     // let ddduv = 60.0 * (6.0 * f * f - 6.0 * f + 1.0);
 
-    let ga = hash_seeded(i + ivec2(0, 0), seed);
-    let gb = hash_seeded(i + ivec2(1, 0), seed);
-    let gc = hash_seeded(i + ivec2(0, 1), seed);
-    let gd = hash_seeded(i + ivec2(1, 1), seed);
+    let ga = hash_22_seeded(i + vec2<i32>(0, 0), seed, hasher);
+    let gb = hash_22_seeded(i + vec2<i32>(1, 0), seed, hasher);
+    let gc = hash_22_seeded(i + vec2<i32>(0, 1), seed, hasher);
+    let gd = hash_22_seeded(i + vec2<i32>(1, 1), seed, hasher);
 
-    let va = dot(ga, f - vec2(0.0, 0.0));
-    let vb = dot(gb, f - vec2(1.0, 0.0));
-    let vc = dot(gc, f - vec2(0.0, 1.0));
-    let vd = dot(gd, f - vec2(1.0, 1.0));
+    let va = dot(ga, f - vec2<f32>(0.0, 0.0));
+    let vb = dot(gb, f - vec2<f32>(1.0, 0.0));
+    let vc = dot(gc, f - vec2<f32>(0.0, 1.0));
+    let vd = dot(gd, f - vec2<f32>(1.0, 1.0));
     //     va(x,y) = ga_x * x + ga_y * y
     //     vb(x,y) = gb_x * x + gb_y * y
     //     vc(x,y) = gc_x * x + gc_y * y
@@ -103,7 +239,7 @@ fn perlin_noise_2d(unscaled_p: vec2<f32>, scale: f32, seed: u32) -> Value2Dt2 {
     let value = k0 + uv.x * k1 + uv.y * k2 + uv.x * uv.y * k4;
 
     // d/dx n(x,y) = g0_x + u(x) * g1_x + v(y) * g2_x + u(x) * v(y) * g4_x + d/dx(u(x)) * v(y) * k4(x,y) + d/dx(u(x)) * k1(x,y);
-    let d1 = (g0 + uv.x * g1 + uv.y * g2 + uv.x * uv.y * g4 + duv * (vec2(k1, k2) + uv.yx() * k4)) * scale;
+    let d1 = (g0 + uv.x * g1 + uv.y * g2 + uv.x * uv.y * g4 + duv * (vec2<f32>(k1, k2) + uv.yx * k4)) * scale;
 
     let dxx = duv.x * g1.x + duv.x * uv.y * g4.x + dduv.x * uv.y * k4 + duv.x * uv.y * g4.x + dduv.x * k1 + duv.x * g1.x;
     // let dxx =
@@ -122,7 +258,7 @@ fn perlin_noise_2d(unscaled_p: vec2<f32>, scale: f32, seed: u32) -> Value2Dt2 {
     // d^2/dy^2 n(x,y) = (g2_y + u(x) g4_y) * d/dy v(y) + d/dy^2 v(y) * (u(x) k4(x,y) + k2(x,y)) + d/dy v(y) * (u(x) g4_y + g2_y)
 
     let d2 = vec3<f32>(dxx, dyy, dxy) * (scale * scale);
-    return Value2Dt2(value, d1, d2);
+    return Dt2Value(value, d1, d2);
 
     // let dxxx = dduv.x * g1.x
     //     + dduv.x * uv.y * g4.x
@@ -146,95 +282,84 @@ fn perlin_noise_2d(unscaled_p: vec2<f32>, scale: f32, seed: u32) -> Value2Dt2 {
 }
 
 
-fn value_dt2_to_dt1(dt2: Value2Dt2) -> Value2Dt1 {
-    return Value2Dt1(dt2.value, dt2.d1);
+fn truncate_dt2(dt: Dt2Value) -> Dt1Value {
+    return Dt1Value(dt.value, dt.gradient);
 }
 
-fn add_dt2_dt2(dt_1: Value2Dt1, dt_2: Value2Dt2) -> Value2Dt2 {
-    return Value2Dt2(dt_1.value + dt_2.value, dt_1.d1 + dt_2.d1, dt_1.d2 + dt_2.d2);
+fn add_dt2(dt_1: Dt2Value, dt_2: Dt2Value) -> Dt2Value {
+    return Dt2Value(dt_1.value + dt_2.value, dt_1.gradient + dt_2.gradient, dt_1.hessian + dt_2.hessian);
 }
 
-fn sub_dt2_dt2(dt_1: Value2Dt1, dt_2: Value2Dt2) -> Value2Dt2 {
-    return Value2Dt2(dt_1.value - dt_2.value, dt_1.d1 - dt_2.d1, dt_1.d2 - dt_2.d2);
+fn sub_dt2(dt_1: Dt2Value, dt_2: Dt2Value) -> Dt2Value {
+    return Dt2Value(dt_1.value - dt_2.value, dt_1.gradient - dt_2.gradient, dt_1.hessian - dt_2.hessian);
 }
 
-fn mul_dt1_dt1(dt_1: Value2Dt1, dt_2: Value2Dt1) -> Value2Dt1 {
-    let value = dt_1.value * dt_2.value;
-    let d1 = dt_1.d1 * dt_2.value + dt_2.d1 * dt_1.value
-
-    return Value2Dt1(value, d1);
+fn add_dt2_f(dt_1: Dt2Value, f: f32) -> Dt2Value {
+    return Dt2Value(dt_1.value + f, dt_1.gradient, dt_1.hessian);
 }
 
-fn div_dt1_dt1(dt_1: Value2Dt1, dt_2: Value2Dt1) -> Value2Dt1 {
-    let value = dt_1.value / dt_2.value;
-    let d1 = (dt_1.d1 * dt_2.value - dt_2.d1 * dt_1.value) / (dt_2.value * dt_2.value);
-
-    return Value2Dt1(value, d1);
-}
-
-fn add_dt2_f(dt_1: Value2Dt2, f: f32) -> Value2Dt2 {
-    return Value2Dt2(dt_1.value + f, dt_1.d1, dt_1.d2);
+fn sub_dt2_f(dt_1: Dt2Value, f: f32) -> Dt2Value {
+    return Dt2Value(dt_1.value - f, dt_1.gradient, dt_1.hessian);
 }
 
-fn sub_dt2_f(dt_1: Value2Dt2, f: f32) -> Value2Dt2 {
-    return Value2Dt2(dt_1.value - f, dt_1.d1, dt_1.d2);
+fn mul_dt2_f(dt_1: Dt2Value, f: f32) -> Dt2Value {
+    return Dt2Value(dt_1.value * f, dt_1.gradient * f, dt_1.hessian * f);
 }
 
-fn mul_dt2_f(dt_1: Value2Dt2, f: f32) -> Value2Dt2 {
-    return Value2Dt2(dt_1.value * f, dt_1.d1 * f, dt_1.d2 * f);
+fn div_dt2_f(dt_1: Dt2Value, f: f32) -> Dt2Value {
+    return Dt2Value(dt_1.value / f, dt_1.gradient / f, dt_1.hessian / f);
 }
 
-fn div_dt2_f(dt_1: Value2Dt2, f: f32) -> Value2Dt2 {
-    return Value2Dt2(dt_1.value / f, dt_1.d1 / f, dt_1.d2 / f);
+fn add_dt1(dt_1: Dt1Value, dt_2: Dt1Value) -> Dt1Value {
+    return Dt1Value(dt_1.value + dt_2.value, dt_1.gradient + dt_2.gradient);
 }
 
-fn add_dt1_dt1(dt_1: Value2Dt1, dt_2: Value2Dt1) -> Value2Dt1 {
-    return Value2Dt1(dt_1.value + dt_2.value, dt_1.d1 + dt_2.d1);
+fn sub_dt1(dt_1: Dt1Value, dt_2: Dt1Value) -> Dt1Value {
+    return Dt1Value(dt_1.value - dt_2.value, dt_1.gradient - dt_2.gradient);
 }
 
-fn sub_dt1_dt1(dt_1: Value2Dt1, dt_2: Value2Dt1) -> Value2Dt1 {
-    return Value2Dt1(dt_1.value - dt_2.value, dt_1.d1 - dt_2.d1);
+fn mul_dt1(dt_1: Dt1Value, dt_2: Dt1Value) -> Dt1Value {
+    return Dt1Value(dt_1.value * dt_2.value, dt_1.gradient * dt_2.value + dt_2.gradient * dt_1.value);
 }
 
-fn mul_dt1_dt1(dt_1: Value2Dt1, dt_2: Value2Dt1) -> Value2Dt1 {
-    return Value2Dt1(dt_1.value * dt_2.value, dt_1.d1 * dt_2.value + dt_2.d1 * dt_1.value);
-}
+fn div_dt1(dt_1: Dt1Value, dt_2: Dt1Value) -> Dt1Value {
+    let value = dt_1.value / dt_2.value;
+    let d1 = (dt_1.gradient * dt_2.value - dt_2.gradient * dt_1.value) / (dt_2.value * dt_2.value);
 
-fn div_dt1_dt1(dt_1: Value2Dt1, dt_2: Value2Dt1) -> Value2Dt1 {
-    return Value2Dt1(dt_1.value / dt_2.value, dt_1.d1 * dt_2.value - dt_2.d1 * dt_1.value) / (dt_2.value * dt_2.value);
+    return Dt1Value(value, d1);
 }
 
-fn add_dt1_f(dt_1: Value2Dt1, f: f32) -> Value2Dt1 {
-    return Value2Dt1(dt_1.value + f, dt_1.d1);
+fn add_dt1_f(dt_1: Dt1Value, f: f32) -> Dt1Value {
+    return Dt1Value(dt_1.value + f, dt_1.gradient);
 }
 
-fn sub_dt1_f(dt_1: Value2Dt1, f: f32) -> Value2Dt1 {
-    return Value2Dt1(dt_1.value - f, dt_1.d1);
+fn sub_dt1_f(dt_1: Dt1Value, f: f32) -> Dt1Value {
+    return Dt1Value(dt_1.value - f, dt_1.gradient);
 }
 
-fn mul_dt1_f(dt_1: Value2Dt1, f: f32) -> Value2Dt1 {
-    return Value2Dt1(dt_1.value * f, dt_1.d1 * f);
+fn mul_dt1_f(dt_1: Dt1Value, f: f32) -> Dt1Value {
+    return Dt1Value(dt_1.value * f, dt_1.gradient * f);
 }
 
-fn div_dt1_f(dt_1: Value2Dt1, f: f32) -> Value2Dt1 {
-    return Value2Dt1(dt_1.value / f, dt_1.d1 / f);
+fn div_dt1_f(dt_1: Dt1Value, f: f32) -> Dt1Value {
+    return Dt1Value(dt_1.value / f, dt_1.gradient / f);
 }
 
-fn dt2_length(dt: Value2Dt2) -> Value2Dt1 {
-    let d1 = dt.d1;
-    let d2 = dt.d2;
+fn dt2_length(dt: Dt2Value) -> Dt1Value {
+    let d1 = dt.gradient;
+    let d2 = dt.hessian;
     let grad_len = length(d1);
 
     let grad_len_dx = (d1.x * d2.x + d1.y * d2.z) / grad_len;
     let grad_len_dy = (d1.x * d2.z + d1.y * d2.y) / grad_len;
 
-    return Value2Dt1(grad_len, vec2(grad_len_dx, grad_len_dy));
+    return Dt1Value(grad_len, vec2<f32>(grad_len_dx, grad_len_dy));
 }
 
-fn dt1_length(dt: Value2Dt1) -> f32 {
-    return length(dt.d1);
+fn dt1_length(dt: Dt1Value) -> f32 {
+    return length(dt.gradient);
 }
 
 fn compute_normal(derivative: vec2<f32>) -> vec3<f32> {
-    return (vec3(-derivative.x, 1.0, -derivative.y));
+    return normalize(vec3<f32>(-derivative.x, 1.0, -derivative.y));
 }
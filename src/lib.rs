@@ -103,8 +103,6 @@ impl Plugin for AppPlugin {
         // Spawn the main camera.
         app.add_systems(Startup, spawn_camera_ui);
 
-        // app.add_plugins(RenderStatePlugin::new());
-
         // Add other plugins.
         app.add_plugins((game::plugin, screen::plugin, ui::plugin));
 
@@ -2,7 +2,7 @@
 //! This reduces stuttering, especially for audio on WASM.
 
 use crate::{
-    game::assets::{HandleMap, ImageKey, SfxKey, SoundtrackKey},
+    game::assets::{HandleMap, ImageKey, SfxKey, SoundtrackKey, TerrainPresetKey},
     prelude::*,
     ui::prelude::*,
 };
@@ -29,10 +29,12 @@ fn all_assets_loaded(
     image_handles: Res<HandleMap<ImageKey>>,
     sfx_handles: Res<HandleMap<SfxKey>>,
     soundtrack_handles: Res<HandleMap<SoundtrackKey>>,
+    terrain_preset_handles: Res<HandleMap<TerrainPresetKey>>,
 ) -> bool {
     image_handles.all_loaded(&asset_server)
         && sfx_handles.all_loaded(&asset_server)
         && soundtrack_handles.all_loaded(&asset_server)
+        && terrain_preset_handles.all_loaded(&asset_server)
 }
 
 fn continue_to_title(mut next_screen: ResMut<NextState<GameState>>) {
@@ -1,34 +1,61 @@
 //! The title screen that appears when the game starts.
 
+use bevy::input::{
+    keyboard::{Key, KeyboardInput},
+    ButtonState,
+};
+
 use crate::prelude::*;
 use crate::ui::prelude::*;
+use map::BaseSeed;
 
 pub(super) fn plugin(app: &mut App) {
     app.add_systems(OnEnter(GameState::Title), enter_title);
 
     app.register_type::<TitleAction>();
+    app.init_resource::<SeedField>();
     app.add_systems(
         Update,
-        handle_title_action.run_if(in_state(GameState::Title)),
+        (handle_title_action, type_seed, update_seed_label).run_if(in_state(GameState::Title)),
     );
 }
 
+/// Label showing the [`BaseSeed`] the world will be generated with.
+#[derive(Component)]
+struct SeedLabel;
+
+/// Whether the seed was typed, the first key replaces a random seed instead of appending to it.
+#[derive(Resource, Default)]
+struct SeedField {
+    is_typed: bool,
+}
+
 #[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Reflect)]
 #[reflect(Component)]
 enum TitleAction {
     Play,
+    RandomSeed,
     Credits,
     /// Exit doesn't work well with embedded applications.
     #[cfg(not(target_family = "wasm"))]
     Exit,
 }
 
-fn enter_title(mut commands: Commands) {
+fn enter_title(
+    mut commands: Commands,
+    base_seed: Res<BaseSeed>,
+    mut seed_field: ResMut<SeedField>,
+) {
+    seed_field.is_typed = false;
     commands
         .ui_root()
         .insert(StateScoped(GameState::Title))
         .with_children(|children| {
             children.button("Play").insert(TitleAction::Play);
+            children.label(seed_text(&base_seed)).insert(SeedLabel);
+            children
+                .button("Random seed")
+                .insert(TitleAction::RandomSeed);
             children.button("Credits").insert(TitleAction::Credits);
 
             #[cfg(not(target_family = "wasm"))]
@@ -38,6 +65,8 @@ fn enter_title(mut commands: Commands) {
 
 fn handle_title_action(
     mut next_screen: ResMut<NextState<GameState>>,
+    mut base_seed: ResMut<BaseSeed>,
+    mut seed_field: ResMut<SeedField>,
     mut button_query: InteractionQuery<&TitleAction>,
     #[cfg(not(target_family = "wasm"))] mut app_exit: EventWriter<AppExit>,
 ) {
@@ -45,6 +74,10 @@ fn handle_title_action(
         if matches!(interaction, Interaction::Pressed) {
             match action {
                 TitleAction::Play => next_screen.set(GameState::Playing),
+                TitleAction::RandomSeed => {
+                    *base_seed = BaseSeed::random();
+                    seed_field.is_typed = false;
+                }
                 TitleAction::Credits => next_screen.set(GameState::Credits),
 
                 #[cfg(not(target_family = "wasm"))]
@@ -55,3 +88,59 @@ fn handle_title_action(
         }
     }
 }
+
+fn seed_text(base_seed: &BaseSeed) -> String {
+    format!("Seed: {} (type to change)", base_seed.0)
+}
+
+/// Digits typed on the title screen replace the seed, backspace removes the last one.
+/// The first key clears a random seed.
+fn type_seed(
+    mut keyboard_input: EventReader<KeyboardInput>,
+    mut base_seed: ResMut<BaseSeed>,
+    mut seed_field: ResMut<SeedField>,
+) {
+    for event in keyboard_input.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+
+        let seed = if seed_field.is_typed { base_seed.0 } else { 0 };
+        let seed = match &event.logical_key {
+            Key::Backspace => seed / 10,
+            Key::Character(character) => {
+                let Some(digit) = character.chars().next().and_then(|c| c.to_digit(10)) else {
+                    continue;
+                };
+                // digits that would overflow the seed are ignored
+                let Some(seed) = seed
+                    .checked_mul(10)
+                    .and_then(|seed| seed.checked_add(digit))
+                else {
+                    continue;
+                };
+                seed
+            }
+            _ => continue,
+        };
+        seed_field.is_typed = true;
+        base_seed.set_if_neq(BaseSeed(seed));
+    }
+}
+
+fn update_seed_label(
+    base_seed: Res<BaseSeed>,
+    label_query: Query<&Children, With<SeedLabel>>,
+    mut text_query: Query<&mut Text>,
+) {
+    if !base_seed.is_changed() {
+        return;
+    }
+
+    for children in label_query.iter() {
+        let mut text_iter = text_query.iter_many_mut(children);
+        while let Some(mut text) = text_iter.fetch_next() {
+            text.sections[0].value = seed_text(&base_seed);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::{error::Error, fmt};
+
+use bevy::render::{
+    render_asset::RenderAssetUsages,
+    render_resource::{Extent3d, TextureDimension, TextureFormat},
+    texture::{
+        Image, ImageAddressMode, ImageSampler, ImageSamplerDescriptor, TextureFormatPixelInfo,
+    },
+};
+
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BakeNoiseError {
+    UnsupportedFormat(TextureFormat),
+}
+
+impl fmt::Display for BakeNoiseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BakeNoiseError::UnsupportedFormat(format) => {
+                write!(f, "noise cannot be baked into {format:?} images")
+            }
+        }
+    }
+}
+
+impl Error for BakeNoiseError {}
+
+/// Bakes `noise` into a repeating image of `size` pixels.
+/// `noise` is sampled at the uv of every pixel corner in [0, 1), the pixel after the last one
+/// would be at uv 1, so noise repeating over [0, 1] gives a seamless image.
+/// Channels past the ones of `format` are dropped, normalized formats clamp the values to [0, 1].
+pub fn bake_noise_image(
+    size: UVec2,
+    format: TextureFormat,
+    noise: impl Fn(Vec2) -> Vec4,
+) -> Result<Image, BakeNoiseError> {
+    let (channels, encode): (usize, fn(f32, &mut Vec<u8>)) = match format {
+        TextureFormat::R8Unorm => (1, encode_unorm8),
+        TextureFormat::Rg8Unorm => (2, encode_unorm8),
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => (4, encode_unorm8),
+        TextureFormat::R16Unorm => (1, encode_unorm16),
+        TextureFormat::Rg16Unorm => (2, encode_unorm16),
+        TextureFormat::Rgba16Unorm => (4, encode_unorm16),
+        TextureFormat::R32Float => (1, encode_float32),
+        TextureFormat::Rg32Float => (2, encode_float32),
+        TextureFormat::Rgba32Float => (4, encode_float32),
+        _ => return Err(BakeNoiseError::UnsupportedFormat(format)),
+    };
+
+    let mut data = Vec::with_capacity((size.x * size.y) as usize * format.pixel_size());
+    for (y, x) in itertools::iproduct!(0..size.y, 0..size.x) {
+        let value = noise(uvec2(x, y).as_vec2() / size.as_vec2());
+        for channel in value.to_array().into_iter().take(channels) {
+            encode(channel, &mut data);
+        }
+    }
+
+    let mut image = Image::new(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        format,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+        address_mode_u: ImageAddressMode::Repeat,
+        address_mode_v: ImageAddressMode::Repeat,
+        ..ImageSamplerDescriptor::linear()
+    });
+
+    Ok(image)
+}
+
+fn encode_unorm8(value: f32, data: &mut Vec<u8>) {
+    data.push((value.clamp(0.0, 1.0) * u8::MAX as f32).round() as u8);
+}
+
+fn encode_unorm16(value: f32, data: &mut Vec<u8>) {
+    let value = (value.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16;
+    data.extend(value.to_le_bytes());
+}
+
+fn encode_float32(value: f32, data: &mut Vec<u8>) {
+    data.extend(value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn baked_image_matches_format() {
+        let size = uvec2(8, 4);
+        for (format, pixel_size) in [
+            (TextureFormat::R8Unorm, 1),
+            (TextureFormat::Rg16Unorm, 4),
+            (TextureFormat::Rgba32Float, 16),
+        ] {
+            let image = bake_noise_image(size, format, |uv| uv.extend(0.0).extend(1.0)).unwrap();
+
+            assert_eq!(image.texture_descriptor.format, format);
+            assert_eq!(image.data.len(), 8 * 4 * pixel_size, "{format:?}");
+        }
+
+        let image = bake_noise_image(size, TextureFormat::Rgba8Unorm, |uv| {
+            uv.extend(-1.0).extend(2.0)
+        })
+        .unwrap();
+        // the last pixel is one pixel short of uv 1
+        assert_eq!(image.data[image.data.len() - 4..], [223, 191, 0, 255]);
+
+        assert_eq!(
+            bake_noise_image(size, TextureFormat::Depth32Float, |_| Vec4::ZERO).unwrap_err(),
+            BakeNoiseError::UnsupportedFormat(TextureFormat::Depth32Float)
+        );
+    }
+
+    #[test]
+    fn baked_periodic_noise_is_seamless() {
+        let hasher = SimpleHasher::new(0);
+        let size = uvec2(64, 32);
+        let image = bake_noise_image(size, TextureFormat::R32Float, |uv| {
+            let value = perlin_noise_2d_periodic(uv * 4.0, 1.0, uvec2(4, 4), &hasher).value;
+            Vec4::splat(value)
+        })
+        .unwrap();
+        let pixel = |x: u32, y: u32| {
+            let start = ((y % size.y) * size.x + x % size.x) as usize * 4;
+            f32::from_le_bytes(image.data[start..start + 4].try_into().unwrap())
+        };
+
+        // wrapping around the border changes the noise no more than stepping inside the image
+        let max_step = itertools::iproduct!(0..size.x - 1, 0..size.y - 1)
+            .map(|(x, y)| {
+                (pixel(x + 1, y) - pixel(x, y))
+                    .abs()
+                    .max((pixel(x, y + 1) - pixel(x, y)).abs())
+            })
+            .fold(0.0, f32::max);
+        for y in 0..size.y {
+            assert!((pixel(size.x, y) - pixel(size.x - 1, y)).abs() <= max_step * 1.5);
+        }
+        for x in 0..size.x {
+            assert!((pixel(x, size.y) - pixel(x, size.y - 1)).abs() <= max_step * 1.5);
+        }
+    }
+}
@@ -158,6 +158,24 @@ impl Value2Dt2 {
 
         Value2Dt1::new(grad_len, vec2(grad_len_dx, grad_len_dy))
     }
+
+    /// Chain rule of a 1D function `g` applied to the value, given `g`, `g'` and `g''` at the value.
+    pub fn compose(self, g: f32, dg: f32, ddg: f32) -> Self {
+        let d1 = self.d1.0;
+        // (g∘f)'' = g''(f) f' f'ᵀ + g'(f) f''
+        let d2 = self.d2 * dg + vec3(d1.x * d1.x, d1.y * d1.y, d1.x * d1.y) * ddg;
+
+        Self::new(g, d1 * dg, d2)
+    }
+
+    /// Not differentiable where the value crosses 0, the derivatives of the side it is on are used.
+    pub fn abs(self) -> Self {
+        if self.value < 0.0 {
+            -self
+        } else {
+            self
+        }
+    }
 }
 
 impl Add<f32> for Value2Dt2 {
@@ -256,6 +274,31 @@ impl Div<Value2Dt2> for Value2Dt2 {
         Self { value, d1, d2 }
     }
 }
+
+impl Mul<Value2Dt2> for Value2Dt2 {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        let (a, b) = (self.d1.0, rhs.d1.0);
+        // (fg)'' = f''g + f'g' + g'f' + fg''
+        let d2 = self.d2 * rhs.value
+            + rhs.d2 * self.value
+            + vec3(2.0 * a.x * b.x, 2.0 * a.y * b.y, a.x * b.y + a.y * b.x);
+
+        Self {
+            value: self.value * rhs.value,
+            d1: Dt2(a * rhs.value + b * self.value),
+            d2,
+        }
+    }
+}
+
+impl Neg for Value2Dt2 {
+    type Output = Self;
+    fn neg(self) -> Self {
+        self * -1.0
+    }
+}
+
 impl Value2Dt1 {
     pub fn new(value: f32, derivative: Vec2) -> Self {
         Self {
@@ -445,6 +488,8 @@ impl Div<Dt2> for Dt2 {
     }
 }
 
+/// Value of a 3D function and its gradient.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub struct ValueDt3 {
     pub value: f32,
     pub derivative: Vec3,
@@ -454,6 +499,83 @@ impl ValueDt3 {
     pub fn new(value: f32, derivative: Vec3) -> Self {
         Self { value, derivative }
     }
+
+    pub fn constant(value: f32) -> Self {
+        Self::new(value, Vec3::ZERO)
+    }
+
+    /// Lifts a function of the horizontal plane into 3D, the 2D x and y map to x and z.
+    pub fn from_xz(value: Value2Dt1) -> Self {
+        Self::new(value.value, vec3(value.d1.0.x, 0.0, value.d1.0.y))
+    }
+
+    /// Smaller of the two, the derivative follows the side that is picked.
+    pub fn min(self, rhs: Self) -> Self {
+        if self.value <= rhs.value {
+            self
+        } else {
+            rhs
+        }
+    }
+
+    /// Bigger of the two, the derivative follows the side that is picked.
+    pub fn max(self, rhs: Self) -> Self {
+        if self.value >= rhs.value {
+            self
+        } else {
+            rhs
+        }
+    }
+
+    /// Normal of the surface where the value crosses 0, pointing towards lower values.
+    pub fn get_normal(&self) -> Vec3 {
+        (-self.derivative).normalize_or_zero()
+    }
+}
+
+impl Add<ValueDt3> for ValueDt3 {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.value + rhs.value, self.derivative + rhs.derivative)
+    }
+}
+
+impl Sub<ValueDt3> for ValueDt3 {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.value - rhs.value, self.derivative - rhs.derivative)
+    }
+}
+
+impl Add<f32> for ValueDt3 {
+    type Output = Self;
+    fn add(self, rhs: f32) -> Self {
+        Self::new(self.value + rhs, self.derivative)
+    }
+}
+
+impl Mul<f32> for ValueDt3 {
+    type Output = Self;
+    fn mul(self, rhs: f32) -> Self {
+        Self::new(self.value * rhs, self.derivative * rhs)
+    }
+}
+
+impl Mul<ValueDt3> for ValueDt3 {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(
+            self.value * rhs.value,
+            self.derivative * rhs.value + rhs.derivative * self.value,
+        )
+    }
+}
+
+impl Neg for ValueDt3 {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Self::new(-self.value, -self.derivative)
+    }
 }
 
 #[cfg(test)]
@@ -577,6 +699,49 @@ mod tests {
         assert_eq!(mul1.d1.0.x, expected_derivative);
     }
 
+    #[test]
+    fn test_value_dt_dt2_multiplication_with_hessian() {
+        let f = |p: Vec2| f1(p.x) * f2(p.y);
+        let g = |p: Vec2| f2(p.x) + f1(p.y);
+
+        let pos = vec2(1.0, 2.0);
+        let value_f = Value2Dt2::new(
+            f(pos),
+            vec2(df1(pos.x) * f2(pos.y), f1(pos.x) * df2(pos.y)),
+            vec3(
+                ddf1(pos.x) * f2(pos.y),
+                f1(pos.x) * ddf2(pos.y),
+                df1(pos.x) * df2(pos.y),
+            ),
+        );
+        let value_g = Value2Dt2::new(
+            g(pos),
+            vec2(df2(pos.x), df1(pos.y)),
+            vec3(ddf2(pos.x), ddf1(pos.y), 0.0),
+        );
+
+        let product = value_f * value_g;
+        let product_fn = |p: Vec2| f(p) * g(p);
+        let expected_d1 = vec2(
+            estimate_dt1(pos.x, |x| product_fn(vec2(x, pos.y))),
+            estimate_dt1(pos.y, |y| product_fn(vec2(pos.x, y))),
+        );
+        let expected_d2 = crate::utils::noise::estimate_dt2(pos, product_fn);
+
+        assert_eq!(product, value_g * value_f);
+        assert_eq!(product.value, product_fn(pos));
+        assert!(
+            (product.d1.0 - expected_d1).length() < 0.01 * expected_d1.length(),
+            "{:?}!={expected_d1:?}",
+            product.d1.0
+        );
+        assert!(
+            (product.d2 - expected_d2).length() < 0.01 * expected_d2.length(),
+            "{:?}!={expected_d2:?}",
+            product.d2
+        );
+    }
+
     #[test]
     fn test_value_dt2_division() {
         let pos = 1.0;
@@ -6,18 +6,55 @@ use super::*;
 // Point has to be unscaled
 // output  [0, 1]
 pub fn value_noise_2d(unscaled_p: Vec2, scale: f32, hasher: &impl NoiseHasher) -> Value2Dt2 {
+    value_noise_2d_lattice(unscaled_p, scale, hasher, |cell| cell)
+}
+
+// Same as value_noise_2d, repeats every `period` cells, so every `period / scale` unscaled.
+// output  [0, 1]
+pub fn value_noise_2d_periodic(
+    unscaled_p: Vec2,
+    scale: f32,
+    period: UVec2,
+    hasher: &impl NoiseHasher,
+) -> Value2Dt2 {
+    value_noise_2d_lattice(unscaled_p, scale, hasher, periodic_lattice(period))
+}
+
+/// Lattice cells are mapped through `lattice` before they are hashed.
+fn value_noise_2d_lattice(
+    unscaled_p: Vec2,
+    scale: f32,
+    hasher: &impl NoiseHasher,
+    lattice: impl Fn(IVec2) -> IVec2,
+) -> Value2Dt2 {
     let p = unscaled_p * scale;
     let i = p.floor().as_ivec2();
     let f = p.fract_gl();
 
-    let u = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
-    let du = 30.0 * f * f * (f * (f - 2.0) + 1.0);
-    let ddu = 60.0 * f * (1.0 + f * (-3.0 + 2.0 * f));
+    let corners = value_noise_2d_corners(i, hasher, &lattice);
+    value_noise_2d_cell(Fade::new(value_fade(f.x), value_fade(f.y)), corners, scale)
+}
+
+fn value_noise_2d_corners(
+    i: IVec2,
+    hasher: &impl NoiseHasher,
+    lattice: &impl Fn(IVec2) -> IVec2,
+) -> [f32; 4] {
+    LATTICE_CORNERS_2D.map(|corner| hasher.hash_22f_seeded(lattice(i + corner)).x)
+}
 
-    let va = hasher.hash_22f_seeded(i + ivec2(0, 0)).x;
-    let vb = hasher.hash_22f_seeded(i + ivec2(1, 0)).x;
-    let vc = hasher.hash_22f_seeded(i + ivec2(0, 1)).x;
-    let vd = hasher.hash_22f_seeded(i + ivec2(1, 1)).x;
+fn value_fade(f: f32) -> [f32; 3] {
+    [
+        f * f * f * (f * (f * 6.0 - 15.0) + 10.0),
+        30.0 * f * f * (f * (f - 2.0) + 1.0),
+        60.0 * f * (1.0 + f * (-3.0 + 2.0 * f)),
+    ]
+}
+
+/// Value noise of a point in a cell, from its fade and the values of the corners.
+fn value_noise_2d_cell(fade: Fade, corners: [f32; 4], scale: f32) -> Value2Dt2 {
+    let Fade { u, du, ddu } = fade;
+    let [va, vb, vc, vd] = corners;
 
     let k0 = va;
     let k1 = vb - va;
@@ -33,37 +70,83 @@ pub fn value_noise_2d(unscaled_p: Vec2, scale: f32, hasher: &impl NoiseHasher) -
         ddu.x * (k1 + k4 * u.y),
         ddu.y * (k2 + k4 * u.x),
         du.x * k4 * du.y,
-    );
+    ) * (scale * scale);
     Value2Dt2::new(v, de, he)
 }
 
 // Point has to be unscaled
 // output  [-1, 1]
 pub fn perlin_noise_2d(unscaled_p: Vec2, scale: f32, hasher: &impl NoiseHasher) -> Value2Dt2 {
+    perlin_noise_2d_lattice(unscaled_p, scale, hasher, |cell| cell)
+}
+
+// Same as perlin_noise_2d, repeats every `period` cells, so every `period / scale` unscaled.
+// output  [-1, 1]
+pub fn perlin_noise_2d_periodic(
+    unscaled_p: Vec2,
+    scale: f32,
+    period: UVec2,
+    hasher: &impl NoiseHasher,
+) -> Value2Dt2 {
+    perlin_noise_2d_lattice(unscaled_p, scale, hasher, periodic_lattice(period))
+}
+
+/// Wraps lattice cells into `[0, period)`.
+fn periodic_lattice(period: UVec2) -> impl Fn(IVec2) -> IVec2 {
+    let period = period.max(UVec2::ONE).as_ivec2();
+    move |cell| cell.rem_euclid(period)
+}
+
+/// Lattice cells are mapped through `lattice` before they are hashed.
+fn perlin_noise_2d_lattice(
+    unscaled_p: Vec2,
+    scale: f32,
+    hasher: &impl NoiseHasher,
+    lattice: impl Fn(IVec2) -> IVec2,
+) -> Value2Dt2 {
     let p = unscaled_p * scale;
     let i = p.floor().as_ivec2();
     let f = p.fract_gl();
 
-    // quintic interpolation
-    // u(x) = 6x^5 - 15x^4 + 10x^3
-    // v(y) = 6y^5 - 15y^4 + 10y^3
-    let uv = f * f * f * (6.0 * f * f - 15.0 * f + 10.0);
-    // d/dx u(x) = 30x^4 - 60x^3 + 30x^2
-    // d/dy v(y) = 30y^4 - 60y^3 + 30y^2
-    let duv = 30.0 * f * f * (f - 1.0) * (f - 1.0);
+    let corners = perlin_noise_2d_corners(i, hasher, &lattice);
+    perlin_noise_2d_cell(
+        f,
+        Fade::new(perlin_fade(f.x), perlin_fade(f.y)),
+        corners,
+        scale,
+    )
+}
 
-    // d/dx^2 u(x) = 120x^3 - 180x^2 + 60x
-    // d/dy^2 v(y) = 120y^3 - 180y^2 + 60y
-    let dduv = 60.0 * f * (2.0 * f - 1.0) * (f - 1.0);
+fn perlin_noise_2d_corners(
+    i: IVec2,
+    hasher: &impl NoiseHasher,
+    lattice: &impl Fn(IVec2) -> IVec2,
+) -> [Vec2; 4] {
+    LATTICE_CORNERS_2D.map(|corner| hasher.hash_22f_seeded(lattice(i + corner)))
+}
 
-    // d/dx^3 u(x) = 360x^2 - 360x + 60
-    // d/dy^3 v(y) = 360y^2 - 360y + 60
-    // let ddduv = 60.0 * (6.0 * f * f - 6.0 * f + 1.0);
+fn perlin_fade(f: f32) -> [f32; 3] {
+    [
+        // quintic interpolation
+        // u(x) = 6x^5 - 15x^4 + 10x^3
+        f * f * f * (6.0 * f * f - 15.0 * f + 10.0),
+        // d/dx u(x) = 30x^4 - 60x^3 + 30x^2
+        30.0 * f * f * (f - 1.0) * (f - 1.0),
+        // d/dx^2 u(x) = 120x^3 - 180x^2 + 60x
+        60.0 * f * (2.0 * f - 1.0) * (f - 1.0),
+        // d/dx^3 u(x) = 360x^2 - 360x + 60
+        // 60.0 * (6.0 * f * f - 6.0 * f + 1.0)
+    ]
+}
 
-    let ga = hasher.hash_22f_seeded(i + ivec2(0, 0));
-    let gb = hasher.hash_22f_seeded(i + ivec2(1, 0));
-    let gc = hasher.hash_22f_seeded(i + ivec2(0, 1));
-    let gd = hasher.hash_22f_seeded(i + ivec2(1, 1));
+/// Perlin noise of the point `f` in a cell, from the gradients of its corners.
+fn perlin_noise_2d_cell(f: Vec2, fade: Fade, corners: [Vec2; 4], scale: f32) -> Value2Dt2 {
+    let Fade {
+        u: uv,
+        du: duv,
+        ddu: dduv,
+    } = fade;
+    let [ga, gb, gc, gd] = corners;
 
     let va = ga.dot(f - vec2(0.0, 0.0));
     let vb = gb.dot(f - vec2(1.0, 0.0));
@@ -171,6 +254,580 @@ pub fn perlin_noise_2d(unscaled_p: Vec2, scale: f32, hasher: &impl NoiseHasher)
     // Value2Dt3::new(value, d1, d2, d3)
 }
 
+/// Corners of a 2D lattice cell.
+const LATTICE_CORNERS_2D: [IVec2; 4] = [
+    IVec2::new(0, 0),
+    IVec2::new(1, 0),
+    IVec2::new(0, 1),
+    IVec2::new(1, 1),
+];
+
+/// Fade curve of a point in a cell along both axes, and its first two derivatives.
+#[derive(Clone, Copy)]
+struct Fade {
+    u: Vec2,
+    du: Vec2,
+    ddu: Vec2,
+}
+
+impl Fade {
+    fn new([ux, dux, ddux]: [f32; 3], [uy, duy, dduy]: [f32; 3]) -> Self {
+        Self {
+            u: vec2(ux, uy),
+            du: vec2(dux, duy),
+            ddu: vec2(ddux, dduy),
+        }
+    }
+}
+
+/// [`value_noise_2d`] on a grid of `size` points spaced by `step` from `origin`, row by row.
+pub fn value_noise_2d_grid(
+    origin: Vec2,
+    step: f32,
+    size: UVec2,
+    scale: f32,
+    hasher: &impl NoiseHasher,
+    out: &mut [Value2Dt2],
+) {
+    let grid = LatticeGrid::new(origin, step, size, scale, value_fade);
+    grid.sample(
+        out,
+        |i| hasher.hash_22f_seeded(i).x,
+        |_, fade, corners| value_noise_2d_cell(fade, corners, scale),
+    );
+}
+
+/// [`perlin_noise_2d`] on a grid of `size` points spaced by `step` from `origin`, row by row.
+pub fn perlin_noise_2d_grid(
+    origin: Vec2,
+    step: f32,
+    size: UVec2,
+    scale: f32,
+    hasher: &impl NoiseHasher,
+    out: &mut [Value2Dt2],
+) {
+    let grid = LatticeGrid::new(origin, step, size, scale, perlin_fade);
+    grid.sample(
+        out,
+        |i| hasher.hash_22f_seeded(i),
+        |f, fade, corners| perlin_noise_2d_cell(f, fade, corners, scale),
+    );
+}
+
+/// Lattice cells and fades of the columns and rows of a grid.
+/// Every column shares its fade along x and every row its fade along y, so they are computed
+/// once per axis instead of once per point, with the same arithmetic as the scalar noise so
+/// both give identical results.
+struct LatticeGrid {
+    columns: LatticeAxis,
+    rows: LatticeAxis,
+}
+
+/// Points of a grid along one axis, every field is an array with an entry per point
+/// so a row is evaluated from contiguous memory.
+struct LatticeAxis {
+    /// Lattice coordinates the points need, both corners of their cell, sorted and unique.
+    lattice: Vec<i32>,
+    /// Index in `lattice` of the near corner of the cell of every point,
+    /// the far corner is the next one.
+    cells: Vec<usize>,
+    f: Vec<f32>,
+    u: Vec<f32>,
+    du: Vec<f32>,
+    ddu: Vec<f32>,
+}
+
+impl LatticeAxis {
+    fn new(origin: f32, step: f32, count: u32, scale: f32, fade: fn(f32) -> [f32; 3]) -> Self {
+        let count = count as usize;
+        let mut floors = Vec::with_capacity(count);
+        let mut axis = Self {
+            lattice: Vec::new(),
+            cells: Vec::with_capacity(count),
+            f: Vec::with_capacity(count),
+            u: Vec::with_capacity(count),
+            du: Vec::with_capacity(count),
+            ddu: Vec::with_capacity(count),
+        };
+
+        for index in 0..count {
+            let p = (origin + index as f32 * step) * scale;
+            let f = p - p.floor();
+            let [u, du, ddu] = fade(f);
+            floors.push(p.floor() as i32);
+            axis.f.push(f);
+            axis.u.push(u);
+            axis.du.push(du);
+            axis.ddu.push(ddu);
+        }
+
+        axis.lattice = floors
+            .iter()
+            .flat_map(|&cell| [cell, cell + 1])
+            .sorted_unstable()
+            .dedup()
+            .collect();
+        axis.cells = floors
+            .iter()
+            .map(|cell| axis.lattice.binary_search(cell).unwrap())
+            .collect();
+        axis
+    }
+
+    fn len(&self) -> usize {
+        self.f.len()
+    }
+}
+
+impl LatticeGrid {
+    fn new(origin: Vec2, step: f32, size: UVec2, scale: f32, fade: fn(f32) -> [f32; 3]) -> Self {
+        Self {
+            columns: LatticeAxis::new(origin.x, step, size.x, scale, fade),
+            rows: LatticeAxis::new(origin.y, step, size.y, scale, fade),
+        }
+    }
+
+    /// Every lattice point the grid needs is hashed once, then the grid is evaluated row by row.
+    fn sample<H: Copy>(
+        &self,
+        out: &mut [Value2Dt2],
+        hash: impl Fn(IVec2) -> H,
+        cell: impl Fn(Vec2, Fade, [H; 4]) -> Value2Dt2,
+    ) {
+        let Self { columns, rows } = self;
+        assert_eq!(out.len(), columns.len() * rows.len());
+        if out.is_empty() {
+            return;
+        }
+
+        let width = columns.lattice.len();
+        let mut hashes = Vec::with_capacity(width * rows.lattice.len());
+        for &y in &rows.lattice {
+            hashes.extend(columns.lattice.iter().map(|&x| hash(ivec2(x, y))));
+        }
+
+        for (row, out_row) in out.chunks_exact_mut(columns.len()).enumerate() {
+            let near = &hashes[rows.cells[row] * width..];
+            let far = &hashes[(rows.cells[row] + 1) * width..];
+            for (column, out) in out_row.iter_mut().enumerate() {
+                let x = columns.cells[column];
+                let fade = Fade {
+                    u: vec2(columns.u[column], rows.u[row]),
+                    du: vec2(columns.du[column], rows.du[row]),
+                    ddu: vec2(columns.ddu[column], rows.ddu[row]),
+                };
+                let corners = [near[x], near[x + 1], far[x], far[x + 1]];
+                *out = cell(vec2(columns.f[column], rows.f[row]), fade, corners);
+            }
+        }
+    }
+}
+
+/// Corners of a 3D lattice cell, the index of a corner is x + 2y + 4z.
+const CELL_CORNERS_3D: [IVec3; 8] = [
+    IVec3::new(0, 0, 0),
+    IVec3::new(1, 0, 0),
+    IVec3::new(0, 1, 0),
+    IVec3::new(1, 1, 0),
+    IVec3::new(0, 0, 1),
+    IVec3::new(1, 0, 1),
+    IVec3::new(0, 1, 1),
+    IVec3::new(1, 1, 1),
+];
+
+// Point has to be unscaled
+// output  [0, 1]
+pub fn value_noise_3d(unscaled_p: Vec3, scale: f32, hasher: &impl NoiseHasher) -> ValueDt3 {
+    let p = unscaled_p * scale;
+    let i = p.floor().as_ivec3();
+    let (u, du) = quintic_3d(p - p.floor());
+
+    let values = CELL_CORNERS_3D.map(|corner| hasher.hash_33f_seeded(i + corner).x);
+    let result = interpolate_cell_3d(values, u, du);
+
+    ValueDt3::new(result.value, result.derivative * scale)
+}
+
+// https://iquilezles.org/articles/gradientnoise/
+// Point has to be unscaled
+// output  [-1, 1]
+pub fn perlin_noise_3d(unscaled_p: Vec3, scale: f32, hasher: &impl NoiseHasher) -> ValueDt3 {
+    let p = unscaled_p * scale;
+    let i = p.floor().as_ivec3();
+    let f = p - p.floor();
+    let (u, du) = quintic_3d(f);
+
+    let gradients = CELL_CORNERS_3D.map(|corner| gradient_3d(i + corner, hasher));
+    let values =
+        std::array::from_fn(|index| gradients[index].dot(f - CELL_CORNERS_3D[index].as_vec3()));
+    let result = interpolate_cell_3d(values, u, du);
+
+    // the corner values are linear in the point, their gradients are blended with the same weights
+    let blended_gradient = CELL_CORNERS_3D
+        .iter()
+        .zip(gradients)
+        .map(|(corner, gradient)| {
+            let corner = corner.as_vec3();
+            let weight = corner * u + (1.0 - corner) * (1.0 - u);
+            gradient * weight.element_product()
+        })
+        .sum::<Vec3>();
+
+    ValueDt3::new(result.value, (result.derivative + blended_gradient) * scale)
+}
+
+/// Quintic fade curve 6x^5 - 15x^4 + 10x^3 and its derivative.
+fn quintic_3d(f: Vec3) -> (Vec3, Vec3) {
+    let u = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+    let du = 30.0 * f * f * (f - 1.0) * (f - 1.0);
+    (u, du)
+}
+
+/// Blends the corners of a cell, `values` are ordered as [`CELL_CORNERS_3D`].
+/// The derivative only accounts for the fade curve, corner values are taken as constants.
+fn interpolate_cell_3d(values: [f32; 8], u: Vec3, du: Vec3) -> ValueDt3 {
+    let [va, vb, vc, vd, ve, vf, vg, vh] = values;
+
+    let k0 = va;
+    let k1 = vb - va;
+    let k2 = vc - va;
+    let k3 = ve - va;
+    let k4 = va - vb - vc + vd;
+    let k5 = va - vc - ve + vg;
+    let k6 = va - vb - ve + vf;
+    let k7 = -va + vb + vc - vd + ve - vf - vg + vh;
+
+    let value = k0
+        + k1 * u.x
+        + k2 * u.y
+        + k3 * u.z
+        + k4 * u.x * u.y
+        + k5 * u.y * u.z
+        + k6 * u.z * u.x
+        + k7 * u.x * u.y * u.z;
+    let derivative = du
+        * vec3(
+            k1 + k4 * u.y + k6 * u.z + k7 * u.y * u.z,
+            k2 + k4 * u.x + k5 * u.z + k7 * u.x * u.z,
+            k3 + k5 * u.y + k6 * u.x + k7 * u.x * u.y,
+        );
+
+    ValueDt3::new(value, derivative)
+}
+
+/// (sqrt(3) - 1) / 2, skews a point onto the simplex lattice.
+const SIMPLEX_2D_SKEW: f32 = 0.366_025_42;
+/// (3 - sqrt(3)) / 6, unskews a lattice point.
+const SIMPLEX_2D_UNSKEW: f32 = 0.211_324_87;
+/// Brings the sum of the corner contributions back to [-1, 1].
+const SIMPLEX_2D_NORMALIZATION: f32 = 70.0;
+const SIMPLEX_3D_SKEW: f32 = 1.0 / 3.0;
+const SIMPLEX_3D_UNSKEW: f32 = 1.0 / 6.0;
+/// A corner contributes at most (4/9)^4 / (3 sqrt(2)) ~ 0.0092, 1 / (3 sqrt(2)) away from it,
+/// the other corners add about 1% there. Maps the sum to about [-0.97, 0.97].
+const SIMPLEX_3D_NORMALIZATION: f32 = 104.0;
+
+fn gradient_2d(cell: IVec2, hasher: &impl NoiseHasher) -> Vec2 {
+    hasher.hash_22f_seeded(cell).normalize_or_zero()
+}
+
+fn gradient_3d(cell: IVec3, hasher: &impl NoiseHasher) -> Vec3 {
+    (hasher.hash_33f_seeded(cell) * 2.0 - 1.0).normalize_or_zero()
+}
+
+// https://weber.itn.liu.se/~stegu/simplexnoise/simplexnoise.pdf
+// Corners fall off with (0.5 - d²)^4, the noise and its derivatives are continuous.
+// Point has to be unscaled
+// output  [-1, 1]
+pub fn simplex_noise_2d(unscaled_p: Vec2, scale: f32, hasher: &impl NoiseHasher) -> Value2Dt2 {
+    let p = unscaled_p * scale;
+    let i = (p + (p.x + p.y) * SIMPLEX_2D_SKEW).floor();
+    let d0 = p - (i - (i.x + i.y) * SIMPLEX_2D_UNSKEW);
+
+    // lower or upper triangle of the skewed cell
+    let i1 = if d0.x > d0.y { IVec2::X } else { IVec2::Y };
+    let corners = [
+        (IVec2::ZERO, d0),
+        (i1, d0 - i1.as_vec2() + SIMPLEX_2D_UNSKEW),
+        (IVec2::ONE, d0 - 1.0 + 2.0 * SIMPLEX_2D_UNSKEW),
+    ];
+
+    let cell = i.as_ivec2();
+    let mut value = 0.0;
+    let mut d1 = Vec2::ZERO;
+    let mut d2 = Vec3::ZERO;
+    for (offset, d) in corners {
+        let t = 0.5 - d.length_squared();
+        if t <= 0.0 {
+            continue;
+        }
+
+        let g = gradient_2d(cell + offset, hasher);
+        let a = g.dot(d);
+        let t2 = t * t;
+        let t3 = t2 * t;
+
+        // n = t^4 a
+        value += t2 * t2 * a;
+        // dn = t^4 g - 8 t^3 a d
+        d1 += t2 * t2 * g - 8.0 * t3 * a * d;
+        // ddn_ij = -8 t^3 (g_i d_j + g_j d_i) + 48 t^2 a d_i d_j - 8 t^3 a δ_ij
+        d2 += vec3(
+            -16.0 * t3 * g.x * d.x + 48.0 * t2 * a * d.x * d.x - 8.0 * t3 * a,
+            -16.0 * t3 * g.y * d.y + 48.0 * t2 * a * d.y * d.y - 8.0 * t3 * a,
+            -8.0 * t3 * (g.x * d.y + g.y * d.x) + 48.0 * t2 * a * d.x * d.y,
+        );
+    }
+
+    Value2Dt2::new(value, d1 * scale, d2 * (scale * scale)) * SIMPLEX_2D_NORMALIZATION
+}
+
+// Same as simplex_noise_2d on a tetrahedral lattice, without the Hessian.
+// Corners fall off with (0.5 - d²)^4 instead of the usual (0.6 - d²)^4, which reaches past
+// the tetrahedron and makes the noise jump where the corners of a point change.
+// Point has to be unscaled
+// output  [-1, 1]
+pub fn simplex_noise_3d(unscaled_p: Vec3, scale: f32, hasher: &impl NoiseHasher) -> ValueDt3 {
+    let p = unscaled_p * scale;
+    let i = (p + p.element_sum() * SIMPLEX_3D_SKEW).floor();
+    let d0 = p - (i - i.element_sum() * SIMPLEX_3D_UNSKEW);
+
+    // the components of d0 ordered from the biggest pick the tetrahedron of the skewed cell
+    let (i1, i2) = if d0.x >= d0.y {
+        if d0.y >= d0.z {
+            (IVec3::X, ivec3(1, 1, 0))
+        } else if d0.x >= d0.z {
+            (IVec3::X, ivec3(1, 0, 1))
+        } else {
+            (IVec3::Z, ivec3(1, 0, 1))
+        }
+    } else if d0.y < d0.z {
+        (IVec3::Z, ivec3(0, 1, 1))
+    } else if d0.x < d0.z {
+        (IVec3::Y, ivec3(0, 1, 1))
+    } else {
+        (IVec3::Y, ivec3(1, 1, 0))
+    };
+    let corners = [
+        (IVec3::ZERO, d0),
+        (i1, d0 - i1.as_vec3() + SIMPLEX_3D_UNSKEW),
+        (i2, d0 - i2.as_vec3() + 2.0 * SIMPLEX_3D_UNSKEW),
+        (IVec3::ONE, d0 - 1.0 + 3.0 * SIMPLEX_3D_UNSKEW),
+    ];
+
+    let cell = i.as_ivec3();
+    let mut value = 0.0;
+    let mut derivative = Vec3::ZERO;
+    for (offset, d) in corners {
+        let t = 0.5 - d.length_squared();
+        if t <= 0.0 {
+            continue;
+        }
+
+        let g = gradient_3d(cell + offset, hasher);
+        let a = g.dot(d);
+        let t3 = t * t * t;
+
+        value += t3 * t * a;
+        derivative += t3 * t * g - 8.0 * t3 * a * d;
+    }
+
+    ValueDt3::new(
+        value * SIMPLEX_3D_NORMALIZATION,
+        derivative * scale * SIMPLEX_3D_NORMALIZATION,
+    )
+}
+
+/// Distances to the closest and the second closest feature point, there is a feature point per cell.
+/// Distances are in cells, derivatives are with respect to the unscaled point.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct WorleyDt2 {
+    pub f1: Value2Dt2,
+    pub f2: Value2Dt2,
+}
+
+impl WorleyDt2 {
+    /// 0 on the borders between cells, grows towards their feature point.
+    pub fn f2_minus_f1(&self) -> Value2Dt2 {
+        self.f2 - self.f1
+    }
+}
+
+// https://www.rhythmiccanvas.com/research/papers/worley.pdf
+// Derivatives are not continuous where the closest feature points swap, on the borders between cells.
+// Point has to be unscaled
+// output  f1 in [0, sqrt(2)], f2 in [f1, 2 sqrt(2)]
+pub fn worley_noise_2d(unscaled_p: Vec2, scale: f32, hasher: &impl NoiseHasher) -> WorleyDt2 {
+    let p = unscaled_p * scale;
+    let cell = p.floor().as_ivec2();
+
+    // squared distance and offset from the feature point
+    let mut closest = [(f32::INFINITY, Vec2::ZERO); 2];
+    // feature points can be anywhere in their cell, the second closest one is less than
+    // 2 cells away and every cell 3 cells away is further than that
+    for (x, y) in (-2..=2).cartesian_product(-2..=2) {
+        let neighbour = cell + ivec2(x, y);
+        let feature = neighbour.as_vec2() + hasher.hash_22f_seeded(neighbour) * 0.5 + 0.5;
+        let offset = p - feature;
+        let distance = offset.length_squared();
+
+        if distance < closest[0].0 {
+            closest[1] = closest[0];
+            closest[0] = (distance, offset);
+        } else if distance < closest[1].0 {
+            closest[1] = (distance, offset);
+        }
+    }
+
+    let [f1, f2] = closest.map(|(_, offset)| feature_distance(offset, scale));
+    WorleyDt2 { f1, f2 }
+}
+
+fn feature_distance(offset: Vec2, scale: f32) -> Value2Dt2 {
+    let distance = offset.length().max(f32::EPSILON);
+    let n = offset / distance;
+
+    // d|x| = x / |x|
+    // dd|x| = (I - n nᵀ) / |x|
+    let d2 = vec3(1.0 - n.x * n.x, 1.0 - n.y * n.y, -n.x * n.y) / distance;
+    Value2Dt2::new(distance, n * scale, d2 * (scale * scale))
+}
+
+/// Octaves summed by the fractal noises, each octave has `lacunarity` times the frequency
+/// and `gain` times the amplitude of the previous one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fbm {
+    pub octaves: u32,
+    pub lacunarity: f32,
+    pub gain: f32,
+}
+
+impl Default for Fbm {
+    fn default() -> Self {
+        Self {
+            octaves: 5,
+            lacunarity: 2.0,
+            gain: 0.5,
+        }
+    }
+}
+
+impl Fbm {
+    /// Frequency, amplitude and hasher of every octave, amplitudes sum to 1.
+    fn octaves<H: NoiseHasher>(&self, hasher: &H) -> impl Iterator<Item = (f32, f32, H)> {
+        let Fbm {
+            octaves,
+            lacunarity,
+            gain,
+        } = *self;
+        let total_amplitude = (0..self.octaves)
+            .map(|i| self.gain.powi(i as i32))
+            .sum::<f32>();
+        let hashers = std::iter::successors(Some(hasher.with_seed(hasher.seed())), |hasher| {
+            Some(hasher.with_next_seed())
+        });
+
+        (0..octaves).zip(hashers).map(move |(i, hasher)| {
+            let frequency = lacunarity.powi(i as i32);
+            let amplitude = gain.powi(i as i32) / total_amplitude;
+            (frequency, amplitude, hasher)
+        })
+    }
+}
+
+// Point has to be unscaled
+// output  range of `noise`
+pub fn fbm_noise_2d<H: NoiseHasher>(
+    unscaled_p: Vec2,
+    scale: f32,
+    fbm: &Fbm,
+    hasher: &H,
+    noise: impl Fn(Vec2, f32, &H) -> Value2Dt2,
+) -> Value2Dt2 {
+    fbm.octaves(hasher)
+        .map(|(frequency, amplitude, hasher)| {
+            noise(unscaled_p, scale * frequency, &hasher) * amplitude
+        })
+        .fold(Value2Dt2::default(), |total, octave| total + octave)
+}
+
+// Rounded hills and sharp valleys, fBm of the absolute value of the noise.
+// Point has to be unscaled
+// output  [-1, 1] for a noise in [-1, 1]
+pub fn billow_noise_2d<H: NoiseHasher>(
+    unscaled_p: Vec2,
+    scale: f32,
+    fbm: &Fbm,
+    hasher: &H,
+    noise: impl Fn(Vec2, f32, &H) -> Value2Dt2,
+) -> Value2Dt2 {
+    fbm_noise_2d(unscaled_p, scale, fbm, hasher, |p, scale, hasher| {
+        noise(p, scale, hasher).abs() * 2.0 + -1.0
+    })
+}
+
+// https://www.classes.cs.uchicago.edu/archive/2015/fall/23700-1/final-project/MusgraveTerrain00.pdf
+// Sharp ridges, each octave is weighted by the previous one so valleys stay smooth.
+// Point has to be unscaled
+// output  [0, 1] for a noise in [-1, 1]
+pub fn ridged_multifractal_noise_2d<H: NoiseHasher>(
+    unscaled_p: Vec2,
+    scale: f32,
+    fbm: &Fbm,
+    hasher: &H,
+    noise: impl Fn(Vec2, f32, &H) -> Value2Dt2,
+) -> Value2Dt2 {
+    let mut weight = Value2Dt2::new(1.0, Vec2::ZERO, Vec3::ZERO);
+    let mut total = Value2Dt2::default();
+
+    for (frequency, amplitude, hasher) in fbm.octaves(hasher) {
+        let ridge = 1.0 + -noise(unscaled_p, scale * frequency, &hasher).abs();
+        let signal = ridge * ridge * weight;
+        total = total + signal * amplitude;
+        weight = signal;
+    }
+
+    total
+}
+
+// https://iquilezles.org/articles/warp/
+// `sample` at `pos` moved by the vector field `offset`, J = I + ∂offset/∂pos carries the derivatives over.
+// output  range of `sample`
+pub fn warp(
+    pos: Vec2,
+    offset: impl Fn(Vec2) -> [Value2Dt2; 2],
+    sample: impl Fn(Vec2) -> Value2Dt2,
+) -> Value2Dt2 {
+    let [x, y] = offset(pos);
+    let warped = sample(pos + vec2(x.value, y.value));
+
+    // rows are the gradients of the warped position
+    let jacobian = Mat2::from_cols(
+        vec2(1.0 + x.d1.0.x, y.d1.0.x),
+        vec2(x.d1.0.y, 1.0 + y.d1.0.y),
+    );
+    let gradient = warped.d1.0;
+
+    // H = Jᵀ H_f J + Σ ∂f/∂w_k H_w_k
+    let hessian = jacobian.transpose() * hessian_matrix(warped.d2) * jacobian
+        + hessian_matrix(x.d2) * gradient.x
+        + hessian_matrix(y.d2) * gradient.y;
+
+    Value2Dt2::new(
+        warped.value,
+        jacobian.transpose() * gradient,
+        hessian_components(hessian),
+    )
+}
+
+fn hessian_matrix(d2: Vec3) -> Mat2 {
+    Mat2::from_cols(vec2(d2.x, d2.z), vec2(d2.z, d2.y))
+}
+
+fn hessian_components(hessian: Mat2) -> Vec3 {
+    vec3(hessian.x_axis.x, hessian.y_axis.y, hessian.y_axis.x)
+}
+
 pub fn fract_gl(v: f32) -> f32 {
     v - v.floor()
 }
@@ -283,6 +940,27 @@ mod tests {
         assert!((result.d1.0.y - numerical_derivative_y).abs() < 0.01);
     }
 
+    #[test]
+    fn value_noise_2d_hessian() {
+        let hasher = SimpleHasher::new(3);
+
+        for scale in [0.25, 1.0, 2.0] {
+            for position in [vec2(1.3, 2.7), vec2(-4.1, 0.6), vec2(7.45, -3.2)] {
+                let position = position / scale;
+                let expected =
+                    estimate_dt2(position, |pos| value_noise_2d(pos, scale, &hasher).value);
+                let received = value_noise_2d(position, scale, &hasher).d2;
+
+                for (label, received, expected) in zip_hessians(received, expected) {
+                    assert!(
+                        (expected - received).abs() < 0.05 * (1.0 + expected.abs()),
+                        "scale {scale} {label}: {received} != {expected}",
+                    );
+                }
+            }
+        }
+    }
+
     #[test]
     fn perlin_noise_2d_range() {
         let scales = [0.1, 1.0, 1.5, 10.0];
@@ -346,6 +1024,309 @@ mod tests {
         }
     }
 
+    #[test]
+    fn periodic_noise_2d_tiles() {
+        let hasher = SimpleHasher::new(7);
+        let scale = 0.25;
+        let period = uvec2(3, 5);
+        let unscaled_period = period.as_vec2() / scale;
+
+        for (x, y) in itertools::iproduct!(-10..10, -10..10) {
+            let p = vec2(x as f32, y as f32) * 1.37;
+            for noise in [value_noise_2d_periodic, perlin_noise_2d_periodic] {
+                let result = noise(p, scale, period, &hasher);
+                for offset in [vec2(unscaled_period.x, 0.0), vec2(0.0, -unscaled_period.y)] {
+                    let wrapped = noise(p + offset, scale, period, &hasher);
+                    assert!((result.value - wrapped.value).abs() < 1e-4, "{p} {offset}");
+                    assert!((result.d1.0 - wrapped.d1.0).length() < 1e-3, "{p} {offset}");
+                }
+            }
+        }
+
+        assert_derivatives(vec2(1.3, 2.7), |p| {
+            perlin_noise_2d_periodic(p, 1.0, period, &hasher)
+        });
+    }
+
+    #[test]
+    fn noise_2d_grid_matches_points() {
+        type Grid = fn(Vec2, f32, UVec2, f32, &SimpleHasher, &mut [Value2Dt2]);
+        type Point = fn(Vec2, f32, &SimpleHasher) -> Value2Dt2;
+        let noises: [(Grid, Point); 2] = [
+            (value_noise_2d_grid, value_noise_2d),
+            (perlin_noise_2d_grid, perlin_noise_2d),
+        ];
+        let hasher = SimpleHasher::new(3);
+        let (origin, step, size) = (vec2(-7.3, 2.1), 0.7, uvec2(23, 17));
+        let mut out = vec![Value2Dt2::default(); (size.x * size.y) as usize];
+
+        for scale in [0.05, 0.3, 1.0, 2.5] {
+            for (grid, point) in noises {
+                grid(origin, step, size, scale, &hasher, &mut out);
+                for (index, result) in out.iter().enumerate() {
+                    let index = uvec2(index as u32 % size.x, index as u32 / size.x);
+                    let p = origin + index.as_vec2() * step;
+                    assert_eq!(*result, point(p, scale, &hasher), "{p} {scale}");
+                }
+            }
+
+            // Every lattice point is hashed once per grid.
+            let grid = LatticeGrid::new(origin, step, size, scale, value_fade);
+            let hashed = std::cell::RefCell::new(Vec::new());
+            grid.sample(
+                &mut out,
+                |i| hashed.borrow_mut().push(i),
+                |_, _, _| Value2Dt2::default(),
+            );
+            let hashed = hashed.into_inner();
+            assert_eq!(hashed.len(), hashed.iter().unique().count(), "{scale}");
+        }
+    }
+
+    #[test]
+    fn simplex_noise_2d_range() {
+        let scales = [0.1, 1.0, 1.5, 10.0];
+        let seeds = [0, 100, 1000, 10000];
+
+        for (x, y, scale, seed) in itertools::iproduct!(-100..100, -100..100, scales, seeds) {
+            let hasher = SimpleHasher::new(seed);
+            let result = simplex_noise_2d(vec2(x as f32, y as f32) / 3.0, scale, &hasher);
+
+            assert!(result.value.abs() <= 1.0, "{}", result.value);
+        }
+    }
+
+    #[test]
+    fn simplex_noise_2d_derivative() {
+        let hasher = SimpleHasher::new(0);
+        assert_derivatives(vec2(1.5, 2.5), |p| simplex_noise_2d(p, 1.0, &hasher));
+        assert_derivatives(vec2(-3.2, 0.7), |p| simplex_noise_2d(p, 0.5, &hasher));
+    }
+
+    #[test]
+    fn value_noise_3d_range() {
+        let hasher = SimpleHasher::new(0);
+
+        for (x, y, z) in itertools::iproduct!(-20..20, -20..20, -20..20) {
+            let result = value_noise_3d(vec3(x as f32, y as f32, z as f32) / 3.0, 1.0, &hasher);
+
+            assert!((0.0..=1.0).contains(&result.value), "{}", result.value);
+        }
+    }
+
+    #[test]
+    fn value_noise_3d_derivative() {
+        let hasher = SimpleHasher::new(0);
+        assert_derivatives_3d(vec3(1.3, 2.7, -0.4), |p| value_noise_3d(p, 1.0, &hasher));
+        assert_derivatives_3d(vec3(-7.1, 0.2, 3.9), |p| value_noise_3d(p, 0.3, &hasher));
+    }
+
+    #[test]
+    fn perlin_noise_3d_range() {
+        let seeds = [0, 100, 1000, 10000];
+
+        for (x, y, z, seed) in itertools::iproduct!(-20..20, -20..20, -20..20, seeds) {
+            let hasher = SimpleHasher::new(seed);
+            let result = perlin_noise_3d(vec3(x as f32, y as f32, z as f32) / 3.0, 1.0, &hasher);
+
+            assert!(result.value.abs() <= 1.0, "{}", result.value);
+        }
+    }
+
+    #[test]
+    fn perlin_noise_3d_derivative() {
+        let hasher = SimpleHasher::new(0);
+        assert_derivatives_3d(vec3(1.3, 2.7, -0.4), |p| perlin_noise_3d(p, 1.0, &hasher));
+        assert_derivatives_3d(vec3(-7.1, 0.2, 3.9), |p| perlin_noise_3d(p, 0.3, &hasher));
+    }
+
+    #[test]
+    fn simplex_noise_3d_range() {
+        let seeds = [0, 100, 1000, 10000];
+        let mut max = 0.0_f32;
+
+        for (x, y, z, seed) in itertools::iproduct!(-30..30, -30..30, -30..30, seeds) {
+            let hasher = SimpleHasher::new(seed);
+            let result = simplex_noise_3d(vec3(x as f32, y as f32, z as f32) / 3.0, 1.0, &hasher);
+
+            assert!(result.value.abs() <= 1.0, "{}", result.value);
+            max = max.max(result.value.abs());
+        }
+        // the normalization is not so conservative that the noise is flat
+        assert!(max > 0.8, "{max}");
+    }
+
+    #[test]
+    fn simplex_noise_3d_derivative() {
+        let hasher = SimpleHasher::new(0);
+        assert_derivatives_3d(vec3(1.5, 2.5, -0.7), |p| simplex_noise_3d(p, 0.8, &hasher));
+    }
+
+    #[test]
+    fn worley_noise_2d_derivative() {
+        let hasher = SimpleHasher::new(0);
+        let position = vec2(1.3, 2.7);
+
+        assert_derivatives(position, |p| worley_noise_2d(p, 1.0, &hasher).f1);
+        assert_derivatives(position, |p| worley_noise_2d(p, 1.0, &hasher).f2);
+        assert_derivatives(position, |p| worley_noise_2d(p, 1.0, &hasher).f2_minus_f1());
+    }
+
+    #[test]
+    fn worley_noise_2d_range() {
+        let hasher = SimpleHasher::new(0);
+
+        for (x, y) in (-100..100).cartesian_product(-100..100) {
+            let result = worley_noise_2d(vec2(x as f32, y as f32) / 3.0, 1.0, &hasher);
+
+            assert!((0.0..=2.0f32.sqrt()).contains(&result.f1.value));
+            assert!(result.f1.value <= result.f2.value);
+        }
+    }
+
+    #[test]
+    fn worley_noise_2d_matches_brute_force() {
+        let scale = 0.7;
+
+        for seed in [0, 7, 1000] {
+            let hasher = SimpleHasher::new(seed);
+            for (x, y) in (-40..40).cartesian_product(-40..40) {
+                let position = vec2(x as f32, y as f32) * 0.37;
+                let p = position * scale;
+                let cell = p.floor().as_ivec2();
+                let distances = (-5..=5)
+                    .cartesian_product(-5..=5)
+                    .map(|(x, y)| {
+                        let neighbour = cell + ivec2(x, y);
+                        let feature =
+                            neighbour.as_vec2() + hasher.hash_22f_seeded(neighbour) * 0.5 + 0.5;
+                        p.distance(feature)
+                    })
+                    .sorted_by(f32::total_cmp)
+                    .collect_vec();
+
+                let result = worley_noise_2d(position, scale, &hasher);
+                assert!((result.f1.value - distances[0]).abs() < 1e-5, "{position}");
+                assert!((result.f2.value - distances[1]).abs() < 1e-5, "{position}");
+            }
+        }
+    }
+
+    #[test]
+    fn fractal_noise_2d_derivative() {
+        let fbm = Fbm::default();
+        let hasher = SimpleHasher::new(0);
+        // the highest octave stays coarse enough for the finite differences
+        let position = vec2(1.5, 2.5);
+
+        assert_derivatives(position, |p| {
+            fbm_noise_2d(p, 0.05, &fbm, &hasher, perlin_noise_2d)
+        });
+        assert_derivatives(position, |p| {
+            fbm_noise_2d(p, 0.05, &fbm, &hasher, simplex_noise_2d)
+        });
+        assert_derivatives(position, |p| {
+            billow_noise_2d(p, 0.05, &fbm, &hasher, simplex_noise_2d)
+        });
+        assert_derivatives(position, |p| {
+            ridged_multifractal_noise_2d(p, 0.05, &fbm, &hasher, simplex_noise_2d)
+        });
+    }
+
+    #[test]
+    fn fractal_noise_2d_range() {
+        let fbm = Fbm::default();
+        let hasher = SimpleHasher::new(0);
+
+        for (x, y) in (-100..100).cartesian_product(-100..100) {
+            let p = vec2(x as f32, y as f32) / 3.0;
+
+            let fbm_value = fbm_noise_2d(p, 0.3, &fbm, &hasher, simplex_noise_2d).value;
+            let billow = billow_noise_2d(p, 0.3, &fbm, &hasher, simplex_noise_2d).value;
+            let ridged = ridged_multifractal_noise_2d(p, 0.3, &fbm, &hasher, simplex_noise_2d);
+
+            assert!(fbm_value.abs() <= 1.0, "{fbm_value}");
+            assert!(billow.abs() <= 1.0, "{billow}");
+            assert!((0.0..=1.0).contains(&ridged.value), "{}", ridged.value);
+        }
+    }
+
+    #[test]
+    fn warp_derivative() {
+        let hasher = SimpleHasher::new(0);
+
+        for strength in [0.0, 0.5, 2.0] {
+            let offset = |p: Vec2| {
+                [1, 2].map(|seed| perlin_noise_2d(p, 0.4, &SimpleHasher::new(seed)) * strength)
+            };
+
+            for position in [vec2(1.5, 2.5), vec2(-3.2, 0.7), vec2(4.1, -5.3)] {
+                assert_derivatives(position, |p| {
+                    warp(p, offset, |p| perlin_noise_2d(p, 0.5, &hasher))
+                });
+            }
+        }
+    }
+
+    #[test]
+    fn warp_without_offset_is_identity() {
+        let hasher = SimpleHasher::new(0);
+        let position = vec2(1.5, 2.5);
+
+        let warped = warp(
+            position,
+            |_| [Value2Dt2::default(); 2],
+            |p| simplex_noise_2d(p, 0.5, &hasher),
+        );
+
+        assert_eq!(warped, simplex_noise_2d(position, 0.5, &hasher));
+    }
+
+    /// Checks the gradient and the Hessian of `noise` against finite differences.
+    fn assert_derivatives(position: Vec2, noise: impl Fn(Vec2) -> Value2Dt2) {
+        let result = noise(position);
+
+        let df_dx = |x: f32| noise(vec2(x, position.y)).value;
+        let df_dy = |y: f32| noise(vec2(position.x, y)).value;
+        let numerical_derivative = vec2(
+            estimate_dt1(position.x, df_dx),
+            estimate_dt1(position.y, df_dy),
+        );
+        assert!(
+            (result.d1.0 - numerical_derivative).abs().max_element() < 0.01,
+            "{}!={numerical_derivative}",
+            result.d1.0,
+        );
+
+        let expected = estimate_dt2(position, |p| noise(p).value);
+        for (label, expected, received) in zip_hessians(result.d2, expected) {
+            assert!(
+                (expected - received).abs() < 0.1,
+                "{label}: {}!={}",
+                received,
+                expected,
+            );
+        }
+    }
+
+    fn assert_derivatives_3d(position: Vec3, noise: impl Fn(Vec3) -> ValueDt3) {
+        let result = noise(position);
+
+        for axis in 0..3 {
+            let numerical_derivative = estimate_dt1(position[axis], |v| {
+                let mut p = position;
+                p[axis] = v;
+                noise(p).value
+            });
+
+            assert!(
+                (result.derivative[axis] - numerical_derivative).abs() < 0.01,
+                "{position} {axis}: {}!={numerical_derivative}",
+                result.derivative[axis],
+            );
+        }
+    }
+
     fn derivative_label(i: usize) -> String {
         let label = match i {
             0 => "dtx",
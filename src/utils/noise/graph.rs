@@ -0,0 +1,916 @@
+//! Noise composed from nodes, derivatives go through every node with the chain rule.
+//! Graphs are stored as an arena, a node only reads the nodes before it and the last node is the output.
+
+use std::{error::Error, fmt};
+
+use bevy::reflect::Reflect;
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+/// Noise function of a [`NoiseNode::Noise`] or a [`NoiseLayer`].
+#[derive(Debug, Reflect, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NoiseKind {
+    #[default]
+    Perlin,
+    Value,
+}
+
+impl NoiseKind {
+    /// Samples the noise in [-1, 1].
+    pub fn sample(&self, pos: Vec2, scale: f32, hasher: &impl NoiseHasher) -> Value2Dt2 {
+        match self {
+            NoiseKind::Perlin => perlin_noise_2d(pos, scale, hasher),
+            NoiseKind::Value => value_noise_2d(pos, scale, hasher),
+        }
+    }
+
+    /// Samples the noise on a grid of `size` points spaced by `step` from `origin`, row by row.
+    pub fn sample_grid(
+        &self,
+        origin: Vec2,
+        step: f32,
+        size: UVec2,
+        scale: f32,
+        hasher: &impl NoiseHasher,
+        out: &mut [Value2Dt2],
+    ) {
+        match self {
+            NoiseKind::Perlin => perlin_noise_2d_grid(origin, step, size, scale, hasher, out),
+            NoiseKind::Value => value_noise_2d_grid(origin, step, size, scale, hasher, out),
+        }
+    }
+}
+
+/// Noise in [0, amplitude], eroded by the steepness of the layers before it in a [`NoiseNode::Layers`].
+#[derive(Debug, Reflect, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct NoiseLayer {
+    pub size: f32,
+    pub amplitude: f32,
+    pub erosion: f32,
+    #[serde(default)]
+    pub noise: NoiseKind,
+    pub seed_offset: u32,
+}
+
+impl NoiseLayer {
+    pub fn new(size: f32, amplitude: f32, erosion: f32, seed_offset: u32) -> Self {
+        Self {
+            size,
+            amplitude,
+            erosion,
+            noise: NoiseKind::default(),
+            seed_offset,
+        }
+    }
+
+    pub fn with_noise(mut self, noise: NoiseKind) -> Self {
+        self.noise = noise;
+        self
+    }
+
+    pub fn from_vec(weights: Vec<(f32, f32, f32)>) -> Vec<Self> {
+        weights
+            .into_iter()
+            .enumerate()
+            .map(|(seed_offset, (size, amplitude, erosion))| {
+                Self::new(size, amplitude, erosion, seed_offset as u32)
+            })
+            .collect()
+    }
+
+    pub fn sample(&self, pos: Vec2, hasher: &impl NoiseHasher) -> Value2Dt2 {
+        self.remap_noise(self.noise.sample(pos, 1.0 / self.size, hasher))
+    }
+
+    /// Maps the noise of the layer from [-1, 1] to [0, amplitude].
+    fn remap_noise(&self, noise: Value2Dt2) -> Value2Dt2 {
+        (noise / 2.0 + 0.5) * self.amplitude
+    }
+
+    /// The layer flattened where it or the layers before it are steep, and its own steepness.
+    /// The Hessian ignores how the erosion changes, it is only used to chain the steepness.
+    pub fn sample_eroded(
+        &self,
+        pos: Vec2,
+        erosion_factor: f32,
+        hasher: &impl NoiseHasher,
+    ) -> (Value2Dt2, f32) {
+        self.erode(self.sample(pos, hasher), erosion_factor)
+    }
+
+    fn erode(&self, layer: Value2Dt2, erosion_factor: f32) -> (Value2Dt2, f32) {
+        let layer_steepiness = layer.dt_length();
+
+        let pre_erosion_factor = erosion_factor + layer_steepiness;
+        let v = 1.0 + self.erosion * pre_erosion_factor;
+        let eroded = layer.to_dt1() / v;
+
+        (
+            Value2Dt2::new(eroded.value, eroded.d1.0, layer.d2 / v.value),
+            (layer.to_dt1() / v.value).dt_length(),
+        )
+    }
+
+    /// Sum of the eroded layers and the steepness of the last one.
+    pub fn sample_many<'a, H: NoiseHasher>(
+        pos: Vec2,
+        layers: impl Iterator<Item = &'a Self>,
+        seeds: &impl Fn(u32) -> H,
+    ) -> (Value2Dt2, f32) {
+        let mut erosion_factor = 0.0;
+        let mut terrain = Value2Dt2::default();
+
+        for layer in layers {
+            let hasher = seeds(layer.seed_offset);
+            let (eroded, layer_steepiness) = layer.sample_eroded(pos, erosion_factor, &hasher);
+            terrain = terrain + eroded;
+            erosion_factor = layer_steepiness;
+        }
+
+        (terrain, erosion_factor)
+    }
+
+    /// [`NoiseLayer::sample_many`] on a grid of `size` points spaced by `step` from `origin`,
+    /// row by row, without the steepness.
+    pub fn sample_many_grid<'a, H: NoiseHasher>(
+        origin: Vec2,
+        step: f32,
+        size: UVec2,
+        layers: impl Iterator<Item = &'a Self>,
+        seeds: &impl Fn(u32) -> H,
+        out: &mut [Value2Dt2],
+    ) {
+        let mut erosion_factors = vec![0.0; out.len()];
+        let mut noise = vec![Value2Dt2::default(); out.len()];
+        out.fill(Value2Dt2::default());
+
+        for layer in layers {
+            let hasher = seeds(layer.seed_offset);
+            layer
+                .noise
+                .sample_grid(origin, step, size, 1.0 / layer.size, &hasher, &mut noise);
+
+            for ((terrain, erosion_factor), noise) in
+                out.iter_mut().zip(&mut erosion_factors).zip(&noise)
+            {
+                let (eroded, layer_steepiness) =
+                    layer.erode(layer.remap_noise(*noise), *erosion_factor);
+                *terrain = *terrain + eroded;
+                *erosion_factor = layer_steepiness;
+            }
+        }
+    }
+}
+
+/// Index of a node in its [`NoiseGraph`].
+#[derive(Debug, Reflect, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct NodeId(pub u32);
+
+#[derive(Debug, Reflect, Clone, PartialEq, Serialize, Deserialize)]
+pub enum NoiseNode {
+    /// Same value everywhere.
+    Constant(f32),
+    /// Noise in [-1, 1] with features of `size`.
+    Noise {
+        noise: NoiseKind,
+        size: f32,
+        seed_offset: u32,
+    },
+    /// Layers from the biggest to the smallest, each layer is eroded by the ones before it.
+    Layers(Vec<NoiseLayer>),
+    Scale {
+        input: NodeId,
+        factor: f32,
+    },
+    Offset {
+        input: NodeId,
+        offset: f32,
+    },
+    Add(Vec<NodeId>),
+    Mul(NodeId, NodeId),
+    /// `input` sampled at the point moved by `strength` times the values of `x` and `y`.
+    Warp {
+        input: NodeId,
+        x: NodeId,
+        y: NodeId,
+        strength: f32,
+    },
+    /// `steps` terraces per unit of `input`, a `sharpness` of 0 leaves it untouched and 1 gives flat steps.
+    Terrace {
+        input: NodeId,
+        steps: f32,
+        sharpness: f32,
+    },
+    /// Derivatives are 0 where the value is clamped.
+    Clamp {
+        input: NodeId,
+        min: f32,
+        max: f32,
+    },
+    /// `a` where `mask` is 0 and `b` where it is 1, the mask is clamped to [0, 1].
+    Blend {
+        a: NodeId,
+        b: NodeId,
+        mask: NodeId,
+    },
+    /// `a` below `threshold` of `mask` and `b` above it, blended over `falloff` on both sides.
+    Select {
+        a: NodeId,
+        b: NodeId,
+        mask: NodeId,
+        threshold: f32,
+        falloff: f32,
+    },
+    /// `input` remapped by a monotone cubic spline through the points, sorted by their first coordinate.
+    /// Flat before the first point and after the last one.
+    Curve {
+        input: NodeId,
+        points: Vec<(f32, f32)>,
+    },
+}
+
+impl NoiseNode {
+    /// Nodes read by this node.
+    pub fn inputs(&self) -> Vec<NodeId> {
+        match self {
+            NoiseNode::Constant(_) | NoiseNode::Noise { .. } | NoiseNode::Layers(_) => Vec::new(),
+            NoiseNode::Scale { input, .. }
+            | NoiseNode::Offset { input, .. }
+            | NoiseNode::Terrace { input, .. }
+            | NoiseNode::Clamp { input, .. }
+            | NoiseNode::Curve { input, .. } => vec![*input],
+            NoiseNode::Add(inputs) => inputs.clone(),
+            NoiseNode::Mul(a, b) => vec![*a, *b],
+            NoiseNode::Warp { input, x, y, .. } => vec![*input, *x, *y],
+            NoiseNode::Blend { a, b, mask } | NoiseNode::Select { a, b, mask, .. } => {
+                vec![*a, *b, *mask]
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NoiseGraphError {
+    Empty,
+    /// A node reads itself or a node after it.
+    ForwardInput {
+        node: u32,
+        input: u32,
+    },
+    UnsortedCurve {
+        node: u32,
+    },
+}
+
+impl fmt::Display for NoiseGraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NoiseGraphError::Empty => write!(f, "noise graph has no nodes"),
+            NoiseGraphError::ForwardInput { node, input } => write!(
+                f,
+                "noise node {node} reads node {input}, nodes can only read the nodes before them"
+            ),
+            NoiseGraphError::UnsortedCurve { node } => {
+                write!(f, "curve points of noise node {node} are not sorted")
+            }
+        }
+    }
+}
+
+impl Error for NoiseGraphError {}
+
+/// Nodes of the graph in order, the last node is the output.
+#[derive(Debug, Reflect, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<NoiseNode>", into = "Vec<NoiseNode>")]
+pub struct NoiseGraph {
+    nodes: Vec<NoiseNode>,
+}
+
+impl TryFrom<Vec<NoiseNode>> for NoiseGraph {
+    type Error = NoiseGraphError;
+
+    fn try_from(nodes: Vec<NoiseNode>) -> Result<Self, Self::Error> {
+        Self::new(nodes)
+    }
+}
+
+impl From<NoiseGraph> for Vec<NoiseNode> {
+    fn from(graph: NoiseGraph) -> Self {
+        graph.nodes
+    }
+}
+
+impl NoiseGraph {
+    pub fn new(nodes: Vec<NoiseNode>) -> Result<Self, NoiseGraphError> {
+        if nodes.is_empty() {
+            return Err(NoiseGraphError::Empty);
+        }
+
+        for (index, node) in nodes.iter().enumerate() {
+            let index = index as u32;
+            if let Some(input) = node.inputs().into_iter().find(|input| input.0 >= index) {
+                return Err(NoiseGraphError::ForwardInput {
+                    node: index,
+                    input: input.0,
+                });
+            }
+            if let NoiseNode::Curve { points, .. } = node {
+                if points.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+                    return Err(NoiseGraphError::UnsortedCurve { node: index });
+                }
+            }
+        }
+
+        Ok(Self { nodes })
+    }
+
+    /// Graph of a single layer stack.
+    pub fn from_layers(layers: Vec<NoiseLayer>) -> Self {
+        Self {
+            nodes: vec![NoiseNode::Layers(layers)],
+        }
+    }
+
+    /// Layers of a graph made of a single layer stack.
+    pub fn as_layers(&self) -> Option<&[NoiseLayer]> {
+        match self.nodes.as_slice() {
+            [NoiseNode::Layers(layers)] => Some(layers),
+            _ => None,
+        }
+    }
+
+    pub fn nodes(&self) -> &[NoiseNode] {
+        &self.nodes
+    }
+
+    pub fn output(&self) -> NodeId {
+        NodeId(self.nodes.len() as u32 - 1)
+    }
+
+    /// Value of the output at `pos`, `seeds` gives the hasher of every seed offset.
+    pub fn sample<H: NoiseHasher>(&self, pos: Vec2, seeds: &impl Fn(u32) -> H) -> Value2Dt2 {
+        self.sample_until(self.output(), pos, seeds, &mut Vec::new())
+    }
+
+    /// Value of `id` at `pos`, the nodes up to it are evaluated front to back into `values`
+    /// so a node read by several others is only evaluated once.
+    fn sample_until<H: NoiseHasher>(
+        &self,
+        id: NodeId,
+        pos: Vec2,
+        seeds: &impl Fn(u32) -> H,
+        values: &mut Vec<Value2Dt2>,
+    ) -> Value2Dt2 {
+        values.clear();
+        for node in &self.nodes[..=id.0 as usize] {
+            let value = self.sample_node(node, pos, seeds, values);
+            values.push(value);
+        }
+
+        values[id.0 as usize]
+    }
+
+    /// Output on a grid of `size` points spaced by `step` from `origin`, row by row.
+    /// Layer stacks share their lattice hashes and fades across the grid, other graphs are
+    /// sampled point by point.
+    pub fn sample_grid<H: NoiseHasher>(
+        &self,
+        origin: Vec2,
+        step: f32,
+        size: UVec2,
+        seeds: &impl Fn(u32) -> H,
+        out: &mut [Value2Dt2],
+    ) {
+        assert_eq!(out.len(), (size.x * size.y) as usize);
+
+        if let Some(layers) = self.as_layers() {
+            NoiseLayer::sample_many_grid(origin, step, size, layers.iter(), seeds, out);
+            return;
+        }
+
+        let points = (0..size.y).flat_map(|y| (0..size.x).map(move |x| vec2(x as f32, y as f32)));
+        let mut values = Vec::with_capacity(self.nodes.len());
+        for (out, point) in out.iter_mut().zip(points) {
+            *out = self.sample_until(self.output(), origin + point * step, seeds, &mut values);
+        }
+    }
+
+    /// Range of the output.
+    pub fn bounds(&self) -> (f32, f32) {
+        self.node_bounds(self.output())
+    }
+
+    /// Value of `node` at `pos`, `values` are the nodes before it at `pos`.
+    fn sample_node<H: NoiseHasher>(
+        &self,
+        node: &NoiseNode,
+        pos: Vec2,
+        seeds: &impl Fn(u32) -> H,
+        values: &[Value2Dt2],
+    ) -> Value2Dt2 {
+        let sample = |input: &NodeId| values[input.0 as usize];
+
+        match node {
+            NoiseNode::Constant(value) => constant(*value),
+            NoiseNode::Noise {
+                noise,
+                size,
+                seed_offset,
+            } => noise.sample(pos, 1.0 / size, &seeds(*seed_offset)),
+            NoiseNode::Layers(layers) => NoiseLayer::sample_many(pos, layers.iter(), seeds).0,
+            NoiseNode::Scale { input, factor } => sample(input) * *factor,
+            NoiseNode::Offset { input, offset } => sample(input) + *offset,
+            NoiseNode::Add(inputs) => inputs
+                .iter()
+                .map(sample)
+                .fold(Value2Dt2::default(), |total, value| total + value),
+            NoiseNode::Mul(a, b) => sample(a) * sample(b),
+            NoiseNode::Warp {
+                input,
+                x,
+                y,
+                strength,
+            } => {
+                let offset = [sample(x), sample(y)].map(|offset| offset * *strength);
+                // the input is read at the moved point, its nodes are evaluated again there
+                warp(
+                    pos,
+                    |_| offset,
+                    |pos| self.sample_until(*input, pos, seeds, &mut Vec::new()),
+                )
+            }
+            NoiseNode::Terrace {
+                input,
+                steps,
+                sharpness,
+            } => terrace(sample(input), *steps, *sharpness),
+            NoiseNode::Clamp { input, min, max } => clamp(sample(input), *min, *max),
+            NoiseNode::Blend { a, b, mask } => {
+                blend(sample(a), sample(b), clamp(sample(mask), 0.0, 1.0))
+            }
+            NoiseNode::Select {
+                a,
+                b,
+                mask,
+                threshold,
+                falloff,
+            } => {
+                let mask = smoothstep(threshold - falloff, threshold + falloff, sample(mask));
+                blend(sample(a), sample(b), mask)
+            }
+            NoiseNode::Curve { input, points } => curve(sample(input), points),
+        }
+    }
+
+    fn node_bounds(&self, id: NodeId) -> (f32, f32) {
+        let bounds = |input: &NodeId| self.node_bounds(*input);
+
+        match &self.nodes[id.0 as usize] {
+            NoiseNode::Constant(value) => (*value, *value),
+            NoiseNode::Noise { .. } => (-1.0, 1.0),
+            NoiseNode::Layers(layers) => (0.0, layers.iter().map(|layer| layer.amplitude).sum()),
+            NoiseNode::Scale { input, factor } => {
+                let (min, max) = bounds(input);
+                let (a, b) = (min * factor, max * factor);
+                (a.min(b), a.max(b))
+            }
+            NoiseNode::Offset { input, offset } => {
+                let (min, max) = bounds(input);
+                (min + offset, max + offset)
+            }
+            NoiseNode::Add(inputs) => inputs
+                .iter()
+                .map(bounds)
+                .fold((0.0, 0.0), |(min, max), (a, b)| (min + a, max + b)),
+            NoiseNode::Mul(a, b) => {
+                let (a, b) = (bounds(a), bounds(b));
+                let products = [a.0 * b.0, a.0 * b.1, a.1 * b.0, a.1 * b.1];
+                products
+                    .into_iter()
+                    .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), product| {
+                        (min.min(product), max.max(product))
+                    })
+            }
+            NoiseNode::Warp { input, .. } => bounds(input),
+            NoiseNode::Terrace {
+                input,
+                steps,
+                sharpness,
+            } => {
+                // terraces never reverse the slope
+                let (min, max) = bounds(input);
+                let terraced = |value| terrace(constant(value), *steps, *sharpness).value;
+                (terraced(min), terraced(max))
+            }
+            NoiseNode::Clamp { input, min, max } => {
+                let (a, b) = bounds(input);
+                (a.clamp(*min, *max), b.clamp(*min, *max))
+            }
+            NoiseNode::Blend { a, b, .. } | NoiseNode::Select { a, b, .. } => {
+                let (a, b) = (bounds(a), bounds(b));
+                (a.0.min(b.0), a.1.max(b.1))
+            }
+            NoiseNode::Curve { input, points } => {
+                if points.is_empty() {
+                    return bounds(input);
+                }
+                // monotone segments never overshoot their points
+                points
+                    .iter()
+                    .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), (_, y)| {
+                        (min.min(*y), max.max(*y))
+                    })
+            }
+        }
+    }
+}
+
+fn constant(value: f32) -> Value2Dt2 {
+    Value2Dt2::new(value, Vec2::ZERO, Vec3::ZERO)
+}
+
+fn blend(a: Value2Dt2, b: Value2Dt2, mask: Value2Dt2) -> Value2Dt2 {
+    a + (b - a) * mask
+}
+
+fn clamp(value: Value2Dt2, min: f32, max: f32) -> Value2Dt2 {
+    if value.value < min {
+        constant(min)
+    } else if value.value > max {
+        constant(max)
+    } else {
+        value
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, value: Value2Dt2) -> Value2Dt2 {
+    if value.value <= edge0 {
+        return constant(0.0);
+    }
+    if value.value >= edge1 {
+        return constant(1.0);
+    }
+
+    let width = edge1 - edge0;
+    let t = (value.value - edge0) / width;
+    value.compose(
+        t * t * (3.0 - 2.0 * t),
+        6.0 * t * (1.0 - t) / width,
+        (6.0 - 12.0 * t) / (width * width),
+    )
+}
+
+fn terrace(value: Value2Dt2, steps: f32, sharpness: f32) -> Value2Dt2 {
+    if steps <= 0.0 {
+        return value;
+    }
+
+    let h = value.value * steps;
+    let f = h - h.floor();
+    // quintic steps are flat at both ends, terraces join with continuous derivatives
+    let s = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+    let ds = 30.0 * f * f * (f - 1.0) * (f - 1.0);
+    let dds = 60.0 * f * (2.0 * f - 1.0) * (f - 1.0);
+
+    value.compose(
+        (h.floor() + f + sharpness * (s - f)) / steps,
+        1.0 + sharpness * (ds - 1.0),
+        sharpness * dds * steps,
+    )
+}
+
+// https://en.wikipedia.org/wiki/Monotone_cubic_interpolation
+fn curve(value: Value2Dt2, points: &[(f32, f32)]) -> Value2Dt2 {
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return value;
+    };
+    if value.value <= first.0 {
+        return constant(first.1);
+    }
+    if value.value >= last.0 {
+        return constant(last.1);
+    }
+
+    let tangents = curve_tangents(points);
+    let segment = points.partition_point(|(x, _)| *x <= value.value) - 1;
+    let ((x0, y0), (x1, y1)) = (points[segment], points[segment + 1]);
+    let (m0, m1) = (tangents[segment], tangents[segment + 1]);
+
+    let h = x1 - x0;
+    let t = (value.value - x0) / h;
+    let (t2, t3) = (t * t, t * t * t);
+
+    // cubic Hermite basis and its derivatives with respect to t
+    let g = (2.0 * t3 - 3.0 * t2 + 1.0) * y0
+        + (t3 - 2.0 * t2 + t) * h * m0
+        + (-2.0 * t3 + 3.0 * t2) * y1
+        + (t3 - t2) * h * m1;
+    let dg = (6.0 * t2 - 6.0 * t) * y0
+        + (3.0 * t2 - 4.0 * t + 1.0) * h * m0
+        + (-6.0 * t2 + 6.0 * t) * y1
+        + (3.0 * t2 - 2.0 * t) * h * m1;
+    let ddg = (12.0 * t - 6.0) * y0
+        + (6.0 * t - 4.0) * h * m0
+        + (-12.0 * t + 6.0) * y1
+        + (6.0 * t - 2.0) * h * m1;
+
+    value.compose(g, dg / h, ddg / (h * h))
+}
+
+/// Fritsch-Carlson tangents, the spline stays monotone between two points.
+fn curve_tangents(points: &[(f32, f32)]) -> Vec<f32> {
+    let slopes = points
+        .windows(2)
+        .map(|pair| (pair[1].1 - pair[0].1) / (pair[1].0 - pair[0].0))
+        .collect::<Vec<_>>();
+
+    let mut tangents = Vec::with_capacity(points.len());
+    tangents.push(slopes.first().copied().unwrap_or(0.0));
+    for pair in slopes.windows(2) {
+        let tangent = if pair[0] * pair[1] <= 0.0 {
+            0.0
+        } else {
+            (pair[0] + pair[1]) / 2.0
+        };
+        tangents.push(tangent);
+    }
+    if let Some(last) = slopes.last() {
+        tangents.push(*last);
+    }
+
+    for (index, slope) in slopes.iter().enumerate() {
+        if *slope == 0.0 {
+            tangents[index] = 0.0;
+            tangents[index + 1] = 0.0;
+            continue;
+        }
+
+        let a = tangents[index] / slope;
+        let b = tangents[index + 1] / slope;
+        let length = (a * a + b * b).sqrt();
+        if length > 3.0 {
+            tangents[index] = 3.0 / length * a * slope;
+            tangents[index + 1] = 3.0 / length * b * slope;
+        }
+    }
+
+    tangents
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+
+    use super::*;
+
+    fn seeds(seed_offset: u32) -> SimpleHasher {
+        SimpleHasher::new(seed_offset)
+    }
+
+    fn noise(seed_offset: u32) -> NoiseNode {
+        NoiseNode::Noise {
+            noise: NoiseKind::Perlin,
+            size: 20.0,
+            seed_offset,
+        }
+    }
+
+    fn graph(nodes: Vec<NoiseNode>) -> NoiseGraph {
+        NoiseGraph::new(nodes).unwrap()
+    }
+
+    /// A graph for every node, each reading noise so the derivatives are not trivial.
+    /// Layers are not eroded, erosion only keeps the Hessian of the noise.
+    fn node_graphs() -> Vec<NoiseGraph> {
+        vec![
+            graph(vec![NoiseNode::Layers(NoiseLayer::from_vec(vec![
+                (40.0, 2.0, 0.0),
+                (20.0, 1.0, 0.0),
+            ]))]),
+            graph(vec![
+                noise(0),
+                NoiseNode::Scale {
+                    input: NodeId(0),
+                    factor: 3.0,
+                },
+            ]),
+            graph(vec![
+                noise(0),
+                NoiseNode::Offset {
+                    input: NodeId(0),
+                    offset: 2.0,
+                },
+            ]),
+            graph(vec![
+                noise(0),
+                noise(1),
+                NoiseNode::Add(vec![NodeId(0), NodeId(1)]),
+            ]),
+            graph(vec![
+                noise(0),
+                noise(1),
+                NoiseNode::Mul(NodeId(0), NodeId(1)),
+            ]),
+            graph(vec![
+                noise(0),
+                noise(1),
+                noise(2),
+                NoiseNode::Warp {
+                    input: NodeId(0),
+                    x: NodeId(1),
+                    y: NodeId(2),
+                    strength: 8.0,
+                },
+            ]),
+            graph(vec![
+                noise(0),
+                NoiseNode::Terrace {
+                    input: NodeId(0),
+                    steps: 3.0,
+                    sharpness: 0.7,
+                },
+            ]),
+            graph(vec![
+                noise(0),
+                NoiseNode::Clamp {
+                    input: NodeId(0),
+                    min: -0.3,
+                    max: 0.3,
+                },
+            ]),
+            // the mask stays inside [0, 1], away from the kinks of its clamp
+            graph(vec![
+                noise(0),
+                noise(1),
+                noise(2),
+                NoiseNode::Scale {
+                    input: NodeId(2),
+                    factor: 0.4,
+                },
+                NoiseNode::Offset {
+                    input: NodeId(3),
+                    offset: 0.5,
+                },
+                NoiseNode::Blend {
+                    a: NodeId(0),
+                    b: NodeId(1),
+                    mask: NodeId(4),
+                },
+            ]),
+            graph(vec![
+                noise(0),
+                noise(1),
+                noise(2),
+                NoiseNode::Select {
+                    a: NodeId(0),
+                    b: NodeId(1),
+                    mask: NodeId(2),
+                    threshold: 0.0,
+                    falloff: 2.0,
+                },
+            ]),
+            graph(vec![
+                noise(0),
+                NoiseNode::Curve {
+                    input: NodeId(0),
+                    points: vec![(-2.0, 0.0), (0.0, 0.5), (2.0, 3.0)],
+                },
+            ]),
+        ]
+    }
+
+    fn sample_positions() -> impl Iterator<Item = Vec2> {
+        (-5..5)
+            .cartesian_product(-5..5)
+            .map(|(x, y)| vec2(x as f32 * 7.3 + 0.5, y as f32 * 6.9 + 0.25))
+    }
+
+    #[test]
+    fn graph_rejects_invalid_nodes() {
+        assert_eq!(NoiseGraph::new(Vec::new()), Err(NoiseGraphError::Empty));
+        assert_eq!(
+            NoiseGraph::new(vec![noise(0), NoiseNode::Add(vec![NodeId(0), NodeId(1)]),]),
+            Err(NoiseGraphError::ForwardInput { node: 1, input: 1 })
+        );
+        assert_eq!(
+            NoiseGraph::new(vec![
+                noise(0),
+                NoiseNode::Curve {
+                    input: NodeId(0),
+                    points: vec![(1.0, 0.0), (0.0, 1.0)],
+                },
+            ]),
+            Err(NoiseGraphError::UnsortedCurve { node: 1 })
+        );
+    }
+
+    #[test]
+    fn graph_grid_matches_point_samples() {
+        let eroded = graph(vec![NoiseNode::Layers(vec![
+            NoiseLayer::new(30.0, 5.0, 0.3, 0),
+            NoiseLayer::new(7.0, 1.0, 0.9, 1).with_noise(NoiseKind::Value),
+        ])]);
+        let (origin, step, size) = (vec2(-13.7, 5.2), 1.9, uvec2(9, 7));
+
+        for graph in node_graphs().into_iter().chain([eroded]) {
+            let mut grid = vec![Value2Dt2::default(); (size.x * size.y) as usize];
+            graph.sample_grid(origin, step, size, &seeds, &mut grid);
+
+            let points = (0..size.y).cartesian_product(0..size.x);
+            for (sample, (y, x)) in grid.into_iter().zip_eq(points) {
+                let pos = origin + vec2(x as f32, y as f32) * step;
+                assert_eq!(sample, graph.sample(pos, &seeds), "{pos} {graph:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn shared_nodes_are_sampled_once() {
+        // every node reads the one before it twice, recursion would sample the noise 2^24 times
+        let mut nodes = vec![noise(0)];
+        nodes.extend((0..24).map(|index| NoiseNode::Add(vec![NodeId(index), NodeId(index)])));
+        let doubled = graph(nodes);
+
+        let hashers = std::cell::Cell::new(0);
+        let counted_seeds = |seed_offset| {
+            hashers.set(hashers.get() + 1);
+            seeds(seed_offset)
+        };
+        let pos = vec2(3.3, -7.1);
+        let sample = doubled.sample(pos, &counted_seeds);
+
+        assert_eq!(hashers.get(), 1);
+        let single = graph(vec![noise(0)]).sample(pos, &seeds);
+        assert_eq!(sample.value, single.value * (1 << 24) as f32);
+    }
+
+    #[test]
+    fn graph_roundtrips_through_ron() {
+        for graph in node_graphs() {
+            let serialized = ron::to_string(&graph).unwrap();
+            let deserialized: NoiseGraph = ron::from_str(&serialized).unwrap();
+            assert_eq!(graph, deserialized);
+        }
+
+        let error = ron::from_str::<NoiseGraph>("[Add([0])]").unwrap_err();
+        assert!(error.to_string().contains("reads node 0"), "{error}");
+    }
+
+    #[test]
+    fn graph_derivatives_match_finite_differences() {
+        for (index, graph) in node_graphs().into_iter().enumerate() {
+            for position in sample_positions() {
+                let sample = graph.sample(position, &seeds);
+
+                let dfdx = estimate_dt1(position.x, |x| {
+                    graph.sample(vec2(x, position.y), &seeds).value
+                });
+                let dfdy = estimate_dt1(position.y, |y| {
+                    graph.sample(vec2(position.x, y), &seeds).value
+                });
+                let expected = vec2(dfdx, dfdy);
+                assert!(
+                    (sample.d1.0 - expected).length() < 0.01 * (1.0 + expected.length()),
+                    "graph {index} at {position}: {:?} != {expected:?}",
+                    sample.d1.0
+                );
+
+                let expected = estimate_dt2(position, |p| graph.sample(p, &seeds).value);
+                assert!(
+                    (sample.d2 - expected).length() < 0.05 * (1.0 + expected.length()),
+                    "graph {index} at {position}: {:?} != {expected:?}",
+                    sample.d2
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn graph_samples_stay_within_bounds() {
+        for (index, graph) in node_graphs().into_iter().enumerate() {
+            let (min, max) = graph.bounds();
+            for position in sample_positions() {
+                let value = graph.sample(position, &seeds).value;
+                assert!(
+                    (min - 1e-4..=max + 1e-4).contains(&value),
+                    "graph {index} at {position}: {value} outside [{min}, {max}]"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn curve_is_monotone() {
+        let points = [(0.0, 0.0), (1.0, 0.1), (2.0, 2.0), (3.0, 2.1)];
+        let values = (0..=300)
+            .map(|i| curve(constant(i as f32 / 100.0), &points).value)
+            .collect_vec();
+
+        assert!(values.windows(2).all(|pair| pair[0] <= pair[1] + 1e-6));
+        for (x, y) in points {
+            assert!((curve(constant(x), &points).value - y).abs() < 1e-5);
+        }
+    }
+}
@@ -1,60 +1,29 @@
+use serde::{Deserialize, Serialize};
+
 use crate::prelude::*;
 
+/// Hashes integer lattice points into pseudo random values, every seed gives different values.
+/// `hash_22f_seeded` is signed in [-1, 1] like the WGSL `hash`, the other float hashes are in [0, 1].
 pub trait NoiseHasher {
-    fn hash(v: i32) -> u32;
-
     fn seed(&self) -> u32;
 
-    fn from_seed(seed: u32) -> Self;
+    /// Hasher of the same kind with another seed.
+    fn with_seed(&self, seed: u32) -> Self;
     fn with_next_seed(&self) -> Self;
 
-    fn hashf(v: i32) -> f32 {
-        u_to_f(Self::hash(v))
-    }
-
-    fn hash_22f(v: IVec2) -> Vec2 {
-        vec2(Self::hashf(v.x), Self::hashf(v.y))
-    }
-    fn hash_33f(v: IVec3) -> Vec3 {
-        vec3(Self::hashf(v.x), Self::hashf(v.y), Self::hashf(v.z))
-    }
-    fn hash_44f(v: IVec4) -> Vec4 {
-        vec4(
-            Self::hashf(v.x),
-            Self::hashf(v.y),
-            Self::hashf(v.z),
-            Self::hashf(v.w),
-        )
-    }
-    fn hash_21f(v: IVec2) -> f32 {
-        let v = Self::hash((v.x as u32 ^ Self::hash(v.y)) as i32);
-        u_to_f(v)
-    }
-    fn hash_31f(v: IVec3) -> f32 {
-        let v = Self::hash((v.x as u32 ^ Self::hash(v.y) ^ Self::hash(v.z)) as i32);
-        u_to_f(v)
-    }
-    fn hash_41f(v: IVec4) -> f32 {
-        let v =
-            Self::hash((v.x as u32 ^ Self::hash(v.y) ^ Self::hash(v.z) ^ Self::hash(v.w)) as i32);
-        u_to_f(v)
-    }
+    /// Hash of `v` mixed with the seed.
+    fn hash(&self, v: u32) -> u32;
 
     fn hashf_seeded(&self, v: u32) -> f32 {
-        Self::hashf((v ^ self.seed()) as i32)
-    }
-    fn hash_22f_seeded(&self, v: IVec2) -> Vec2 {
-        Self::hash_22f((v.as_uvec2() ^ UVec2::splat(self.seed())).as_ivec2())
-    }
-    fn hash_33f_seeded(&self, v: IVec3) -> Vec3 {
-        Self::hash_33f((v.as_uvec3() ^ UVec3::splat(self.seed())).as_ivec3())
-    }
-    fn hash_44f_seeded(&self, v: IVec4) -> Vec4 {
-        Self::hash_44f((v.as_uvec4() ^ UVec4::splat(self.seed())).as_ivec4())
+        u_to_f(self.hash(v))
     }
+    fn hash_22f_seeded(&self, v: IVec2) -> Vec2;
+    fn hash_33f_seeded(&self, v: IVec3) -> Vec3;
+    fn hash_44f_seeded(&self, v: IVec4) -> Vec4;
 }
 
-#[derive(Clone)]
+/// PCG hashes, and the Hugo Elias hash of the WGSL noise for 2D lattices.
+#[derive(Clone, Debug)]
 pub struct SimpleHasher {
     seed: u32,
 }
@@ -64,6 +33,39 @@ impl SimpleHasher {
         Self { seed }
     }
 
+    // mirrored by `hash` in noise.wgsl
+    fn elias_22f(p: IVec2) -> Vec2 {
+        // 2D -> 1D
+        //let mut n = p.x * ivec2(3, 37) + p.y * ivec2(311, 113);
+        let mut n_x = p.x.wrapping_mul(3).wrapping_add(p.y.wrapping_mul(311));
+        let mut n_y = p.x.wrapping_mul(37).wrapping_add(p.y.wrapping_mul(113));
+
+        // 1D hash by Hugo Elias
+        n_x = (n_x << 13) ^ n_x;
+        n_y = (n_y << 13) ^ n_y;
+        // n = n * (n * n * 15731 + 789221) + 1376312589;
+        n_x = n_x
+            .wrapping_mul(
+                n_x.wrapping_mul(n_x)
+                    .wrapping_mul(15731)
+                    .wrapping_add(789221),
+            )
+            .wrapping_add(1376312589);
+        n_y = n_y
+            .wrapping_mul(
+                n_y.wrapping_mul(n_y)
+                    .wrapping_mul(15731)
+                    .wrapping_add(789221),
+            )
+            .wrapping_add(1376312589);
+
+        // return -1.0 + 2.0 * vec2(n & ivec2(0x0fffffff)) / float(0x0fffffff);
+        let n_x = -1.0 + 2.0 * (n_x & 0x0fffffff) as f32 / 0x0fffffff as f32;
+        let n_y = -1.0 + 2.0 * (n_y & 0x0fffffff) as f32 / 0x0fffffff as f32;
+
+        vec2(n_x, n_y)
+    }
+
     // https://www.pcg-random.org/
     fn pcg(v: u32) -> u32 {
         let state = v.wrapping_mul(747796405).wrapping_add(2891336453);
@@ -150,59 +152,336 @@ impl SimpleHasher {
 }
 
 impl NoiseHasher for SimpleHasher {
-    fn hash(v: i32) -> u32 {
-        Self::pcg(v as u32)
+    fn seed(&self) -> u32 {
+        self.seed
+    }
+    fn with_seed(&self, seed: u32) -> Self {
+        Self::new(seed)
+    }
+    fn with_next_seed(&self) -> Self {
+        // seeds are xored into the lattice, consecutive seeds would only mirror the noise
+        Self::new(Self::pcg(self.seed))
+    }
+
+    fn hash(&self, v: u32) -> u32 {
+        Self::pcg(v ^ self.seed)
+    }
+
+    fn hash_22f_seeded(&self, v: IVec2) -> Vec2 {
+        Self::elias_22f((v.as_uvec2() ^ UVec2::splat(self.seed)).as_ivec2())
+    }
+    fn hash_33f_seeded(&self, v: IVec3) -> Vec3 {
+        let v = Self::pcg3d(v.as_uvec3() ^ UVec3::splat(self.seed));
+        vec3(u_to_f(v.x), u_to_f(v.y), u_to_f(v.z))
+    }
+    fn hash_44f_seeded(&self, v: IVec4) -> Vec4 {
+        let v = Self::pcg4d(v.as_uvec4() ^ UVec4::splat(self.seed));
+        vec4(u_to_f(v.x), u_to_f(v.y), u_to_f(v.z), u_to_f(v.w))
+    }
+}
+
+/// xxHash32 rounds over the coordinates, the seed goes into the hash state
+/// instead of being xored into the lattice.
+#[derive(Clone, Debug)]
+pub struct XxHasher {
+    seed: u32,
+}
+
+impl XxHasher {
+    const PRIME_2: u32 = 2246822519;
+    const PRIME_3: u32 = 3266489917;
+    const PRIME_4: u32 = 668265263;
+    const PRIME_5: u32 = 374761393;
+
+    pub fn new(seed: u32) -> Self {
+        Self { seed }
     }
+
+    // https://github.com/Cyan4973/xxHash/blob/dev/doc/xxhash_spec.md, short input path
+    fn xxhash32(seed: u32, words: &[u32]) -> u32 {
+        let mut hash = seed
+            .wrapping_add(Self::PRIME_5)
+            .wrapping_add(words.len() as u32 * 4);
+        for word in words {
+            hash = hash
+                .wrapping_add(word.wrapping_mul(Self::PRIME_3))
+                .rotate_left(17)
+                .wrapping_mul(Self::PRIME_4);
+        }
+
+        hash ^= hash >> 15;
+        hash = hash.wrapping_mul(Self::PRIME_2);
+        hash ^= hash >> 13;
+        hash = hash.wrapping_mul(Self::PRIME_3);
+        hash ^ (hash >> 16)
+    }
+
+    /// One hash per axis, the axis is hashed as one more coordinate.
+    fn hash_axes<const N: usize>(&self, v: [i32; N]) -> [f32; N] {
+        std::array::from_fn(|axis| {
+            let mut words = [axis as u32; 5];
+            for (word, coordinate) in words.iter_mut().zip(v) {
+                *word = coordinate as u32;
+            }
+            u_to_f(Self::xxhash32(self.seed, &words[..=N]))
+        })
+    }
+}
+
+impl NoiseHasher for XxHasher {
     fn seed(&self) -> u32 {
         self.seed
     }
-    fn from_seed(seed: u32) -> Self {
+    fn with_seed(&self, seed: u32) -> Self {
         Self::new(seed)
     }
     fn with_next_seed(&self) -> Self {
-        Self::new(self.seed.wrapping_add(1))
+        Self::new(Self::xxhash32(self.seed, &[]))
     }
 
-    fn hash_22f(p: IVec2) -> Vec2 {
-        // 2D -> 1D
-        //let mut n = p.x * ivec2(3, 37) + p.y * ivec2(311, 113);
-        let mut n_x = p.x.wrapping_mul(3).wrapping_add(p.y.wrapping_mul(311));
-        let mut n_y = p.x.wrapping_mul(37).wrapping_add(p.y.wrapping_mul(113));
+    fn hash(&self, v: u32) -> u32 {
+        Self::xxhash32(self.seed, &[v])
+    }
 
-        // 1D hash by Hugo Elias
-        n_x = (n_x << 13) ^ n_x;
-        n_y = (n_y << 13) ^ n_y;
-        // n = n * (n * n * 15731 + 789221) + 1376312589;
-        n_x = n_x
-            .wrapping_mul(
-                n_x.wrapping_mul(n_x)
-                    .wrapping_mul(15731)
-                    .wrapping_add(789221),
-            )
-            .wrapping_add(1376312589);
-        n_y = n_y
-            .wrapping_mul(
-                n_y.wrapping_mul(n_y)
-                    .wrapping_mul(15731)
-                    .wrapping_add(789221),
-            )
-            .wrapping_add(1376312589);
+    fn hash_22f_seeded(&self, v: IVec2) -> Vec2 {
+        Vec2::from_array(self.hash_axes(v.to_array())) * 2.0 - 1.0
+    }
+    fn hash_33f_seeded(&self, v: IVec3) -> Vec3 {
+        Vec3::from_array(self.hash_axes(v.to_array()))
+    }
+    fn hash_44f_seeded(&self, v: IVec4) -> Vec4 {
+        Vec4::from_array(self.hash_axes(v.to_array()))
+    }
+}
 
-        // return -1.0 + 2.0 * vec2(n & ivec2(0x0fffffff)) / float(0x0fffffff);
-        let n_x = -1.0 + 2.0 * (n_x & 0x0fffffff) as f32 / 0x0fffffff as f32;
-        let n_y = -1.0 + 2.0 * (n_y & 0x0fffffff) as f32 / 0x0fffffff as f32;
+/// Ken Perlin's reference permutation.
+const PERLIN_PERMUTATION: [u8; 256] = [
+    151, 160, 137, 91, 90, 15, 131, 13, 201, 95, 96, 53, 194, 233, 7, 225, 140, 36, 103, 30, 69,
+    142, 8, 99, 37, 240, 21, 10, 23, 190, 6, 148, 247, 120, 234, 75, 0, 26, 197, 62, 94, 252, 219,
+    203, 117, 35, 11, 32, 57, 177, 33, 88, 237, 149, 56, 87, 174, 20, 125, 136, 171, 168, 68, 175,
+    74, 165, 71, 134, 139, 48, 27, 166, 77, 146, 158, 231, 83, 111, 229, 122, 60, 211, 133, 230,
+    220, 105, 92, 41, 55, 46, 245, 40, 244, 102, 143, 54, 65, 25, 63, 161, 1, 216, 80, 73, 209, 76,
+    132, 187, 208, 89, 18, 169, 200, 196, 135, 130, 116, 188, 159, 86, 164, 100, 109, 198, 173,
+    186, 3, 64, 52, 217, 226, 250, 124, 123, 5, 202, 38, 147, 118, 126, 255, 82, 85, 212, 207, 206,
+    59, 227, 47, 16, 58, 17, 182, 189, 28, 42, 223, 183, 170, 213, 119, 248, 152, 2, 44, 154, 163,
+    70, 221, 153, 101, 155, 167, 43, 172, 9, 129, 22, 39, 253, 19, 98, 108, 110, 79, 113, 224, 232,
+    178, 185, 112, 104, 218, 246, 97, 228, 251, 34, 242, 193, 238, 210, 144, 12, 191, 179, 162,
+    241, 81, 51, 145, 235, 249, 14, 239, 107, 49, 192, 214, 31, 181, 199, 106, 157, 184, 84, 204,
+    176, 115, 121, 50, 45, 127, 4, 150, 254, 138, 236, 205, 93, 222, 114, 67, 29, 24, 72, 243, 141,
+    128, 195, 78, 66, 215, 61, 156, 180,
+];
+
+/// Lattice hash of classic Perlin noise, `p[p[p[x] + y] + z]` over the reference permutation.
+/// With seed 0 the lattice matches classic implementations, other seeds offset each axis by one
+/// byte of the seed. The hash repeats every 256 cells and only has 256 values.
+#[derive(Clone, Debug)]
+pub struct PermutationHasher {
+    seed: u32,
+}
 
-        vec2(n_x, n_y)
+impl PermutationHasher {
+    pub fn new(seed: u32) -> Self {
+        Self { seed }
     }
 
-    fn hash_33f(v: IVec3) -> Vec3 {
-        let v = Self::pcg3d(v.as_uvec3());
-        vec3(u_to_f(v.x), u_to_f(v.y), u_to_f(v.z))
+    fn lattice(&self, v: &[i32]) -> u8 {
+        v.iter().enumerate().fold(0, |hash, (axis, &coordinate)| {
+            let offset = self.seed.rotate_right(axis as u32 * 8);
+            let index = (hash as u32)
+                .wrapping_add(coordinate as u32)
+                .wrapping_add(offset);
+            PERLIN_PERMUTATION[index as usize & 255]
+        })
     }
 
-    fn hash_44f(v: IVec4) -> Vec4 {
-        let v = Self::pcg4d(v.as_uvec4());
-        vec4(u_to_f(v.x), u_to_f(v.y), u_to_f(v.z), u_to_f(v.w))
+    /// One hash per axis, the axis is hashed as one more coordinate.
+    fn hash_axes<const N: usize>(&self, v: [i32; N]) -> [f32; N] {
+        let hash = self.lattice(&v) as usize;
+        std::array::from_fn(|axis| byte_to_f(PERLIN_PERMUTATION[(hash + axis) & 255]))
+    }
+}
+
+impl NoiseHasher for PermutationHasher {
+    fn seed(&self) -> u32 {
+        self.seed
+    }
+    fn with_seed(&self, seed: u32) -> Self {
+        Self::new(seed)
+    }
+    fn with_next_seed(&self) -> Self {
+        Self::new(SimpleHasher::pcg(self.seed))
+    }
+
+    fn hash(&self, v: u32) -> u32 {
+        // spread the byte over the word
+        self.lattice(&[v as i32]) as u32 * 0x01010101
+    }
+
+    fn hashf_seeded(&self, v: u32) -> f32 {
+        byte_to_f(self.lattice(&[v as i32]))
+    }
+    fn hash_22f_seeded(&self, v: IVec2) -> Vec2 {
+        Vec2::from_array(self.hash_axes(v.to_array())) * 2.0 - 1.0
+    }
+    fn hash_33f_seeded(&self, v: IVec3) -> Vec3 {
+        Vec3::from_array(self.hash_axes(v.to_array()))
+    }
+    fn hash_44f_seeded(&self, v: IVec4) -> Vec4 {
+        Vec4::from_array(self.hash_axes(v.to_array()))
+    }
+}
+
+/// PCG hashes with the seed as one more coordinate, mirrored bit for bit by the `pcg_hash`
+/// functions of `noise.wgsl`.
+/// Floats keep the top 24 bits of the hash so the conversion is exact on every gpu.
+#[derive(Clone, Debug)]
+pub struct WgslPcgHasher {
+    seed: u32,
+}
+
+impl WgslPcgHasher {
+    pub fn new(seed: u32) -> Self {
+        Self { seed }
+    }
+}
+
+impl NoiseHasher for WgslPcgHasher {
+    fn seed(&self) -> u32 {
+        self.seed
+    }
+    fn with_seed(&self, seed: u32) -> Self {
+        Self::new(seed)
+    }
+    fn with_next_seed(&self) -> Self {
+        Self::new(SimpleHasher::pcg(self.seed))
+    }
+
+    fn hash(&self, v: u32) -> u32 {
+        SimpleHasher::pcg2d(uvec2(v, self.seed)).x
+    }
+
+    fn hashf_seeded(&self, v: u32) -> f32 {
+        unorm24(self.hash(v))
+    }
+    fn hash_22f_seeded(&self, v: IVec2) -> Vec2 {
+        let v = SimpleHasher::pcg3d(v.as_uvec2().extend(self.seed));
+        vec2(unorm24(v.x), unorm24(v.y)) * 2.0 - 1.0
+    }
+    fn hash_33f_seeded(&self, v: IVec3) -> Vec3 {
+        let v = SimpleHasher::pcg4d(v.as_uvec3().extend(self.seed));
+        vec3(unorm24(v.x), unorm24(v.y), unorm24(v.z))
+    }
+    fn hash_44f_seeded(&self, v: IVec4) -> Vec4 {
+        let v = SimpleHasher::pcg4d(v.as_uvec4() ^ UVec4::splat(SimpleHasher::pcg(self.seed)));
+        vec4(unorm24(v.x), unorm24(v.y), unorm24(v.z), unorm24(v.w))
+    }
+}
+
+/// Hasher picked at runtime, see [`HasherKind`].
+#[derive(Clone, Debug)]
+pub enum AnyHasher {
+    Simple(SimpleHasher),
+    Xx(XxHasher),
+    Permutation(PermutationHasher),
+    WgslPcg(WgslPcgHasher),
+}
+
+macro_rules! dispatch {
+    ($any:expr, $hasher:ident => $body:expr) => {
+        match $any {
+            AnyHasher::Simple($hasher) => $body,
+            AnyHasher::Xx($hasher) => $body,
+            AnyHasher::Permutation($hasher) => $body,
+            AnyHasher::WgslPcg($hasher) => $body,
+        }
+    };
+}
+
+impl NoiseHasher for AnyHasher {
+    fn seed(&self) -> u32 {
+        dispatch!(self, hasher => hasher.seed())
+    }
+    fn with_seed(&self, seed: u32) -> Self {
+        dispatch!(self, hasher => hasher.with_seed(seed).into())
+    }
+    fn with_next_seed(&self) -> Self {
+        dispatch!(self, hasher => hasher.with_next_seed().into())
+    }
+
+    fn hash(&self, v: u32) -> u32 {
+        dispatch!(self, hasher => hasher.hash(v))
+    }
+
+    fn hashf_seeded(&self, v: u32) -> f32 {
+        dispatch!(self, hasher => hasher.hashf_seeded(v))
+    }
+    fn hash_22f_seeded(&self, v: IVec2) -> Vec2 {
+        dispatch!(self, hasher => hasher.hash_22f_seeded(v))
+    }
+    fn hash_33f_seeded(&self, v: IVec3) -> Vec3 {
+        dispatch!(self, hasher => hasher.hash_33f_seeded(v))
+    }
+    fn hash_44f_seeded(&self, v: IVec4) -> Vec4 {
+        dispatch!(self, hasher => hasher.hash_44f_seeded(v))
+    }
+}
+
+impl From<SimpleHasher> for AnyHasher {
+    fn from(hasher: SimpleHasher) -> Self {
+        Self::Simple(hasher)
+    }
+}
+
+impl From<XxHasher> for AnyHasher {
+    fn from(hasher: XxHasher) -> Self {
+        Self::Xx(hasher)
+    }
+}
+
+impl From<PermutationHasher> for AnyHasher {
+    fn from(hasher: PermutationHasher) -> Self {
+        Self::Permutation(hasher)
+    }
+}
+
+impl From<WgslPcgHasher> for AnyHasher {
+    fn from(hasher: WgslPcgHasher) -> Self {
+        Self::WgslPcg(hasher)
+    }
+}
+
+/// Hasher a sampler uses for its noise.
+/// Only [`HasherKind::Simple`] and [`HasherKind::WgslPcg`] have a WGSL mirror,
+/// shaders get the kind as its discriminant.
+#[derive(Reflect, Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum HasherKind {
+    #[default]
+    Simple,
+    Xx,
+    Permutation,
+    WgslPcg,
+}
+
+impl HasherKind {
+    pub const ALL: [HasherKind; 4] = [
+        HasherKind::Simple,
+        HasherKind::Xx,
+        HasherKind::Permutation,
+        HasherKind::WgslPcg,
+    ];
+
+    pub fn hasher(self, seed: u32) -> AnyHasher {
+        match self {
+            HasherKind::Simple => SimpleHasher::new(seed).into(),
+            HasherKind::Xx => XxHasher::new(seed).into(),
+            HasherKind::Permutation => PermutationHasher::new(seed).into(),
+            HasherKind::WgslPcg => WgslPcgHasher::new(seed).into(),
+        }
+    }
+
+    /// Whether `noise.wgsl` hashes the lattice the same way.
+    pub fn has_wgsl_mirror(self) -> bool {
+        matches!(self, HasherKind::Simple | HasherKind::WgslPcg)
     }
 }
 
@@ -210,6 +489,15 @@ fn u_to_f(v: u32) -> f32 {
     v as f32 / u32::MAX as f32
 }
 
+/// Centers of 256 equal bins of [0, 1], every byte is equally likely to land in any half.
+fn byte_to_f(v: u8) -> f32 {
+    (v as f32 + 0.5) / 256.0
+}
+
+fn unorm24(v: u32) -> f32 {
+    (v >> 8) as f32 / 16777216.0
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -333,6 +621,209 @@ mod tests {
         // assert!((avg - 0.5).abs() < 0.01, "avg is too far from 0.5: {avg}");
     }
 
+    const QUALITY_SAMPLES: u32 = 4096;
+
+    /// Lattice points of a 128 x 128 square around the origin.
+    fn lattice_2d() -> impl Iterator<Item = IVec2> {
+        itertools::iproduct!(-64..64, -64..64).map(|(y, x)| ivec2(x, y))
+    }
+
+    /// Share of output bits flipped by flipping each input bit, 0.5 is a perfect avalanche.
+    fn avalanche(hasher: &impl NoiseHasher) -> [f64; 32] {
+        std::array::from_fn(|bit| {
+            let flipped: u32 = (0..QUALITY_SAMPLES)
+                .map(|i| {
+                    let v = SimpleHasher::pcg(i);
+                    (hasher.hash(v) ^ hasher.hash(v ^ (1 << bit))).count_ones()
+                })
+                .sum();
+            flipped as f64 / (QUALITY_SAMPLES * 32) as f64
+        })
+    }
+
+    /// Pearson's chi-squared statistic of `data` in [0, 1] against a uniform distribution.
+    fn chi_squared(data: &[f32], bins: usize) -> f64 {
+        let expected = data.len() as f64 / bins as f64;
+        calculate_histogram(bins, data)
+            .into_iter()
+            .map(|count| (count as f64 - expected).powi(2) / expected)
+            .sum()
+    }
+
+    fn correlation(a: &[f32], b: &[f32]) -> f64 {
+        let mean = |data: &[f32]| data.iter().map(|&v| v as f64).sum::<f64>() / data.len() as f64;
+        let (mean_a, mean_b) = (mean(a), mean(b));
+        let (mut covariance, mut variance_a, mut variance_b) = (0.0, 0.0, 0.0);
+        for (&a, &b) in a.iter().zip_eq(b) {
+            let (a, b) = (a as f64 - mean_a, b as f64 - mean_b);
+            covariance += a * b;
+            variance_a += a * a;
+            variance_b += b * b;
+        }
+        covariance / (variance_a * variance_b).sqrt()
+    }
+
+    #[test]
+    fn perlin_permutation_is_classic() {
+        let mut sorted = PERLIN_PERMUTATION;
+        sorted.sort();
+        assert!(sorted.iter().enumerate().all(|(i, &v)| i == v as usize));
+
+        // p[p[p[x] + y] + z] over the doubled table of the reference implementation
+        let p = |i: usize| PERLIN_PERMUTATION[i % 256] as usize;
+        let hasher = PermutationHasher::new(0);
+        for (x, y, z) in itertools::iproduct!((0..256).step_by(7), (0..256).step_by(11), 0..4) {
+            let classic = p(p(p(x) + y) + z);
+            assert_eq!(
+                hasher.lattice(&[x as i32, y as i32, z as i32]) as usize,
+                classic
+            );
+        }
+    }
+
+    #[test]
+    fn wgsl_pcg_matches_wgsl() {
+        // results of the pcg_hash_*_seeded functions of noise.wgsl on a gpu
+        let cases = [
+            (
+                0,
+                ivec4(-7, 3, 11, -2),
+                0.42451435,
+                vec2(0.67827725, 0.05617106),
+                vec3(0.40360194, 0.98120606, 0.092538595),
+                vec4(0.71799034, 0.47665375, 0.17659187, 0.7854872),
+            ),
+            (
+                42,
+                ivec4(123456, -98765, 4242, 1),
+                0.92853296,
+                vec2(0.28854692, -0.40331018),
+                vec3(0.3961144, 0.7990869, 0.42981476),
+                vec4(0.66497254, 0.08856988, 0.46411592, 0.7696314),
+            ),
+            (
+                0xdeadbeef,
+                IVec4::ZERO,
+                0.584941,
+                vec2(-0.24837208, 0.2521416),
+                vec3(0.09815556, 0.17693144, 0.9969532),
+                vec4(0.9891554, 0.5129889, 0.7424407, 0.16995364),
+            ),
+        ];
+
+        for (seed, p, hash_11, hash_22, hash_33, hash_44) in cases {
+            let hasher = WgslPcgHasher::new(seed);
+            assert_eq!(hasher.hashf_seeded(p.x as u32), hash_11, "{seed} {p}");
+            assert_eq!(hasher.hash_22f_seeded(p.xy()), hash_22, "{seed} {p}");
+            assert_eq!(hasher.hash_33f_seeded(p.xyz()), hash_33, "{seed} {p}");
+            assert_eq!(hasher.hash_44f_seeded(p), hash_44, "{seed} {p}");
+        }
+    }
+
+    #[test]
+    fn hashes_avalanche() {
+        // the permutation hash only has 8 bits and repeats every 256 cells
+        for kind in [HasherKind::Simple, HasherKind::Xx, HasherKind::WgslPcg] {
+            let hasher = kind.hasher(12345);
+            for (bit, flipped) in avalanche(&hasher).into_iter().enumerate() {
+                assert!(
+                    (flipped - 0.5).abs() < 0.05,
+                    "{kind:?} flips {flipped} of the bits for input bit {bit}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn lattice_hashes_are_uniform() {
+        // 16 bins keep the 256 values of the permutation hash evenly spread
+        let bins = 16;
+        // 99.9th percentile of chi-squared with 15 degrees of freedom
+        let critical = 37.7;
+        for kind in HasherKind::ALL {
+            let hasher = kind.hasher(7);
+            let values_2d = lattice_2d()
+                .flat_map(|v| (hasher.hash_22f_seeded(v) * 0.5 + 0.5).to_array())
+                .collect_vec();
+            let values_3d = itertools::iproduct!(-16..16, -16..16, -16..16)
+                .flat_map(|(x, y, z)| hasher.hash_33f_seeded(ivec3(x, y, z)).to_array())
+                .collect_vec();
+
+            for (name, values) in [("2D", values_2d), ("3D", values_3d)] {
+                assert!(values.iter().all(|v| (0.0..=1.0).contains(v)), "{kind:?}");
+                let chi_squared = chi_squared(&values, bins);
+                assert!(
+                    chi_squared < critical,
+                    "{kind:?} {name} hashes are not uniform, chi squared {chi_squared}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn lattice_hashes_are_uncorrelated() {
+        // correlated neighbours or axes show up as streaks along the lattice
+        for kind in HasherKind::ALL {
+            // neighbours along an axis of the permutation hash only form 256 distinct pairs
+            let limit = match kind {
+                HasherKind::Permutation => 0.2,
+                _ => 0.05,
+            };
+            let hasher = kind.hasher(7);
+            let hashes = |offset: IVec2| {
+                lattice_2d()
+                    .map(|v| hasher.hash_22f_seeded(v + offset))
+                    .collect_vec()
+            };
+            let axis = |hashes: &[Vec2], axis: usize| hashes.iter().map(|v| v[axis]).collect_vec();
+
+            let origin = hashes(IVec2::ZERO);
+            for (name, a, b) in [
+                ("x and y", axis(&origin, 0), axis(&origin, 1)),
+                ("right", axis(&origin, 0), axis(&hashes(IVec2::X), 0)),
+                ("up", axis(&origin, 0), axis(&hashes(IVec2::Y), 0)),
+                ("diagonal", axis(&origin, 1), axis(&hashes(IVec2::ONE), 1)),
+            ] {
+                let correlation = correlation(&a, &b);
+                assert!(
+                    correlation.abs() < limit,
+                    "{kind:?} {name} hashes are correlated by {correlation}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn seeds_are_independent() {
+        for kind in HasherKind::ALL {
+            assert_seeds_difference(
+                |seed, pos| kind.hasher(seed).hash_22f_seeded(pos.as_ivec2()),
+                |a, b| (a.x - b.x).abs() < 0.01 && (a.y - b.y).abs() < 0.01,
+            );
+
+            // layers and octaves chain their seeds
+            let hashers =
+                std::iter::successors(Some(kind.hasher(0)), |hasher| Some(hasher.with_next_seed()))
+                    .take(8)
+                    .collect_vec();
+            let samples = hashers
+                .iter()
+                .map(|hasher| {
+                    lattice_2d()
+                        .map(|v| hasher.hash_22f_seeded(v).x)
+                        .collect_vec()
+                })
+                .collect_vec();
+            for (i, j) in (0..samples.len()).tuple_combinations() {
+                let correlation = correlation(&samples[i], &samples[j]);
+                assert!(
+                    correlation.abs() < 0.05,
+                    "{kind:?} seeds {i} and {j} are correlated by {correlation}"
+                );
+            }
+        }
+    }
+
     fn assert_seeds_difference<T: std::ops::Sub, F: Fn(u32, Vec2) -> T, C: Fn(&T, &T) -> bool>(
         f: F,
         checker: C,
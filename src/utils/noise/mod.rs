@@ -1,8 +1,12 @@
+mod bake;
 mod data;
 mod functions;
+mod graph;
 mod hashers;
 
+pub use bake::*;
 pub use bevy::math::*;
 pub use data::*;
 pub use functions::*;
+pub use graph::*;
 pub use hashers::*;
@@ -1,14 +1,70 @@
 use bevy::{
+    math::ivec3,
     prelude::*,
     render::{mesh::*, render_asset::*},
 };
 
+/// Level of detail difference to the neighbour on each edge of a plane.
+/// A neighbour with a delta of `n` has `2^n` times fewer vertices along the shared edge.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Reflect)]
+pub struct EdgeLodDeltas {
+    /// Edge at -Z
+    pub north: u8,
+    /// Edge at +X
+    pub east: u8,
+    /// Edge at +Z
+    pub south: u8,
+    /// Edge at -X
+    pub west: u8,
+}
+
+impl EdgeLodDeltas {
+    pub fn max(&self) -> u8 {
+        self.north.max(self.east).max(self.south).max(self.west)
+    }
+}
+
 pub fn create_subdivided_plane<F: Fn(f32, f32) -> (f32, [f32; 3])>(
     subdivisions: u32,
     size: f32,
     height_function: F,
 ) -> Mesh {
+    create_stitched_plane(
+        subdivisions,
+        size,
+        EdgeLodDeltas::default(),
+        height_function,
+    )
+}
+
+/// Local XZ positions of a subdivided plane's vertices, row by row.
+pub fn plane_vertex_positions(subdivisions: u32, size: f32) -> impl Iterator<Item = Vec2> {
+    plane_vertex_uvs(subdivisions).map(move |uv| (uv - 0.5) * size)
+}
+
+fn plane_vertex_uvs(subdivisions: u32) -> impl Iterator<Item = Vec2> {
     let subdivisions_less = subdivisions - 1;
+    (0..=subdivisions_less)
+        .flat_map(move |y| (0..=subdivisions_less).map(move |x| UVec2::new(x, y)))
+        .map(move |p| p.as_vec2() / subdivisions_less as f32)
+}
+
+/// Subdivided plane that skips edge vertices its coarser neighbours do not have,
+/// so there are no T-junctions between planes of different LODs.
+/// Skipped vertices stay in the vertex buffer, only the indices are stitched.
+/// `height_function` is called once per vertex, in the order of [`plane_vertex_positions`].
+pub fn create_stitched_plane<F: Fn(f32, f32) -> (f32, [f32; 3])>(
+    subdivisions: u32,
+    size: f32,
+    edge_lod_deltas: EdgeLodDeltas,
+    height_function: F,
+) -> Mesh {
+    let subdivisions_less = subdivisions - 1;
+    assert!(
+        subdivisions_less % (1 << edge_lod_deltas.max()) == 0,
+        "{subdivisions} vertices per side cannot be stitched with {edge_lod_deltas:?}"
+    );
+
     let mut mesh = Mesh::new(
         PrimitiveTopology::TriangleList,
         RenderAssetUsages::RENDER_WORLD,
@@ -23,31 +79,44 @@ pub fn create_subdivided_plane<F: Fn(f32, f32) -> (f32, [f32; 3])>(
     let mut uvs = Vec::with_capacity(num_vertices);
     let mut indices = Vec::with_capacity(num_indices);
 
-    for y in 0..=subdivisions_less {
-        for x in 0..=subdivisions_less {
-            let u = x as f32 / subdivisions_less as f32;
-            let v = y as f32 / subdivisions_less as f32;
-            let x = (u - 0.5) * size;
-            let z = (v - 0.5) * size;
-
-            let (y, normal) = height_function(x, z);
+    for (position, uv) in
+        plane_vertex_positions(subdivisions, size).zip(plane_vertex_uvs(subdivisions))
+    {
+        let (y, normal) = height_function(position.x, position.y);
 
-            positions.push([x, y, z]);
-            normals.push(normal);
-            uvs.push([u, v]);
-        }
+        positions.push([position.x, y, position.y]);
+        normals.push(normal);
+        uvs.push(uv.into());
     }
 
+    // edge vertices missing on the coarser side collapse onto a shared one,
+    // always towards the (0, 0) and (max, max) corners, which are not split by the quad diagonals
+    let stitched_index = |x: u32, y: u32| {
+        let snap_down = |value: u32, delta: u8| value - value % (1 << delta);
+        let snap_up = |value: u32, delta: u8| snap_down(value + (1 << delta) - 1, delta);
+        let (x, y) = match (x, y) {
+            (x, 0) => (snap_down(x, edge_lod_deltas.north), y),
+            (x, y) if y == subdivisions_less => (snap_up(x, edge_lod_deltas.south), y),
+            (0, y) => (x, snap_down(y, edge_lod_deltas.west)),
+            (x, y) if x == subdivisions_less => (x, snap_up(y, edge_lod_deltas.east)),
+            _ => (x, y),
+        };
+        y * (subdivisions_less + 1) + x
+    };
+
     for y in 0..subdivisions_less {
         for x in 0..subdivisions_less {
-            let i = y * (subdivisions_less + 1) + x;
-            indices.push(i);
-            indices.push(i + subdivisions_less + 1);
-            indices.push(i + 1);
+            let i = stitched_index(x, y);
+            let right = stitched_index(x + 1, y);
+            let down = stitched_index(x, y + 1);
+            let down_right = stitched_index(x + 1, y + 1);
 
-            indices.push(i + 1);
-            indices.push(i + subdivisions_less + 1);
-            indices.push(i + subdivisions_less + 2);
+            for [a, b, c] in [[i, down, right], [right, down, down_right]] {
+                // collapsed triangles have no area
+                if a != b && b != c && a != c {
+                    indices.extend([a, b, c]);
+                }
+            }
         }
     }
 
@@ -65,6 +134,165 @@ pub fn create_subdivided_plane<F: Fn(f32, f32) -> (f32, [f32; 3])>(
     mesh
 }
 
+/// Heights of a subdivided plane's vertices on the plane covering twice its size with as many vertices,
+/// `heights` are in the order of [`plane_vertex_positions`].
+/// Vertices at even coordinates are shared with the coarser plane, the others are interpolated
+/// along the edges and quad diagonals of [`create_stitched_plane`] so they lie on its triangles.
+pub fn coarser_plane_heights(subdivisions: u32, heights: &[f32]) -> Vec<f32> {
+    let subdivisions_less = subdivisions - 1;
+    assert!(
+        subdivisions_less % 2 == 0,
+        "{subdivisions} vertices per side have no coarser plane"
+    );
+    assert_eq!(heights.len(), (subdivisions * subdivisions) as usize);
+
+    let height = |x: u32, y: u32| heights[(y * subdivisions + x) as usize];
+    (0..subdivisions)
+        .flat_map(|y| (0..subdivisions).map(move |x| (x, y)))
+        .map(|(x, y)| match (x % 2, y % 2) {
+            (0, 0) => height(x, y),
+            (1, 0) => (height(x - 1, y) + height(x + 1, y)) / 2.0,
+            (0, _) => (height(x, y - 1) + height(x, y + 1)) / 2.0,
+            // quads are split from their top right to their bottom left corner
+            _ => (height(x + 1, y - 1) + height(x - 1, y + 1)) / 2.0,
+        })
+        .collect()
+}
+
+/// Isosurface of a density field by dual contouring, positive densities are inside.
+/// The field is sampled on a grid of `resolution` cells of `cell_size` per side starting at the
+/// grid corner `origin`, positions are relative to that corner and normals follow the gradient.
+/// `density_function` returns the density and its gradient at a world position.
+///
+/// A grid also samples the layer of cells before its min faces and only emits the faces of the
+/// edges starting inside it, so adjacent grids place the vertices they share identically
+/// and every face is emitted exactly once.
+pub fn create_isosurface<F: Fn(Vec3) -> (f32, Vec3)>(
+    origin: IVec3,
+    resolution: u32,
+    cell_size: f32,
+    density_function: F,
+) -> Mesh {
+    let resolution = resolution as i32;
+    // corners go from -1 to resolution, cells from -1 to resolution - 1
+    let corners_per_side = resolution + 2;
+    let grid_index = |p: IVec3, per_side: i32| {
+        let p = p + 1;
+        (p.x + per_side * (p.y + per_side * p.z)) as usize
+    };
+    let local_position = |p: IVec3| p.as_vec3() * cell_size;
+
+    let samples = itertools::iproduct!(-1..=resolution, -1..=resolution, -1..=resolution)
+        .map(|(z, y, x)| density_function((origin + ivec3(x, y, z)).as_vec3() * cell_size))
+        .collect::<Vec<_>>();
+    let sample = |p: IVec3| samples[grid_index(p, corners_per_side)];
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut cell_vertices = vec![None; (resolution + 1).pow(3) as usize];
+
+    for (z, y, x) in itertools::iproduct!(-1..resolution, -1..resolution, -1..resolution) {
+        let cell = ivec3(x, y, z);
+        let crossings = cell_edges(cell)
+            .filter_map(|(start, end)| {
+                let ((d0, g0), (d1, g1)) = (sample(start), sample(end));
+                ((d0 > 0.0) != (d1 > 0.0)).then(|| {
+                    let t = d0 / (d0 - d1);
+                    let point = local_position(start).lerp(local_position(end), t);
+                    (point, -g0.lerp(g1, t).normalize_or_zero())
+                })
+            })
+            .collect::<Vec<_>>();
+        if crossings.is_empty() {
+            continue;
+        }
+
+        let min = local_position(cell);
+        let position = solve_qef(&crossings).clamp(min, min + cell_size);
+        let average_normal = crossings.iter().map(|(_, normal)| *normal).sum::<Vec3>();
+        let (_, gradient) = density_function(position + origin.as_vec3() * cell_size);
+        let normal = (-gradient)
+            .try_normalize()
+            .or_else(|| average_normal.try_normalize())
+            .unwrap_or(Vec3::Y);
+
+        cell_vertices[grid_index(cell, resolution + 1)] = Some(positions.len() as u32);
+        positions.push(position.to_array());
+        normals.push(normal.to_array());
+    }
+
+    let mut indices = Vec::new();
+    for (z, y, x) in itertools::iproduct!(0..resolution, 0..resolution, 0..resolution) {
+        let start = ivec3(x, y, z);
+        let (d0, _) = sample(start);
+        for axis in 0..3 {
+            let [along, u, v] = [0, 1, 2].map(|offset| IVec3::AXES[(axis + offset) % 3]);
+            let (d1, _) = sample(start + along);
+            if (d0 > 0.0) == (d1 > 0.0) {
+                continue;
+            }
+
+            // the cells around the edge, counterclockwise seen from the end of the edge
+            let quad = [start - u - v, start - v, start, start - u]
+                .map(|cell| cell_vertices[grid_index(cell, resolution + 1)].unwrap());
+            let [a, b, c, d] = if d0 > 0.0 {
+                quad
+            } else {
+                [quad[3], quad[2], quad[1], quad[0]]
+            };
+            indices.extend([a, b, c, a, c, d]);
+        }
+    }
+
+    let mut mesh = Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_POSITION,
+        VertexAttributeValues::Float32x3(positions),
+    );
+    mesh.insert_attribute(
+        Mesh::ATTRIBUTE_NORMAL,
+        VertexAttributeValues::Float32x3(normals),
+    );
+    mesh.insert_indices(Indices::U32(indices));
+
+    mesh
+}
+
+/// The 12 edges of a grid cell as pairs of corners.
+fn cell_edges(cell: IVec3) -> impl Iterator<Item = (IVec3, IVec3)> {
+    IVec3::AXES
+        .into_iter()
+        .enumerate()
+        .flat_map(move |(axis, along)| {
+            let [u, v] = [1, 2].map(|offset| IVec3::AXES[(axis + offset) % 3]);
+            [IVec3::ZERO, u, v, u + v].map(|offset| (cell + offset, cell + offset + along))
+        })
+}
+
+/// Point closest to the planes of the surface crossings, pulled towards their mass point
+/// so flat or degenerate cells stay well defined.
+fn solve_qef(crossings: &[(Vec3, Vec3)]) -> Vec3 {
+    const BIAS: f32 = 0.05;
+
+    let mass_point =
+        crossings.iter().map(|(point, _)| *point).sum::<Vec3>() / crossings.len() as f32;
+    // relative to the mass point so the bias pulls towards it
+    let (ata, atb) =
+        crossings
+            .iter()
+            .fold((Mat3::ZERO, Vec3::ZERO), |(ata, atb), (point, normal)| {
+                let outer =
+                    Mat3::from_cols(*normal * normal.x, *normal * normal.y, *normal * normal.z);
+                (ata + outer, atb + *normal * normal.dot(*point - mass_point))
+            });
+
+    let system = ata + Mat3::from_diagonal(Vec3::splat(BIAS));
+    mass_point + system.inverse() * atb
+}
+
 pub fn create_subdivided_plane_smooth<F: Fn(f32, f32) -> (f32, [f32; 3])>(
     subdivisions: u32,
     size: f32,
@@ -121,3 +349,200 @@ pub fn create_subdivided_plane_smooth<F: Fn(f32, f32) -> (f32, [f32; 3])>(
 
     mesh
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::{math::vec3, utils::HashMap};
+    use itertools::Itertools;
+
+    fn triangle_areas(mesh: &Mesh) -> Vec<f32> {
+        let positions = mesh
+            .attribute(Mesh::ATTRIBUTE_POSITION)
+            .and_then(|positions| positions.as_float3())
+            .unwrap();
+
+        mesh.indices()
+            .unwrap()
+            .iter()
+            .tuples()
+            .map(|(a, b, c)| {
+                let [a, b, c] = [a, b, c].map(|i| Vec3::from(positions[i]).xz());
+                // positive when facing up
+                (c - a).perp_dot(b - a) / 2.0
+            })
+            .collect()
+    }
+
+    fn sphere_density(center: Vec3, radius: f32) -> impl Fn(Vec3) -> (f32, Vec3) {
+        move |p| {
+            let offset = p - center;
+            (radius - offset.length(), -offset.normalize_or_zero())
+        }
+    }
+
+    fn world_triangles(mesh: &Mesh, origin: Vec3) -> Vec<[Vec3; 3]> {
+        let positions = mesh
+            .attribute(Mesh::ATTRIBUTE_POSITION)
+            .and_then(|positions| positions.as_float3())
+            .unwrap();
+
+        mesh.indices()
+            .unwrap()
+            .iter()
+            .tuples()
+            .map(|(a, b, c)| [a, b, c].map(|i| origin + Vec3::from(positions[i])))
+            .collect()
+    }
+
+    #[test]
+    fn isosurface_fits_sphere() {
+        let center = vec3(4.2, 3.9, 4.1);
+        let mesh = create_isosurface(IVec3::ZERO, 8, 1.0, sphere_density(center, 2.7));
+        let positions = mesh
+            .attribute(Mesh::ATTRIBUTE_POSITION)
+            .and_then(|positions| positions.as_float3())
+            .unwrap();
+        let normals = mesh
+            .attribute(Mesh::ATTRIBUTE_NORMAL)
+            .and_then(|normals| normals.as_float3())
+            .unwrap();
+
+        assert!(!positions.is_empty());
+        for (position, normal) in positions.iter().zip(normals) {
+            let radial = Vec3::from(*position) - center;
+            // tangent planes of such a coarse sphere meet slightly outside of it
+            assert!((radial.length() - 2.7).abs() < 0.15, "{radial}");
+            assert!(
+                radial.normalize().dot(Vec3::from(*normal)) > 0.99,
+                "{radial}"
+            );
+        }
+        for [a, b, c] in world_triangles(&mesh, Vec3::ZERO) {
+            let facing = (b - a).cross(c - a).dot((a + b + c) / 3.0 - center);
+            assert!(facing > 0.0, "{a} {b} {c} faces inwards");
+        }
+    }
+
+    #[test]
+    fn adjacent_isosurfaces_are_watertight() {
+        // the sphere crosses the faces, edges and corner shared by 8 grids
+        let density = sphere_density(vec3(8.3, 7.8, 8.1), 4.4);
+        let resolution = 8;
+        let cell_size = 0.5;
+
+        let mut edges = HashMap::new();
+        for (x, y, z) in itertools::iproduct!(0..2, 0..2, 0..2) {
+            let origin = ivec3(x, y, z) * resolution * 2;
+            let mesh = create_isosurface(origin, resolution as u32 * 2, cell_size, &density);
+            for triangle in world_triangles(&mesh, origin.as_vec3() * cell_size) {
+                let [a, b, c] = triangle.map(|p| (p * 1000.0).round().as_ivec3());
+                for edge in [(a, b), (b, c), (c, a)] {
+                    *edges.entry(edge).or_insert(0) += 1;
+                }
+            }
+        }
+
+        assert!(!edges.is_empty());
+        for ((a, b), count) in &edges {
+            assert_eq!(*count, 1, "{a} {b} is used more than once");
+            assert_eq!(edges.get(&(*b, *a)), Some(&1), "{a} {b} is a border");
+        }
+    }
+
+    #[test]
+    fn isosurface_without_crossings_is_empty() {
+        let mesh = create_isosurface(IVec3::ZERO, 4, 1.0, |p| (p.y - 10.0, Vec3::Y));
+        assert_eq!(mesh.indices().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn stitched_plane_covers_whole_area() {
+        let size = 8.0;
+        for (north, east, south, west) in
+            itertools::iproduct!(0..=2_u8, 0..=2_u8, 0..=2_u8, 0..=2_u8)
+        {
+            let deltas = EdgeLodDeltas {
+                north,
+                east,
+                south,
+                west,
+            };
+            let mesh = create_stitched_plane(9, size, deltas, |_, _| (0.0, [0.0, 1.0, 0.0]));
+            let areas = triangle_areas(&mesh);
+
+            assert!(areas.iter().all(|area| *area > 0.0), "{deltas:?}");
+            let total = areas.iter().sum::<f32>();
+            assert!((total - size * size).abs() < 0.001, "{deltas:?} {total}");
+        }
+    }
+
+    #[test]
+    fn coarser_plane_heights_match_coarser_plane() {
+        let height = |x: f32, z: f32| (x * 0.7).sin() * 3.0 + (z * 0.3).cos() * 2.0 + x * z * 0.1;
+        // fine plane is the (-X, -Z) quarter of the coarser one
+        let fine_positions = plane_vertex_positions(9, 8.0)
+            .map(|position| position - 4.0)
+            .collect_vec();
+        let fine_heights = fine_positions
+            .iter()
+            .map(|position| height(position.x, position.y))
+            .collect_vec();
+        let coarser_heights = coarser_plane_heights(9, &fine_heights);
+
+        let coarse_mesh = create_stitched_plane(9, 16.0, EdgeLodDeltas::default(), |x, z| {
+            (height(x, z), [0.0, 1.0, 0.0])
+        });
+        let coarse_positions = coarse_mesh
+            .attribute(Mesh::ATTRIBUTE_POSITION)
+            .and_then(|positions| positions.as_float3())
+            .unwrap();
+        let coarse_triangles = coarse_mesh
+            .indices()
+            .unwrap()
+            .iter()
+            .tuples()
+            .map(|(a, b, c)| [a, b, c].map(|i| Vec3::from(coarse_positions[i])))
+            .collect_vec();
+
+        for (position, coarser_height) in fine_positions.into_iter().zip(coarser_heights) {
+            let expected = coarse_triangles
+                .iter()
+                .find_map(|[a, b, c]| {
+                    let (ab, ac, ap) = (b.xz() - a.xz(), c.xz() - a.xz(), position - a.xz());
+                    let area = ab.perp_dot(ac);
+                    let wb = ap.perp_dot(ac) / area;
+                    let wc = ab.perp_dot(ap) / area;
+                    let wa = 1.0 - wb - wc;
+                    let inside = [wa, wb, wc].iter().all(|w| *w >= -1e-5);
+                    inside.then(|| wa * a.y + wb * b.y + wc * c.y)
+                })
+                .unwrap();
+
+            assert!(
+                (coarser_height - expected).abs() < 1e-4,
+                "{position}: {coarser_height} != {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn stitched_plane_skips_coarse_edge_vertices() {
+        let deltas = EdgeLodDeltas {
+            north: 1,
+            west: 2,
+            ..default()
+        };
+        let mesh = create_stitched_plane(9, 8.0, deltas, |_, _| (0.0, [0.0, 1.0, 0.0]));
+
+        for i in mesh.indices().unwrap().iter() {
+            let (x, y) = (i % 9, i / 9);
+            if y == 0 {
+                assert_eq!(x % 2, 0, "north vertex {x} should be skipped");
+            }
+            if x == 0 {
+                assert_eq!(y % 4, 0, "west vertex {y} should be skipped");
+            }
+        }
+    }
+}
@@ -1,61 +1,122 @@
 use bevy::prelude::*;
 use bevy::render::{
-    render_resource::{Buffer, Maintain, MapMode},
+    render_resource::{Buffer, BufferAsyncError, Maintain, MapMode},
     renderer::RenderDevice,
 };
 use crossbeam_channel::*;
 
+use super::creators;
 use super::BindGroupBuilderError;
 
+/// Data copied back from a gpu buffer.
+/// `id` is the readback id of the node that copied it, see
+/// [`RenderBurritoNodeTrait::readback_id`](super::prelude::RenderBurritoNodeTrait::readback_id).
+#[derive(Debug, Clone)]
+pub struct Readback {
+    pub id: u64,
+    pub data: Vec<u8>,
+}
+
+/// How readback buffers get their data to the cpu once the render graph is submitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadbackMode {
+    /// Waits for the gpu every frame, the data is sent the frame it was copied.
+    Blocking,
+    /// Maps the staging buffers without waiting and polls them on the following frames.
+    /// Every readback buffer cycles through `frames_in_flight` staging buffers.
+    Async { frames_in_flight: usize },
+}
+
+impl Default for ReadbackMode {
+    fn default() -> Self {
+        Self::Async {
+            frames_in_flight: 3,
+        }
+    }
+}
+
+impl ReadbackMode {
+    fn staging_buffer_count(self) -> usize {
+        match self {
+            ReadbackMode::Blocking => 1,
+            ReadbackMode::Async { frames_in_flight } => frames_in_flight.max(1),
+        }
+    }
+
+    pub(crate) fn maintain(self) -> Maintain {
+        match self {
+            ReadbackMode::Blocking => Maintain::wait(),
+            ReadbackMode::Async { .. } => Maintain::Poll,
+        }
+    }
+}
+
 #[derive(Resource)]
-pub struct ReadbackReceiver(pub Receiver<Vec<u8>>);
+pub struct ReadbackReceiver(pub Receiver<Readback>);
 
 #[derive(Resource)]
-pub struct ReadbackSender(pub Sender<Vec<u8>>);
+pub struct ReadbackSender(pub Sender<Readback>);
 
+enum StagingState {
+    Free,
+    /// Reserved for the copy of the readback `id` this frame.
+    Copying(u64),
+    /// Waiting for the map of the readback `id`.
+    Mapping(u64, Receiver<Result<(), BufferAsyncError>>),
+    /// The map callback was dropped without a result, the buffer or its device is gone.
+    /// The map may still be pending, so the buffer is never copied to again.
+    Lost,
+}
+
+struct StagingBuffer {
+    buffer: Buffer,
+    state: StagingState,
+}
+
+/// Cpu side of a readable gpu buffer, a ring of staging buffers the gpu buffer is copied into.
 pub struct ReadbackBuffer {
-    pub buffer: Buffer,
-    has_changed: bool,
+    staging: Vec<StagingBuffer>,
+    size: u64,
 }
 
 pub(crate) trait Readable {
-    fn try_read_raw(&self) -> Result<Option<Vec<u8>>, BindGroupBuilderError>;
+    fn try_read_raw(&self) -> Result<Option<Readback>, BindGroupBuilderError>;
 
     fn try_read<T: bytemuck::AnyBitPattern>(&self) -> Result<Option<T>, BindGroupBuilderError> {
         let result = self.try_read_raw()?;
-        let Some(bytes) = result else {
+        let Some(readback) = result else {
             return Ok(None);
         };
 
-        match bytemuck::try_from_bytes::<T>(bytes.as_slice()) {
+        match bytemuck::try_from_bytes::<T>(readback.data.as_slice()) {
             Ok(data) => Ok(Some(*data)),
             Err(err) => Err(BindGroupBuilderError::CastFailed(err)),
         }
     }
 
-    fn try_read_vec<T: bytemuck::AnyBitPattern>(
+    fn try_read_vec_with_id<T: bytemuck::AnyBitPattern>(
         &self,
-    ) -> Result<Option<Vec<T>>, BindGroupBuilderError> {
+    ) -> Result<Option<(u64, Vec<T>)>, BindGroupBuilderError> {
         let result = self.try_read_raw()?;
-        let Some(bytes) = result else {
+        let Some(readback) = result else {
             return Ok(None);
         };
 
-        match bytemuck::try_cast_slice::<u8, T>(&bytes) {
-            Ok(data) => Ok(Some(data.to_vec())),
+        match bytemuck::try_cast_slice::<u8, T>(&readback.data) {
+            Ok(data) => Ok(Some((readback.id, data.to_vec()))),
             Err(err) => Err(BindGroupBuilderError::CastFailed(err)),
         }
     }
 }
 
 impl ReadbackReceiver {
-    pub fn new(receiver: Receiver<Vec<u8>>) -> Self {
+    pub fn new(receiver: Receiver<Readback>) -> Self {
         Self(receiver)
     }
 }
 
 impl Readable for ReadbackReceiver {
-    fn try_read_raw(&self) -> Result<Option<Vec<u8>>, BindGroupBuilderError> {
+    fn try_read_raw(&self) -> Result<Option<Readback>, BindGroupBuilderError> {
         let data = self.0.try_recv();
         match data {
             Ok(data) => Ok(Some(data)),
@@ -66,103 +127,244 @@ impl Readable for ReadbackReceiver {
 }
 
 impl ReadbackBuffer {
-    #[inline]
-    pub fn new(buffer: Buffer) -> Self {
-        Self {
-            buffer,
-            has_changed: false,
-        }
+    pub fn new<K: std::fmt::Debug>(
+        device: &RenderDevice,
+        name: &K,
+        size: u64,
+        mode: ReadbackMode,
+    ) -> Self {
+        let staging = (0..mode.staging_buffer_count())
+            .map(|_| StagingBuffer {
+                buffer: creators::cpu_buffer(device, name, size),
+                state: StagingState::Free,
+            })
+            .collect();
+
+        Self { staging, size }
     }
 
     #[inline]
-    pub fn unmap(&self) {
-        self.buffer.unmap();
+    pub fn size(&self) -> u64 {
+        self.size
     }
 
-    #[inline]
-    pub fn mark_sent(&mut self) {
-        self.has_changed = false;
+    /// Staging buffers reserved or waiting for their map.
+    pub fn in_flight(&self) -> usize {
+        self.staging
+            .iter()
+            .filter(|staging| {
+                matches!(
+                    staging.state,
+                    StagingState::Copying(_) | StagingState::Mapping(..)
+                )
+            })
+            .count()
     }
-    #[inline]
-    pub fn has_changed(&self) -> bool {
-        self.has_changed
+
+    /// Reserves a free staging buffer for the copy of the readback `id`,
+    /// false when every staging buffer is still in flight.
+    pub fn reserve(&mut self, id: u64) -> bool {
+        let Some(staging) = self
+            .staging
+            .iter_mut()
+            .find(|staging| matches!(staging.state, StagingState::Free))
+        else {
+            return false;
+        };
+
+        staging.state = StagingState::Copying(id);
+        true
     }
-    #[inline]
-    pub fn mark_changed(&mut self) {
-        self.has_changed = true;
+
+    /// Staging buffer reserved this frame, the gpu buffer is copied into it.
+    pub fn copy_target(&self) -> Option<&Buffer> {
+        self.staging
+            .iter()
+            .find(|staging| matches!(staging.state, StagingState::Copying(_)))
+            .map(|staging| &staging.buffer)
+    }
+
+    /// Maps the staging buffers copied this frame, the copy has to be submitted already.
+    pub(crate) fn start_mapping(&mut self) {
+        for staging in self.staging.iter_mut() {
+            let StagingState::Copying(id) = staging.state else {
+                continue;
+            };
+
+            // the callback runs once the copy is done, during a later device poll
+            let (sender, receiver) = bounded(1);
+            staging
+                .buffer
+                .slice(..)
+                .map_async(MapMode::Read, move |result| {
+                    // the receiver is gone when the buffer was replaced while mapping
+                    let _ = sender.send(result);
+                });
+            staging.state = StagingState::Mapping(id, receiver);
+        }
+    }
+
+    /// Readbacks of the staging buffers mapped since the last call, oldest first.
+    pub(crate) fn take_mapped(&mut self) -> Vec<Result<Readback, BindGroupBuilderError>> {
+        let mut readbacks = Vec::new();
+        for staging in self.staging.iter_mut() {
+            let StagingState::Mapping(id, receiver) = &staging.state else {
+                continue;
+            };
+            let id = *id;
+
+            let (readback, state) = match receiver.try_recv() {
+                Ok(Ok(())) => {
+                    let data = staging.buffer.slice(..).get_mapped_range().to_vec();
+                    // unmap so the staging buffer can be copied to again
+                    staging.buffer.unmap();
+                    (Ok(Readback { id, data }), StagingState::Free)
+                }
+                Ok(Err(err)) => (
+                    Err(BindGroupBuilderError::MapFailed(err)),
+                    StagingState::Free,
+                ),
+                Err(TryRecvError::Empty) => continue,
+                Err(TryRecvError::Disconnected) => (
+                    Err(BindGroupBuilderError::ReceiverDistonnected),
+                    StagingState::Lost,
+                ),
+            };
+            staging.state = state;
+            readbacks.push((id, readback));
+        }
+
+        readbacks.sort_by_key(|(id, _)| *id);
+        readbacks
+            .into_iter()
+            .map(|(_, readback)| readback)
+            .collect()
     }
 }
 
 impl ReadbackSender {
     #[inline]
-    pub fn new(sender: Sender<Vec<u8>>) -> Self {
+    pub fn new(sender: Sender<Readback>) -> Self {
         Self(sender)
     }
 
-    pub fn try_send(
-        &self,
+    pub fn send(&self, readback: Readback) -> Result<(), BindGroupBuilderError> {
+        self.0
+            .send(readback)
+            .map_err(BindGroupBuilderError::SendFailed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::render_resource::*;
+    use itertools::Itertools;
+
+    use super::*;
+    use crate::utils::wgsl::testing::software_gpu;
+
+    fn test_device() -> (RenderDevice, wgpu::Queue) {
+        let gpu = software_gpu();
+        (gpu.device, gpu.queue)
+    }
+
+    /// Copies `data` into the staging buffer reserved for `readback`.
+    fn submit_copy(
         device: &RenderDevice,
-        buffer: &ReadbackBuffer,
-    ) -> Result<(), BindGroupBuilderError> {
-        let data = poll_map_and_read(device, &buffer.buffer);
-        let result = self.0.send(data).map_err(BindGroupBuilderError::SendFailed);
-        // We need to make sure all `BufferView`'s are dropped before we do what we're about
-        // to do.
-        // Unmap so that we can copy to the staging buffer in the next iteration.
-        buffer.unmap();
+        queue: &wgpu::Queue,
+        readback: &ReadbackBuffer,
+        data: &[u32],
+    ) {
+        let source = device.create_buffer_with_data(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(data),
+            usage: BufferUsages::COPY_SRC,
+        });
+        let mut encoder = device.create_command_encoder(&default());
+        encoder.copy_buffer_to_buffer(
+            &source,
+            0,
+            readback.copy_target().unwrap(),
+            0,
+            readback.size(),
+        );
+        queue.submit([encoder.finish()]);
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter, run with --ignored"]
+    fn staging_buffers_cycle() {
+        let (device, _) = test_device();
+        let mode = ReadbackMode::Async {
+            frames_in_flight: 2,
+        };
+        let mut readback = ReadbackBuffer::new(&device, &"cycle", 16, mode);
 
-        result
+        assert!(readback.copy_target().is_none());
+        assert!(readback.reserve(1));
+        readback.start_mapping();
+        assert!(readback.reserve(2));
+        readback.start_mapping();
+        assert_eq!(readback.in_flight(), 2);
+        assert!(!readback.reserve(3), "both staging buffers are mapping");
     }
-}
 
-pub(crate) fn poll_map_and_read(device: &RenderDevice, buffer: &Buffer) -> Vec<u8> {
-    // Finally time to get our data back from the gpu.
-    // First we get a buffer slice which represents a chunk of the buffer (which we
-    // can't access yet).
-    // We want the whole thing so use unbounded range.
-    let buffer_slice = buffer.slice(..);
-
-    // Now things get complicated. WebGPU, for safety reasons, only allows either the GPU
-    // or CPU to access a buffer's contents at a time. We need to "map" the buffer which means
-    // flipping ownership of the buffer over to the CPU and making access legal. We do this
-    // with `BufferSlice::map_async`.
-    //
-    // The problem is that map_async is not an async function so we can't await it. What
-    // we need to do instead is pass in a closure that will be executed when the slice is
-    // either mapped or the mapping has failed.
-    //
-    // The problem with this is that we don't have a reliable way to wait in the main
-    // code for the buffer to be mapped and even worse, calling get_mapped_range or
-    // get_mapped_range_mut prematurely will cause a panic, not return an error.
-    //
-    // Using channels solves this as awaiting the receiving of a message from
-    // the passed closure will force the outside code to wait. It also doesn't hurt
-    // if the closure finishes before the outside code catches up as the message is
-    // buffered and receiving will just pick that up.
-    //
-    // It may also be worth noting that although on native, the usage of asynchronous
-    // channels is wholly unnecessary, for the sake of portability to Wasm
-    // we'll use async channels that work on both native and Wasm.
-
-    let (s, r) = crossbeam_channel::unbounded::<()>();
-
-    // Maps the buffer so it can be read on the cpu
-    buffer_slice.map_async(MapMode::Read, move |r| match r {
-        // This will execute once the gpu is ready, so after the call to poll()
-        Ok(_) => s.send(()).expect("Failed to send map update"),
-        Err(err) => panic!("Failed to map buffer {err}"),
-    });
-
-    // In order for the mapping to be completed, one of three things must happen.
-    // One of those can be calling `Device::poll`. This isn't necessary on the web as devices
-    // are polled automatically but natively, we need to make sure this happens manually.
-    // `Maintain::Wait` will cause the thread to wait on native but not on WebGpu.
-
-    // This blocks until the gpu is done executing everything
-    device.poll(Maintain::wait()).panic_on_timeout();
-
-    // This blocks until the buffer is mapped
-    r.recv().expect("Failed to receive the map_async message");
-
-    buffer_slice.get_mapped_range().to_vec()
+    #[test]
+    #[ignore = "needs a wgpu adapter, run with --ignored"]
+    fn lost_staging_buffers_leave_the_ring() {
+        let (device, _) = test_device();
+        let mode = ReadbackMode::Async {
+            frames_in_flight: 2,
+        };
+        let mut readback = ReadbackBuffer::new(&device, &"lost", 16, mode);
+
+        // a map callback dropped without a result
+        let (sender, receiver) = bounded(1);
+        drop(sender);
+        readback.staging[0].state = StagingState::Mapping(1, receiver);
+
+        let taken = readback.take_mapped();
+        assert!(matches!(
+            taken[..],
+            [Err(BindGroupBuilderError::ReceiverDistonnected)]
+        ));
+        assert_eq!(readback.in_flight(), 0);
+        assert!(readback.reserve(2));
+        assert!(
+            !readback.reserve(3),
+            "the lost staging buffer is not reused"
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter, run with --ignored"]
+    fn readbacks_keep_their_id() {
+        let (device, queue) = test_device();
+
+        for mode in [ReadbackMode::Blocking, ReadbackMode::default()] {
+            let mut readback = ReadbackBuffer::new(&device, &"ids", 16, mode);
+            let mut received = Vec::new();
+            for id in 10..13 {
+                assert!(readback.reserve(id));
+                submit_copy(&device, &queue, &readback, &[id as u32; 4]);
+                readback.start_mapping();
+                device.poll(mode.maintain()).panic_on_timeout();
+                received.extend(readback.take_mapped());
+            }
+            device.poll(Maintain::wait()).panic_on_timeout();
+            received.extend(readback.take_mapped());
+
+            let received = received.into_iter().map(Result::unwrap).collect_vec();
+            assert_eq!(
+                received.iter().map(|readback| readback.id).collect_vec(),
+                [10, 11, 12],
+                "{mode:?}"
+            );
+            for readback in received {
+                let data: &[u32] = bytemuck::cast_slice(&readback.data);
+                assert_eq!(data, [readback.id as u32; 4]);
+            }
+            assert_eq!(readback.in_flight(), 0);
+        }
+    }
 }
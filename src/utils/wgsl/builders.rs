@@ -1,6 +1,7 @@
 use super::buffers_readback::*;
 use super::creators;
 use super::errors::BindGroupBuilderError;
+use super::reflection::{ShaderLayout, ShaderReflection, SlotLayout};
 use bevy::prelude::*;
 use bevy::render::render_resource::*;
 use bevy::{
@@ -23,6 +24,7 @@ pub struct BufferMutateBuilder<
     device: &'a RenderDevice,
     buffer_map: &'b mut HashMap<B, Buffer>,
     readback_buffer_map: &'c mut HashMap<B, ReadbackBuffer>,
+    readback_mode: ReadbackMode,
 }
 
 impl<'a, 'b, 'c, B> BufferMutateBuilder<'a, 'b, 'c, B>
@@ -33,11 +35,13 @@ where
         device: &'a RenderDevice,
         buffer_map: &'b mut HashMap<B, Buffer>,
         readback_buffer_map: &'c mut HashMap<B, ReadbackBuffer>,
+        readback_mode: ReadbackMode,
     ) -> Self {
         Self {
             device,
             buffer_map,
             readback_buffer_map,
+            readback_mode,
         }
     }
 
@@ -129,10 +133,9 @@ where
         let device = self.get_device();
         let buffer = creators::storage_buffer_rw(device, &name, payload);
         let size = buffer.size();
-        let readback_buffer = creators::cpu_buffer::<B>(device, &name, size);
 
         self.insert_buffer(name.to_owned(), buffer);
-        self.insert_readback_buffer(name, readback_buffer);
+        self.insert_readback_buffer(name, size);
 
         self
     }
@@ -144,10 +147,9 @@ where
         let device = self.get_device();
         let buffer = creators::storage_empty_rw(device, &name, size);
         let size = buffer.size();
-        let readback_buffer = creators::cpu_buffer::<B>(device, &name, size);
 
         self.insert_buffer(name.to_owned(), buffer);
-        self.insert_readback_buffer(name, readback_buffer);
+        self.insert_readback_buffer(name, size);
 
         self
     }
@@ -196,9 +198,20 @@ where
         self.buffer_map.insert(name, buffer);
     }
 
-    fn insert_readback_buffer(&mut self, name: B, buffer: Buffer) {
-        self.readback_buffer_map
-            .insert(name, ReadbackBuffer::new(buffer));
+    /// Keeps the staging buffers of a readback buffer of the same size,
+    /// readbacks still in flight are only lost when the size changes.
+    fn insert_readback_buffer(&mut self, name: B, size: u64) {
+        if let Some(readback_buffer) = self.readback_buffer_map.get(&name) {
+            if readback_buffer.size() == size {
+                return;
+            }
+            if readback_buffer.in_flight() > 0 {
+                warn!("readback buffer {name:?} resized, dropping its readbacks in flight");
+            }
+        }
+
+        let readback_buffer = ReadbackBuffer::new(self.device, &name, size, self.readback_mode);
+        self.readback_buffer_map.insert(name, readback_buffer);
     }
 
     fn get_device(&self) -> &RenderDevice {
@@ -206,58 +219,97 @@ where
     }
 }
 
+/// Slots of a bind group layout, only [`build`](Self::build) needs a device.
 pub struct BindLayoutBuilder<
-    'a,
     N: std::fmt::Debug + PartialEq + Eq + std::hash::Hash + ToOwned<Owned = N>,
 > {
     name: N,
-    device: &'a RenderDevice,
     entries: Vec<BindGroupLayoutEntry>,
+    /// Rust type of each entry, checked against the shader, `None` when read from it.
+    slots: Vec<Option<SlotLayout>>,
     visibility: ShaderStages,
 }
 
-impl<'a, N> BindLayoutBuilder<'a, N>
+impl<N> BindLayoutBuilder<N>
 where
     N: std::fmt::Debug + PartialEq + Eq + std::hash::Hash + ToOwned<Owned = N>,
 {
-    pub fn new(device: &'a RenderDevice, name: N, visibility: ShaderStages) -> Self {
+    pub fn new(name: N, visibility: ShaderStages) -> Self {
         Self {
-            device,
             name,
             visibility,
             entries: Vec::new(),
+            slots: Vec::new(),
         }
     }
 
-    fn push_binding(&mut self, binding_builder: BindGroupLayoutEntryBuilder) {
+    /// Slots of the buffers `entry_point` uses in `group`, as declared in the shader.
+    pub fn from_reflection(
+        name: N,
+        visibility: ShaderStages,
+        reflection: &ShaderReflection,
+        entry_point: &str,
+        group: u32,
+    ) -> Result<Self, BindGroupBuilderError> {
+        let entries = reflection.layout_entries(entry_point, group, visibility)?;
+        Ok(Self {
+            name,
+            visibility,
+            slots: vec![None; entries.len()],
+            entries,
+        })
+    }
+
+    /// Checks the slots against the buffers `entry_point` uses in `group`, a wrong order
+    /// or a rust type not laid out member by member like its WGSL struct is an error.
+    pub fn validate(
+        &self,
+        reflection: &ShaderReflection,
+        entry_point: &str,
+        group: u32,
+    ) -> Result<(), BindGroupBuilderError> {
+        reflection.validate_entries(&self.name, entry_point, group, &self.entries, &self.slots)
+    }
+
+    fn push_binding<T: ShaderLayout>(&mut self, binding_builder: BindGroupLayoutEntryBuilder) {
         let binding_index = self.entries.len() as u32;
         let name = &self.name;
         let binding = binding_builder.build(binding_index, self.visibility);
         info!("layout {name:?} binding {binding_index} {binding:?}");
 
         self.entries.push(binding);
+        self.slots.push(Some(SlotLayout::of::<T>()));
     }
 
-    pub fn with_uniform_slot<T: WriteInto + ShaderType>(mut self) -> Self {
+    pub fn with_uniform_slot<T: WriteInto + ShaderLayout>(mut self) -> Self {
         T::assert_uniform_compat();
 
-        self.push_binding(binding_types::uniform_buffer::<T>(false));
+        self.push_binding::<T>(binding_types::uniform_buffer::<T>(false));
+
+        self
+    }
+
+    pub fn with_storage_slot<T: WriteInto + ShaderLayout>(mut self) -> Self {
+        self.push_binding::<T>(binding_types::storage_buffer::<T>(false));
 
         self
     }
 
-    pub fn with_storage_slot<T: WriteInto + ShaderType>(mut self) -> Self {
-        self.push_binding(binding_types::storage_buffer::<T>(false));
+    pub fn with_readonly_storage_slot<T: WriteInto + ShaderLayout>(mut self) -> Self {
+        self.push_binding::<T>(binding_types::storage_buffer_read_only::<T>(false));
 
         self
     }
 
-    pub fn build(self) -> BindGroupLayout {
-        let device = &self.device;
+    pub fn name(&self) -> &N {
+        &self.name
+    }
+
+    pub fn build(&self, device: &RenderDevice) -> BindGroupLayout {
         let entries = self.entries.as_slice();
-        let name = self.name;
+        let name = &self.name;
 
-        creators::create_bind_group_layout(device, name.to_owned(), entries)
+        creators::create_bind_group_layout(&device, name.to_owned(), entries)
     }
 }
 
@@ -342,3 +394,46 @@ impl<'a, 'c, P: std::fmt::Debug, BL: PartialEq + Eq + std::hash::Hash + std::fmt
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHADER: &str = "
+@group(0) @binding(0) var<storage, read> positions: array<vec2<f32>>;
+@group(0) @binding(1) var<storage, read_write> heights: array<f32>;
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    heights[id.x] = length(positions[id.x]);
+}
+";
+
+    #[test]
+    fn reflected_layout_matches_its_slots() {
+        let reflection = ShaderReflection::from_wgsl(SHADER).unwrap();
+        let reflected = BindLayoutBuilder::from_reflection(
+            "reflected",
+            ShaderStages::COMPUTE,
+            &reflection,
+            "main",
+            0,
+        )
+        .unwrap();
+        let written = BindLayoutBuilder::new("written", ShaderStages::COMPUTE)
+            .with_readonly_storage_slot::<Vec<Vec2>>()
+            .with_storage_slot::<Vec<f32>>();
+
+        assert_eq!(reflected.entries, written.entries);
+        reflected.validate(&reflection, "main", 0).unwrap();
+        written.validate(&reflection, "main", 0).unwrap();
+        assert!(BindLayoutBuilder::from_reflection(
+            "missing",
+            ShaderStages::COMPUTE,
+            &reflection,
+            "missing",
+            0
+        )
+        .is_err());
+    }
+}
@@ -1,8 +1,11 @@
 use std::error::Error;
 
+use bevy::render::render_resource::BufferAsyncError;
 use bytemuck::PodCastError;
 use crossbeam_channel::SendError;
 
+use super::buffers_readback::Readback;
+
 #[derive(Debug)]
 pub enum BindGroupBuilderError {
     NoBufferFound(String),
@@ -12,9 +15,14 @@ pub enum BindGroupBuilderError {
     NoBindGroupFound(String),
     NoLayoutFound(String),
     NoPipelineFound(String),
+    PipelineNotReady(String),
+    PipelineFailed(String, String),
+    ShaderReflectionFailed(String),
+    LayoutMismatch(String, String),
     ReceiverDistonnected,
     CastFailed(PodCastError),
-    SendFailed(SendError<Vec<u8>>),
+    SendFailed(SendError<Readback>),
+    MapFailed(BufferAsyncError),
     BufferSizeMismatch(u64, u64),
     EmptyBuffer(String),
 }
@@ -30,9 +38,16 @@ impl std::fmt::Display for BindGroupBuilderError {
             Self::NoBindGroupFound(name) => write!(f, "No bind group found with name {name}"),
             Self::NoLayoutFound(name) => write!(f, "No layout found with name {name}"),
             Self::NoPipelineFound(name) => write!(f, "No pipeline found with name {name}"),
+            Self::PipelineNotReady(name) => write!(f, "Pipeline {name} is not compiled yet"),
+            Self::PipelineFailed(name, error) => write!(f, "Pipeline {name} failed: {error}"),
+            Self::ShaderReflectionFailed(error) => write!(f, "Shader reflection failed: {error}"),
+            Self::LayoutMismatch(name, reason) => {
+                write!(f, "Layout {name} does not match the shader: {reason}")
+            }
             Self::ReceiverDistonnected => write!(f, "Receiver is disconnected"),
             Self::CastFailed(err) => write!(f, "Cast failed: {err}"),
             Self::SendFailed(err) => write!(f, "Send failed: {err}"),
+            Self::MapFailed(err) => write!(f, "Map failed: {err}"),
             Self::BufferSizeMismatch(from_size, to_size) => {
                 write!(f, "Buffer size mismatch: {from_size} != {to_size}")
             }
@@ -62,6 +77,15 @@ impl BindGroupBuilderError {
     pub fn no_pipeline_found<K: std::fmt::Debug>(name: K) -> Self {
         Self::NoPipelineFound(format!("{:?}", name))
     }
+    pub fn pipeline_not_ready<K: std::fmt::Debug>(name: K) -> Self {
+        Self::PipelineNotReady(format!("{:?}", name))
+    }
+    pub fn pipeline_failed<K: std::fmt::Debug>(name: K, error: &str) -> Self {
+        Self::PipelineFailed(format!("{:?}", name), error.to_owned())
+    }
+    pub fn layout_mismatch<K: std::fmt::Debug>(name: K, reason: String) -> Self {
+        Self::LayoutMismatch(format!("{:?}", name), reason)
+    }
     pub fn empty_buffer<K: std::fmt::Debug>(name: K) -> Self {
         Self::EmptyBuffer(format!("{:?}", name))
     }
@@ -0,0 +1,675 @@
+use std::{collections::VecDeque, marker::PhantomData};
+
+use bevy::{
+    prelude::*,
+    render::{
+        graph::CameraDriverLabel,
+        render_graph::{self, RenderGraph, RenderLabel},
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice},
+        Render, RenderApp, RenderSet,
+    },
+    utils::hashbrown::HashMap,
+};
+use crossbeam_channel::{Receiver, Sender, TryRecvError};
+
+use super::buffers_readback::{ReadbackBuffer, ReadbackMode};
+use super::builders::{BindLayoutBuilder, PipelineBuilder};
+use super::creators;
+use super::errors::BindGroupBuilderError;
+use super::pipeline_state::PipelineStatus;
+use super::reflection::ShaderReflection;
+
+/// Compute shader dispatched on inputs submitted from the main world with [`ComputeJobs`].
+/// Every job gets its own buffers, so several jobs of a pipeline can be in flight at once.
+pub trait ComputeJob: Send + Sync + 'static {
+    type In: Send + Sync + 'static;
+    type Out: Send + 'static;
+
+    /// Label of the pipeline, the render graph node and the buffers of the jobs.
+    const LABEL: &'static str;
+
+    fn shader(asset_server: &AssetServer) -> Handle<Shader>;
+    fn entry_point() -> &'static str {
+        "main"
+    }
+    /// Layout of the bind group of every job, group 0 of the shader.
+    /// It is checked against the shader before the pipeline is queued and on every reload.
+    fn layout() -> BindLayoutBuilder<&'static str>;
+    /// Buffers of the bind group of `input`.
+    fn buffers(
+        input: &Self::In,
+        device: &RenderDevice,
+    ) -> Result<ComputeJobBuffers, BindGroupBuilderError>;
+    fn workgroups(input: &Self::In) -> [u32; 3];
+    /// Output of the job from the bytes read back from its output buffer.
+    fn decode(input: &Self::In, bytes: &[u8]) -> Self::Out;
+}
+
+/// Buffers bound to a job, in binding order.
+pub struct ComputeJobBuffers {
+    pub bindings: Vec<Buffer>,
+    /// Index in `bindings` of the buffer read back once the job ran, it needs `COPY_SRC`.
+    pub output: usize,
+}
+
+pub type ComputeJobResult<Out> = Result<Out, BindGroupBuilderError>;
+
+/// Output of a submitted job, ready a few frames after the submit.
+pub struct JobHandle<Out> {
+    id: u64,
+    /// `None` once the result was taken.
+    receiver: Option<Receiver<ComputeJobResult<Out>>>,
+}
+
+impl<Out> JobHandle<Out> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// `None` while the job is in flight and once its result was taken.
+    pub fn try_take(&mut self) -> Option<ComputeJobResult<Out>> {
+        let result = match self.receiver.as_ref()?.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => Err(BindGroupBuilderError::ReceiverDistonnected),
+        };
+        self.receiver = None;
+        Some(result)
+    }
+}
+
+struct SubmittedJob<J: ComputeJob> {
+    id: u64,
+    input: J::In,
+    sender: Sender<ComputeJobResult<J::Out>>,
+}
+
+/// Main world side of the jobs of `J`.
+#[derive(Resource)]
+pub struct ComputeJobs<J: ComputeJob> {
+    next_id: u64,
+    sender: Sender<SubmittedJob<J>>,
+    shader: Handle<Shader>,
+    shader_checks: Sender<ShaderCheck>,
+}
+
+/// Outcome of checking the layout of a job against its shader.
+type ShaderCheck = Result<(), String>;
+
+impl<J: ComputeJob> ComputeJobs<J> {
+    /// Queues a job on `input`, it is dispatched once the pipeline is ready and a slot is free.
+    pub fn submit(&mut self, input: J::In) -> JobHandle<J::Out> {
+        self.next_id += 1;
+        let (sender, receiver) = crossbeam_channel::bounded(1);
+        let job = SubmittedJob {
+            id: self.next_id,
+            input,
+            sender,
+        };
+        // without a render world the job is dropped and its handle reports it
+        if self.sender.send(job).is_err() {
+            warn!("no render world to run {} jobs", J::LABEL);
+        }
+
+        JobHandle {
+            id: self.next_id,
+            receiver: Some(receiver),
+        }
+    }
+}
+
+struct JobInFlight<J: ComputeJob> {
+    job: SubmittedJob<J>,
+    bind_group: BindGroup,
+    buffers: ComputeJobBuffers,
+    workgroups: [u32; 3],
+    readback: ReadbackBuffer,
+}
+
+impl<J: ComputeJob> JobInFlight<J> {
+    fn new(
+        job: SubmittedJob<J>,
+        layout: &BindGroupLayout,
+        device: &RenderDevice,
+    ) -> Result<Self, (SubmittedJob<J>, BindGroupBuilderError)> {
+        let buffers = match J::buffers(&job.input, device) {
+            Ok(buffers) => buffers,
+            Err(error) => return Err((job, error)),
+        };
+        let entries = buffers
+            .bindings
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer)| BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect();
+        let bind_group = creators::create_bind_group(device, J::LABEL, layout, entries);
+
+        // jobs are read back once, a single staging buffer is enough
+        let readback = ReadbackBuffer::new(
+            device,
+            &J::LABEL,
+            buffers.bindings[buffers.output].size(),
+            ReadbackMode::Async {
+                frames_in_flight: 1,
+            },
+        );
+
+        Ok(Self {
+            workgroups: J::workgroups(&job.input),
+            job,
+            bind_group,
+            buffers,
+            readback,
+        })
+    }
+
+    fn output(&self) -> &Buffer {
+        &self.buffers.bindings[self.buffers.output]
+    }
+}
+
+/// Render world side of the jobs of `J`.
+#[derive(Resource)]
+struct ComputeJobQueue<J: ComputeJob> {
+    receiver: Receiver<SubmittedJob<J>>,
+    layout: Option<BindGroupLayout>,
+    pipeline: Option<CachedComputePipelineId>,
+    shader: Handle<Shader>,
+    shader_checks: Receiver<ShaderCheck>,
+    /// Why the layout doesn't match the shader, the pipeline is not used meanwhile.
+    shader_error: Option<String>,
+    status: PipelineStatus,
+    max_in_flight: usize,
+    waiting: VecDeque<SubmittedJob<J>>,
+    in_flight: Vec<JobInFlight<J>>,
+}
+
+impl<J: ComputeJob> ComputeJobQueue<J> {
+    fn get_pipeline<'a>(&self, pipeline_cache: &'a PipelineCache) -> Option<&'a ComputePipeline> {
+        if self.shader_error.is_some() {
+            return None;
+        }
+        self.pipeline
+            .and_then(|pipeline| pipeline_cache.get_compute_pipeline(pipeline))
+    }
+
+    /// Fails the jobs that were not dispatched yet, dispatched jobs are still read back.
+    fn fail_pending(&mut self, error: &str) {
+        let (pending, dispatched) = std::mem::take(&mut self.in_flight)
+            .into_iter()
+            .partition::<Vec<_>, _>(|job| job.readback.in_flight() == 0);
+        self.in_flight = dispatched;
+
+        for job in self
+            .waiting
+            .drain(..)
+            .chain(pending.into_iter().map(|job| job.job))
+        {
+            let _ = job
+                .sender
+                .send(Err(BindGroupBuilderError::pipeline_failed(J::LABEL, error)));
+        }
+    }
+
+    /// Reserves the staging buffers of the jobs not dispatched yet, they are dispatched this frame.
+    fn reserve_dispatches(&mut self) {
+        for job in self.in_flight.iter_mut() {
+            if job.readback.in_flight() == 0 {
+                job.readback.reserve(job.job.id);
+            }
+        }
+    }
+
+    /// Dispatches the jobs reserved this frame and copies their output to their staging buffer.
+    fn encode_dispatches(&self, pipeline: &ComputePipeline, encoder: &mut CommandEncoder) {
+        for job in self.in_flight.iter() {
+            let Some(staging_buffer) = job.readback.copy_target() else {
+                continue;
+            };
+
+            {
+                let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                    label: Some(J::LABEL),
+                    ..default()
+                });
+                pass.set_pipeline(pipeline);
+                pass.set_bind_group(0, &job.bind_group, &[]);
+                let [x, y, z] = job.workgroups;
+                pass.dispatch_workgroups(x, y, z);
+            }
+
+            let output = job.output();
+            encoder.copy_buffer_to_buffer(output, 0, staging_buffer, 0, output.size());
+        }
+    }
+}
+
+/// Runs the jobs of `J` submitted through [`ComputeJobs<J>`].
+pub struct ComputeJobPlugin<J> {
+    max_in_flight: usize,
+    _phantom: PhantomData<fn() -> J>,
+}
+
+impl<J> ComputeJobPlugin<J> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Jobs dispatched and not read back yet, later jobs wait for a slot.
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = max_in_flight.max(1);
+        self
+    }
+}
+
+impl<J> Default for ComputeJobPlugin<J> {
+    fn default() -> Self {
+        Self {
+            max_in_flight: 4,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<J: ComputeJob> Plugin for ComputeJobPlugin<J> {
+    fn build(&self, app: &mut App) {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let (check_sender, check_receiver) = crossbeam_channel::unbounded();
+        let shader = J::shader(app.world().resource::<AssetServer>());
+        app.insert_resource(ComputeJobs::<J> {
+            next_id: 0,
+            sender,
+            shader: shader.clone(),
+            shader_checks: check_sender,
+        })
+        .add_systems(PostUpdate, check_shader::<J>);
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .insert_resource(ComputeJobQueue::<J> {
+                receiver,
+                layout: None,
+                pipeline: None,
+                shader,
+                shader_checks: check_receiver,
+                shader_error: None,
+                status: PipelineStatus::Compiling,
+                max_in_flight: self.max_in_flight,
+                waiting: VecDeque::new(),
+                in_flight: Vec::new(),
+            })
+            .add_systems(
+                Render,
+                (
+                    queue_pipeline::<J>.before(RenderSet::PrepareBindGroups),
+                    prepare_jobs::<J>.in_set(RenderSet::PrepareBindGroups),
+                    // after submit() so the copies of this frame are queued
+                    (read_back_jobs::<J>, track_pipeline::<J>).after(RenderSet::Render),
+                ),
+            );
+
+        let mut render_graph = render_app.world_mut().resource_mut::<RenderGraph>();
+        render_graph.add_node(
+            ComputeJobLabel(J::LABEL),
+            ComputeJobNode::<J> {
+                _phantom: PhantomData,
+            },
+        );
+        render_graph.add_node_edge(ComputeJobLabel(J::LABEL), CameraDriverLabel);
+    }
+}
+
+/// Checks the layout of `J` against its shader once loaded and after every reload.
+/// A mismatch would otherwise only show up as a wgpu validation panic when the pipeline is created.
+fn check_shader<J: ComputeJob>(
+    mut events: EventReader<AssetEvent<Shader>>,
+    shaders: Res<Assets<Shader>>,
+    jobs: Res<ComputeJobs<J>>,
+) {
+    let changed = events.read().any(|event| {
+        event.is_loaded_with_dependencies(&jobs.shader) || event.is_modified(&jobs.shader)
+    });
+    if !changed {
+        return;
+    }
+    let Some(shader) = shaders.get(&jobs.shader) else {
+        return;
+    };
+
+    let check = ShaderReflection::from_shader(shader, &shaders)
+        .and_then(|reflection| J::layout().validate(&reflection, J::entry_point(), 0));
+    if let Err(error) = &check {
+        error!("{} shader rejected: {error}", J::LABEL);
+    }
+    // the render world is gone when the app exits
+    let _ = jobs
+        .shader_checks
+        .send(check.map_err(|error| error.to_string()));
+}
+
+/// Queues the pipeline once the shader passed its first check.
+fn queue_pipeline<J: ComputeJob>(
+    mut queue: ResMut<ComputeJobQueue<J>>,
+    device: Res<RenderDevice>,
+    pipeline_cache: Res<PipelineCache>,
+) {
+    let Some(check) = queue.shader_checks.try_iter().last() else {
+        return;
+    };
+    queue.shader_error = check.err();
+    if queue.shader_error.is_some() || queue.pipeline.is_some() {
+        return;
+    }
+
+    let layout = J::layout().build(&device);
+    let layouts = HashMap::<&str, BindGroupLayout>::new();
+    let descriptor =
+        PipelineBuilder::new(J::LABEL, queue.shader.clone(), J::entry_point(), &layouts)
+            .with_layout_value(layout.clone())
+            .build();
+
+    queue.pipeline = Some(pipeline_cache.queue_compute_pipeline(descriptor));
+    queue.layout = Some(layout);
+}
+
+fn prepare_jobs<J: ComputeJob>(device: Res<RenderDevice>, mut queue: ResMut<ComputeJobQueue<J>>) {
+    let queue = queue.as_mut();
+    queue.waiting.extend(queue.receiver.try_iter());
+    let Some(layout) = queue.layout.as_ref() else {
+        return;
+    };
+
+    while queue.in_flight.len() < queue.max_in_flight {
+        let Some(job) = queue.waiting.pop_front() else {
+            break;
+        };
+        match JobInFlight::new(job, layout, &device) {
+            Ok(job) => queue.in_flight.push(job),
+            Err((job, error)) => {
+                error!("{} job {} failed: {error}", J::LABEL, job.id);
+                let _ = job.sender.send(Err(error));
+            }
+        }
+    }
+}
+
+fn read_back_jobs<J: ComputeJob>(device: Res<RenderDevice>, mut queue: ResMut<ComputeJobQueue<J>>) {
+    for job in queue.in_flight.iter_mut() {
+        job.readback.start_mapping();
+    }
+    device.poll(Maintain::Poll).panic_on_timeout();
+
+    queue.in_flight.retain_mut(|job| {
+        let Some(readback) = job.readback.take_mapped().pop() else {
+            return true;
+        };
+
+        let result = readback.map(|readback| J::decode(&job.job.input, &readback.data));
+        // the handle may be dropped already
+        let _ = job.job.sender.send(result);
+        false
+    });
+}
+
+/// Jobs fail while the shader has errors instead of waiting for a fix,
+/// the pipeline is compiled again when the shader is reloaded.
+fn track_pipeline<J: ComputeJob>(
+    pipeline_cache: Res<PipelineCache>,
+    mut queue: ResMut<ComputeJobQueue<J>>,
+) {
+    let status = match (queue.shader_error.as_ref(), queue.pipeline) {
+        (Some(error), _) => PipelineStatus::Failed(error.clone()),
+        (None, Some(pipeline)) => PipelineStatus::of(&pipeline_cache, pipeline),
+        (None, None) => return,
+    };
+    if status != queue.status {
+        status.log_change(&J::LABEL, Some(&queue.status));
+        queue.status = status;
+    }
+    if let PipelineStatus::Failed(error) = &queue.status {
+        let error = error.clone();
+        queue.fail_pending(&error);
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+pub struct ComputeJobLabel(pub &'static str);
+
+struct ComputeJobNode<J> {
+    _phantom: PhantomData<fn() -> J>,
+}
+
+impl<J: ComputeJob> render_graph::Node for ComputeJobNode<J> {
+    /// Reserves the staging buffers of the jobs dispatched this frame,
+    /// jobs wait while the pipeline is still compiling.
+    fn update(&mut self, world: &mut World) {
+        world.resource_scope(|world, mut queue: Mut<ComputeJobQueue<J>>| {
+            if queue.get_pipeline(world.resource()).is_some() {
+                queue.reserve_dispatches();
+            }
+        });
+    }
+
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let queue = world.resource::<ComputeJobQueue<J>>();
+        let Some(pipeline) = queue.get_pipeline(world.resource()) else {
+            return Ok(());
+        };
+
+        queue.encode_dispatches(pipeline, render_context.command_encoder());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+    use itertools::Itertools;
+
+    use super::*;
+    use crate::utils::wgsl::testing::{software_gpu, SoftwareGpu};
+
+    /// Doubles every input.
+    struct DoubleJob;
+
+    const DOUBLE_SHADER: &str = "
+        @group(0) @binding(0) var<storage, read> input: array<u32>;
+        @group(0) @binding(1) var<storage, read_write> output: array<u32>;
+
+        @compute @workgroup_size(64)
+        fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+            if id.x < arrayLength(&input) {
+                output[id.x] = input[id.x] * 2u;
+            }
+        }
+    ";
+
+    impl ComputeJob for DoubleJob {
+        type In = Vec<u32>;
+        type Out = Vec<u32>;
+
+        const LABEL: &'static str = "double";
+
+        fn shader(_asset_server: &AssetServer) -> Handle<Shader> {
+            Handle::default()
+        }
+
+        fn layout() -> BindLayoutBuilder<&'static str> {
+            BindLayoutBuilder::new(Self::LABEL, ShaderStages::COMPUTE)
+                .with_readonly_storage_slot::<Vec<u32>>()
+                .with_storage_slot::<Vec<u32>>()
+        }
+
+        fn buffers(
+            input: &Vec<u32>,
+            device: &RenderDevice,
+        ) -> Result<ComputeJobBuffers, BindGroupBuilderError> {
+            Ok(ComputeJobBuffers {
+                bindings: vec![
+                    creators::storage_buffer(device, &"double input", input),
+                    creators::storage_empty_rw(device, &"double output", input.len() as u64 * 4),
+                ],
+                output: 1,
+            })
+        }
+
+        fn workgroups(input: &Vec<u32>) -> [u32; 3] {
+            [(input.len() as u32).div_ceil(64), 1, 1]
+        }
+
+        fn decode(_input: &Vec<u32>, bytes: &[u8]) -> Vec<u32> {
+            bytemuck::cast_slice(bytes).to_vec()
+        }
+    }
+
+    /// Render world with the queue of [`DoubleJob`], its main world side and the compiled pipeline.
+    fn test_world(
+        max_in_flight: usize,
+    ) -> (World, ComputeJobs<DoubleJob>, ComputePipeline, wgpu::Queue) {
+        let SoftwareGpu { device, queue, .. } = software_gpu();
+
+        let layout = DoubleJob::layout().build(&device);
+        let module = device.create_shader_module(ShaderModuleDescriptor {
+            label: None,
+            source: ShaderSource::Wgsl(DOUBLE_SHADER.into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&RawComputePipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: "main",
+            compilation_options: default(),
+        });
+
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let (check_sender, check_receiver) = crossbeam_channel::unbounded();
+        let jobs = ComputeJobs::<DoubleJob> {
+            next_id: 0,
+            sender,
+            shader: Handle::default(),
+            shader_checks: check_sender,
+        };
+
+        let mut world = World::new();
+        world.insert_resource(ComputeJobQueue::<DoubleJob> {
+            receiver,
+            layout: Some(layout),
+            pipeline: None,
+            shader: Handle::default(),
+            shader_checks: check_receiver,
+            shader_error: None,
+            status: PipelineStatus::Ready,
+            max_in_flight,
+            waiting: VecDeque::new(),
+            in_flight: Vec::new(),
+        });
+        world.insert_resource(device);
+
+        (world, jobs, pipeline, queue)
+    }
+
+    /// One render frame: new jobs are prepared, reserved jobs dispatched and mapped jobs decoded.
+    fn run_frame(world: &mut World, pipeline: &ComputePipeline, queue: &wgpu::Queue) {
+        world.run_system_once(prepare_jobs::<DoubleJob>);
+
+        let mut encoder = world
+            .resource::<RenderDevice>()
+            .create_command_encoder(&default());
+        let mut job_queue = world.resource_mut::<ComputeJobQueue<DoubleJob>>();
+        job_queue.reserve_dispatches();
+        job_queue.encode_dispatches(pipeline, &mut encoder);
+        queue.submit([encoder.finish()]);
+
+        world.run_system_once(read_back_jobs::<DoubleJob>);
+    }
+
+    /// Runs frames until the job is read back.
+    fn wait_for(
+        world: &mut World,
+        pipeline: &ComputePipeline,
+        queue: &wgpu::Queue,
+        handle: &mut JobHandle<Vec<u32>>,
+    ) -> ComputeJobResult<Vec<u32>> {
+        for _ in 0..100 {
+            if let Some(result) = handle.try_take() {
+                return result;
+            }
+            run_frame(world, pipeline, queue);
+            world
+                .resource::<RenderDevice>()
+                .poll(Maintain::wait())
+                .panic_on_timeout();
+        }
+        panic!("job {} was never read back", handle.id());
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter, run with --ignored"]
+    fn jobs_are_dispatched_and_decoded() {
+        let (mut world, mut jobs, pipeline, queue) = test_world(2);
+
+        let mut handles = (0..3)
+            .map(|job| jobs.submit((0..100).map(|i| i + job * 1000).collect()))
+            .collect_vec();
+        world.run_system_once(prepare_jobs::<DoubleJob>);
+        let job_queue = world.resource::<ComputeJobQueue<DoubleJob>>();
+        assert_eq!(job_queue.in_flight.len(), 2);
+        assert_eq!(job_queue.waiting.len(), 1, "a job waits for a free slot");
+
+        for (job, handle) in handles.iter_mut().enumerate() {
+            let doubled = (0..100).map(|i| (i + job as u32 * 1000) * 2).collect_vec();
+            assert_eq!(
+                wait_for(&mut world, &pipeline, &queue, handle).unwrap(),
+                doubled
+            );
+            assert!(handle.try_take().is_none(), "results are taken once");
+        }
+        let job_queue = world.resource::<ComputeJobQueue<DoubleJob>>();
+        assert!(job_queue.in_flight.is_empty() && job_queue.waiting.is_empty());
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter, run with --ignored"]
+    fn failed_pipelines_fail_pending_jobs() {
+        let (mut world, mut jobs, pipeline, queue) = test_world(2);
+
+        let mut dispatched = jobs.submit(vec![1, 2, 3]);
+        run_frame(&mut world, &pipeline, &queue);
+        let mut in_flight = jobs.submit(vec![4]);
+        let mut waiting = jobs.submit(vec![5]);
+        world.run_system_once(prepare_jobs::<DoubleJob>);
+
+        world
+            .resource_mut::<ComputeJobQueue<DoubleJob>>()
+            .fail_pending("broken shader");
+
+        for handle in [&mut in_flight, &mut waiting] {
+            assert!(matches!(
+                handle.try_take(),
+                Some(Err(BindGroupBuilderError::PipelineFailed(..)))
+            ));
+        }
+        // already dispatched jobs still get their result
+        assert_eq!(
+            wait_for(&mut world, &pipeline, &queue, &mut dispatched).unwrap(),
+            [2, 4, 6]
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use std::{fmt::Debug, hash::Hash};
+
+/// Anything a buffer, layout, bind group or pipeline can be stored under.
+pub trait BurritoKey:
+    PartialEq + Eq + Hash + Debug + ToOwned<Owned = Self> + Send + Sync + 'static
+{
+}
+
+impl<K> BurritoKey for K where
+    K: PartialEq + Eq + Hash + Debug + ToOwned<Owned = K> + Send + Sync + 'static
+{
+}
+
+/// Key types of a burrito, the plugin, resources, nodes and readback channels are generic over it.
+/// [`burrito_keys!`](crate::burrito_keys) declares the key enums and their `BurritoKeys` type.
+pub trait BurritoKeys: Send + Sync + 'static {
+    type Buffer: BurritoKey;
+    type Layout: BurritoKey;
+    type BindGroup: BurritoKey;
+    type Pipeline: BurritoKey;
+}
+
+/// Keys of the `*Str` burritos, everything is named by a string.
+pub struct StrKeys;
+
+impl BurritoKeys for StrKeys {
+    type Buffer = &'static str;
+    type Layout = &'static str;
+    type BindGroup = &'static str;
+    type Pipeline = &'static str;
+}
+
+/// Declares an enum for each kind of key and a [`BurritoKeys`] type tying them together.
+///
+/// ```ignore
+/// burrito_keys! {
+///     pub struct MyKeys {
+///         type Buffer = MyBufferKey { Positions, Heights };
+///         type Layout = MyLayoutKey { Layout };
+///         type BindGroup = MyBindGroupKey { BindGroup };
+///         type Pipeline = MyPipelineKey { Pipeline };
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! burrito_keys {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $keys:ident {
+            type Buffer = $buffer:ident { $($(#[$buffer_meta:meta])* $buffer_variant:ident),* $(,)? };
+            type Layout = $layout:ident { $($(#[$layout_meta:meta])* $layout_variant:ident),* $(,)? };
+            type BindGroup = $bind_group:ident { $($(#[$bind_group_meta:meta])* $bind_group_variant:ident),* $(,)? };
+            type Pipeline = $pipeline:ident { $($(#[$pipeline_meta:meta])* $pipeline_variant:ident),* $(,)? };
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $keys;
+
+        impl $crate::utils::wgsl::prelude::BurritoKeys for $keys {
+            type Buffer = $buffer;
+            type Layout = $layout;
+            type BindGroup = $bind_group;
+            type Pipeline = $pipeline;
+        }
+
+        $crate::burrito_keys!(@key $vis $buffer { $($(#[$buffer_meta])* $buffer_variant),* });
+        $crate::burrito_keys!(@key $vis $layout { $($(#[$layout_meta])* $layout_variant),* });
+        $crate::burrito_keys!(@key $vis $bind_group { $($(#[$bind_group_meta])* $bind_group_variant),* });
+        $crate::burrito_keys!(@key $vis $pipeline { $($(#[$pipeline_meta])* $pipeline_variant),* });
+    };
+    (@key $vis:vis $name:ident { $($(#[$meta:meta])* $variant:ident),* }) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        $vis enum $name {
+            $($(#[$meta])* $variant),*
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    crate::burrito_keys! {
+        #[allow(dead_code)]
+        struct TestKeys {
+            type Buffer = TestBufferKey { Input, Output };
+            type Layout = TestLayoutKey { Layout };
+            type BindGroup = TestBindGroupKey {};
+            type Pipeline = TestPipelineKey { First, Second, };
+        }
+    }
+
+    fn names<K: BurritoKey>(keys: &[K]) -> Vec<String> {
+        keys.iter().map(|key| format!("{key:?}")).collect()
+    }
+
+    #[test]
+    fn burrito_keys_declares_key_enums() {
+        fn buffers<K: BurritoKeys>(keys: &[K::Buffer]) -> Vec<String> {
+            names(keys)
+        }
+        fn pipelines<K: BurritoKeys>(keys: &[K::Pipeline]) -> Vec<String> {
+            names(keys)
+        }
+
+        assert_eq!(
+            buffers::<TestKeys>(&[TestBufferKey::Input, TestBufferKey::Output]),
+            ["Input", "Output"]
+        );
+        assert_eq!(names(&[TestLayoutKey::Layout]), ["Layout"]);
+        assert_eq!(
+            pipelines::<TestKeys>(&[TestPipelineKey::First, TestPipelineKey::Second]),
+            ["First", "Second"]
+        );
+        assert_ne!(TestPipelineKey::First, TestPipelineKey::Second);
+    }
+}
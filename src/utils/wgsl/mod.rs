@@ -2,26 +2,35 @@ mod buffers_readback;
 mod builders;
 mod creators;
 mod errors;
+mod jobs;
+mod keys;
+mod pipeline_state;
 mod plugin;
+mod reflection;
 mod render_node;
 mod resources;
+#[cfg(test)]
+pub(crate) mod testing;
 
 use buffers_readback::*;
 use builders::*;
 use errors::*;
 
 pub mod prelude {
+    pub use super::buffers_readback::{Readback, ReadbackMode};
     pub use super::builders::{BindLayoutBuilder, PipelineBuilder};
     pub use super::creators::*;
     pub use super::errors::*;
+    pub use super::jobs::*;
+    pub use super::keys::*;
+    pub use super::pipeline_state::*;
     pub use super::plugin::*;
+    pub use super::reflection::*;
     pub use super::render_node::*;
     pub use super::resources::*;
     pub use super::*;
 }
 
-pub type WgslBurritoPluginStr =
-    plugin::WgslBurritoPlugin<&'static str, &'static str, &'static str, &'static str>;
-pub type WgslMainBurritoStr = resources::WgslMainBurrito<&'static str>;
-pub type WgslRenderBurritoStr =
-    resources::WgslRenderBurrito<&'static str, &'static str, &'static str, &'static str>;
+pub type WgslBurritoPluginStr = plugin::WgslBurritoPlugin<keys::StrKeys>;
+pub type WgslMainBurritoStr = resources::WgslMainBurrito<keys::StrKeys>;
+pub type WgslRenderBurritoStr = resources::WgslRenderBurrito<keys::StrKeys>;
@@ -0,0 +1,170 @@
+use bevy::{
+    log::Level,
+    prelude::*,
+    render::render_resource::{
+        CachedComputePipelineId, CachedPipelineState, ComputePipeline, PipelineCache,
+        PipelineCacheError,
+    },
+};
+
+use super::errors::BindGroupBuilderError;
+use super::keys::BurritoKeys;
+
+/// State of a compute pipeline in the [`PipelineCache`].
+/// Pipelines go back to compiling whenever their shader is reloaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PipelineStatus {
+    /// Waiting for its shader or being compiled.
+    Compiling,
+    Ready,
+    /// The shader failed to compile, it is compiled again once the shader changes.
+    Failed(String),
+}
+
+impl PipelineStatus {
+    pub fn of(pipeline_cache: &PipelineCache, pipeline: CachedComputePipelineId) -> Self {
+        Self::from_state(pipeline_cache.get_compute_pipeline_state(pipeline))
+    }
+
+    fn from_state(state: &CachedPipelineState) -> Self {
+        match state {
+            CachedPipelineState::Ok(_) => Self::Ready,
+            // the cache retries these by itself
+            CachedPipelineState::Err(
+                PipelineCacheError::ShaderNotLoaded(_)
+                | PipelineCacheError::ShaderImportNotYetAvailable,
+            ) => Self::Compiling,
+            CachedPipelineState::Err(error) => Self::Failed(error.to_string()),
+            CachedPipelineState::Queued | CachedPipelineState::Creating(_) => Self::Compiling,
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        matches!(self, Self::Ready)
+    }
+
+    /// Level the change from `previous` is logged at, compile errors are the only errors.
+    fn change_level(&self, previous: Option<&Self>) -> Level {
+        match (previous, self) {
+            (_, Self::Failed(_)) => Level::ERROR,
+            // a reload, first compiles are not worth an info
+            (Some(Self::Ready | Self::Failed(_)), Self::Compiling) => Level::INFO,
+            _ => Level::DEBUG,
+        }
+    }
+
+    /// Logs the change from `previous`.
+    pub(crate) fn log_change<N: std::fmt::Debug>(&self, name: &N, previous: Option<&Self>) {
+        match (self.change_level(previous), self) {
+            (_, Self::Failed(error)) => error!("pipeline {name:?} failed: {error}"),
+            (Level::INFO, _) => info!("pipeline {name:?} shader changed, recompiling"),
+            _ => debug!("pipeline {name:?} is {self:?}"),
+        }
+    }
+}
+
+/// Sent in the main world when a pipeline of a burrito changes state.
+#[derive(Event)]
+pub struct PipelineStateChanged<K: BurritoKeys> {
+    pub pipeline: K::Pipeline,
+    pub status: PipelineStatus,
+}
+
+/// The compiled pipeline, or why it can't be used this frame.
+pub fn ready_compute_pipeline<N: std::fmt::Debug>(
+    pipeline_cache: &PipelineCache,
+    pipeline: CachedComputePipelineId,
+    name: N,
+) -> Result<&ComputePipeline, BindGroupBuilderError> {
+    if let Some(compute_pipeline) = pipeline_cache.get_compute_pipeline(pipeline) {
+        return Ok(compute_pipeline);
+    }
+
+    match PipelineStatus::of(pipeline_cache, pipeline) {
+        PipelineStatus::Failed(error) => Err(BindGroupBuilderError::pipeline_failed(name, &error)),
+        _ => Err(BindGroupBuilderError::pipeline_not_ready(name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::render_resource::Shader;
+
+    use super::*;
+    use crate::utils::wgsl::{keys::StrKeys, resources::WgslRenderBurrito};
+
+    #[test]
+    fn missing_shaders_are_still_compiling() {
+        let status = |error| PipelineStatus::from_state(&CachedPipelineState::Err(error));
+
+        assert_eq!(
+            PipelineStatus::from_state(&CachedPipelineState::Queued),
+            PipelineStatus::Compiling
+        );
+        assert_eq!(
+            status(PipelineCacheError::ShaderNotLoaded(
+                AssetId::<Shader>::default()
+            )),
+            PipelineStatus::Compiling
+        );
+        assert_eq!(
+            status(PipelineCacheError::ShaderImportNotYetAvailable),
+            PipelineStatus::Compiling
+        );
+        assert!(matches!(
+            status(PipelineCacheError::CreateShaderModule("bad wgsl".into())),
+            PipelineStatus::Failed(error) if error.contains("bad wgsl")
+        ));
+    }
+
+    #[test]
+    fn only_reloads_and_failures_are_loud() {
+        use PipelineStatus::*;
+        let failed = || Failed("bad wgsl".into());
+
+        assert_eq!(Compiling.change_level(None), Level::DEBUG);
+        assert_eq!(Ready.change_level(Some(&Compiling)), Level::DEBUG);
+        assert_eq!(Compiling.change_level(Some(&Ready)), Level::INFO);
+        assert_eq!(Compiling.change_level(Some(&failed())), Level::INFO);
+        assert_eq!(failed().change_level(None), Level::ERROR);
+        assert_eq!(failed().change_level(Some(&Compiling)), Level::ERROR);
+    }
+
+    #[test]
+    fn only_changes_are_sent() {
+        use PipelineStatus::*;
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let mut wgsl = WgslRenderBurrito::<StrKeys>::new().with_pipeline_state_sender(sender);
+        wgsl.pipelines
+            .insert("pipeline", CachedComputePipelineId::INVALID);
+
+        let frames = [
+            Compiling,
+            Compiling,
+            Ready,
+            Ready,
+            Compiling,
+            Failed("bad wgsl".into()),
+            Failed("bad wgsl".into()),
+        ];
+        for status in frames {
+            wgsl.update_pipeline_states(|_| status.clone());
+        }
+
+        let sent: Vec<_> = receiver
+            .try_iter()
+            .map(|changed| {
+                assert_eq!(changed.pipeline, "pipeline");
+                changed.status
+            })
+            .collect();
+        assert_eq!(
+            sent,
+            [Compiling, Ready, Compiling, Failed("bad wgsl".into())]
+        );
+        assert_eq!(
+            wgsl.pipeline_state(&"pipeline"),
+            Some(&Failed("bad wgsl".into()))
+        );
+    }
+}
@@ -1,120 +1,76 @@
 use bevy::prelude::*;
+use bevy::render::ExtractSchedule;
 use bevy::render::Render;
 use bevy::render::RenderApp;
 use bevy::render::RenderSet;
 use std::marker::PhantomData;
 
+use super::buffers_readback::ReadbackMode;
+use super::keys::BurritoKeys;
+use super::pipeline_state::PipelineStateChanged;
 use super::resources::*;
 
-pub struct WgslBurritoPlugin<B, BL, BG, P> {
-    pub(crate) _phantom: PhantomData<(B, BL, BG, P)>,
+pub struct WgslBurritoPlugin<K> {
+    pub(crate) readback_mode: ReadbackMode,
+    pub(crate) _phantom: PhantomData<K>,
 }
 
-impl<B, BL, BG, P> WgslBurritoPlugin<B, BL, BG, P> {
+impl<K> WgslBurritoPlugin<K> {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Readback buffers are read asynchronously unless [`ReadbackMode::Blocking`] is opted into.
+    pub fn with_readback_mode(mut self, readback_mode: ReadbackMode) -> Self {
+        self.readback_mode = readback_mode;
+        self
+    }
 }
 
-impl<B, BL, BG, P> Default for WgslBurritoPlugin<B, BL, BG, P> {
+impl<K> Default for WgslBurritoPlugin<K> {
     fn default() -> Self {
         Self {
+            readback_mode: ReadbackMode::default(),
             _phantom: PhantomData,
         }
     }
 }
 
-impl<B, BL, BG, P> Plugin for WgslBurritoPlugin<B, BL, BG, P>
-where
-    B: PartialEq
-        + Eq
-        + std::hash::Hash
-        + std::fmt::Debug
-        + ToOwned<Owned = B>
-        + Send
-        + Sync
-        + 'static,
-    BL: PartialEq
-        + Eq
-        + std::hash::Hash
-        + std::fmt::Debug
-        + ToOwned<Owned = BL>
-        + Send
-        + Sync
-        + 'static,
-    BG: PartialEq
-        + Eq
-        + std::hash::Hash
-        + std::fmt::Debug
-        + ToOwned<Owned = BG>
-        + Send
-        + Sync
-        + 'static,
-    P: PartialEq
-        + Eq
-        + std::hash::Hash
-        + std::fmt::Debug
-        + ToOwned<Owned = P>
-        + Send
-        + Sync
-        + 'static,
-{
+impl<K: BurritoKeys> Plugin for WgslBurritoPlugin<K> {
     fn build(&self, app: &mut App) {
-        app.insert_resource(WgslMainBurrito::<B>::new())
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        app.add_event::<PipelineStateChanged<K>>()
+            .insert_resource(WgslMainBurrito::<K>::new().with_pipeline_state_receiver(receiver))
+            .add_systems(PreUpdate, receive_pipeline_states::<K>)
             .sub_app_mut(RenderApp)
-            .insert_resource(WgslRenderBurrito::<B, BL, BG, P>::new())
+            .insert_resource(
+                WgslRenderBurrito::<K>::new()
+                    .with_readback_mode(self.readback_mode)
+                    .with_pipeline_state_sender(sender),
+            )
+            .add_systems(ExtractSchedule, check_pipeline_shaders::<K>)
             .add_systems(
                 Render,
-                // We need to run it after the render graph is done
-                // because this needs to happen after submit()
-                map_and_read_buffer::<B, BL, BG, P>.after(RenderSet::Render),
+                (
+                    // We need to run it after the render graph is done
+                    // because this needs to happen after submit()
+                    map_and_read_buffer::<K>,
+                    track_pipeline_states::<K>,
+                )
+                    .after(RenderSet::Render),
             );
     }
 }
 
-pub fn insert_burrito_channel<B, BL, BG, P>(app: &mut App, buffer_key: B)
-where
-    B: PartialEq
-        + Eq
-        + std::hash::Hash
-        + std::fmt::Debug
-        + ToOwned<Owned = B>
-        + Send
-        + Sync
-        + 'static,
-    BL: PartialEq
-        + Eq
-        + std::hash::Hash
-        + std::fmt::Debug
-        + ToOwned<Owned = BL>
-        + Send
-        + Sync
-        + 'static,
-    BG: PartialEq
-        + Eq
-        + std::hash::Hash
-        + std::fmt::Debug
-        + ToOwned<Owned = BG>
-        + Send
-        + Sync
-        + 'static,
-    P: PartialEq
-        + Eq
-        + std::hash::Hash
-        + std::fmt::Debug
-        + ToOwned<Owned = P>
-        + Send
-        + Sync
-        + 'static,
-{
+pub fn insert_burrito_channel<K: BurritoKeys>(app: &mut App, buffer_key: K::Buffer) {
     let (sender, receiver) = crossbeam_channel::unbounded();
     app.world_mut()
-        .resource_mut::<WgslMainBurrito<B>>()
+        .resource_mut::<WgslMainBurrito<K>>()
         .insert_receiver(buffer_key.to_owned(), receiver);
 
     let render_app = &mut app.sub_app_mut(RenderApp);
     render_app
         .world_mut()
-        .resource_mut::<WgslRenderBurrito<B, BL, BG, P>>()
+        .resource_mut::<WgslRenderBurrito<K>>()
         .insert_sender(buffer_key.to_owned(), sender);
 }
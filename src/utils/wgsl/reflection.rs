@@ -0,0 +1,705 @@
+use std::num::NonZeroU64;
+
+use bevy::{
+    prelude::*,
+    render::render_resource::{
+        encase::ShaderSize, BindGroupLayoutEntry, BindingType, BufferBindingType, Shader,
+        ShaderStages, ShaderType,
+    },
+};
+pub use naga::ScalarKind;
+use naga::{
+    proc::Layouter,
+    valid::{Capabilities, ModuleInfo, ValidationFlags, Validator},
+    AddressSpace, StorageAccess, TypeInner,
+};
+use naga_oil::compose::{Composer, NagaModuleDescriptor};
+
+use super::errors::BindGroupBuilderError;
+
+/// Buffer binding as declared in a shader.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReflectedBinding {
+    pub binding: u32,
+    pub name: String,
+    pub type_name: String,
+    pub ty: BufferBindingType,
+    /// Size of the WGSL type, runtime sized arrays count a single element like `ShaderType::min_size`.
+    pub size: u64,
+    /// Scalar kind of the type or of its array elements, `None` for structs.
+    pub kind: Option<ScalarKind>,
+    /// Members of the struct of the type or of its array elements.
+    pub members: Vec<ShaderMember>,
+}
+
+/// Member of a struct as laid out in a buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShaderMember {
+    pub name: String,
+    pub offset: u64,
+    pub size: u64,
+    /// Scalar kind of scalars, vectors, matrices and arrays of them, `None` for structs.
+    pub kind: Option<ScalarKind>,
+}
+
+/// How a rust type is laid out in a buffer, checked against the WGSL type it is bound to.
+/// Structs implement it with [`shader_struct!`](crate::shader_struct).
+pub trait ShaderLayout: ShaderType {
+    /// Scalar kind of scalars, vectors, matrices and arrays of them, `None` for structs.
+    const KIND: Option<ScalarKind>;
+
+    /// Members of a struct, in declaration order.
+    fn members() -> Vec<ShaderMember> {
+        Vec::new()
+    }
+}
+
+macro_rules! impl_scalar_layout {
+    ($kind:ident: $($ty:ty),*) => {
+        $(impl ShaderLayout for $ty {
+            const KIND: Option<ScalarKind> = Some(ScalarKind::$kind);
+        })*
+    };
+}
+
+impl_scalar_layout!(Float: f32, Vec2, Vec3, Vec4, Mat2, Mat3, Mat4);
+impl_scalar_layout!(Uint: u32, UVec2, UVec3, UVec4);
+impl_scalar_layout!(Sint: i32, IVec2, IVec3, IVec4);
+
+impl<T: ShaderLayout + ShaderSize> ShaderLayout for Vec<T> {
+    const KIND: Option<ScalarKind> = T::KIND;
+
+    fn members() -> Vec<ShaderMember> {
+        T::members()
+    }
+}
+
+impl<T: ShaderLayout + ShaderSize, const N: usize> ShaderLayout for [T; N] {
+    const KIND: Option<ScalarKind> = T::KIND;
+
+    fn members() -> Vec<ShaderMember> {
+        T::members()
+    }
+}
+
+/// Declares a struct deriving `ShaderType` and implements [`ShaderLayout`] from its fields,
+/// so a reordered field fails the layout check even when every offset stays the same.
+///
+/// ```ignore
+/// shader_struct! {
+///     #[derive(ShaderType, Clone, Copy)]
+///     pub struct Particle {
+///         position: Vec2,
+///         seed: u32,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! shader_struct {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$field_meta:meta])* $field_vis:vis $field:ident: $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $($(#[$field_meta])* $field_vis $field: $ty),*
+        }
+
+        impl $crate::utils::wgsl::prelude::ShaderLayout for $name {
+            const KIND: Option<$crate::utils::wgsl::prelude::ScalarKind> = None;
+
+            fn members() -> Vec<$crate::utils::wgsl::prelude::ShaderMember> {
+                use ::bevy::render::render_resource::ShaderType;
+                use $crate::utils::wgsl::prelude::{ShaderLayout, ShaderMember};
+
+                // the offsets the `ShaderType` derive writes the fields at
+                let offsets = <Self as ShaderType>::METADATA.extra.offsets;
+                let fields = [$((
+                    stringify!($field),
+                    <$ty as ShaderType>::min_size().get(),
+                    <$ty as ShaderLayout>::KIND,
+                )),*];
+
+                fields
+                    .into_iter()
+                    .zip(offsets)
+                    .map(|((name, size, kind), offset)| ShaderMember {
+                        name: name.to_owned(),
+                        offset,
+                        size,
+                        kind,
+                    })
+                    .collect()
+            }
+        }
+    };
+}
+
+/// Rust type of a layout slot, checked against the binding of the shader.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlotLayout {
+    pub type_name: &'static str,
+    pub kind: Option<ScalarKind>,
+    pub members: Vec<ShaderMember>,
+}
+
+impl SlotLayout {
+    pub fn of<T: ShaderLayout>() -> Self {
+        Self {
+            type_name: std::any::type_name::<T>(),
+            kind: T::KIND,
+            members: T::members(),
+        }
+    }
+}
+
+/// Bindings of a shader read with naga, to derive bind group layouts or check them against it.
+pub struct ShaderReflection {
+    module: naga::Module,
+    info: ModuleInfo,
+}
+
+impl ShaderReflection {
+    /// Reflects a WGSL source without `#import`s.
+    pub fn from_wgsl(source: &str) -> Result<Self, BindGroupBuilderError> {
+        let module = naga::front::wgsl::parse_str(source).map_err(|error| {
+            BindGroupBuilderError::ShaderReflectionFailed(error.emit_to_string(source))
+        })?;
+
+        Self::from_module(module)
+    }
+
+    /// Reflects `shader` with its imports composed from the loaded `shaders`.
+    pub fn from_shader(
+        shader: &Shader,
+        shaders: &Assets<Shader>,
+    ) -> Result<Self, BindGroupBuilderError> {
+        let mut composer = Composer::default();
+        add_imports(&mut composer, shader, shaders)?;
+
+        let module = composer
+            .make_naga_module(NagaModuleDescriptor::from(shader))
+            .map_err(|error| {
+                BindGroupBuilderError::ShaderReflectionFailed(error.emit_to_string(&composer))
+            })?;
+
+        Self::from_module(module)
+    }
+
+    fn from_module(module: naga::Module) -> Result<Self, BindGroupBuilderError> {
+        let info = Validator::new(ValidationFlags::all(), Capabilities::all())
+            .validate(&module)
+            .map_err(|error| BindGroupBuilderError::ShaderReflectionFailed(error.to_string()))?;
+
+        Ok(Self { module, info })
+    }
+
+    /// Buffers of `group` used by `entry_point`, sorted by binding.
+    pub fn bindings(
+        &self,
+        entry_point: &str,
+        group: u32,
+    ) -> Result<Vec<ReflectedBinding>, BindGroupBuilderError> {
+        let Some(index) = self
+            .module
+            .entry_points
+            .iter()
+            .position(|entry| entry.name == entry_point)
+        else {
+            return Err(BindGroupBuilderError::ShaderReflectionFailed(format!(
+                "no entry point {entry_point}"
+            )));
+        };
+        let function_info = self.info.get_entry_point(index);
+
+        let mut layouter = Layouter::default();
+        layouter
+            .update(self.module.to_ctx())
+            .map_err(|error| BindGroupBuilderError::ShaderReflectionFailed(error.to_string()))?;
+
+        let mut bindings = Vec::new();
+        for (handle, variable) in self.module.global_variables.iter() {
+            let Some(resource) = variable.binding.as_ref() else {
+                continue;
+            };
+            // wgpu only checks the bindings the entry point uses
+            if resource.group != group || function_info[handle].is_empty() {
+                continue;
+            }
+
+            let name = variable.name.clone().unwrap_or_default();
+            let ty = match variable.space {
+                AddressSpace::Uniform => BufferBindingType::Uniform,
+                AddressSpace::Storage { access } => BufferBindingType::Storage {
+                    read_only: !access.contains(StorageAccess::STORE),
+                },
+                _ => {
+                    return Err(BindGroupBuilderError::ShaderReflectionFailed(format!(
+                        "binding {} `{name}` is not a buffer, only buffers are reflected",
+                        resource.binding
+                    )))
+                }
+            };
+
+            bindings.push(ReflectedBinding {
+                binding: resource.binding,
+                name,
+                type_name: self.type_name(variable.ty),
+                ty,
+                size: layouter[variable.ty].size as u64,
+                kind: self.scalar_kind(variable.ty),
+                members: self.members(variable.ty, &layouter),
+            });
+        }
+
+        bindings.sort_by_key(|binding| binding.binding);
+        Ok(bindings)
+    }
+
+    /// Layout entries of the buffers of `group` used by `entry_point`.
+    pub fn layout_entries(
+        &self,
+        entry_point: &str,
+        group: u32,
+        visibility: ShaderStages,
+    ) -> Result<Vec<BindGroupLayoutEntry>, BindGroupBuilderError> {
+        let entries = self
+            .bindings(entry_point, group)?
+            .into_iter()
+            .map(|binding| BindGroupLayoutEntry {
+                binding: binding.binding,
+                visibility,
+                ty: BindingType::Buffer {
+                    ty: binding.ty,
+                    has_dynamic_offset: false,
+                    min_binding_size: NonZeroU64::new(binding.size),
+                },
+                count: None,
+            })
+            .collect();
+
+        Ok(entries)
+    }
+
+    /// Checks `entries` against the buffers of `group` used by `entry_point`,
+    /// `slots` are the rust types of the entries, `None` for the ones read from the shader.
+    pub fn validate_entries<N: std::fmt::Debug>(
+        &self,
+        layout_name: N,
+        entry_point: &str,
+        group: u32,
+        entries: &[BindGroupLayoutEntry],
+        slots: &[Option<SlotLayout>],
+    ) -> Result<(), BindGroupBuilderError> {
+        let mismatch =
+            |reason: String| BindGroupBuilderError::layout_mismatch(&layout_name, reason);
+
+        for binding in self.bindings(entry_point, group)? {
+            let ReflectedBinding {
+                binding: index,
+                name,
+                type_name: wgsl_type,
+                ..
+            } = &binding;
+
+            let Some((position, entry)) = entries
+                .iter()
+                .enumerate()
+                .find(|(_, entry)| entry.binding == *index)
+            else {
+                return Err(mismatch(format!(
+                    "binding {index} `{name}: {wgsl_type}` has no slot"
+                )));
+            };
+            let slot = slots.get(position).and_then(Option::as_ref);
+            let rust_type = slot.map_or("?", |slot| slot.type_name);
+
+            let BindingType::Buffer {
+                ty,
+                min_binding_size,
+                ..
+            } = entry.ty
+            else {
+                return Err(mismatch(format!(
+                    "binding {index} `{name}: {wgsl_type}` is a buffer, its slot is {:?}",
+                    entry.ty
+                )));
+            };
+            if ty != binding.ty {
+                return Err(mismatch(format!(
+                    "binding {index} `{name}` is {} in the shader but its slot {rust_type} is {}",
+                    describe_buffer(binding.ty),
+                    describe_buffer(ty)
+                )));
+            }
+
+            let size = min_binding_size.map_or(0, NonZeroU64::get);
+            if size != binding.size {
+                return Err(mismatch(format!(
+                    "binding {index} `{name}: {wgsl_type}` is {} bytes but its slot {rust_type} is {size} bytes",
+                    binding.size
+                )));
+            }
+
+            let Some(slot) = slot else {
+                continue;
+            };
+            if slot.kind != binding.kind {
+                return Err(mismatch(format!(
+                    "binding {index} `{name}: {wgsl_type}` holds {} but its slot {rust_type} holds {}",
+                    describe_kind(binding.kind),
+                    describe_kind(slot.kind)
+                )));
+            }
+            // the first member laid out differently, the name is only there to find it
+            let count = binding.members.len().max(slot.members.len());
+            for member in 0..count {
+                let wgsl_member = binding.members.get(member);
+                let rust_member = slot.members.get(member);
+                let same = |wgsl: &ShaderMember, rust: &ShaderMember| {
+                    (wgsl.offset, wgsl.size, wgsl.kind) == (rust.offset, rust.size, rust.kind)
+                };
+                if let (Some(wgsl), Some(rust)) = (wgsl_member, rust_member) {
+                    if same(wgsl, rust) {
+                        continue;
+                    }
+                }
+
+                return Err(mismatch(format!(
+                    "binding {index} `{name}: {wgsl_type}` has {} as member {member} but its slot {rust_type} has {}",
+                    describe_member(wgsl_member),
+                    describe_member(rust_member)
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Scalar kind of `ty` or of its array elements, `None` for structs.
+    fn scalar_kind(&self, ty: naga::Handle<naga::Type>) -> Option<ScalarKind> {
+        match self.module.types[ty].inner {
+            TypeInner::Scalar(scalar)
+            | TypeInner::Vector { scalar, .. }
+            | TypeInner::Matrix { scalar, .. }
+            | TypeInner::Atomic(scalar) => Some(scalar.kind),
+            TypeInner::Array { base, .. } => self.scalar_kind(base),
+            _ => None,
+        }
+    }
+
+    /// Members of the struct of `ty` or of its array elements.
+    fn members(&self, ty: naga::Handle<naga::Type>, layouter: &Layouter) -> Vec<ShaderMember> {
+        match &self.module.types[ty].inner {
+            TypeInner::Array { base, .. } => self.members(*base, layouter),
+            TypeInner::Struct { members, .. } => members
+                .iter()
+                .map(|member| ShaderMember {
+                    name: member.name.clone().unwrap_or_default(),
+                    offset: member.offset as u64,
+                    size: layouter[member.ty].size as u64,
+                    kind: self.scalar_kind(member.ty),
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn type_name(&self, ty: naga::Handle<naga::Type>) -> String {
+        let ty = &self.module.types[ty];
+        if let Some(name) = ty.name.as_ref() {
+            return name.clone();
+        }
+
+        match ty.inner {
+            TypeInner::Scalar(scalar) => format!("{:?}{}", scalar.kind, scalar.width * 8),
+            TypeInner::Vector { size, scalar } => {
+                format!("vec{}<{:?}{}>", size as u8, scalar.kind, scalar.width * 8)
+            }
+            TypeInner::Array { base, .. } => format!("array<{}>", self.type_name(base)),
+            ref inner => format!("{inner:?}"),
+        }
+    }
+}
+
+fn describe_kind(kind: Option<ScalarKind>) -> String {
+    kind.map_or("structs".to_owned(), |kind| format!("{kind:?}s"))
+}
+
+fn describe_member(member: Option<&ShaderMember>) -> String {
+    let Some(member) = member else {
+        return "nothing".to_owned();
+    };
+    let kind = member
+        .kind
+        .map_or("struct".to_owned(), |kind| format!("{kind:?}"));
+    format!(
+        "`{}` ({kind}, {} bytes at offset {})",
+        member.name, member.size, member.offset
+    )
+}
+
+fn describe_buffer(ty: BufferBindingType) -> &'static str {
+    match ty {
+        BufferBindingType::Uniform => "a uniform",
+        BufferBindingType::Storage { read_only: true } => "read-only storage",
+        BufferBindingType::Storage { read_only: false } => "read-write storage",
+    }
+}
+
+/// Adds the imports of `shader` to `composer`, imports of imports first.
+fn add_imports(
+    composer: &mut Composer,
+    shader: &Shader,
+    shaders: &Assets<Shader>,
+) -> Result<(), BindGroupBuilderError> {
+    for import in shader.imports() {
+        if composer.contains_module(&import.module_name()) {
+            continue;
+        }
+        let Some(imported) = shaders
+            .iter()
+            .map(|(_, shader)| shader)
+            .find(|shader| shader.import_path() == import)
+        else {
+            return Err(BindGroupBuilderError::ShaderReflectionFailed(format!(
+                "import {} of {} is not loaded",
+                import.module_name(),
+                shader.path
+            )));
+        };
+
+        add_imports(composer, imported, shaders)?;
+        let added = composer.add_composable_module(imported.into()).map(|_| ());
+        added.map_err(|error| {
+            BindGroupBuilderError::ShaderReflectionFailed(error.emit_to_string(composer))
+        })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::render_resource::{binding_types, BindGroupLayoutEntryBuilder, ShaderType};
+
+    use super::*;
+
+    const SHADER: &str = "
+struct Params {
+    scale: f32,
+    count: u32,
+}
+
+struct Sample {
+    value: f32,
+    gradient: vec2<f32>,
+}
+
+@group(0) @binding(0) var<storage, read> positions: array<vec2<f32>>;
+@group(0) @binding(1) var<storage, read_write> samples: array<Sample>;
+@group(0) @binding(2) var<uniform> params: Params;
+@group(0) @binding(3) var<storage, read> unused: array<f32>;
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let position = positions[id.x] * params.scale;
+    samples[id.x] = Sample(f32(params.count), position);
+}
+";
+
+    crate::shader_struct! {
+        #[derive(ShaderType)]
+        struct Params {
+            scale: f32,
+            count: u32,
+        }
+    }
+
+    crate::shader_struct! {
+        #[derive(ShaderType)]
+        struct Sample {
+            value: f32,
+            gradient: Vec2,
+        }
+    }
+
+    crate::shader_struct! {
+        /// [`Params`] with its members swapped, the same size and offsets.
+        #[derive(ShaderType)]
+        struct SwappedParams {
+            count: u32,
+            scale: f32,
+        }
+    }
+
+    fn entries(slots: [BindingType; 3]) -> Vec<BindGroupLayoutEntry> {
+        slots
+            .into_iter()
+            .enumerate()
+            .map(|(binding, ty)| BindGroupLayoutEntry {
+                binding: binding as u32,
+                visibility: ShaderStages::COMPUTE,
+                ty,
+                count: None,
+            })
+            .collect()
+    }
+
+    fn slot(builder: BindGroupLayoutEntryBuilder) -> BindingType {
+        builder.build(0, ShaderStages::COMPUTE).ty
+    }
+
+    /// Layout of `T` named `name` in the errors.
+    fn named<T: ShaderLayout>(name: &'static str) -> Option<SlotLayout> {
+        Some(SlotLayout {
+            type_name: name,
+            ..SlotLayout::of::<T>()
+        })
+    }
+
+    #[test]
+    fn reflects_used_buffers() {
+        let reflection = ShaderReflection::from_wgsl(SHADER).unwrap();
+        let bindings = reflection.bindings("main", 0).unwrap();
+
+        assert_eq!(
+            bindings
+                .iter()
+                .map(|binding| (binding.binding, binding.name.as_str(), binding.size))
+                .collect::<Vec<_>>(),
+            [(0, "positions", 8), (1, "samples", 16), (2, "params", 8)],
+            "unused bindings are skipped"
+        );
+        assert_eq!(bindings[1].type_name, "array<Sample>");
+        assert_eq!(bindings[1].members, Sample::members());
+        assert_eq!(bindings[0].kind, Some(ScalarKind::Float));
+        assert_eq!(
+            bindings[0].ty,
+            BufferBindingType::Storage { read_only: true }
+        );
+        assert_eq!(bindings[2].ty, BufferBindingType::Uniform);
+    }
+
+    #[test]
+    fn rust_types_match_reflected_layout() {
+        let reflection = ShaderReflection::from_wgsl(SHADER).unwrap();
+        let rust_entries = entries([
+            slot(binding_types::storage_buffer_read_only::<Vec<Vec2>>(false)),
+            slot(binding_types::storage_buffer::<Vec<Sample>>(false)),
+            slot(binding_types::uniform_buffer::<Params>(false)),
+        ]);
+
+        reflection
+            .validate_entries(
+                "test",
+                "main",
+                0,
+                &rust_entries,
+                &[
+                    Some(SlotLayout::of::<Vec<Vec2>>()),
+                    Some(SlotLayout::of::<Vec<Sample>>()),
+                    Some(SlotLayout::of::<Params>()),
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            reflection
+                .layout_entries("main", 0, ShaderStages::COMPUTE)
+                .unwrap(),
+            rust_entries
+        );
+    }
+
+    #[test]
+    fn mismatches_are_described() {
+        let reflection = ShaderReflection::from_wgsl(SHADER).unwrap();
+        let check = |slots: [BindingType; 3], layouts: [Option<SlotLayout>; 3]| {
+            reflection
+                .validate_entries("test", "main", 0, &entries(slots), &layouts)
+                .unwrap_err()
+                .to_string()
+        };
+
+        // a struct with a missing member
+        let error = check(
+            [
+                slot(binding_types::storage_buffer_read_only::<Vec<Vec2>>(false)),
+                slot(binding_types::storage_buffer::<Vec<Vec2>>(false)),
+                slot(binding_types::uniform_buffer::<Params>(false)),
+            ],
+            [
+                named::<Vec<Vec2>>("A"),
+                named::<Vec<Vec2>>("B"),
+                named::<Params>("C"),
+            ],
+        );
+        assert!(
+            error.contains("`samples: array<Sample>` is 16 bytes"),
+            "{error}"
+        );
+        assert!(error.contains("B is 8 bytes"), "{error}");
+
+        // bindings swapped
+        let error = check(
+            [
+                slot(binding_types::storage_buffer_read_only::<Vec<Vec2>>(false)),
+                slot(binding_types::uniform_buffer::<Params>(false)),
+                slot(binding_types::storage_buffer::<Vec<Sample>>(false)),
+            ],
+            [
+                named::<Vec<Vec2>>("A"),
+                named::<Params>("B"),
+                named::<Vec<Sample>>("C"),
+            ],
+        );
+        assert!(
+            error.contains("read-write storage in the shader but its slot B is a uniform"),
+            "{error}"
+        );
+
+        // members swapped, every offset is the same
+        let error = check(
+            [
+                slot(binding_types::storage_buffer_read_only::<Vec<Vec2>>(false)),
+                slot(binding_types::storage_buffer::<Vec<Sample>>(false)),
+                slot(binding_types::uniform_buffer::<SwappedParams>(false)),
+            ],
+            [
+                named::<Vec<Vec2>>("A"),
+                named::<Vec<Sample>>("B"),
+                named::<SwappedParams>("C"),
+            ],
+        );
+        assert!(
+            error.contains(
+                "`params: Params` has `scale` (Float, 4 bytes at offset 0) as member 0 \
+                 but its slot C has `count` (Uint, 4 bytes at offset 0)"
+            ),
+            "{error}"
+        );
+
+        // a scalar kind changed
+        let error = check(
+            [
+                slot(binding_types::storage_buffer_read_only::<Vec<UVec2>>(false)),
+                slot(binding_types::storage_buffer::<Vec<Sample>>(false)),
+                slot(binding_types::uniform_buffer::<Params>(false)),
+            ],
+            [
+                named::<Vec<UVec2>>("A"),
+                named::<Vec<Sample>>("B"),
+                named::<Params>("C"),
+            ],
+        );
+        assert!(
+            error.contains("holds Floats but its slot A holds Uints"),
+            "{error}"
+        );
+
+        assert!(reflection.bindings("missing", 0).is_err());
+        assert!(ShaderReflection::from_wgsl("fn broken(").is_err());
+    }
+}
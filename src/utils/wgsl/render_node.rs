@@ -1,79 +1,53 @@
 use super::prelude::*;
 use bevy::{
+    core::FrameCount,
     prelude::*,
     render::{
         render_graph,
-        render_resource::{ComputePassDescriptor, PipelineCache},
+        render_resource::{BindGroup, ComputePassDescriptor, ComputePipeline, PipelineCache},
         renderer::RenderContext,
     },
 };
 use std::marker::PhantomData;
 
-pub trait RenderBurritoPassTrait<B, BG, P> {
+pub trait RenderBurritoPassTrait<K: BurritoKeys> {
     fn workgroup_size(&self, _world: &World) -> [u32; 3] {
         [1, 1, 1]
     }
-    fn pipeline_key(&self, world: &World) -> &P;
-    fn bind_group_key(&self, world: &World) -> &BG;
+    fn pipeline_key(&self, world: &World) -> &K::Pipeline;
+    fn bind_group_key(&self, world: &World) -> &K::BindGroup;
 }
 
-pub trait RenderBurritoNodeTrait<B, BG, P, Pass: RenderBurritoPassTrait<B, BG, P>> {
+pub trait RenderBurritoNodeTrait<K: BurritoKeys, Pass: RenderBurritoPassTrait<K>> {
     fn passes(&self) -> &[Pass];
     fn label(&self) -> &str;
-    fn staging_buffers(&self, world: &World) -> &[B];
+    fn staging_buffers(&self, world: &World) -> &[K::Buffer];
     fn should_run(&self, _world: &World) -> bool {
         true
     }
+    /// Id the staging buffers copied this frame are read back with, the frame number by default.
+    /// Nodes dispatching requests return the request id so results can be matched to them.
+    fn readback_id(&self, world: &World) -> u64 {
+        world
+            .get_resource::<FrameCount>()
+            .map_or(0, |frame_count| frame_count.0 as u64)
+    }
 }
 
 pub struct NodeBurrito<
-    N: RenderBurritoNodeTrait<B, BG, P, Pass>,
-    Pass: RenderBurritoPassTrait<B, BG, P>,
-    B,
-    BL,
-    BG,
-    P,
+    N: RenderBurritoNodeTrait<K, Pass>,
+    Pass: RenderBurritoPassTrait<K>,
+    K: BurritoKeys,
 > {
     node: N,
-    _phantom: PhantomData<(B, BL, BG, P, Pass)>,
+    _phantom: PhantomData<(K, Pass)>,
 }
 
-impl<N, Pass, B, BL, BG, P> NodeBurrito<N, Pass, B, BL, BG, P>
+impl<N, Pass, K> NodeBurrito<N, Pass, K>
 where
-    N: RenderBurritoNodeTrait<B, BG, P, Pass> + Sync + Send + 'static,
-    Pass: RenderBurritoPassTrait<B, BG, P> + Sync + Send + 'static,
-    B: PartialEq
-        + Eq
-        + std::hash::Hash
-        + std::fmt::Debug
-        + ToOwned<Owned = B>
-        + Send
-        + Sync
-        + 'static,
-    BL: PartialEq
-        + Eq
-        + std::hash::Hash
-        + std::fmt::Debug
-        + ToOwned<Owned = BL>
-        + Send
-        + Sync
-        + 'static,
-    BG: PartialEq
-        + Eq
-        + std::hash::Hash
-        + std::fmt::Debug
-        + ToOwned<Owned = BG>
-        + Send
-        + Sync
-        + 'static,
-    P: PartialEq
-        + Eq
-        + std::hash::Hash
-        + std::fmt::Debug
-        + ToOwned<Owned = P>
-        + Send
-        + Sync
-        + 'static,
+    N: RenderBurritoNodeTrait<K, Pass> + Sync + Send + 'static,
+    Pass: RenderBurritoPassTrait<K> + Sync + Send + 'static,
+    K: BurritoKeys,
 {
     pub fn new(node: N) -> Self {
         Self {
@@ -81,55 +55,53 @@ where
             _phantom: PhantomData,
         }
     }
+
+    /// Pipeline and bind group of a pass, `None` while the pipeline compiles or the bind group is missing.
+    fn pass_resources<'w>(
+        &self,
+        node_pass: &Pass,
+        world: &'w World,
+    ) -> Option<(&'w ComputePipeline, &'w BindGroup)> {
+        let wgsl = world.resource::<WgslRenderBurrito<K>>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        // compile errors are reported by the pipeline tracking, not every frame
+        let pipeline = wgsl
+            .try_get_compute_pipeline(node_pass.pipeline_key(world), pipeline_cache)
+            .ok()?;
+        let bind_group = wgsl.get_bind_group(node_pass.bind_group_key(world))?;
+        Some((pipeline, bind_group))
+    }
 }
 
-impl<N, Pass, B, BL, BG, P> render_graph::Node for NodeBurrito<N, Pass, B, BL, BG, P>
+impl<N, Pass, K> render_graph::Node for NodeBurrito<N, Pass, K>
 where
-    N: RenderBurritoNodeTrait<B, BG, P, Pass> + Sync + Send + 'static,
-    Pass: RenderBurritoPassTrait<B, BG, P> + Sync + Send + 'static,
-    B: PartialEq
-        + Eq
-        + std::hash::Hash
-        + std::fmt::Debug
-        + ToOwned<Owned = B>
-        + Send
-        + Sync
-        + 'static,
-    BL: PartialEq
-        + Eq
-        + std::hash::Hash
-        + std::fmt::Debug
-        + ToOwned<Owned = BL>
-        + Send
-        + Sync
-        + 'static,
-    BG: PartialEq
-        + Eq
-        + std::hash::Hash
-        + std::fmt::Debug
-        + ToOwned<Owned = BG>
-        + Send
-        + Sync
-        + 'static,
-    P: PartialEq
-        + Eq
-        + std::hash::Hash
-        + std::fmt::Debug
-        + ToOwned<Owned = P>
-        + Send
-        + Sync
-        + 'static,
+    N: RenderBurritoNodeTrait<K, Pass> + Sync + Send + 'static,
+    Pass: RenderBurritoPassTrait<K> + Sync + Send + 'static,
+    K: BurritoKeys,
 {
     fn update(&mut self, world: &mut World) {
         if !self.node.should_run(world) {
             return;
         }
-        for buffer in self.node.staging_buffers(world) {
-            let wgsl = &mut world.resource_mut::<WgslRenderBurrito<B, BL, BG, P>>();
-            let Some(buffer) = wgsl.get_buffer_readback_mut(buffer) else {
+        // a staging buffer reserved without a dispatch would be read back with stale data
+        let ready = self
+            .node
+            .passes()
+            .iter()
+            .all(|node_pass| self.pass_resources(node_pass, world).is_some());
+        if !ready {
+            return;
+        }
+
+        let id = self.node.readback_id(world);
+        for name in self.node.staging_buffers(world) {
+            let wgsl = &mut world.resource_mut::<WgslRenderBurrito<K>>();
+            let Some(buffer) = wgsl.get_buffer_readback_mut(name) else {
                 continue;
             };
-            buffer.mark_changed();
+            if !buffer.reserve(id) {
+                warn!("every staging buffer of {name:?} is in flight, readback {id} is skipped");
+            }
         }
     }
     fn run(
@@ -143,16 +115,8 @@ where
         }
 
         for node_pass in self.node.passes() {
-            let wgsl = world.resource::<WgslRenderBurrito<B, BL, BG, P>>();
-            let pipeline_cache = world.resource::<PipelineCache>();
-            let Some(pipeline) = wgsl
-                .get_pipeline(node_pass.pipeline_key(world))
-                .and_then(|p| pipeline_cache.get_compute_pipeline(*p))
-            else {
-                return Ok(());
-            };
-
-            let Some(bind_group) = wgsl.get_bind_group(&node_pass.bind_group_key(world)) else {
+            // `update` reserved nothing unless every pass is ready
+            let Some((pipeline, bind_group)) = self.pass_resources(node_pass, world) else {
                 return Ok(());
             };
 
@@ -172,8 +136,7 @@ where
         }
 
         // Copy the gpu accessible buffer to the cpu accessible buffer
-        let wgsl = world.resource::<WgslRenderBurrito<B, BL, BG, P>>();
-        info!("copying buffers");
+        let wgsl = world.resource::<WgslRenderBurrito<K>>();
         let encoder = render_context.command_encoder();
         for buffer in self.node.staging_buffers(world) {
             wgsl.copy_to_readback_buffer(encoder, &buffer);
@@ -181,3 +144,82 @@ where
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        ecs::system::RunSystemOnce,
+        render::renderer::{RenderAdapter, WgpuWrapper},
+    };
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::utils::wgsl::resources::map_and_read_buffer;
+    use crate::utils::wgsl::testing::{software_gpu, SoftwareGpu};
+
+    crate::burrito_keys! {
+        #[allow(dead_code)]
+        struct TestKeys {
+            type Buffer = TestBufferKey { Output };
+            type Layout = TestLayoutKey {};
+            type BindGroup = TestBindGroupKey { BindGroup };
+            type Pipeline = TestPipelineKey { Pipeline };
+        }
+    }
+
+    struct TestPass;
+
+    impl RenderBurritoPassTrait<TestKeys> for TestPass {
+        fn pipeline_key(&self, _world: &World) -> &TestPipelineKey {
+            &TestPipelineKey::Pipeline
+        }
+        fn bind_group_key(&self, _world: &World) -> &TestBindGroupKey {
+            &TestBindGroupKey::BindGroup
+        }
+    }
+
+    struct TestNode([TestPass; 1]);
+
+    impl RenderBurritoNodeTrait<TestKeys, TestPass> for TestNode {
+        fn passes(&self) -> &[TestPass] {
+            &self.0
+        }
+        fn label(&self) -> &str {
+            "test"
+        }
+        fn staging_buffers(&self, _world: &World) -> &[TestBufferKey] {
+            &[TestBufferKey::Output]
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a wgpu adapter, run with --ignored"]
+    fn nothing_is_read_back_without_a_pipeline() {
+        let SoftwareGpu {
+            adapter, device, ..
+        } = software_gpu();
+        let adapter = RenderAdapter(Arc::new(WgpuWrapper::new(adapter)));
+
+        let mut wgsl =
+            WgslRenderBurrito::<TestKeys>::new().with_readback_mode(ReadbackMode::Blocking);
+        wgsl.start_create_buffers(&device)
+            .create_empty_storage_readable(TestBufferKey::Output, 16);
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        wgsl.insert_sender(TestBufferKey::Output, sender);
+
+        let mut world = World::new();
+        world.insert_resource(PipelineCache::new(device.clone(), adapter, true));
+        world.insert_resource(device);
+        world.insert_resource(wgsl);
+
+        // the pipeline was never queued, `run` dispatches nothing
+        let mut node = NodeBurrito::new(TestNode([TestPass]));
+        render_graph::Node::update(&mut node, &mut world);
+        let wgsl = world.resource::<WgslRenderBurrito<TestKeys>>();
+        let readback = wgsl.get_buffer_readback(&TestBufferKey::Output).unwrap();
+        assert_eq!(readback.in_flight(), 0);
+
+        world.run_system_once(map_and_read_buffer::<TestKeys>);
+        assert!(receiver.try_recv().is_err());
+    }
+}
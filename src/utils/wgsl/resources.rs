@@ -1,128 +1,167 @@
 use bevy::render::render_resource::*;
+use bevy::render::Extract;
 use bevy::utils::hashbrown::HashMap;
 use bevy::{prelude::*, render::renderer::RenderDevice};
 use bytemuck::AnyBitPattern;
+use crossbeam_channel::{Receiver, Sender};
 
 use super::prelude::*;
 
 #[derive(Resource)]
-pub struct WgslMainBurrito<B>
-where
-    B: PartialEq + Eq + std::hash::Hash + std::fmt::Debug + ToOwned<Owned = B>,
-{
-    pub(crate) receivers: HashMap<B, ReadbackReceiver>,
+pub struct WgslMainBurrito<K: BurritoKeys> {
+    pub(crate) receivers: HashMap<K::Buffer, ReadbackReceiver>,
+    pub(crate) pipeline_states: HashMap<K::Pipeline, PipelineStatus>,
+    pub(crate) pipeline_state_receiver: Option<Receiver<PipelineStateChanged<K>>>,
 }
 
 #[derive(Resource)]
-pub struct WgslRenderBurrito<B, BL, BG, P> {
-    pub(crate) buffers: HashMap<B, Buffer>,
-    pub(crate) buffers_readback: HashMap<B, ReadbackBuffer>,
-    pub(crate) senders: HashMap<B, ReadbackSender>,
-    pub(crate) layouts: HashMap<BL, BindGroupLayout>,
-    pub(crate) bind_groups: HashMap<BG, BindGroup>,
-    pub(crate) pipelines: HashMap<P, CachedComputePipelineId>,
+pub struct WgslRenderBurrito<K: BurritoKeys> {
+    pub(crate) buffers: HashMap<K::Buffer, Buffer>,
+    pub(crate) buffers_readback: HashMap<K::Buffer, ReadbackBuffer>,
+    pub(crate) senders: HashMap<K::Buffer, ReadbackSender>,
+    pub(crate) layouts: HashMap<K::Layout, BindGroupLayout>,
+    /// Slots of the layouts made by [`create_layout`](Self::create_layout), checked against shaders.
+    pub(crate) layout_builders: HashMap<K::Layout, BindLayoutBuilder<K::Layout>>,
+    pub(crate) bind_groups: HashMap<K::BindGroup, BindGroup>,
+    pub(crate) pipelines: HashMap<K::Pipeline, CachedComputePipelineId>,
+    /// Pipelines of [`queue_pipeline`](Self::queue_pipeline), kept to check them on shader reloads.
+    pub(crate) pipeline_descriptors: HashMap<K::Pipeline, ComputePipelineDescriptor>,
+    /// Why the shader of a pipeline was rejected, until it passes again.
+    pub(crate) shader_errors: HashMap<K::Pipeline, String>,
+    pub(crate) pipeline_states: HashMap<K::Pipeline, PipelineStatus>,
+    pub(crate) pipeline_state_sender: Option<Sender<PipelineStateChanged<K>>>,
+    pub(crate) readback_mode: ReadbackMode,
 }
 
-pub(crate) fn map_and_read_buffer<B, BL, BG, P>(
+pub(crate) fn map_and_read_buffer<K: BurritoKeys>(
     device: Res<RenderDevice>,
-    mut wgsl: ResMut<WgslRenderBurrito<B, BL, BG, P>>,
-) where
-    B: PartialEq
-        + Eq
-        + std::hash::Hash
-        + std::fmt::Debug
-        + ToOwned<Owned = B>
-        + Send
-        + Sync
-        + 'static,
-    BL: PartialEq
-        + Eq
-        + std::hash::Hash
-        + std::fmt::Debug
-        + ToOwned<Owned = BL>
-        + Send
-        + Sync
-        + 'static,
-    BG: PartialEq
-        + Eq
-        + std::hash::Hash
-        + std::fmt::Debug
-        + ToOwned<Owned = BG>
-        + Send
-        + Sync
-        + 'static,
-    P: PartialEq
-        + Eq
-        + std::hash::Hash
-        + std::fmt::Debug
-        + ToOwned<Owned = P>
-        + Send
-        + Sync
-        + 'static,
-{
-    let mut sent_buffers = Vec::new();
-    for name in wgsl.readback_buffer_keys() {
-        let buffer = wgsl
-            .buffers_readback
-            .get(name)
-            .ok_or_else(|| (BindGroupBuilderError::no_readback_buffer_found(name)));
-
-        let sender = wgsl
-            .senders
-            .get(name)
-            .ok_or_else(|| (BindGroupBuilderError::no_sender_found(name)));
-
-        let buffer_sender = match (buffer, sender) {
-            (Ok(buffer), Ok(sender)) => Ok((buffer, sender)),
-            (Err(err), _) | (_, Err(err)) => Err(err),
-        };
-
-        let sent_result = buffer_sender.and_then(|(buffer, sender)| {
-            if buffer.has_changed() {
-                sender.try_send(device.as_ref(), buffer).map(|_| true)
-            } else {
-                Ok(false)
-            }
-        });
-
-        match sent_result {
-            Ok(true) => {
-                info!("buffer {name:?} sent to main");
-                sent_buffers.push(name.to_owned());
+    mut wgsl: ResMut<WgslRenderBurrito<K>>,
+) {
+    let wgsl = wgsl.as_mut();
+    // the copies of this frame are submitted, their maps complete during this or a later poll
+    for buffer in wgsl.buffers_readback.values_mut() {
+        buffer.start_mapping();
+    }
+    device
+        .poll(wgsl.readback_mode.maintain())
+        .panic_on_timeout();
+
+    for (name, buffer) in wgsl.buffers_readback.iter_mut() {
+        for readback in buffer.take_mapped() {
+            let sent_result = readback.and_then(|readback| {
+                let id = readback.id;
+                wgsl.senders
+                    .get(name)
+                    .ok_or_else(|| BindGroupBuilderError::no_sender_found(name))
+                    .and_then(|sender| sender.send(readback))
+                    .map(|_| id)
+            });
+
+            match sent_result {
+                Ok(id) => debug!("buffer {name:?} readback {id} sent to main"),
+                Err(err) => error!("error sending buffer {name:?}: {err}"),
             }
-            Err(err) => error!("error sending buffer {name:?}: {err}"),
-            _ => {}
         }
     }
+}
+
+/// Checks the layouts of the queued pipelines against their shader once loaded and after every reload.
+/// A mismatch would otherwise only show up as a wgpu validation panic when the pipeline is created.
+pub(crate) fn check_pipeline_shaders<K: BurritoKeys>(
+    mut events: Extract<EventReader<AssetEvent<Shader>>>,
+    shaders: Extract<Res<Assets<Shader>>>,
+    asset_server: Extract<Res<AssetServer>>,
+    pipeline_cache: Res<PipelineCache>,
+    mut wgsl: ResMut<WgslRenderBurrito<K>>,
+) {
+    let changed: Vec<AssetId<Shader>> = events
+        .read()
+        .filter_map(|event| match event {
+            AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id } => Some(*id),
+            _ => None,
+        })
+        .collect();
+
+    let due: Vec<(K::Pipeline, AssetId<Shader>)> = wgsl
+        .pipeline_descriptors
+        .iter()
+        .filter(|(name, descriptor)| {
+            let id = descriptor.shader.id();
+            // queued after their shader loaded, no event is coming for them
+            let unchecked =
+                !wgsl.pipelines.contains_key(*name) && !wgsl.shader_errors.contains_key(*name);
+            changed.contains(&id) || (unchecked && asset_server.is_loaded_with_dependencies(id))
+        })
+        .map(|(name, descriptor)| (name.to_owned(), descriptor.shader.id()))
+        .collect();
+
+    for (name, shader) in due {
+        let Some(shader) = shaders.get(shader) else {
+            continue;
+        };
+        let reflection = ShaderReflection::from_shader(shader, &shaders);
+        wgsl.check_pipeline(&name, reflection, |descriptor| {
+            pipeline_cache.queue_compute_pipeline(descriptor)
+        });
+    }
+}
 
-    for name in sent_buffers {
-        info!("marking {name:?} as sent");
-        wgsl.get_buffer_readback_mut(&name).unwrap().mark_sent();
+/// Follows the pipelines of the burrito through shader reloads and reports their changes.
+pub(crate) fn track_pipeline_states<K: BurritoKeys>(
+    pipeline_cache: Res<PipelineCache>,
+    mut wgsl: ResMut<WgslRenderBurrito<K>>,
+) {
+    wgsl.update_pipeline_states(|pipeline| PipelineStatus::of(&pipeline_cache, pipeline));
+}
+
+pub(crate) fn receive_pipeline_states<K: BurritoKeys>(
+    mut wgsl: ResMut<WgslMainBurrito<K>>,
+    mut events: EventWriter<PipelineStateChanged<K>>,
+) {
+    let wgsl = wgsl.as_mut();
+    let Some(receiver) = wgsl.pipeline_state_receiver.as_ref() else {
+        return;
+    };
+
+    for changed in receiver.try_iter() {
+        wgsl.pipeline_states
+            .insert(changed.pipeline.to_owned(), changed.status.clone());
+        events.send(changed);
     }
 }
 
-impl<B: std::fmt::Debug + std::hash::Hash + PartialEq + Eq + ToOwned<Owned = B>> Default
-    for WgslMainBurrito<B>
-{
+impl<K: BurritoKeys> Default for WgslMainBurrito<K> {
     fn default() -> Self {
         Self {
             receivers: HashMap::new(),
+            pipeline_states: HashMap::new(),
+            pipeline_state_receiver: None,
         }
     }
 }
 
-impl<B> WgslMainBurrito<B>
-where
-    B: PartialEq + Eq + std::hash::Hash + std::fmt::Debug + ToOwned<Owned = B>,
-{
+impl<K: BurritoKeys> WgslMainBurrito<K> {
     pub fn new() -> Self {
         Self::default()
     }
 
+    pub(crate) fn with_pipeline_state_receiver(
+        mut self,
+        receiver: Receiver<PipelineStateChanged<K>>,
+    ) -> Self {
+        self.pipeline_state_receiver = Some(receiver);
+        self
+    }
+
+    /// Latest state of the pipeline received from the render world.
+    pub fn pipeline_state(&self, pipeline_name: &K::Pipeline) -> Option<&PipelineStatus> {
+        self.pipeline_states.get(pipeline_name)
+    }
+
     pub fn insert_receiver(
         &mut self,
-        buffer_name: B,
-        receiver: crossbeam_channel::Receiver<Vec<u8>>,
+        buffer_name: K::Buffer,
+        receiver: crossbeam_channel::Receiver<Readback>,
     ) -> &mut Self {
         self.receivers
             .insert(buffer_name, ReadbackReceiver(receiver));
@@ -132,7 +171,7 @@ where
 
     pub fn insert_many_receivers(
         &mut self,
-        receivers: Vec<(B, crossbeam_channel::Receiver<Vec<u8>>)>,
+        receivers: Vec<(K::Buffer, crossbeam_channel::Receiver<Readback>)>,
     ) -> &mut Self {
         for (buffer_name, receiver) in receivers {
             self.insert_receiver(buffer_name, receiver);
@@ -141,7 +180,7 @@ where
         self
     }
 
-    pub fn try_receive<T: AnyBitPattern>(&self, buffer_name: B) -> Option<T> {
+    pub fn try_receive<T: AnyBitPattern>(&self, buffer_name: K::Buffer) -> Option<T> {
         let result = match self.receivers.get(&buffer_name) {
             Some(receiver) => receiver.try_read::<T>(),
             None => Err(BindGroupBuilderError::no_receiver_found(
@@ -158,9 +197,18 @@ where
         }
     }
 
-    pub fn try_receive_vec<T: AnyBitPattern>(&self, buffer_name: B) -> Option<Vec<T>> {
+    pub fn try_receive_vec<T: AnyBitPattern>(&self, buffer_name: K::Buffer) -> Option<Vec<T>> {
+        self.try_receive_vec_with_id(buffer_name)
+            .map(|(_, data)| data)
+    }
+
+    /// Oldest readback of `buffer_name` with the readback id it was copied with.
+    pub fn try_receive_vec_with_id<T: AnyBitPattern>(
+        &self,
+        buffer_name: K::Buffer,
+    ) -> Option<(u64, Vec<T>)> {
         let result = match self.receivers.get(&buffer_name) {
-            Some(receiver) => receiver.try_read_vec::<T>(),
+            Some(receiver) => receiver.try_read_vec_with_id::<T>(),
             None => Err(BindGroupBuilderError::no_receiver_found(
                 buffer_name.to_owned(),
             )),
@@ -176,95 +224,127 @@ where
     }
 }
 
-impl<B, BL, BG, P> Default for WgslRenderBurrito<B, BL, BG, P>
-where
-    B: PartialEq + Eq + std::hash::Hash + std::fmt::Debug + ToOwned<Owned = B>,
-    BL: PartialEq + Eq + std::hash::Hash + std::fmt::Debug + ToOwned<Owned = BL>,
-    BG: PartialEq + Eq + std::hash::Hash + std::fmt::Debug + ToOwned<Owned = BG>,
-    P: PartialEq + Eq + std::hash::Hash + std::fmt::Debug + ToOwned<Owned = P>,
-{
+impl<K: BurritoKeys> Default for WgslRenderBurrito<K> {
     fn default() -> Self {
         Self {
             buffers: HashMap::new(),
             buffers_readback: HashMap::new(),
             senders: HashMap::new(),
             layouts: HashMap::new(),
+            layout_builders: HashMap::new(),
             bind_groups: HashMap::new(),
             pipelines: HashMap::new(),
+            pipeline_descriptors: HashMap::new(),
+            shader_errors: HashMap::new(),
+            pipeline_states: HashMap::new(),
+            pipeline_state_sender: None,
+            readback_mode: ReadbackMode::default(),
         }
     }
 }
 
-impl<B, BL, BG, P> WgslRenderBurrito<B, BL, BG, P>
-where
-    B: PartialEq + Eq + std::hash::Hash + std::fmt::Debug + ToOwned<Owned = B>,
-    BL: PartialEq + Eq + std::hash::Hash + std::fmt::Debug + ToOwned<Owned = BL>,
-    BG: PartialEq + Eq + std::hash::Hash + std::fmt::Debug + ToOwned<Owned = BG>,
-    P: PartialEq + Eq + std::hash::Hash + std::fmt::Debug + ToOwned<Owned = P>,
-{
+impl<K: BurritoKeys> WgslRenderBurrito<K> {
     pub fn new() -> Self {
         Self::default()
     }
 
+    pub fn with_readback_mode(mut self, readback_mode: ReadbackMode) -> Self {
+        self.readback_mode = readback_mode;
+        self
+    }
+    pub fn readback_mode(&self) -> ReadbackMode {
+        self.readback_mode
+    }
+
+    pub(crate) fn with_pipeline_state_sender(
+        mut self,
+        sender: Sender<PipelineStateChanged<K>>,
+    ) -> Self {
+        self.pipeline_state_sender = Some(sender);
+        self
+    }
+
     pub fn start_create_buffers<'a, 'b>(
         &'b mut self,
         device: &'a RenderDevice,
-    ) -> BufferMutateBuilder<'a, 'b, 'b, B> {
-        BufferMutateBuilder::new(device, &mut self.buffers, &mut self.buffers_readback)
+    ) -> BufferMutateBuilder<'a, 'b, 'b, K::Buffer> {
+        BufferMutateBuilder::new(
+            device,
+            &mut self.buffers,
+            &mut self.buffers_readback,
+            self.readback_mode,
+        )
     }
 
-    pub fn get_buffer(&self, buffer_name: &B) -> Option<&Buffer> {
+    pub fn get_buffer(&self, buffer_name: &K::Buffer) -> Option<&Buffer> {
         self.buffers.get(buffer_name)
     }
-    pub fn has_buffer(&self, buffer_name: &B) -> bool {
+    pub fn has_buffer(&self, buffer_name: &K::Buffer) -> bool {
         self.buffers.contains_key(buffer_name)
     }
-    pub fn buffer_keys(&self) -> impl Iterator<Item = &B> {
+    pub fn buffer_keys(&self) -> impl Iterator<Item = &K::Buffer> {
         self.buffers.keys()
     }
-    pub fn get_buffer_readback(&self, buffer_name: &B) -> Option<&ReadbackBuffer> {
+    pub fn get_buffer_readback(&self, buffer_name: &K::Buffer) -> Option<&ReadbackBuffer> {
         self.buffers_readback.get(buffer_name)
     }
-    pub fn get_buffer_readback_mut(&mut self, buffer_name: &B) -> Option<&mut ReadbackBuffer> {
+    pub fn get_buffer_readback_mut(
+        &mut self,
+        buffer_name: &K::Buffer,
+    ) -> Option<&mut ReadbackBuffer> {
         self.buffers_readback.get_mut(buffer_name)
     }
-    pub fn has_buffer_readback(&self, buffer_name: &B) -> bool {
+    pub fn has_buffer_readback(&self, buffer_name: &K::Buffer) -> bool {
         self.buffers_readback.contains_key(buffer_name)
     }
-    pub fn readback_buffer_keys(&self) -> impl Iterator<Item = &B> {
+    pub fn readback_buffer_keys(&self) -> impl Iterator<Item = &K::Buffer> {
         self.buffers_readback.keys()
     }
 
-    pub fn builder_layout<'a>(
+    pub fn builder_layout(
         &self,
-        name: BL,
-        device: &'a RenderDevice,
+        name: K::Layout,
         visibility: ShaderStages,
-    ) -> BindLayoutBuilder<'a, BL> {
-        BindLayoutBuilder::new(device, name, visibility)
+    ) -> BindLayoutBuilder<K::Layout> {
+        BindLayoutBuilder::new(name, visibility)
+    }
+    /// Builds the layout and keeps its slots to check the pipelines using it against their shader.
+    pub fn create_layout(
+        &mut self,
+        builder: BindLayoutBuilder<K::Layout>,
+        device: &RenderDevice,
+    ) -> &mut Self {
+        let name = builder.name().to_owned();
+        self.layouts.insert(name.to_owned(), builder.build(device));
+        self.layout_builders.insert(name, builder);
+        self
     }
-    pub fn insert_layout(&mut self, key: BL, layout: BindGroupLayout) -> &mut Self {
+    pub fn insert_layout(&mut self, key: K::Layout, layout: BindGroupLayout) -> &mut Self {
         self.layouts.insert(key, layout);
         self
     }
-    pub fn get_layout(&self, key: &BL) -> Option<&BindGroupLayout> {
+    pub fn get_layout(&self, key: &K::Layout) -> Option<&BindGroupLayout> {
         self.layouts.get(key)
     }
-    pub fn has_layout(&self, key: &BL) -> bool {
+    pub fn has_layout(&self, key: &K::Layout) -> bool {
         self.layouts.contains_key(key)
     }
-    pub fn layout_keys(&self) -> impl Iterator<Item = &BL> {
+    pub fn layout_keys(&self) -> impl Iterator<Item = &K::Layout> {
         self.layouts.keys()
     }
 
-    pub fn insert_bind_group(&mut self, bind_group_name: BG, bind_group: BindGroup) -> &mut Self {
+    pub fn insert_bind_group(
+        &mut self,
+        bind_group_name: K::BindGroup,
+        bind_group: BindGroup,
+    ) -> &mut Self {
         self.bind_groups.insert(bind_group_name, bind_group);
         self
     }
 
     pub(crate) fn get_buffers(
         &self,
-        buffer_entries: &[B],
+        buffer_entries: &[K::Buffer],
     ) -> Result<Vec<&Buffer>, BindGroupBuilderError> {
         let mut buffers = Vec::with_capacity(buffer_entries.len());
         for name in buffer_entries.iter() {
@@ -279,10 +359,10 @@ where
 
     pub fn create_bind_group(
         &mut self,
-        bind_group_name: BG,
+        bind_group_name: K::BindGroup,
         device: &RenderDevice,
-        layout_name: BL,
-        buffer_names: &[B],
+        layout_name: K::Layout,
+        buffer_names: &[K::Buffer],
     ) -> &mut Self {
         let query_result = match (
             self.layouts.get(&layout_name),
@@ -309,42 +389,155 @@ where
         self
     }
 
-    pub fn get_bind_group(&self, bind_group_name: &BG) -> Option<&BindGroup> {
+    pub fn get_bind_group(&self, bind_group_name: &K::BindGroup) -> Option<&BindGroup> {
         self.bind_groups.get(bind_group_name)
     }
-    pub fn has_bind_group(&self, bind_group_name: &BG) -> bool {
+    pub fn has_bind_group(&self, bind_group_name: &K::BindGroup) -> bool {
         self.bind_groups.contains_key(bind_group_name)
     }
-    pub fn bind_group_keys(&self) -> impl Iterator<Item = &BG> {
+    pub fn bind_group_keys(&self) -> impl Iterator<Item = &K::BindGroup> {
         self.bind_groups.keys()
     }
 
     pub fn builder_pipeline<'a, 'b>(
         &'a self,
-        name: P,
+        name: K::Pipeline,
         shader: Handle<Shader>,
         entry_point: &'b str,
-    ) -> PipelineBuilder<'b, 'a, P, BL> {
+    ) -> PipelineBuilder<'b, 'a, K::Pipeline, K::Layout> {
         PipelineBuilder::new(name, shader, entry_point, &self.layouts)
     }
-    pub fn insert_pipeline(&mut self, name: P, pipeline: CachedComputePipelineId) -> &mut Self {
+    pub fn insert_pipeline(
+        &mut self,
+        name: K::Pipeline,
+        pipeline: CachedComputePipelineId,
+    ) -> &mut Self {
         self.pipelines.insert(name, pipeline);
         self
     }
 
-    pub fn get_pipeline(&self, pipeline_name: &P) -> Option<&CachedComputePipelineId> {
+    /// Queues the pipeline into the [`PipelineCache`] once its shader matches the layouts
+    /// made by [`create_layout`](Self::create_layout), layouts inserted as values are not checked.
+    pub fn queue_pipeline(
+        &mut self,
+        name: K::Pipeline,
+        descriptor: ComputePipelineDescriptor,
+    ) -> &mut Self {
+        self.pipeline_descriptors.insert(name, descriptor);
+        self
+    }
+
+    /// Checks the layouts of the pipeline against its shader, it is queued after its first pass.
+    pub(crate) fn check_pipeline(
+        &mut self,
+        name: &K::Pipeline,
+        reflection: Result<ShaderReflection, BindGroupBuilderError>,
+        queue: impl FnOnce(ComputePipelineDescriptor) -> CachedComputePipelineId,
+    ) {
+        let Some(descriptor) = self.pipeline_descriptors.get(name) else {
+            return;
+        };
+        let check =
+            reflection.and_then(|reflection| self.validate_layouts(&reflection, descriptor));
+        if let Err(error) = check {
+            error!("pipeline {name:?} shader rejected: {error}");
+            self.shader_errors
+                .insert(name.to_owned(), error.to_string());
+            return;
+        }
+
+        self.shader_errors.remove(name);
+        if !self.pipelines.contains_key(name) {
+            let pipeline = queue(descriptor.clone());
+            self.pipelines.insert(name.to_owned(), pipeline);
+        }
+    }
+
+    fn validate_layouts(
+        &self,
+        reflection: &ShaderReflection,
+        descriptor: &ComputePipelineDescriptor,
+    ) -> Result<(), BindGroupBuilderError> {
+        for (group, layout) in descriptor.layout.iter().enumerate() {
+            let builder = self
+                .layouts
+                .iter()
+                .find(|(_, known)| known.id() == layout.id())
+                .and_then(|(key, _)| self.layout_builders.get(key));
+            let Some(builder) = builder else {
+                continue;
+            };
+            builder.validate(reflection, &descriptor.entry_point, group as u32)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_pipeline(&self, pipeline_name: &K::Pipeline) -> Option<&CachedComputePipelineId> {
         self.pipelines.get(pipeline_name)
     }
 
-    pub fn has_pipeline(&self, pipeline_name: &P) -> bool {
+    pub fn has_pipeline(&self, pipeline_name: &K::Pipeline) -> bool {
         self.pipelines.contains_key(pipeline_name)
     }
 
-    pub fn pipeline_keys(&self) -> impl Iterator<Item = &P> {
+    pub fn pipeline_keys(&self) -> impl Iterator<Item = &K::Pipeline> {
         self.pipelines.keys()
     }
 
-    pub fn copy_to_readback_buffer(&self, encoder: &mut CommandEncoder, buffer_name: &B) {
+    /// State of the pipeline as of the end of the last frame.
+    pub fn pipeline_state(&self, pipeline_name: &K::Pipeline) -> Option<&PipelineStatus> {
+        self.pipeline_states.get(pipeline_name)
+    }
+
+    /// Records the state of every pipeline, only changes are logged and sent to the main world.
+    pub(crate) fn update_pipeline_states(
+        &mut self,
+        status_of: impl Fn(CachedComputePipelineId) -> PipelineStatus,
+    ) {
+        let rejected = self
+            .shader_errors
+            .keys()
+            .filter(|name| !self.pipelines.contains_key(*name));
+        for name in self.pipelines.keys().chain(rejected) {
+            let status = match (self.shader_errors.get(name), self.pipelines.get(name)) {
+                (Some(error), _) => PipelineStatus::Failed(error.to_owned()),
+                (None, Some(pipeline)) => status_of(*pipeline),
+                (None, None) => continue,
+            };
+            let previous = self.pipeline_states.insert(name.to_owned(), status.clone());
+            if previous.as_ref() == Some(&status) {
+                continue;
+            }
+
+            status.log_change(name, previous.as_ref());
+            if let Some(sender) = self.pipeline_state_sender.as_ref() {
+                // the main world is gone when the app exits
+                let _ = sender.send(PipelineStateChanged {
+                    pipeline: name.to_owned(),
+                    status,
+                });
+            }
+        }
+    }
+
+    /// The compiled pipeline, fails while it compiles or when its shader has errors.
+    pub fn try_get_compute_pipeline<'a>(
+        &self,
+        pipeline_name: &K::Pipeline,
+        pipeline_cache: &'a PipelineCache,
+    ) -> Result<&'a ComputePipeline, BindGroupBuilderError> {
+        if let Some(error) = self.shader_errors.get(pipeline_name) {
+            return Err(BindGroupBuilderError::pipeline_failed(pipeline_name, error));
+        }
+        let Some(pipeline) = self.pipelines.get(pipeline_name) else {
+            return Err(BindGroupBuilderError::no_pipeline_found(pipeline_name));
+        };
+
+        ready_compute_pipeline(pipeline_cache, *pipeline, pipeline_name)
+    }
+
+    pub fn copy_to_readback_buffer(&self, encoder: &mut CommandEncoder, buffer_name: &K::Buffer) {
         let Some(gpu_buffer) = self.buffers.get(buffer_name) else {
             let error = BindGroupBuilderError::no_buffer_found(buffer_name);
             error!("error filling readback buffer {buffer_name:?}: {error}");
@@ -355,14 +548,23 @@ where
             error!("error filling readback buffer {buffer_name:?}: {error}");
             return;
         };
-        if gpu_buffer.size() != cpu_buffer.buffer.size() {
+        // nothing reserved when every staging buffer is in flight
+        let Some(staging_buffer) = cpu_buffer.copy_target() else {
+            return;
+        };
+        if gpu_buffer.size() != cpu_buffer.size() {
             warn!("buffer {buffer_name:?} size mismatch");
         }
 
-        encoder.copy_buffer_to_buffer(gpu_buffer, 0, &cpu_buffer.buffer, 0, gpu_buffer.size());
+        encoder.copy_buffer_to_buffer(gpu_buffer, 0, staging_buffer, 0, gpu_buffer.size());
     }
 
-    pub fn copy_buffer_to_buffer(&self, encoder: &mut CommandEncoder, from: &B, to: &B) {
+    pub fn copy_buffer_to_buffer(
+        &self,
+        encoder: &mut CommandEncoder,
+        from: &K::Buffer,
+        to: &K::Buffer,
+    ) {
         let buffers = match (self.buffers.get(from), self.buffers.get(to)) {
             (Some(from), Some(to)) => {
                 if from.size() != to.size() {
@@ -390,8 +592,8 @@ where
 
     pub fn insert_sender(
         &mut self,
-        buffer_name: B,
-        sender: crossbeam_channel::Sender<Vec<u8>>,
+        buffer_name: K::Buffer,
+        sender: crossbeam_channel::Sender<Readback>,
     ) -> &mut Self {
         self.senders.insert(buffer_name, ReadbackSender(sender));
 
@@ -399,7 +601,7 @@ where
     }
     pub fn insert_many_senders(
         &mut self,
-        senders: Vec<(B, crossbeam_channel::Sender<Vec<u8>>)>,
+        senders: Vec<(K::Buffer, crossbeam_channel::Sender<Readback>)>,
     ) -> &mut Self {
         for (buffer_name, sender) in senders {
             self.insert_sender(buffer_name, sender);
@@ -408,13 +610,13 @@ where
         self
     }
 
-    pub fn get_sender(&self, buffer_name: &B) -> Option<&ReadbackSender> {
+    pub fn get_sender(&self, buffer_name: &K::Buffer) -> Option<&ReadbackSender> {
         self.senders.get(buffer_name)
     }
-    pub fn has_sender(&self, buffer_name: &B) -> bool {
+    pub fn has_sender(&self, buffer_name: &K::Buffer) -> bool {
         self.senders.contains_key(buffer_name)
     }
-    pub fn sender_keys(&self) -> impl Iterator<Item = &B> {
+    pub fn sender_keys(&self) -> impl Iterator<Item = &K::Buffer> {
         self.senders.keys()
     }
 }
@@ -437,3 +639,83 @@ fn buffers_to_bind_entries<'a, B: std::fmt::Debug + ToOwned<Owned = B>>(
     }
     Ok(entries)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::wgsl::testing::software_gpu;
+
+    crate::burrito_keys! {
+        #[allow(dead_code)]
+        struct TestKeys {
+            type Buffer = TestBufferKey {};
+            type Layout = TestLayoutKey { Heights };
+            type BindGroup = TestBindGroupKey {};
+            type Pipeline = TestPipelineKey { Heights };
+        }
+    }
+
+    const SHADER: &str = "
+@group(0) @binding(0) var<storage, read> positions: array<vec2<f32>>;
+@group(0) @binding(1) var<storage, read_write> heights: array<f32>;
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    heights[id.x] = length(positions[id.x]);
+}
+";
+
+    #[test]
+    #[ignore = "needs a wgpu adapter, run with --ignored"]
+    fn pipelines_are_queued_once_their_shader_matches() {
+        let device = software_gpu().device;
+        let mut wgsl = WgslRenderBurrito::<TestKeys>::new();
+        // the slots are swapped
+        let layout = wgsl
+            .builder_layout(TestLayoutKey::Heights, ShaderStages::COMPUTE)
+            .with_storage_slot::<Vec<f32>>()
+            .with_readonly_storage_slot::<Vec<Vec2>>();
+        wgsl.create_layout(layout, &device);
+        let descriptor = wgsl
+            .builder_pipeline(TestPipelineKey::Heights, Handle::default(), "main")
+            .with_layout(&TestLayoutKey::Heights)
+            .build();
+        wgsl.queue_pipeline(TestPipelineKey::Heights, descriptor);
+
+        let reflection = || ShaderReflection::from_wgsl(SHADER);
+        let mut queued = 0;
+        let mut queue = |_| {
+            queued += 1;
+            CachedComputePipelineId::INVALID
+        };
+        wgsl.check_pipeline(&TestPipelineKey::Heights, reflection(), &mut queue);
+        wgsl.update_pipeline_states(|_| PipelineStatus::Ready);
+        assert!(!wgsl.has_pipeline(&TestPipelineKey::Heights));
+        assert!(matches!(
+            wgsl.pipeline_state(&TestPipelineKey::Heights),
+            Some(PipelineStatus::Failed(_))
+        ));
+
+        let layout = wgsl
+            .builder_layout(TestLayoutKey::Heights, ShaderStages::COMPUTE)
+            .with_readonly_storage_slot::<Vec<Vec2>>()
+            .with_storage_slot::<Vec<f32>>();
+        wgsl.create_layout(layout, &device);
+        let descriptor = wgsl
+            .builder_pipeline(TestPipelineKey::Heights, Handle::default(), "main")
+            .with_layout(&TestLayoutKey::Heights)
+            .build();
+        wgsl.queue_pipeline(TestPipelineKey::Heights, descriptor);
+        // a reload checks the pipeline again, it is only queued once
+        wgsl.check_pipeline(&TestPipelineKey::Heights, reflection(), &mut queue);
+        wgsl.check_pipeline(&TestPipelineKey::Heights, reflection(), &mut queue);
+        wgsl.update_pipeline_states(|_| PipelineStatus::Ready);
+
+        assert_eq!(queued, 1);
+        assert!(wgsl.has_pipeline(&TestPipelineKey::Heights));
+        assert_eq!(
+            wgsl.pipeline_state(&TestPipelineKey::Heights),
+            Some(&PipelineStatus::Ready)
+        );
+    }
+}
@@ -0,0 +1,26 @@
+//! Software adapter the gpu tests run on, lavapipe in CI.
+
+use bevy::{prelude::*, render::renderer::RenderDevice, tasks::block_on};
+
+pub(crate) struct SoftwareGpu {
+    pub adapter: wgpu::Adapter,
+    pub device: RenderDevice,
+    pub queue: wgpu::Queue,
+}
+
+/// Device of the fallback adapter, panics without one so a missing driver fails the tests.
+pub(crate) fn software_gpu() -> SoftwareGpu {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let adapter = block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        force_fallback_adapter: true,
+        ..default()
+    }))
+    .expect("no software adapter available");
+    let (device, queue) = block_on(adapter.request_device(&default(), None)).unwrap();
+
+    SoftwareGpu {
+        adapter,
+        device: device.into(),
+        queue,
+    }
+}
@@ -1,41 +1,28 @@
 use bevy::prelude::*;
 
+use crate::burrito_keys;
 use crate::utils::prelude::*;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum RenderBufferKey {
-    MapPointPositions,
-    MapPointData,
+burrito_keys! {
+    pub struct RenderKeys {
+        type Buffer = RenderBufferKey {
+            MapPointPositions,
+            MapPointData,
+        };
+        type Layout = RenderLayoutKey { MapLayout };
+        type BindGroup = RenderBindGroupKey { MapBindGroup };
+        type Pipeline = RenderPipelineKey { MapPipeline };
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum RenderLayoutKey {
-    MapLayout,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum RenderBindGroupKey {
-    MapBindGroup,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum RenderPipelineKey {
-    MapPipeline,
-}
-
-pub type RenderStatePlugin =
-    WgslBurritoPlugin<RenderBufferKey, RenderLayoutKey, RenderBindGroupKey, RenderPipelineKey>;
+pub type RenderStatePlugin = WgslBurritoPlugin<RenderKeys>;
 
-pub type RenderStateMain = WgslMainBurrito<RenderBufferKey>;
+pub type RenderStateMain = WgslMainBurrito<RenderKeys>;
 
-pub type RenderStateRender =
-    WgslRenderBurrito<RenderBufferKey, RenderLayoutKey, RenderBindGroupKey, RenderPipelineKey>;
+pub type RenderStateRender = WgslRenderBurrito<RenderKeys>;
 
-pub type RenderNode<N, Pass> =
-    NodeBurrito<N, Pass, RenderBufferKey, RenderLayoutKey, RenderBindGroupKey, RenderPipelineKey>;
+pub type RenderNode<N, Pass> = NodeBurrito<N, Pass, RenderKeys>;
 
 pub fn insert_readback_channel(app: &mut App, buffer_key: RenderBufferKey) {
-    insert_burrito_channel::<RenderBufferKey, RenderLayoutKey, RenderBindGroupKey, RenderPipelineKey>(
-        app, buffer_key,
-    );
+    insert_burrito_channel::<RenderKeys>(app, buffer_key);
 }